@@ -0,0 +1,136 @@
+//! Throughput of cfheaders verification over a mainnet-sized chain.
+//!
+//! `cargo bench --bench cfheaders [-- HEIGHTS]` (default 800000). The sources
+//! serve precomputed data, so the time is dominated by rolling-header hashing.
+
+#[cfg(niebla_unsend)]
+fn main() {
+    eprintln!("cfheaders bench: built with the `local` feature; rebuild without it");
+}
+
+#[cfg(not(niebla_unsend))]
+fn main() -> anyhow::Result<()> {
+    let heights = std::env::args()
+        .skip(1)
+        .find(|a| !a.starts_with('-'))
+        .map(|a| a.parse())
+        .transpose()?
+        .unwrap_or(800_000);
+    let rt = tokio::runtime::Builder::new_current_thread().build()?;
+    let engine = bench::engine(heights);
+    let started = std::time::Instant::now();
+    rt.block_on(engine.run_to_tip())?;
+    let elapsed = started.elapsed();
+    println!(
+        "cfheaders: {heights} headers in {elapsed:.2?} ({:.0} headers/s)",
+        heights as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+#[cfg(not(niebla_unsend))]
+mod bench {
+    use async_trait::async_trait;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{BlockHash, ScriptBuf, Transaction};
+    use niebla_158::filter_source::{CfHeadersBatch, FilterSource};
+    use niebla_158::headers::HeaderSource;
+    use niebla_158::{Niebla158, Store, WalletHooks};
+
+    /// Filter hash `h` for every height `h`; block hashes are the height too.
+    pub struct Synthetic(u32);
+
+    fn block_hash(height: u32) -> BlockHash {
+        let mut b = [0u8; 32];
+        b[..4].copy_from_slice(&height.to_le_bytes());
+        BlockHash::from_byte_array(b)
+    }
+
+    fn height_of(hash: BlockHash) -> u32 {
+        let b = hash.to_byte_array();
+        u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+    }
+
+    #[async_trait]
+    impl FilterSource for Synthetic {
+        async fn get_cfheaders(
+            &self,
+            start_h: u32,
+            stop: BlockHash,
+        ) -> anyhow::Result<CfHeadersBatch> {
+            let headers = (start_h..=height_of(stop))
+                .map(|h| {
+                    let mut fh = [0u8; 32];
+                    fh[..4].copy_from_slice(&h.to_le_bytes());
+                    fh
+                })
+                .collect();
+            Ok(CfHeadersBatch {
+                start_height: start_h,
+                headers,
+            })
+        }
+
+        async fn get_cfilter(&self, _block: BlockHash) -> anyhow::Result<Vec<u8>> {
+            Ok(vec![0x00])
+        }
+    }
+
+    #[async_trait]
+    impl HeaderSource for Synthetic {
+        async fn tip_height(&self) -> anyhow::Result<u32> {
+            Ok(self.0)
+        }
+
+        async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+            Ok(block_hash(height))
+        }
+    }
+
+    /// Keeps nothing, so the store does not show up in the profile.
+    pub struct NullStore;
+
+    #[async_trait]
+    impl Store for NullStore {
+        async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+            Ok(None)
+        }
+        async fn save_cf_tip(&self, _h: u32, _cf: BlockHash) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn get_last_scanned(&self) -> anyhow::Result<u32> {
+            Ok(0)
+        }
+        async fn set_last_scanned(&self, _h: u32) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn get_birth_height(&self) -> anyhow::Result<Option<u32>> {
+            Ok(None)
+        }
+        async fn set_birth_height(&self, _h: u32) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Empty watchlist: the scan phase is skipped.
+    pub struct NoWallet;
+
+    #[async_trait]
+    impl WalletHooks for NoWallet {
+        async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+            Ok(vec![])
+        }
+        async fn on_block_match(
+            &self,
+            _h: u32,
+            _b: BlockHash,
+            _txs: Vec<Transaction>,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub fn engine(heights: u32) -> Niebla158<NullStore, NoWallet, Synthetic, Synthetic> {
+        Niebla158::new(NullStore, NoWallet, Synthetic(heights), Synthetic(heights))
+    }
+}
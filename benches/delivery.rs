@@ -0,0 +1,193 @@
+//! Allocations made while delivering a large matched block.
+//!
+//! `cargo bench --bench delivery [-- TXS]` (default 5000). A single block of
+//! `TXS` two-in, two-out transactions, one in fifty paying a watched script,
+//! is scanned and delivered to one wallet, to three wallets and with prevouts.
+//! A counting allocator reports how many allocations and bytes each run takes,
+//! so clones of the block in the delivery path show up directly.
+
+#[cfg(niebla_unsend)]
+fn main() {
+    eprintln!("delivery bench: built with the `local` feature; rebuild without it");
+}
+
+#[cfg(not(niebla_unsend))]
+#[global_allocator]
+static ALLOC: bench::Counting = bench::Counting;
+
+#[cfg(not(niebla_unsend))]
+fn main() -> anyhow::Result<()> {
+    let txs = std::env::args()
+        .skip(1)
+        .find(|a| !a.starts_with('-'))
+        .map(|a| a.parse())
+        .transpose()?
+        .unwrap_or(5_000);
+    let rt = tokio::runtime::Builder::new_current_thread().build()?;
+    let chain = bench::Chain::new(txs)?;
+    println!("delivery: one block of {txs} transactions");
+    for (name, wallets, prevouts) in [
+        ("1 wallet", 1, false),
+        ("3 wallets", 3, false),
+        ("1 wallet + prevouts", 1, true),
+    ] {
+        let engine = chain.engine(wallets, prevouts);
+        let (before, started) = (bench::Counting::snapshot(), std::time::Instant::now());
+        rt.block_on(engine.run_to_tip())?;
+        let elapsed = started.elapsed();
+        let (allocs, bytes) = bench::Counting::since(before);
+        println!(
+            "  {name:<20} {allocs:>8} allocations {:>8.1} MiB {elapsed:>10.2?}",
+            bytes as f64 / (1 << 20) as f64
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(niebla_unsend))]
+mod bench {
+    use async_trait::async_trait;
+    use bitcoin::bip158::BlockFilter;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{
+        absolute::LockTime, consensus, transaction, Amount, BlockHash, OutPoint, ScriptBuf,
+        Sequence, Transaction, TxIn, TxOut, Txid, WPubkeyHash, Witness,
+    };
+    use niebla_158::testing::{
+        block_paying, genesis_hash, MemoryStore, MockFilterSource, MockHeaderSource,
+    };
+    use niebla_158::{MatchDetails, Niebla158, WalletHooks};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+    static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+    /// The system allocator, counting allocations and bytes requested.
+    pub struct Counting;
+
+    impl Counting {
+        pub fn snapshot() -> (usize, usize) {
+            (
+                ALLOCS.load(Ordering::Relaxed),
+                BYTES.load(Ordering::Relaxed),
+            )
+        }
+
+        pub fn since((allocs, bytes): (usize, usize)) -> (usize, usize) {
+            let (a, b) = Self::snapshot();
+            (a - allocs, b - bytes)
+        }
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCS.fetch_add(1, Ordering::Relaxed);
+            BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            ALLOCS.fetch_add(1, Ordering::Relaxed);
+            BYTES.fetch_add(new_size, Ordering::Relaxed);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    fn script(b: u32) -> ScriptBuf {
+        let mut h = [0u8; 20];
+        h[..4].copy_from_slice(&b.to_le_bytes());
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(h))
+    }
+
+    /// Drops every delivery.
+    pub struct Sink;
+
+    #[async_trait]
+    impl WalletHooks for Sink {
+        async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+            Ok(vec![script(0)])
+        }
+        async fn on_block_match(
+            &self,
+            _h: u32,
+            _b: BlockHash,
+            _txs: Vec<Transaction>,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn on_match(&self, _details: MatchDetails) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A genesis stand-in followed by one large block.
+    pub struct Chain {
+        filters: MockFilterSource,
+        headers: MockHeaderSource,
+    }
+
+    impl Chain {
+        pub fn new(txs: u32) -> anyhow::Result<Self> {
+            let mut block = block_paying(1, genesis_hash(), &[script(0)]);
+            for i in 1..txs {
+                let input = |vout| TxIn {
+                    previous_output: OutPoint::new(Txid::from_byte_array([7; 32]), vout),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::from_slice(&[[1u8; 72].as_slice(), [2u8; 33].as_slice()]),
+                };
+                let output = |s| TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: script(s),
+                };
+                let pays = if i % 50 == 0 { 0 } else { i };
+                block.txdata.push(Transaction {
+                    version: transaction::Version::TWO,
+                    lock_time: LockTime::ZERO,
+                    input: vec![input(2 * i), input(2 * i + 1)],
+                    output: vec![output(pays), output(txs + i)],
+                });
+            }
+            block.header.merkle_root = block.compute_merkle_root().expect("has transactions");
+            let filter =
+                BlockFilter::new_script_filter(&block, |_| Ok(ScriptBuf::from(vec![0x51])))?;
+
+            let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+            let prev = headers.push(genesis_hash());
+            filters.add_raw(
+                1,
+                block.block_hash(),
+                prev,
+                filter.content,
+                consensus::serialize(&block),
+            );
+            headers.push(block.block_hash());
+            Ok(Self { filters, headers })
+        }
+
+        pub fn engine(
+            &self,
+            wallets: usize,
+            prevouts: bool,
+        ) -> Niebla158<MemoryStore, Sink, MockFilterSource, MockHeaderSource> {
+            let mut engine = Niebla158::new(
+                MemoryStore::new(),
+                Sink,
+                self.filters.clone(),
+                self.headers.clone(),
+            );
+            for _ in 1..wallets {
+                engine = engine.with_wallet(MemoryStore::new(), Sink);
+            }
+            if prevouts {
+                engine = engine.with_prevouts();
+            }
+            engine
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Filter matching throughput against watchlist size (plain, sharded and
+//! indexed), and cfheaders chain application, over a corpus of real filters.
+//!
+//! `NIEBLA_CORPUS=corpus.bin cargo bench --bench filters` (build the corpus
+//! with `examples/filter_corpus.rs`). Without `NIEBLA_CORPUS` a synthetic chain
+//! of 500 blocks paying 200 scripts each is used instead, so the numbers only
+//! mean something relative to each other.
+
+#[cfg(niebla_unsend)]
+fn main() {
+    eprintln!("filters bench: built with the `local` feature; rebuild without it");
+}
+
+#[cfg(not(niebla_unsend))]
+criterion::criterion_main!(bench::benches);
+
+#[cfg(not(niebla_unsend))]
+mod bench {
+    use anyhow::Context;
+    use async_trait::async_trait;
+    use bitcoin::bip158::BlockFilter;
+    use bitcoin::hashes::{sha256d, Hash};
+    use bitcoin::{BlockHash, ScriptBuf, Transaction, WPubkeyHash};
+    use criterion::{criterion_group, BenchmarkId, Criterion, Throughput};
+    use niebla_158::filter_source::{CfHeadersBatch, FilterSource};
+    use niebla_158::headers::HeaderSource;
+    use niebla_158::testing::{block_paying, genesis_hash, MemoryStore};
+    use niebla_158::{BlockSource, Niebla158, Store, WalletHooks};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    /// Consecutive filters starting at `start`, chained onto `prev_header`.
+    pub struct Corpus {
+        start: u32,
+        prev_header: BlockHash,
+        blocks: Vec<BlockHash>,
+        filters: HashMap<BlockHash, Vec<u8>>,
+    }
+
+    impl Corpus {
+        /// Parse the format written by `examples/filter_corpus.rs`.
+        fn load(path: &str) -> anyhow::Result<Self> {
+            let raw = std::fs::read(path).with_context(|| format!("read {path}"))?;
+            let mut rest = raw.strip_prefix(b"NBFC").context("not a filter corpus")?;
+            let start = read_u32(&mut rest)?;
+            let prev_header = BlockHash::from_slice(take(&mut rest, 32)?)?;
+            let (mut blocks, mut filters) = (vec![], HashMap::new());
+            while !rest.is_empty() {
+                let block = BlockHash::from_slice(take(&mut rest, 32)?)?;
+                let len = read_u32(&mut rest)? as usize;
+                filters.insert(block, take(&mut rest, len)?.to_vec());
+                blocks.push(block);
+            }
+            Ok(Self {
+                start,
+                prev_header,
+                blocks,
+                filters,
+            })
+        }
+
+        /// 500 blocks from genesis, each paying 200 distinct scripts.
+        fn synthetic() -> anyhow::Result<Self> {
+            let (mut blocks, mut filters) = (vec![], HashMap::new());
+            let mut prev = genesis_hash();
+            for h in 1..=500u32 {
+                let scripts: Vec<ScriptBuf> = (0..200u32).map(|i| script(h, i)).collect();
+                let block = block_paying(h, prev, &scripts);
+                let filter = BlockFilter::new_script_filter(&block, |_| {
+                    Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(Default::default()))
+                })?;
+                prev = block.block_hash();
+                filters.insert(prev, filter.content);
+                blocks.push(prev);
+            }
+            Ok(Self {
+                start: 1,
+                prev_header: BlockHash::all_zeros(),
+                blocks,
+                filters,
+            })
+        }
+
+        fn tip(&self) -> u32 {
+            self.start + self.blocks.len() as u32 - 1
+        }
+
+        fn height_of(&self, block: BlockHash) -> anyhow::Result<u32> {
+            let i = self
+                .blocks
+                .iter()
+                .position(|b| *b == block)
+                .context("block not in corpus")?;
+            Ok(self.start + i as u32)
+        }
+
+        /// A store whose cfheaders and scan progress sit just below the corpus.
+        async fn store(&self) -> anyhow::Result<MemoryStore> {
+            let store = MemoryStore::new();
+            if let Some(below) = self.start.checked_sub(1) {
+                store.save_cf_tip(below, self.prev_header).await?;
+                store.set_last_scanned(below).await?;
+            }
+            Ok(store)
+        }
+    }
+
+    /// Split `n` bytes off the front of `rest`.
+    fn take<'a>(rest: &mut &'a [u8], n: usize) -> anyhow::Result<&'a [u8]> {
+        anyhow::ensure!(rest.len() >= n, "truncated filter corpus");
+        let (head, tail) = rest.split_at(n);
+        *rest = tail;
+        Ok(head)
+    }
+
+    fn read_u32(rest: &mut &[u8]) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(take(rest, 4)?.try_into()?))
+    }
+
+    fn script(a: u32, b: u32) -> ScriptBuf {
+        let mut hash = [0u8; 20];
+        hash[..4].copy_from_slice(&a.to_le_bytes());
+        hash[4..8].copy_from_slice(&b.to_le_bytes());
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(hash))
+    }
+
+    #[derive(Clone)]
+    struct Source(Arc<Corpus>);
+
+    #[async_trait]
+    impl FilterSource for Source {
+        async fn get_cfheaders(
+            &self,
+            start_h: u32,
+            stop: BlockHash,
+        ) -> anyhow::Result<CfHeadersBatch> {
+            let c = &self.0;
+            let (from, to) = (start_h - c.start, c.height_of(stop)? - c.start);
+            let headers = c.blocks[from as usize..=to as usize]
+                .iter()
+                .map(|b| sha256d::Hash::hash(&c.filters[b]).to_byte_array())
+                .collect();
+            Ok(CfHeadersBatch {
+                start_height: start_h,
+                headers,
+            })
+        }
+
+        async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+            self.0
+                .filters
+                .get(&block)
+                .cloned()
+                .context("block not in corpus")
+        }
+    }
+
+    #[async_trait]
+    impl HeaderSource for Source {
+        async fn tip_height(&self) -> anyhow::Result<u32> {
+            Ok(self.0.tip())
+        }
+
+        async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+            let i = height.checked_sub(self.0.start).context("below corpus")?;
+            self.0
+                .blocks
+                .get(i as usize)
+                .copied()
+                .context("above corpus")
+        }
+    }
+
+    /// Answers every (false-positive) hit with no transactions, so no block
+    /// downloads show up in the profile.
+    struct NoBlocks;
+
+    #[async_trait]
+    impl BlockSource for NoBlocks {
+        async fn get_block(&self, _block: BlockHash) -> anyhow::Result<Vec<u8>> {
+            anyhow::bail!("the corpus has no blocks")
+        }
+
+        async fn get_relevant_txs(
+            &self,
+            _block: BlockHash,
+            _scripts: &[ScriptBuf],
+        ) -> anyhow::Result<Option<Vec<Transaction>>> {
+            Ok(Some(vec![]))
+        }
+    }
+
+    /// Watches scripts no block pays.
+    struct Watching(Vec<ScriptBuf>);
+
+    #[async_trait]
+    impl WalletHooks for Watching {
+        async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+            Ok(self.0.clone())
+        }
+
+        async fn on_block_match(
+            &self,
+            _h: u32,
+            _b: BlockHash,
+            _txs: Vec<Transaction>,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn corpus() -> Arc<Corpus> {
+        let corpus = match std::env::var("NIEBLA_CORPUS") {
+            Ok(path) => Corpus::load(&path),
+            Err(_) => Corpus::synthetic(),
+        };
+        Arc::new(corpus.expect("load filter corpus"))
+    }
+
+    fn matching(c: &mut Criterion) {
+        let corpus = corpus();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut group = c.benchmark_group("match");
+        group.sample_size(10);
+        group.throughput(Throughput::Elements(corpus.blocks.len() as u64));
+        // (scripts, scripts per shard, indexed): the last rows show what
+        // sharding and indexed matching buy.
+        for (size, shards, indexed) in [
+            (1u32, None, false),
+            (100, None, false),
+            (10_000, None, false),
+            (100_000, None, false),
+            (100_000, Some(25_000), false),
+            (100_000, None, true),
+        ] {
+            let watch = (0..size).map(|i| script(u32::MAX, i)).collect();
+            let store = rt.block_on(corpus.store()).unwrap();
+            let source = Source(corpus.clone());
+            let mut engine = Niebla158::new(store.clone(), Watching(watch), source.clone(), source)
+                .with_block_source(NoBlocks);
+            if let Some(per_shard) = shards {
+                engine = engine.with_watchlist_shards(per_shard);
+            }
+            if indexed {
+                engine = engine.with_indexed_matching();
+            }
+            // Verify cfheaders once; each iteration then only scans.
+            rt.block_on(engine.run_to_tip()).unwrap();
+            let id = match (shards, indexed) {
+                (Some(per_shard), _) => BenchmarkId::new(format!("shards of {per_shard}"), size),
+                (None, true) => BenchmarkId::new("indexed", size),
+                (None, false) => BenchmarkId::from_parameter(size),
+            };
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        store
+                            .set_last_scanned(corpus.start.saturating_sub(1))
+                            .await?;
+                        engine.run_to_tip().await
+                    })
+                    .unwrap()
+                })
+            });
+        }
+        group.finish();
+    }
+
+    fn cfheaders(c: &mut Criterion) {
+        let corpus = corpus();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut group = c.benchmark_group("cfheaders");
+        group.sample_size(10);
+        group.throughput(Throughput::Elements(corpus.blocks.len() as u64));
+        group.bench_function("apply", |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let source = Source(corpus.clone());
+                    let store = corpus.store().await?;
+                    Niebla158::new(store, Watching(vec![]), source.clone(), source)
+                        .run_to_tip()
+                        .await
+                })
+                .unwrap()
+            })
+        });
+        group.finish();
+    }
+
+    criterion_group!(benches, matching, cfheaders);
+}
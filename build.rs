@@ -0,0 +1,21 @@
+fn main() {
+    // `niebla_unsend`: async trait futures and trait objects are not required to be
+    // `Send`/`Sync` (single-threaded targets such as the browser, or the `local` feature).
+    println!("cargo:rustc-check-cfg=cfg(niebla_unsend)");
+    let wasm = std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("wasm32");
+    let local = std::env::var_os("CARGO_FEATURE_LOCAL").is_some();
+    if wasm || local {
+        println!("cargo:rustc-cfg=niebla_unsend");
+    }
+
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/niebla.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+        tonic_build::configure()
+            .build_server(true)
+            .compile_protos(&["proto/niebla.proto"], &["proto"])
+            .expect("compile proto/niebla.proto");
+    }
+}
@@ -0,0 +1,63 @@
+//! Download a corpus of real basic filters for `benches/filters.rs`.
+//!
+//! `cargo run --example filter_corpus --features http -- NODE_URL FROM TO OUT`
+//! reads heights `FROM..=TO` from a Bitcoin Core node's REST interface
+//! (`-rest -blockfilterindex`, e.g. `http://127.0.0.1:8332`) and writes them to
+//! `OUT`; point `NIEBLA_CORPUS` at it when benchmarking.
+//!
+//! Format (integers little-endian): `b"NBFC"`, the start height (u32) and the
+//! filter header before it (32 bytes), then per block its hash (32 bytes), the
+//! filter length (u32) and the filter bytes.
+
+#[cfg(niebla_unsend)]
+fn main() {
+    eprintln!("filter_corpus: built with the `local` feature; rebuild without it");
+}
+
+#[cfg(not(niebla_unsend))]
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    use anyhow::Context;
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+    use niebla_158::headers::HeaderSource;
+    use niebla_158::http::CoreRestSource;
+    use niebla_158::FilterSource;
+    use std::io::Write;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [url, from, to, out] = &args[..] else {
+        anyhow::bail!("usage: filter_corpus NODE_URL FROM TO OUT");
+    };
+    let (from, to): (u32, u32) = (from.parse()?, to.parse()?);
+    anyhow::ensure!(from <= to, "FROM must not be above TO");
+
+    let node = CoreRestSource::new(url.as_str());
+    let prev_header = match from {
+        0 => BlockHash::all_zeros(),
+        h => {
+            node.filter_header(node.hash_at_height(h - 1).await?)
+                .await?
+        }
+    };
+
+    let mut file = std::io::BufWriter::new(
+        std::fs::File::create(out).with_context(|| format!("create {out}"))?,
+    );
+    file.write_all(b"NBFC")?;
+    file.write_all(&from.to_le_bytes())?;
+    file.write_all(prev_header.as_byte_array())?;
+    for h in from..=to {
+        let block = node.hash_at_height(h).await?;
+        let filter = node.get_cfilter(block).await?;
+        file.write_all(block.as_byte_array())?;
+        file.write_all(&(filter.len() as u32).to_le_bytes())?;
+        file.write_all(&filter)?;
+        if (h - from) % 100 == 99 {
+            eprintln!("filter_corpus: {h}/{to}");
+        }
+    }
+    file.flush()?;
+    eprintln!("filter_corpus: wrote {} filters to {out}", to - from + 1);
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! An IndexedDB-backed `Store` for the browser, using [`rexie`](https://crates.io/crates/rexie).
+//!
+//! Browser only: `cargo check --example idb_store --target wasm32-unknown-unknown
+//! --no-default-features`. The crate's traits are `?Send` on wasm, so the store
+//! holds the JS database handle as is. Only the required `Store` methods are
+//! kept; the rest fall back to the trait's defaults.
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    eprintln!("idb_store: browser only; build with --target wasm32-unknown-unknown");
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    use niebla_158::Store;
+
+    wasm_bindgen_futures::spawn_local(async {
+        let store = idb::IdbStore::open("niebla").await.expect("open IndexedDB");
+        let scanned = store.get_last_scanned().await.expect("read scan progress");
+        store
+            .set_last_scanned(scanned)
+            .await
+            .expect("write scan progress");
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+mod idb {
+    use async_trait::async_trait;
+    use bitcoin::BlockHash;
+    use niebla_158::Store;
+    use rexie::{ObjectStore, Rexie, TransactionMode};
+    use wasm_bindgen::JsValue;
+
+    /// Progress as strings under fixed keys of one object store.
+    pub struct IdbStore {
+        db: Rexie,
+    }
+
+    impl IdbStore {
+        pub async fn open(name: &str) -> anyhow::Result<Self> {
+            let db = Rexie::builder(name)
+                .version(1)
+                .add_object_store(ObjectStore::new("state"))
+                .build()
+                .await
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+            Ok(Self { db })
+        }
+
+        async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+            let tx = self
+                .db
+                .transaction(&["state"], TransactionMode::ReadOnly)
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+            let v = tx
+                .store("state")
+                .map_err(|e| anyhow::anyhow!("{e}"))?
+                .get(JsValue::from_str(key))
+                .await
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+            Ok(v.and_then(|v| v.as_string()))
+        }
+
+        async fn put(&self, key: &str, value: &str) -> anyhow::Result<()> {
+            let tx = self
+                .db
+                .transaction(&["state"], TransactionMode::ReadWrite)
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+            tx.store("state")
+                .map_err(|e| anyhow::anyhow!("{e}"))?
+                .put(&JsValue::from_str(value), Some(&JsValue::from_str(key)))
+                .await
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+            tx.done().await.map_err(|e| anyhow::anyhow!("{e}"))?;
+            Ok(())
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl Store for IdbStore {
+        async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+            match (
+                self.get("cf_tip_height").await?,
+                self.get("cf_tip_hash").await?,
+            ) {
+                (Some(h), Some(hash)) => Ok(Some((h.parse()?, hash.parse()?))),
+                _ => Ok(None),
+            }
+        }
+        async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
+            self.put("cf_tip_hash", &cfheader.to_string()).await?;
+            self.put("cf_tip_height", &height.to_string()).await
+        }
+        async fn get_last_scanned(&self) -> anyhow::Result<u32> {
+            Ok(self
+                .get("last_scanned")
+                .await?
+                .map(|s| s.parse())
+                .transpose()?
+                .unwrap_or(0))
+        }
+        async fn set_last_scanned(&self, height: u32) -> anyhow::Result<()> {
+            self.put("last_scanned", &height.to_string()).await
+        }
+    }
+}
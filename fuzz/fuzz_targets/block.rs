@@ -0,0 +1,22 @@
+//! Block bytes: decoding must never panic, and a block that decodes fully
+//! re-encodes to the same bytes.
+#![no_main]
+
+use bitcoin::consensus::encode::serialize;
+use bitcoin::Block;
+use libfuzzer_sys::fuzz_target;
+use niebla_158::parse;
+
+fuzz_target!(|raw: &[u8]| {
+    let _ = parse::block_header(raw);
+    let mut txdata = vec![];
+    if parse::block_txs(raw, &mut |tx| {
+        txdata.push(tx);
+        true
+    })
+    .is_ok()
+    {
+        let header = parse::block_header(raw).expect("decoded blocks have a header");
+        assert_eq!(serialize(&Block { header, txdata }), raw);
+    }
+});
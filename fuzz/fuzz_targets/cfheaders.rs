@@ -0,0 +1,27 @@
+//! cfheaders batches and `cfheaders` messages: a batch accepted for a request
+//! applies to a chain at the right height, and no payload panics.
+#![no_main]
+
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use libfuzzer_sys::fuzz_target;
+use niebla_158::cfheaders::CfHeaderChain;
+use niebla_158::filter_source::{CfHeadersBatch, FilterType};
+use niebla_158::parse;
+
+fuzz_target!(|input: (CfHeadersBatch, u32, u32, &[u8])| {
+    let (batch, start, stop, payload) = input;
+    let start = start.max(1);
+    let mut chain = CfHeaderChain::new(FilterType::BASIC, start - 1, BlockHash::all_zeros());
+    if parse::cfheaders_batch(&batch, start, stop).is_ok() {
+        let rolled = chain
+            .apply_batch(batch.start_height, &batch.headers, &[])
+            .expect("an accepted batch extends the chain");
+        assert_eq!(rolled.len(), batch.headers.len());
+    }
+
+    if let Ok(msg) = parse::cfheaders_message(payload) {
+        let mut chain = CfHeaderChain::new(msg.filter_type, 0, msg.previous);
+        let _ = chain.apply_cfheaders_message(payload, &[]);
+    }
+});
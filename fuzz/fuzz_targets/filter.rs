@@ -0,0 +1,21 @@
+//! Filter bytes: decoding must never panic, yield elements in ascending order
+//! below `N * M`, and agree with `validate_filter`.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use niebla_158::filter_source::GolombParams;
+use niebla_158::{matcher, parse};
+
+fuzz_target!(|input: (GolombParams, &[u8])| {
+    let (params, raw) = input;
+    if !(1..=32).contains(&params.p) || params.m == 0 {
+        return;
+    }
+    let mut values = vec![];
+    let decoded = parse::filter_elements(raw, params, |v| values.push(v));
+    assert_eq!(decoded.is_ok(), matcher::validate_filter(raw, params).is_ok());
+    if let Ok(range) = decoded {
+        assert!(values.windows(2).all(|w| w[0] <= w[1]));
+        assert!(values.iter().all(|v| *v < range));
+    }
+});
@@ -0,0 +1,131 @@
+/* niebla-158 C API. Generated by cbindgen from src/capi.rs; do not edit. */
+
+#ifndef NIEBLA_H
+#define NIEBLA_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Bumped only when an existing signature or struct layout changes.
+#define NIEBLA_ABI_VERSION 1
+
+// Result code of every fallible call.
+typedef enum NieblaResult {
+  // Success.
+  NIEBLA_RESULT_OK = 0,
+  // A pointer was null or an argument was malformed.
+  NIEBLA_RESULT_INVALID_ARGUMENT = 1,
+  // The engine, store or filter source failed.
+  NIEBLA_RESULT_ENGINE = 2,
+  // A Rust panic was caught at the boundary; the engine should be freed.
+  NIEBLA_RESULT_PANIC = 3,
+} NieblaResult;
+
+// C view of [`RunState`].
+typedef enum NieblaRunState {
+  // Not inside `niebla_engine_run_to_tip`.
+  NIEBLA_RUN_STATE_IDLE = 0,
+  // Actively verifying cfheaders or scanning filters.
+  NIEBLA_RUN_STATE_RUNNING = 1,
+  // Parked at a safe point, waiting for `niebla_engine_resume`.
+  NIEBLA_RUN_STATE_PAUSED = 2,
+} NieblaRunState;
+
+// Opaque engine handle.
+typedef struct NieblaEngine NieblaEngine;
+
+// Called once per matching block with its 32-byte hash and the block's
+// transactions (`txs[i]` is `tx_lens[i]` bytes). Buffers are only valid during
+// the call. Runs on the thread that called `niebla_engine_run_to_tip`.
+typedef void (*NieblaMatchCallback)(void *user_data,
+                                    uint32_t height,
+                                    const uint8_t *block_hash,
+                                    const uint8_t *const *txs,
+                                    const size_t *tx_lens,
+                                    size_t n_txs);
+
+// Progress snapshot filled by [`niebla_engine_status`].
+typedef struct NieblaStatus {
+  // Current run state.
+  enum NieblaRunState state;
+  // Height of the latest verified cfheader.
+  uint32_t cf_tip_height;
+  // Last height whose filter was scanned.
+  uint32_t scanned_height;
+  // Best height reported by the server at the start of the run.
+  uint32_t chain_tip_height;
+} NieblaStatus;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// ABI version of the loaded library.
+uint32_t niebla_abi_version(void);
+
+// Message for the last failed call on this thread, or null. Owned by the library.
+const char *niebla_last_error(void);
+
+// Open (or create) the SQLite database at `db_path` and scan against the HTTP
+// filter server at `server_url`. On success `*out` owns a new engine.
+//
+// # Safety
+// `db_path` and `server_url` must be NUL-terminated strings; `out` must be writable.
+enum NieblaResult niebla_engine_new(const char *db_path,
+                                    const char *server_url,
+                                    struct NieblaEngine **out);
+
+// Destroy an engine. Passing null is a no-op.
+//
+// # Safety
+// `engine` must come from [`niebla_engine_new`], not be in use, and not be freed twice.
+void niebla_engine_free(struct NieblaEngine *engine);
+
+// Add a scriptPubKey to the watchlist (takes effect on the next run).
+//
+// # Safety
+// `script` must point to `len` readable bytes.
+enum NieblaResult niebla_engine_add_script(const struct NieblaEngine *engine,
+                                           const uint8_t *script,
+                                           size_t len);
+
+// Register the match callback (replacing any previous one); null clears it.
+//
+// # Safety
+// `user_data` is passed back verbatim and must stay valid while the engine runs.
+enum NieblaResult niebla_engine_set_match_callback(const struct NieblaEngine *engine,
+                                                   NieblaMatchCallback callback,
+                                                   void *user_data);
+
+// Verify cfheaders and scan to the server's tip. Blocks until done or failed.
+//
+// # Safety
+// `engine` must be a live engine.
+enum NieblaResult niebla_engine_run_to_tip(const struct NieblaEngine *engine);
+
+// Ask a running engine to pause at its next safe point. Safe from any thread.
+//
+// # Safety
+// `engine` must be a live engine.
+enum NieblaResult niebla_engine_pause(const struct NieblaEngine *engine);
+
+// Resume a paused engine. Safe from any thread.
+//
+// # Safety
+// `engine` must be a live engine.
+enum NieblaResult niebla_engine_resume(const struct NieblaEngine *engine);
+
+// Fill `*out` with the engine's current state and progress. Safe from any thread.
+//
+// # Safety
+// `engine` must be a live engine and `out` writable.
+enum NieblaResult niebla_engine_status(const struct NieblaEngine *engine, struct NieblaStatus *out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NIEBLA_H */
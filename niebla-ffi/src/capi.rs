@@ -0,0 +1,356 @@
+//! `extern "C"` facade for existing C/C++ wallet codebases.
+//!
+//! The header is generated into `include/niebla.h` by the build script. Conventions:
+//!
+//! - every fallible call returns a [`NieblaResult`]; on failure
+//!   [`niebla_last_error`] describes it (per thread, valid until the next call);
+//! - [`NieblaEngine`] is opaque and owned by the caller until [`niebla_engine_free`];
+//! - block hashes are 32 bytes in internal byte order, transactions are
+//!   consensus-serialized;
+//! - the ABI only grows: check [`niebla_abi_version`] against `NIEBLA_ABI_VERSION`.
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bitcoin::{consensus::serialize, hashes::Hash, BlockHash, ScriptBuf, Transaction};
+use niebla_158::{
+    http::HttpFilterSource, EngineHandle, Niebla158, RunState, SqliteStore, WalletHooks,
+};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+    sync::{Arc, Mutex},
+};
+
+/// Bumped only when an existing signature or struct layout changes.
+pub const NIEBLA_ABI_VERSION: u32 = 1;
+
+/// Result code of every fallible call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NieblaResult {
+    /// Success.
+    Ok = 0,
+    /// A pointer was null or an argument was malformed.
+    InvalidArgument = 1,
+    /// The engine, store or filter source failed.
+    Engine = 2,
+    /// A Rust panic was caught at the boundary; the engine should be freed.
+    Panic = 3,
+}
+
+/// C view of [`RunState`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NieblaRunState {
+    /// Not inside `niebla_engine_run_to_tip`.
+    Idle = 0,
+    /// Actively verifying cfheaders or scanning filters.
+    Running = 1,
+    /// Parked at a safe point, waiting for `niebla_engine_resume`.
+    Paused = 2,
+}
+
+/// Progress snapshot filled by [`niebla_engine_status`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NieblaStatus {
+    /// Current run state.
+    pub state: NieblaRunState,
+    /// Height of the latest verified cfheader.
+    pub cf_tip_height: u32,
+    /// Last height whose filter was scanned.
+    pub scanned_height: u32,
+    /// Best height reported by the server at the start of the run.
+    pub chain_tip_height: u32,
+}
+
+/// Called once per matching block with its 32-byte hash and the block's
+/// transactions (`txs[i]` is `tx_lens[i]` bytes). Buffers are only valid during
+/// the call. Runs on the thread that called `niebla_engine_run_to_tip`.
+pub type NieblaMatchCallback = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        height: u32,
+        block_hash: *const u8,
+        txs: *const *const u8,
+        tx_lens: *const usize,
+        n_txs: usize,
+    ),
+>;
+
+struct Callback {
+    f: unsafe extern "C" fn(*mut c_void, u32, *const u8, *const *const u8, *const usize, usize),
+    user_data: *mut c_void,
+}
+
+// The caller promises `user_data` may be used from the thread running the scan.
+unsafe impl Send for Callback {}
+
+#[derive(Default)]
+struct HookState {
+    scripts: Mutex<Vec<ScriptBuf>>,
+    callback: Mutex<Option<Callback>>,
+}
+
+/// Shared with [`NieblaEngine`] so scripts/callbacks can be set after construction.
+#[derive(Clone, Default)]
+struct CHooks(Arc<HookState>);
+
+#[async_trait]
+impl WalletHooks for CHooks {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(self.0.scripts.lock().unwrap().clone())
+    }
+
+    async fn on_block_match(
+        &self,
+        height: u32,
+        block: BlockHash,
+        txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        let guard = self.0.callback.lock().unwrap();
+        let Some(cb) = guard.as_ref() else {
+            return Ok(());
+        };
+        let raw: Vec<Vec<u8>> = txs.iter().map(serialize).collect();
+        let ptrs: Vec<*const u8> = raw.iter().map(|t| t.as_ptr()).collect();
+        let lens: Vec<usize> = raw.iter().map(Vec::len).collect();
+        let hash = block.to_byte_array();
+        unsafe {
+            (cb.f)(
+                cb.user_data,
+                height,
+                hash.as_ptr(),
+                ptrs.as_ptr(),
+                lens.as_ptr(),
+                raw.len(),
+            )
+        };
+        Ok(())
+    }
+}
+
+/// Opaque engine handle.
+pub struct NieblaEngine {
+    engine: Niebla158<SqliteStore, CHooks, HttpFilterSource, HttpFilterSource>,
+    hooks: Arc<HookState>,
+    handle: EngineHandle,
+    runtime: tokio::runtime::Runtime,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', " ")).expect("NULs replaced");
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// Run `f` at the boundary: record its error and turn panics into [`NieblaResult::Panic`].
+fn guard(f: impl FnOnce() -> Result<(), (NieblaResult, anyhow::Error)>) -> NieblaResult {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => NieblaResult::Ok,
+        Ok(Err((code, e))) => {
+            set_last_error(format!("{e:#}"));
+            code
+        }
+        Err(_) => {
+            set_last_error("panic inside niebla".into());
+            NieblaResult::Panic
+        }
+    }
+}
+
+fn invalid(msg: &str) -> (NieblaResult, anyhow::Error) {
+    (NieblaResult::InvalidArgument, anyhow!("{msg}"))
+}
+
+fn engine_err(e: anyhow::Error) -> (NieblaResult, anyhow::Error) {
+    (NieblaResult::Engine, e)
+}
+
+unsafe fn str_arg<'a>(
+    p: *const c_char,
+    name: &str,
+) -> Result<&'a str, (NieblaResult, anyhow::Error)> {
+    if p.is_null() {
+        return Err(invalid(&format!("{name} is null")));
+    }
+    CStr::from_ptr(p)
+        .to_str()
+        .map_err(|_| invalid(&format!("{name} is not UTF-8")))
+}
+
+unsafe fn engine_arg<'a>(
+    p: *const NieblaEngine,
+) -> Result<&'a NieblaEngine, (NieblaResult, anyhow::Error)> {
+    p.as_ref().ok_or_else(|| invalid("engine is null"))
+}
+
+/// ABI version of the loaded library.
+#[no_mangle]
+pub extern "C" fn niebla_abi_version() -> u32 {
+    NIEBLA_ABI_VERSION
+}
+
+/// Message for the last failed call on this thread, or null. Owned by the library.
+#[no_mangle]
+pub extern "C" fn niebla_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// Open (or create) the SQLite database at `db_path` and scan against the HTTP
+/// filter server at `server_url`. On success `*out` owns a new engine.
+///
+/// # Safety
+/// `db_path` and `server_url` must be NUL-terminated strings; `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn niebla_engine_new(
+    db_path: *const c_char,
+    server_url: *const c_char,
+    out: *mut *mut NieblaEngine,
+) -> NieblaResult {
+    guard(|| {
+        if out.is_null() {
+            return Err(invalid("out is null"));
+        }
+        let db_path = str_arg(db_path, "db_path")?;
+        let server_url = str_arg(server_url, "server_url")?;
+        let store = SqliteStore::new(db_path).map_err(engine_err)?;
+        let source = HttpFilterSource::new(server_url);
+        let hooks = CHooks::default();
+        let state = hooks.0.clone();
+        let engine = Niebla158::new(store, hooks, source.clone(), source);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| engine_err(e.into()))?;
+        let boxed = Box::new(NieblaEngine {
+            handle: engine.handle(),
+            hooks: state,
+            engine,
+            runtime,
+        });
+        *out = Box::into_raw(boxed);
+        Ok(())
+    })
+}
+
+/// Destroy an engine. Passing null is a no-op.
+///
+/// # Safety
+/// `engine` must come from [`niebla_engine_new`], not be in use, and not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn niebla_engine_free(engine: *mut NieblaEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Add a scriptPubKey to the watchlist (takes effect on the next run).
+///
+/// # Safety
+/// `script` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn niebla_engine_add_script(
+    engine: *const NieblaEngine,
+    script: *const u8,
+    len: usize,
+) -> NieblaResult {
+    guard(|| {
+        let engine = engine_arg(engine)?;
+        if script.is_null() || len == 0 {
+            return Err(invalid("script is empty"));
+        }
+        let script = ScriptBuf::from_bytes(std::slice::from_raw_parts(script, len).to_vec());
+        engine.hooks.scripts.lock().unwrap().push(script);
+        Ok(())
+    })
+}
+
+/// Register the match callback (replacing any previous one); null clears it.
+///
+/// # Safety
+/// `user_data` is passed back verbatim and must stay valid while the engine runs.
+#[no_mangle]
+pub unsafe extern "C" fn niebla_engine_set_match_callback(
+    engine: *const NieblaEngine,
+    callback: NieblaMatchCallback,
+    user_data: *mut c_void,
+) -> NieblaResult {
+    guard(|| {
+        let engine = engine_arg(engine)?;
+        *engine.hooks.callback.lock().unwrap() = callback.map(|f| Callback { f, user_data });
+        Ok(())
+    })
+}
+
+/// Verify cfheaders and scan to the server's tip. Blocks until done or failed.
+///
+/// # Safety
+/// `engine` must be a live engine.
+#[no_mangle]
+pub unsafe extern "C" fn niebla_engine_run_to_tip(engine: *const NieblaEngine) -> NieblaResult {
+    guard(|| {
+        let engine = engine_arg(engine)?;
+        engine
+            .runtime
+            .block_on(engine.engine.run_to_tip())
+            .map_err(engine_err)
+    })
+}
+
+/// Ask a running engine to pause at its next safe point. Safe from any thread.
+///
+/// # Safety
+/// `engine` must be a live engine.
+#[no_mangle]
+pub unsafe extern "C" fn niebla_engine_pause(engine: *const NieblaEngine) -> NieblaResult {
+    guard(|| {
+        engine_arg(engine)?.handle.pause();
+        Ok(())
+    })
+}
+
+/// Resume a paused engine. Safe from any thread.
+///
+/// # Safety
+/// `engine` must be a live engine.
+#[no_mangle]
+pub unsafe extern "C" fn niebla_engine_resume(engine: *const NieblaEngine) -> NieblaResult {
+    guard(|| {
+        engine_arg(engine)?.handle.resume();
+        Ok(())
+    })
+}
+
+/// Fill `*out` with the engine's current state and progress. Safe from any thread.
+///
+/// # Safety
+/// `engine` must be a live engine and `out` writable.
+#[no_mangle]
+pub unsafe extern "C" fn niebla_engine_status(
+    engine: *const NieblaEngine,
+    out: *mut NieblaStatus,
+) -> NieblaResult {
+    guard(|| {
+        let engine = engine_arg(engine)?;
+        if out.is_null() {
+            return Err(invalid("out is null"));
+        }
+        let s = engine.handle.status();
+        *out = NieblaStatus {
+            state: match s.state {
+                RunState::Idle => NieblaRunState::Idle,
+                RunState::Running => NieblaRunState::Running,
+                RunState::Paused => NieblaRunState::Paused,
+            },
+            cf_tip_height: s.cf_tip_height,
+            scanned_height: s.scanned_height,
+            chain_tip_height: s.chain_tip_height,
+        };
+        Ok(())
+    })
+}
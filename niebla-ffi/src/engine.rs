@@ -0,0 +1,100 @@
+//! The exported engine object and the foreign wallet callback.
+use crate::{EngineStatus, NieblaError};
+use async_trait::async_trait;
+use bitcoin::{consensus::encode::serialize_hex, BlockHash, ScriptBuf, Transaction};
+use niebla_158::{http::HttpFilterSource, EngineHandle, Niebla158, SqliteStore, WalletHooks};
+use std::sync::Arc;
+
+/// Implemented by the host wallet (Swift/Kotlin) to feed scripts and receive matches.
+///
+/// Callbacks run on the thread that called [`NieblaEngine::run_to_tip`].
+#[uniffi::export(with_foreign)]
+pub trait WalletListener: Send + Sync {
+    /// Hex-encoded scriptPubKeys to look for in compact filters.
+    fn watchlist(&self) -> Vec<String>;
+    /// A block at `height` matched; `txs` are its consensus-encoded transactions in hex.
+    fn on_block_match(&self, height: u32, block_hash: String, txs: Vec<String>);
+}
+
+/// Adapts a foreign [`WalletListener`] to the engine's [`WalletHooks`].
+struct ListenerHooks(Arc<dyn WalletListener>);
+
+#[async_trait]
+impl WalletHooks for ListenerHooks {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        self.0
+            .watchlist()
+            .iter()
+            .map(|s| Ok(ScriptBuf::from_hex(s)?))
+            .collect()
+    }
+
+    async fn on_block_match(
+        &self,
+        height: u32,
+        block: BlockHash,
+        txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        let txs = txs.iter().map(serialize_hex).collect();
+        self.0.on_block_match(height, block.to_string(), txs);
+        Ok(())
+    }
+}
+
+type Engine = Niebla158<SqliteStore, ListenerHooks, HttpFilterSource, HttpFilterSource>;
+
+/// A compact-filter scanner backed by SQLite and an HTTP filter server.
+#[derive(uniffi::Object)]
+pub struct NieblaEngine {
+    engine: Engine,
+    handle: EngineHandle,
+    runtime: tokio::runtime::Runtime,
+}
+
+#[uniffi::export]
+impl NieblaEngine {
+    /// Open (or create) the database at `db_path` and scan against the
+    /// `niebla-serve`-compatible server at `server_url`.
+    #[uniffi::constructor]
+    pub fn new(
+        db_path: String,
+        server_url: String,
+        listener: Arc<dyn WalletListener>,
+    ) -> Result<Arc<Self>, NieblaError> {
+        let store = SqliteStore::new(db_path)?;
+        let source = HttpFilterSource::new(server_url);
+        let engine = Niebla158::new(store, ListenerHooks(listener), source.clone(), source);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| NieblaError::Engine(format!("start runtime: {e}")))?;
+        Ok(Arc::new(Self {
+            handle: engine.handle(),
+            engine,
+            runtime,
+        }))
+    }
+
+    /// Verify cfheaders and scan filters up to the server's tip.
+    ///
+    /// Blocks the calling thread until done, paused-and-resumed, or failed; call it
+    /// off the UI thread.
+    pub fn run_to_tip(&self) -> Result<(), NieblaError> {
+        Ok(self.runtime.block_on(self.engine.run_to_tip())?)
+    }
+
+    /// Pause at the next safe point (progress is persisted first).
+    pub fn pause(&self) {
+        self.handle.pause();
+    }
+
+    /// Resume a paused run.
+    pub fn resume(&self) {
+        self.handle.resume();
+    }
+
+    /// Current state and progress.
+    pub fn status(&self) -> EngineStatus {
+        self.handle.status().into()
+    }
+}
@@ -0,0 +1,96 @@
+//! Foreign-language bindings for [`niebla_158`].
+//!
+//! The UniFFI layer lets Swift/Kotlin wallets embed the scanner without writing
+//! Rust: construct a [`NieblaEngine`] with a database path, an HTTP filter
+//! server and a [`WalletListener`], then call `run_to_tip` from a background
+//! thread while the UI pauses, resumes and polls progress.
+//!
+//! Generate the bindings with the bundled CLI:
+//!
+//! ```text
+//! cargo build --release
+//! cargo run --features bindgen --bin uniffi-bindgen -- generate \
+//!     --library target/release/libniebla_ffi.so --language kotlin --out-dir out
+//! ```
+//!
+//! C/C++ callers use the [`capi`] functions and `include/niebla.h` instead.
+#![deny(missing_docs)]
+
+pub mod capi;
+mod engine;
+
+pub use engine::{NieblaEngine, WalletListener};
+
+uniffi::setup_scaffolding!();
+
+/// Error surfaced to foreign callers; carries the full `anyhow` context chain.
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum NieblaError {
+    /// Invalid argument supplied by the caller (bad hex, bad script, ...).
+    InvalidInput(String),
+    /// The engine, store or filter source failed.
+    Engine(String),
+}
+
+impl std::fmt::Display for NieblaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidInput(m) => write!(f, "invalid input: {m}"),
+            Self::Engine(m) => f.write_str(m),
+        }
+    }
+}
+
+impl std::error::Error for NieblaError {}
+
+impl From<anyhow::Error> for NieblaError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Engine(format!("{e:#}"))
+    }
+}
+
+/// Mirror of [`niebla_158::RunState`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum RunState {
+    /// Not inside `run_to_tip`.
+    Idle,
+    /// Actively verifying cfheaders or scanning filters.
+    Running,
+    /// Parked at a safe point, waiting for `resume`.
+    Paused,
+}
+
+impl From<niebla_158::RunState> for RunState {
+    fn from(s: niebla_158::RunState) -> Self {
+        match s {
+            niebla_158::RunState::Idle => Self::Idle,
+            niebla_158::RunState::Running => Self::Running,
+            niebla_158::RunState::Paused => Self::Paused,
+        }
+    }
+}
+
+/// Mirror of [`niebla_158::EngineStatus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Record)]
+pub struct EngineStatus {
+    /// Current run state.
+    pub state: RunState,
+    /// Height of the latest verified cfheader.
+    pub cf_tip_height: u32,
+    /// Last height whose filter was scanned.
+    pub scanned_height: u32,
+    /// Best height reported by the server at the start of the run.
+    pub chain_tip_height: u32,
+}
+
+impl From<niebla_158::EngineStatus> for EngineStatus {
+    fn from(s: niebla_158::EngineStatus) -> Self {
+        Self {
+            state: s.state.into(),
+            cf_tip_height: s.cf_tip_height,
+            scanned_height: s.scanned_height,
+            chain_tip_height: s.chain_tip_height,
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use niebla_ffi::{NieblaEngine, NieblaError, RunState, WalletListener};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Listener {
+    matches: Mutex<Vec<u32>>,
+}
+
+impl WalletListener for Listener {
+    fn watchlist(&self) -> Vec<String> {
+        vec!["0014".to_owned() + &"11".repeat(20)]
+    }
+
+    fn on_block_match(&self, height: u32, _block_hash: String, _txs: Vec<String>) {
+        self.matches.lock().unwrap().push(height);
+    }
+}
+
+#[test]
+fn engine_is_controllable_from_foreign_side() {
+    let dir = tempfile::tempdir().unwrap();
+    let listener = Arc::new(Listener::default());
+    let engine = NieblaEngine::new(
+        dir.path().join("niebla.sqlite").display().to_string(),
+        // Nothing listens here: run_to_tip must fail cleanly, not panic.
+        "http://127.0.0.1:9".into(),
+        listener.clone(),
+    )
+    .unwrap();
+
+    let status = engine.status();
+    assert_eq!(status.state, RunState::Idle);
+    assert_eq!(status.scanned_height, 0);
+
+    engine.pause();
+    engine.resume();
+    assert!(matches!(engine.run_to_tip(), Err(NieblaError::Engine(_))));
+    assert_eq!(engine.status().state, RunState::Idle);
+    assert!(listener.matches.lock().unwrap().is_empty());
+}
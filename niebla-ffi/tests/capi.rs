@@ -0,0 +1,67 @@
+use niebla_ffi::capi::*;
+use std::{
+    ffi::{c_void, CStr, CString},
+    ptr,
+};
+
+unsafe extern "C" fn on_match(
+    _user_data: *mut c_void,
+    _height: u32,
+    _block_hash: *const u8,
+    _txs: *const *const u8,
+    _tx_lens: *const usize,
+    _n_txs: usize,
+) {
+}
+
+#[test]
+fn c_api_lifecycle_and_errors() {
+    assert_eq!(niebla_abi_version(), NIEBLA_ABI_VERSION);
+
+    let dir = tempfile::tempdir().unwrap();
+    let db = CString::new(dir.path().join("c.sqlite").display().to_string()).unwrap();
+    let url = CString::new("http://127.0.0.1:9").unwrap();
+
+    unsafe {
+        let mut engine = ptr::null_mut();
+        assert_eq!(
+            niebla_engine_new(ptr::null(), url.as_ptr(), &mut engine),
+            NieblaResult::InvalidArgument
+        );
+        let msg = CStr::from_ptr(niebla_last_error()).to_str().unwrap();
+        assert!(msg.contains("db_path"), "{msg}");
+
+        assert_eq!(
+            niebla_engine_new(db.as_ptr(), url.as_ptr(), &mut engine),
+            NieblaResult::Ok
+        );
+        assert!(!engine.is_null());
+
+        let script = [0x00, 0x14]
+            .iter()
+            .chain(&[0x11; 20])
+            .copied()
+            .collect::<Vec<u8>>();
+        assert_eq!(
+            niebla_engine_add_script(engine, script.as_ptr(), script.len()),
+            NieblaResult::Ok
+        );
+        assert_eq!(
+            niebla_engine_set_match_callback(engine, Some(on_match), ptr::null_mut()),
+            NieblaResult::Ok
+        );
+
+        let mut status = std::mem::zeroed::<NieblaStatus>();
+        assert_eq!(niebla_engine_status(engine, &mut status), NieblaResult::Ok);
+        assert_eq!(status.state, NieblaRunState::Idle);
+
+        assert_eq!(niebla_engine_pause(engine), NieblaResult::Ok);
+        assert_eq!(niebla_engine_resume(engine), NieblaResult::Ok);
+        // Nothing listens on the server URL.
+        assert_eq!(niebla_engine_run_to_tip(engine), NieblaResult::Engine);
+        assert!(!niebla_last_error().is_null());
+
+        niebla_engine_free(engine);
+        niebla_engine_free(ptr::null_mut());
+    }
+}
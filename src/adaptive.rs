@@ -0,0 +1,37 @@
+//! Request window sizing that follows observed source latency.
+use std::time::{Duration, SystemTime};
+
+/// Number of items to ask a source for per request, halved when a request is
+/// slower than `target` and doubled when it comes back in under a quarter of it.
+pub(crate) struct Window {
+    size: u32,
+    min: u32,
+    max: u32,
+    target: Duration,
+}
+
+impl Window {
+    pub(crate) const fn new(start: u32, min: u32, max: u32, target: Duration) -> Self {
+        Self {
+            size: start,
+            min,
+            max,
+            target,
+        }
+    }
+
+    pub(crate) fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Adjust after a request that started at `started` (per [`crate::rt::now`]).
+    pub(crate) fn record(&mut self, started: SystemTime) {
+        // Wall clock (wasm has no `Instant`); a clock step back counts as fast.
+        let elapsed = crate::rt::now().duration_since(started).unwrap_or_default();
+        if elapsed > self.target {
+            self.size = (self.size / 2).max(self.min);
+        } else if elapsed < self.target / 4 {
+            self.size = self.size.saturating_mul(2).min(self.max);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Integrity reports for long-running scanners, from [`Niebla158::audit`](crate::Niebla158::audit).
+//!
+//! An audit only reads: it compares what the stores hold with what the filter
+//! source serves now and with the engine's checkpoints, and lists every
+//! disagreement. Run against the same state, it produces the same report.
+use crate::filter_source::FilterType;
+use bitcoin::BlockHash;
+use std::ops::RangeInclusive;
+
+/// What [`Niebla158::audit`](crate::Niebla158::audit) checked and found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Filter type of the audited cfheaders chain.
+    pub filter_type: FilterType,
+    /// Heights audited: the requested range, limited to the verified cfheaders
+    /// (empty if none of it was verified yet).
+    pub range: RangeInclusive<u32>,
+    /// Stored cfheaders recomputed from the source's filter hashes.
+    pub cfheaders_checked: u32,
+    /// Checkpoints in the range compared with the stored cfheaders.
+    pub checkpoints_checked: u32,
+    /// [Cached misses](crate::Niebla158::with_verdict_cache) whose filters were
+    /// fetched and matched again.
+    pub misses_checked: u32,
+    /// Every disagreement found, lowest height first.
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl AuditReport {
+    /// Whether the audit found nothing wrong.
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// One disagreement found by an audit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Discrepancy {
+    /// The primary store has no cfheader at a height its cfheaders tip covers.
+    MissingCfHeader {
+        /// The height.
+        height: u32,
+    },
+    /// A stored rolling cfheader is not the one the source's filter hash and
+    /// the stored cfheader below it give.
+    CfHeader {
+        /// The height.
+        height: u32,
+        /// Rolling header in the store.
+        stored: BlockHash,
+        /// Rolling header recomputed from the source.
+        source: BlockHash,
+    },
+    /// A stored rolling cfheader disagrees with a checkpoint.
+    Checkpoint {
+        /// Height of the checkpoint.
+        height: u32,
+        /// Rolling header in the store.
+        stored: BlockHash,
+        /// Rolling header the checkpoint requires.
+        checkpoint: BlockHash,
+    },
+    /// A filter cached as a miss matches the wallet's watchlist after all.
+    CachedMissMatches {
+        /// Index of the wallet: 0 for the primary one, then in
+        /// [`with_wallet`](crate::Niebla158::with_wallet) order.
+        wallet: usize,
+        /// The height.
+        height: u32,
+        /// The block whose filter matched.
+        block: BlockHash,
+    },
+    /// The source now serves a malformed filter for a cached miss.
+    InvalidFilter {
+        /// The height.
+        height: u32,
+        /// The block the filter was served for.
+        block: BlockHash,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+impl Discrepancy {
+    /// Height the discrepancy is at.
+    pub fn height(&self) -> u32 {
+        match self {
+            Self::MissingCfHeader { height }
+            | Self::CfHeader { height, .. }
+            | Self::Checkpoint { height, .. }
+            | Self::CachedMissMatches { height, .. }
+            | Self::InvalidFilter { height, .. } => *height,
+        }
+    }
+}
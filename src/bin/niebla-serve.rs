@@ -0,0 +1,211 @@
+//! `niebla-serve`: serve the crate's HTTP filter protocol in front of Bitcoin Core.
+//!
+//! ```text
+//! bitcoind -rest -blockfilterindex=1
+//! niebla-serve --core http://127.0.0.1:8332 --listen 0.0.0.0:3158
+//! ```
+//!
+//! Hash-addressed responses are marked `immutable` so a CDN can cache them
+//! forever; tip and height lookups are short-lived because reorgs move them.
+// axum handlers must be `Send`; the `local` feature makes the source traits `?Send`.
+#[cfg(niebla_unsend)]
+fn main() {
+    eprintln!("niebla-serve: built with the `local` feature; rebuild without it");
+    std::process::exit(1);
+}
+
+#[cfg(not(niebla_unsend))]
+fn main() -> anyhow::Result<()> {
+    server::main()
+}
+
+#[cfg(not(niebla_unsend))]
+mod server {
+    use anyhow::Context;
+    use axum::{
+        extract::{Path, Request, State},
+        http::{header, HeaderValue, StatusCode},
+        middleware::{self, Next},
+        response::{IntoResponse, Response},
+        routing::get,
+        Router,
+    };
+    use bitcoin::{
+        hashes::{sha256, Hash},
+        BlockHash,
+    };
+    use niebla_158::{
+        filter_source::FilterSource,
+        headers::HeaderSource,
+        http::{CoreRestSource, PREFIX},
+    };
+    use std::sync::Arc;
+
+    const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+    const SHORT_LIVED: &str = "public, max-age=10";
+
+    type Core = Arc<CoreRestSource>;
+
+    struct Args {
+        listen: String,
+        core: String,
+    }
+
+    fn parse_args() -> anyhow::Result<Args> {
+        let mut args = Args {
+            listen: "127.0.0.1:3158".into(),
+            core: "http://127.0.0.1:8332".into(),
+        };
+        let mut it = std::env::args().skip(1);
+        while let Some(flag) = it.next() {
+            let mut value = || it.next().with_context(|| format!("{flag} needs a value"));
+            match flag.as_str() {
+                "--listen" => args.listen = value()?,
+                "--core" => args.core = value()?,
+                "-h" | "--help" => {
+                    println!("usage: niebla-serve [--listen ADDR] [--core URL]");
+                    std::process::exit(0);
+                }
+                other => anyhow::bail!("unknown argument {other}"),
+            }
+        }
+        Ok(args)
+    }
+
+    /// Map a backend error to a status: Core's 404 stays a 404, everything else is a 502.
+    fn upstream_error(e: anyhow::Error) -> Response {
+        let not_found = e
+            .chain()
+            .filter_map(|c| c.downcast_ref::<reqwest::Error>())
+            .any(|re| re.status() == Some(reqwest::StatusCode::NOT_FOUND));
+        if not_found {
+            StatusCode::NOT_FOUND.into_response()
+        } else {
+            (StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response()
+        }
+    }
+
+    fn reply(
+        body: impl Into<Vec<u8>>,
+        cache: &'static str,
+        content_type: &'static str,
+    ) -> Response {
+        (
+            [
+                (header::CACHE_CONTROL, HeaderValue::from_static(cache)),
+                (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            ],
+            body.into(),
+        )
+            .into_response()
+    }
+
+    fn bad_hash() -> Response {
+        (StatusCode::BAD_REQUEST, "bad block hash").into_response()
+    }
+
+    /// Immutable binary body, tagged with its SHA-256 so clients can revalidate it.
+    fn binary(body: Vec<u8>) -> Response {
+        let etag = format!("\"{}\"", sha256::Hash::hash(&body));
+        let mut resp = reply(body, IMMUTABLE, "application/octet-stream");
+        if let Ok(v) = HeaderValue::from_str(&etag) {
+            resp.headers_mut().insert(header::ETAG, v);
+        }
+        resp
+    }
+
+    /// Answer `If-None-Match` requests whose tag still matches with an empty `304`.
+    async fn not_modified(req: Request, next: Next) -> Response {
+        let wanted = req.headers().get(header::IF_NONE_MATCH).cloned();
+        let resp = next.run(req).await;
+        match (wanted, resp.headers().get(header::ETAG)) {
+            (Some(wanted), Some(etag)) if wanted == etag => {
+                (StatusCode::NOT_MODIFIED, [(header::ETAG, etag.clone())]).into_response()
+            }
+            _ => resp,
+        }
+    }
+
+    async fn tip(State(core): State<Core>) -> Response {
+        match core.tip_height().await {
+            Ok(h) => reply(h.to_string(), SHORT_LIVED, "text/plain"),
+            Err(e) => upstream_error(e),
+        }
+    }
+
+    async fn blockhash(State(core): State<Core>, Path(height): Path<u32>) -> Response {
+        match core.hash_at_height(height).await {
+            Ok(h) => reply(h.to_string(), SHORT_LIVED, "text/plain"),
+            Err(e) => upstream_error(e),
+        }
+    }
+
+    async fn cfcheckpt(State(core): State<Core>, Path(stop): Path<String>) -> Response {
+        let Ok(stop) = stop.parse::<BlockHash>() else {
+            return bad_hash();
+        };
+        match core.get_cfcheckpt(stop).await {
+            Ok(v) => binary(v.iter().flat_map(|h| h.to_byte_array()).collect()),
+            Err(e) => upstream_error(e),
+        }
+    }
+
+    async fn cfheaders(
+        State(core): State<Core>,
+        Path((start, stop)): Path<(u32, String)>,
+    ) -> Response {
+        let Ok(stop) = stop.parse::<BlockHash>() else {
+            return bad_hash();
+        };
+        match core.get_cfheaders(start, stop).await {
+            Ok(batch) => binary(batch.headers.concat()),
+            Err(e) => upstream_error(e),
+        }
+    }
+
+    async fn cfilter(State(core): State<Core>, Path(block): Path<String>) -> Response {
+        let Ok(block) = block.parse::<BlockHash>() else {
+            return bad_hash();
+        };
+        match core.get_cfilter(block).await {
+            Ok(v) => binary(v),
+            Err(e) => upstream_error(e),
+        }
+    }
+
+    async fn block(State(core): State<Core>, Path(block): Path<String>) -> Response {
+        let Ok(block) = block.parse::<BlockHash>() else {
+            return bad_hash();
+        };
+        match core.get_block(block).await {
+            Ok(v) => binary(v),
+            Err(e) => upstream_error(e),
+        }
+    }
+
+    #[tokio::main]
+    pub async fn main() -> anyhow::Result<()> {
+        let args = parse_args()?;
+        let core: Core = Arc::new(CoreRestSource::new(&args.core).with_filter_hash_cache());
+
+        let app = Router::new()
+            .route(&format!("{PREFIX}/tip"), get(tip))
+            .route(&format!("{PREFIX}/blockhash/{{height}}"), get(blockhash))
+            .route(&format!("{PREFIX}/cfcheckpt/{{stop}}"), get(cfcheckpt))
+            .route(
+                &format!("{PREFIX}/cfheaders/{{start}}/{{stop}}"),
+                get(cfheaders),
+            )
+            .route(&format!("{PREFIX}/cfilter/{{block}}"), get(cfilter))
+            .route(&format!("{PREFIX}/block/{{block}}"), get(block))
+            .layer(middleware::from_fn(not_modified))
+            .with_state(core);
+
+        let listener = tokio::net::TcpListener::bind(&args.listen)
+            .await
+            .with_context(|| format!("bind {}", args.listen))?;
+        eprintln!("niebla-serve: {} -> {}", args.listen, args.core);
+        axum::serve(listener, app).await?;
+        Ok(())
+    }
+}
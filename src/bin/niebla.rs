@@ -0,0 +1,296 @@
+//! `niebla`: scan the chain for a set of descriptors, xpubs or addresses and
+//! print every match as a JSON line.
+//!
+//! ```text
+//! niebla --core http://127.0.0.1:8332 --birth 800000 \
+//!        --descriptor 'wpkh([d34db33f/84h/0h/0h]xpub.../<0;1>/*)' --address bc1q...
+//! ```
+//!
+//! Output is one `niebla_158::record::MatchRecordV1` line per matching block
+//! (not one line per transaction, as before), with the transactions paying to
+//! (or spending from) the scripts, e.g.
+//! `{"version":1,"height":800123,"block":"…","match_id":"…","sequence":1,"txs":[…],"items":[…]}`.
+//! Progress is kept in `--db`, so re-running continues where the last run stopped.
+//!
+//! As a service, `--webhook URL --follow SECS` keeps syncing every `SECS`
+//! seconds and POSTs matches to `URL` instead (see `niebla_158::webhook`),
+//! signed with `NIEBLA_WEBHOOK_SECRET` when it is set. `--rpc ADDR` also
+//! serves the JSON-RPC control interface (see `niebla_158::rpc`) while following.
+//!
+//! `--export DIR` writes each match to a file in `DIR` instead of printing it
+//! (see `niebla_158::export`), for processing on an offline machine.
+#[cfg(niebla_unsend)]
+fn main() {
+    eprintln!("niebla: built with the `local` feature; rebuild without it");
+    std::process::exit(1);
+}
+
+#[cfg(not(niebla_unsend))]
+fn main() -> anyhow::Result<()> {
+    cli::main()
+}
+
+#[cfg(not(niebla_unsend))]
+mod cli {
+    use anyhow::{bail, Context};
+    use async_trait::async_trait;
+    use bitcoin::{Address, BlockHash, Network, ScriptBuf, Transaction, Txid};
+    use miniscript::{Descriptor, DescriptorPublicKey};
+    use niebla_158::{
+        export::ExportWallet,
+        filter_source::FilterSource,
+        headers::HeaderSource,
+        http::{CoreRestSource, HttpFilterSource},
+        record::MatchRecordV1,
+        rpc::{self, RpcService},
+        webhook::WebhookWallet,
+        MatchDetails, Niebla158, SharedWatchlist, SqliteStore, Store, WalletHooks, WatchItem,
+    };
+    use std::{collections::HashSet, str::FromStr, time::Duration};
+
+    const USAGE: &str = "usage: niebla (--core URL | --server URL) [--db PATH] [--birth HEIGHT]
+              [--network bitcoin|testnet|signet|regtest] [--gap N]
+              [--webhook URL | --export DIR] [--follow SECS [--rpc ADDR]]
+              (--descriptor DESC | --xpub XPUB | --address ADDR)...";
+
+    struct Args {
+        core: Option<String>,
+        server: Option<String>,
+        db: String,
+        birth: Option<u32>,
+        network: Network,
+        gap: u32,
+        descriptors: Vec<String>,
+        xpubs: Vec<String>,
+        addresses: Vec<String>,
+        webhook: Option<String>,
+        export: Option<String>,
+        follow: Option<u64>,
+        rpc: Option<String>,
+    }
+
+    fn parse_args() -> anyhow::Result<Args> {
+        let mut args = Args {
+            core: None,
+            server: None,
+            db: "niebla.sqlite".into(),
+            birth: None,
+            network: Network::Bitcoin,
+            gap: 1_000,
+            descriptors: vec![],
+            xpubs: vec![],
+            addresses: vec![],
+            webhook: None,
+            export: None,
+            follow: None,
+            rpc: None,
+        };
+        let mut it = std::env::args().skip(1);
+        while let Some(flag) = it.next() {
+            let mut value = || it.next().with_context(|| format!("{flag} needs a value"));
+            match flag.as_str() {
+                "--core" => args.core = Some(value()?),
+                "--server" => args.server = Some(value()?),
+                "--db" => args.db = value()?,
+                "--birth" => args.birth = Some(value()?.parse().context("--birth")?),
+                "--network" => args.network = value()?.parse().context("--network")?,
+                "--gap" => args.gap = value()?.parse().context("--gap")?,
+                "--descriptor" => args.descriptors.push(value()?),
+                "--xpub" => args.xpubs.push(value()?),
+                "--address" => args.addresses.push(value()?),
+                "--webhook" => args.webhook = Some(value()?),
+                "--export" => args.export = Some(value()?),
+                "--follow" => args.follow = Some(value()?.parse().context("--follow")?),
+                "--rpc" => args.rpc = Some(value()?),
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                other => bail!("unknown argument {other}\n{USAGE}"),
+            }
+        }
+        if args.rpc.is_some() && args.follow.is_none() {
+            bail!("--rpc needs --follow\n{USAGE}");
+        }
+        if args.webhook.is_some() && args.export.is_some() {
+            bail!("pass at most one of --webhook or --export\n{USAGE}");
+        }
+        if args.core.is_some() == args.server.is_some() {
+            bail!("pass exactly one of --core or --server\n{USAGE}");
+        }
+        Ok(args)
+    }
+
+    /// Expand every descriptor (ranged ones over `0..gap`), xpub and address into scripts.
+    fn watch_scripts(args: &Args) -> anyhow::Result<Vec<ScriptBuf>> {
+        let mut descriptors = args.descriptors.clone();
+        // Bare xpubs mean BIP-84 style single-sig: receive and change chains.
+        for xpub in &args.xpubs {
+            descriptors.push(format!("wpkh({xpub}/0/*)"));
+            descriptors.push(format!("wpkh({xpub}/1/*)"));
+        }
+
+        let mut scripts = Vec::new();
+        for text in &descriptors {
+            let desc = Descriptor::<DescriptorPublicKey>::from_str(text)
+                .with_context(|| format!("parse descriptor {text}"))?;
+            for single in desc.into_single_descriptors()? {
+                let range = if single.has_wildcard() {
+                    0..args.gap
+                } else {
+                    0..1
+                };
+                for i in range {
+                    scripts.push(single.at_derivation_index(i)?.script_pubkey());
+                }
+            }
+        }
+        for text in &args.addresses {
+            let addr = Address::from_str(text)
+                .with_context(|| format!("parse address {text}"))?
+                .require_network(args.network)?;
+            scripts.push(addr.script_pubkey());
+        }
+        if scripts.is_empty() {
+            bail!("nothing to scan for: pass --descriptor, --xpub or --address\n{USAGE}");
+        }
+        Ok(scripts)
+    }
+
+    /// Prints matches with their relevant transactions (see
+    /// [`MatchDetails::relevant_txids`]).
+    struct JsonLines {
+        items: SharedWatchlist,
+    }
+
+    impl JsonLines {
+        fn print(&self, mut record: MatchRecordV1, relevant: &HashSet<Txid>) {
+            record.retain_txs(|txid| relevant.contains(txid));
+            if !record.txs.is_empty() {
+                println!("{}", record.to_json());
+            }
+        }
+    }
+
+    #[async_trait]
+    impl WalletHooks for JsonLines {
+        async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+            Ok(self.items.items().into_iter().map(|i| i.script).collect())
+        }
+
+        async fn on_block_match(
+            &self,
+            height: u32,
+            block: BlockHash,
+            txs: Vec<Transaction>,
+        ) -> anyhow::Result<()> {
+            let relevant = txs.iter().map(Transaction::compute_txid).collect();
+            self.print(
+                MatchRecordV1::from_block_match(height, block, &txs),
+                &relevant,
+            );
+            Ok(())
+        }
+
+        async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+            self.print(
+                MatchRecordV1::from_details(&details),
+                &details.relevant_txids(),
+            );
+            Ok(())
+        }
+    }
+
+    async fn run<B>(args: &Args, backend: B) -> anyhow::Result<()>
+    where
+        B: FilterSource + HeaderSource + Clone + 'static,
+    {
+        let store = SqliteStore::new(&args.db)?;
+        if let Some(birth) = args.birth {
+            // Nothing before the birth height can involve these scripts.
+            if store.get_last_scanned().await? < birth {
+                store.set_last_scanned(birth.saturating_sub(1)).await?;
+            }
+            store.set_birth_height(birth).await?;
+        }
+        let scripts = watch_scripts(args)?;
+        eprintln!("niebla: watching {} scripts", scripts.len());
+
+        let items = scripts.into_iter().map(WatchItem::new).collect();
+        match (&args.webhook, &args.export) {
+            (Some(url), _) => {
+                let mut hooks = WebhookWallet::new(url, items);
+                if let Ok(secret) = std::env::var("NIEBLA_WEBHOOK_SECRET") {
+                    hooks = hooks.with_secret(secret);
+                }
+                let watchlist = hooks.watchlist_handle();
+                let engine = Niebla158::new(store, hooks, backend.clone(), backend)
+                    .with_network(args.network);
+                drive(args, engine, watchlist).await
+            }
+            (_, Some(dir)) => {
+                let hooks = ExportWallet::new(dir, items)?;
+                let watchlist = hooks.watchlist_handle();
+                let engine = Niebla158::new(store, hooks, backend.clone(), backend)
+                    .with_network(args.network);
+                drive(args, engine, watchlist).await
+            }
+            (None, None) => {
+                let hooks = JsonLines {
+                    items: SharedWatchlist::new(items),
+                };
+                let watchlist = hooks.items.clone();
+                let engine = Niebla158::new(store, hooks, backend.clone(), backend)
+                    .with_network(args.network);
+                drive(args, engine, watchlist).await
+            }
+        }
+    }
+
+    /// Sync once, or with `--follow` forever (serving `--rpc` meanwhile), logging
+    /// failed runs and retrying.
+    async fn drive<W, B>(
+        args: &Args,
+        engine: Niebla158<SqliteStore, W, B, B>,
+        watchlist: SharedWatchlist,
+    ) -> anyhow::Result<()>
+    where
+        W: WalletHooks + 'static,
+        B: FilterSource + HeaderSource + 'static,
+    {
+        let Some(secs) = args.follow else {
+            engine.run_to_tip().await?;
+            let status = engine.handle().status();
+            eprintln!("niebla: scanned to height {}", status.scanned_height);
+            return Ok(());
+        };
+        if let Some(addr) = &args.rpc {
+            let app = rpc::router(RpcService::new(engine.handle()).with_watchlist(watchlist));
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("bind {addr}"))?;
+            eprintln!("niebla: JSON-RPC on {addr}");
+            tokio::spawn(async move { axum::serve(listener, app).await });
+        }
+        loop {
+            match engine.run_to_tip().await {
+                Ok(()) => eprintln!(
+                    "niebla: scanned to height {}",
+                    engine.handle().status().scanned_height
+                ),
+                Err(e) => eprintln!("niebla: sync failed: {e:#}"),
+            }
+            tokio::time::sleep(Duration::from_secs(secs)).await;
+        }
+    }
+
+    #[tokio::main]
+    pub async fn main() -> anyhow::Result<()> {
+        let args = parse_args()?;
+        match (&args.core, &args.server) {
+            (Some(core), _) => run(&args, CoreRestSource::new(core)).await,
+            (_, Some(server)) => run(&args, HttpFilterSource::new(server)).await,
+            _ => unreachable!("checked in parse_args"),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Where full blocks come from after a filter hit.
+//!
+//! Kept separate from [`FilterSource`] so filters can come from a cheap mirror
+//! while blocks come from your own node (or another peer), which also keeps the
+//! filter server from learning which blocks matched.
+use crate::compat::{MaybeSend, MaybeSync};
+use crate::filter_source::FilterSource;
+use crate::parse;
+use async_trait::async_trait;
+use bitcoin::{block::Header, BlockHash, ScriptBuf, Transaction};
+
+/// Provider of raw blocks, consulted only for heights whose filter matched.
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+pub trait BlockSource: MaybeSend + MaybeSync {
+    /// Name of this source (e.g. its URL) for errors and events.
+    fn label(&self) -> Option<String> {
+        None
+    }
+
+    /// Fetch the raw consensus-encoded block bytes for `block`.
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>>;
+
+    /// Only the transactions in `block` that pay to or spend from `scripts`
+    /// (e.g. an Esplora `scripthash/txs` style backend), or `None` if this
+    /// source cannot answer that; the engine then downloads the full block.
+    ///
+    /// Returning `Some` must not omit relevant transactions: the engine treats
+    /// the answer as authoritative for this block.
+    async fn get_relevant_txs(
+        &self,
+        _block: BlockHash,
+        _scripts: &[ScriptBuf],
+    ) -> anyhow::Result<Option<Vec<Transaction>>> {
+        Ok(None)
+    }
+}
+
+/// Every [`FilterSource`] can serve blocks through its own `get_block`.
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl<T: FilterSource + ?Sized> BlockSource for T {
+    fn label(&self) -> Option<String> {
+        FilterSource::label(self)
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        FilterSource::get_block(self, block).await
+    }
+}
+
+/// How the engine reads the raw blocks a [`BlockSource`] serves: their hash,
+/// their parent and their transactions.
+///
+/// The default, [`BitcoinCodec`], is Bitcoin's consensus encoding. Forks with
+/// a patched block format (or test frameworks with synthetic blocks) plug in
+/// their own with [`Niebla158::with_block_codec`](crate::Niebla158::with_block_codec),
+/// as long as their transactions decode as Bitcoin's.
+pub trait BlockCodec: MaybeSend + MaybeSync {
+    /// Hash of the block in `raw`.
+    fn block_hash(&self, raw: &[u8]) -> anyhow::Result<BlockHash>;
+
+    /// Hash of the parent of the block in `raw`.
+    fn prev_block_hash(&self, raw: &[u8]) -> anyhow::Result<BlockHash>;
+
+    /// Every transaction of the block in `raw`, in block order.
+    fn decode_txs(&self, raw: &[u8]) -> anyhow::Result<Vec<Transaction>>;
+
+    /// Hand the transactions of the block in `raw` to `visit` one at a time,
+    /// in block order, until it returns `false`. Lets the engine stop at the
+    /// transaction it is looking for. The default decodes them all with
+    /// [`decode_txs`](Self::decode_txs) first; [`BitcoinCodec`] decodes them
+    /// straight from the bytes as it goes.
+    fn visit_txs(
+        &self,
+        raw: &[u8],
+        visit: &mut dyn FnMut(Transaction) -> bool,
+    ) -> anyhow::Result<()> {
+        for tx in self.decode_txs(raw)? {
+            if !visit(tx) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The header of the block in `raw`, for formats whose header is
+    /// Bitcoin's; `None` (the default) leaves it to the
+    /// [`HeaderSource`](crate::headers::HeaderSource).
+    fn decode_header(&self, _raw: &[u8]) -> anyhow::Result<Option<Header>> {
+        Ok(None)
+    }
+}
+
+/// Bitcoin's consensus block encoding.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BitcoinCodec;
+
+impl BlockCodec for BitcoinCodec {
+    fn block_hash(&self, raw: &[u8]) -> anyhow::Result<BlockHash> {
+        Ok(parse::block_header(raw)?.block_hash())
+    }
+
+    fn prev_block_hash(&self, raw: &[u8]) -> anyhow::Result<BlockHash> {
+        Ok(parse::block_header(raw)?.prev_blockhash)
+    }
+
+    fn decode_txs(&self, raw: &[u8]) -> anyhow::Result<Vec<Transaction>> {
+        let mut txs = vec![];
+        self.visit_txs(raw, &mut |tx| {
+            txs.push(tx);
+            true
+        })?;
+        Ok(txs)
+    }
+
+    fn visit_txs(
+        &self,
+        raw: &[u8],
+        visit: &mut dyn FnMut(Transaction) -> bool,
+    ) -> anyhow::Result<()> {
+        parse::block_txs(raw, visit)
+    }
+
+    fn decode_header(&self, raw: &[u8]) -> anyhow::Result<Option<Header>> {
+        parse::block_header(raw).map(Some)
+    }
+}
@@ -1,4 +1,8 @@
-use anyhow::{bail, Result};
+use crate::error::{CheckpointMismatch, EngineError};
+use crate::filter_source::FilterType;
+use anyhow::{bail, Context, Result};
+use bitcoin::consensus::deserialize;
+use bitcoin::p2p::message_filter::CFCheckpt;
 use bitcoin::{
     hashes::{sha256d, Hash},
     BlockHash,
@@ -9,71 +13,198 @@ use bitcoin::{
 /// tip_hash: the *rolling* header after applying up to tip_height
 ///
 /// Rolling update formula (BIP157):
-///   H_n = HASH256( H_{n-1} || F_n )
-/// where F_n is the per-block filter header (HASH256 of the raw filter bytes).
+///   H_n = HASH256( F_n || H_{n-1} )
+/// where F_n is the per-block filter hash (HASH256 of the raw filter bytes),
+/// and the genesis filter rolls over 32 zero bytes: H_0 = HASH256( F_0 || 0 ).
 ///
 /// We verify against optional checkpoints that give H_h at certain heights.
+/// Each filter type has its own independent chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CfHeaderChain {
+    /// Filter type whose headers this chain rolls.
+    pub filter_type: FilterType,
+    /// Last height applied.
     pub tip_height: u32,
+    /// Rolling header at `tip_height`.
     pub tip_hash: BlockHash,
 }
 
 impl CfHeaderChain {
-    /// Initialize from store (or start at height 0 with H_0 = all-zero).
-    pub fn new_from_store(prev: Option<(u32, BlockHash)>) -> Self {
+    /// Start at a trusted rolling header `tip_hash` at `tip_height`, e.g. one
+    /// taken from a `cfcheckpt` reply or an earlier run.
+    pub fn new(filter_type: FilterType, tip_height: u32, tip_hash: BlockHash) -> Self {
+        Self {
+            filter_type,
+            tip_height,
+            tip_hash,
+        }
+    }
+
+    /// Initialize from store, or before genesis: at height 0 with the all-zero
+    /// header that BIP-157 rolls the genesis filter over, so the first batch
+    /// starts at height 0 and yields `H_0 = HASH256(F_0 || 0^32)`.
+    pub fn new_from_store(filter_type: FilterType, prev: Option<(u32, BlockHash)>) -> Self {
         match prev {
-            Some((h, hh)) if h > 0 => Self {
-                tip_height: h,
-                tip_hash: hh,
-            },
-            _ => Self {
-                tip_height: 0,
-                tip_hash: BlockHash::all_zeros(),
-            },
+            Some((h, hh)) => Self::new(filter_type, h, hh),
+            None => Self::new(filter_type, 0, BlockHash::all_zeros()),
+        }
+    }
+
+    /// Whether the genesis filter is still to be applied (see
+    /// [`new_from_store`](Self::new_from_store)).
+    pub fn before_genesis(&self) -> bool {
+        self.tip_height == 0 && self.tip_hash == BlockHash::all_zeros()
+    }
+
+    /// Height the next batch starts at: 0 [before genesis](Self::before_genesis),
+    /// else the one above the tip.
+    pub fn next_height(&self) -> u32 {
+        if self.before_genesis() {
+            0
+        } else {
+            self.tip_height.saturating_add(1)
         }
     }
 
     /// Apply a batch of *per-block filter headers* starting at `start_height`.
     /// `headers[i]` corresponds to height `start_height + i`.
+    ///
+    /// Returns the rolling header computed for each applied height. On error
+    /// the chain is left where it was.
     pub fn apply_batch(
         &mut self,
         start_height: u32,
         headers: &[[u8; 32]],
         checkpoints: &[(u32, BlockHash)],
-    ) -> Result<()> {
+    ) -> Result<Vec<BlockHash>> {
         // Must be the next contiguous chunk
-        let expected = self.tip_height.saturating_add(1);
+        let expected = self.next_height();
         if start_height != expected {
-            bail!("cfheaders batch start mismatch: got {start_height}, expected {expected}");
+            bail!(
+                "{} cfheaders batch start mismatch: got {start_height}, expected {expected}",
+                self.filter_type
+            );
         }
 
         let mut rolling = self.tip_hash;
+        let mut applied = Vec::with_capacity(headers.len());
+        // F_n || H_{n-1}, reused for every header (this runs ~once per block on a full sync).
+        let mut buf = [0u8; 64];
+        for fh_bytes in headers {
+            // H_n = HASH256( F_n || H_{n-1} )
+            buf[..32].copy_from_slice(fh_bytes);
+            buf[32..].copy_from_slice(rolling.as_ref());
+            rolling = BlockHash::from_byte_array(sha256d::Hash::hash(&buf).to_byte_array());
+            applied.push(rolling);
+        }
+        verify_checkpoints(self.filter_type, start_height, &applied, checkpoints)?;
 
-        for (i, fh_bytes) in headers.iter().enumerate() {
-            let h = start_height + i as u32;
-
-            // H_n = HASH256( H_{n-1} || F_n )
-            let cur = {
-                let mut data = Vec::with_capacity(64);
-                data.extend_from_slice(rolling.as_ref()); // H_{n-1}
-                data.extend_from_slice(fh_bytes); // F_n
-                let d = sha256d::Hash::hash(&data);
-                BlockHash::from_byte_array(*d.as_ref())
-            };
+        if let Some(last) = applied.last() {
+            self.tip_height = start_height + applied.len() as u32 - 1;
+            self.tip_hash = *last;
+        }
+        Ok(applied)
+    }
 
-            // Checkpoint verify (if we have one at this height)
-            if let Some((_, chk)) = checkpoints.iter().find(|(hh, _)| *hh == h) {
-                if &cur != chk {
-                    bail!("cfheaders checkpoint mismatch @{}!", h);
-                }
-            }
+    /// Apply the payload of a BIP-157 `cfheaders` message, as received from a
+    /// peer: its filter type must be this chain's and its previous filter
+    /// header this chain's tip. Returns the message's stop hash (for the
+    /// caller to check against the block at [`tip_height`](Self::tip_height)
+    /// afterwards) and the rolling headers, as [`apply_batch`](Self::apply_batch).
+    pub fn apply_cfheaders_message(
+        &mut self,
+        payload: &[u8],
+        checkpoints: &[(u32, BlockHash)],
+    ) -> Result<(BlockHash, Vec<BlockHash>)> {
+        let msg = crate::parse::cfheaders_message(payload)?;
+        if msg.filter_type != self.filter_type {
+            bail!(
+                "cfheaders message is for {}, not {}",
+                msg.filter_type,
+                self.filter_type
+            );
+        }
+        if msg.previous != self.tip_hash {
+            bail!(
+                "{} cfheaders message does not extend the chain at {}",
+                self.filter_type,
+                self.tip_height
+            );
+        }
+        let start = self.next_height();
+        Ok((
+            msg.stop_hash,
+            self.apply_batch(start, &msg.filter_hashes, checkpoints)?,
+        ))
+    }
+}
 
-            // Advance tip
-            rolling = cur;
-            self.tip_height = h;
-            self.tip_hash = rolling;
+/// Check consecutive rolling `headers`, the first at `start_height`, against
+/// the `(height, rolling header)` `checkpoints` that fall among them. The first
+/// disagreement is an [`EngineError::CheckpointMismatch`].
+pub fn verify_checkpoints(
+    filter_type: FilterType,
+    start_height: u32,
+    headers: &[BlockHash],
+    checkpoints: &[(u32, BlockHash)],
+) -> Result<()> {
+    // Only the checkpoints inside this run matter; usually none or one.
+    let end_height = start_height.saturating_add(headers.len() as u32);
+    let mut checks: Vec<&(u32, BlockHash)> = checkpoints
+        .iter()
+        .filter(|(h, _)| (start_height..end_height).contains(h))
+        .collect();
+    checks.sort_by_key(|(h, _)| *h);
+    for (h, expected) in checks {
+        let computed = headers[(h - start_height) as usize];
+        if computed != *expected {
+            return Err(EngineError::CheckpointMismatch(CheckpointMismatch {
+                filter_type,
+                height: *h,
+                expected: *expected,
+                computed,
+                batch_start: start_height,
+                source: None,
+            })
+            .into());
         }
+    }
+    Ok(())
+}
+
+/// The payload of a BIP-157 `cfcheckpt` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CfCheckpoints {
+    /// Filter type the checkpoints are for.
+    pub filter_type: FilterType,
+    /// Block the checkpoints run up to.
+    pub stop_hash: BlockHash,
+    /// `(height, rolling header)` at heights 1000, 2000, ..., ready for
+    /// [`CfHeaderChain::apply_batch`] and [`verify_checkpoints`].
+    pub checkpoints: Vec<(u32, BlockHash)>,
+}
 
-        Ok(())
+impl CfCheckpoints {
+    /// Decode a `cfcheckpt` payload as received from a peer.
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let msg: CFCheckpt = deserialize(payload).context("decode cfcheckpt message")?;
+        let checkpoints = (1u32..)
+            .zip(&msg.filter_headers)
+            .map(|(i, h)| (i * 1000, BlockHash::from_byte_array(h.to_byte_array())))
+            .collect();
+        Ok(Self {
+            filter_type: FilterType(msg.filter_type),
+            stop_hash: msg.stop_hash,
+            checkpoints,
+        })
     }
 }
+
+/// The rolling header following `prev` for a block whose filter hashes to
+/// `filter_hash`: `HASH256(filter_hash || prev)`.
+pub fn next_header(filter_hash: &[u8; 32], prev: BlockHash) -> BlockHash {
+    let mut buf = [0u8; 64];
+    buf[..32].copy_from_slice(filter_hash);
+    buf[32..].copy_from_slice(prev.as_ref());
+    BlockHash::from_byte_array(sha256d::Hash::hash(&buf).to_byte_array())
+}
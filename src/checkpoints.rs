@@ -1,17 +1,244 @@
-use bitcoin::BlockHash;
+//! Rolling cfheader checkpoints: built-in lists per network, and
+//! [`Checkpoints::validate`] to catch malformed lists before a sync.
+//!
+//! Lists also have a text form ([`Checkpoints::from_text`]), which the
+//! `remote-checkpoints` feature fetches and verifies in `remote`.
+use crate::filter_source::FilterType;
+use anyhow::Context;
+use bitcoin::bip158::{BlockFilter, FilterHeader};
+use bitcoin::hashes::Hash;
+use bitcoin::{constants::genesis_block, BlockHash, Network};
+use std::fmt;
+use std::str::FromStr;
+
+/// Signed checkpoint lists fetched over HTTP(S).
+#[cfg(feature = "remote-checkpoints")]
+pub mod remote;
 
 /// Return known rolling cfheader checkpoints for a network.
 /// For now we return an empty list (no external trust). If you have
 /// a vetted list, populate it here (height, rolling_header_hash).
-#[allow(dead_code)]
 pub fn mainnet_checkpoints() -> Vec<(u32, BlockHash)> {
     vec![]
 }
-#[allow(dead_code)]
+/// See [`mainnet_checkpoints`].
 pub fn testnet_checkpoints() -> Vec<(u32, BlockHash)> {
     vec![]
 }
-#[allow(dead_code)]
+/// See [`mainnet_checkpoints`].
+pub fn testnet4_checkpoints() -> Vec<(u32, BlockHash)> {
+    vec![]
+}
+/// See [`mainnet_checkpoints`].
 pub fn signet_checkpoints() -> Vec<(u32, BlockHash)> {
     vec![]
 }
+
+/// Checkpoints for `network`; regtest chains are local, so they have none.
+pub fn for_network(network: Network) -> Vec<(u32, BlockHash)> {
+    match network {
+        Network::Bitcoin => mainnet_checkpoints(),
+        Network::Testnet => testnet_checkpoints(),
+        Network::Testnet4 => testnet4_checkpoints(),
+        Network::Signet => signet_checkpoints(),
+        _ => vec![],
+    }
+}
+
+/// A list of `(height, rolling_cfheader)` checkpoints, as passed to
+/// [`Niebla158::with_checkpoints`](crate::Niebla158::with_checkpoints).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Checkpoints(pub Vec<(u32, BlockHash)>);
+
+impl Checkpoints {
+    /// Check that heights strictly increase (so none repeats).
+    pub fn validate(&self) -> Result<(), CheckpointError> {
+        for pair in self.0.windows(2) {
+            let ((previous, _), (height, _)) = (pair[0], pair[1]);
+            if height == previous {
+                return Err(CheckpointError::Duplicate { height });
+            }
+            if height < previous {
+                return Err(CheckpointError::OutOfOrder { height, previous });
+            }
+        }
+        Ok(())
+    }
+
+    /// [`validate`](Self::validate), and check that a checkpoint at height 0
+    /// is the rolling header of `network`'s genesis filter. Only basic
+    /// filters can be checked that way; other types skip it. Runs verify a
+    /// height-0 checkpoint like any other, since cfheaders chains start by
+    /// rolling the genesis filter (see [`CfHeaderChain`](crate::cfheaders::CfHeaderChain)).
+    pub fn validate_for(
+        &self,
+        network: Network,
+        filter_type: FilterType,
+    ) -> Result<(), CheckpointError> {
+        self.validate()?;
+        let Some(&(_, found)) = self.0.first().filter(|(h, _)| *h == 0) else {
+            return Ok(());
+        };
+        if filter_type != FilterType::BASIC {
+            return Ok(());
+        }
+        let expected = genesis_cfheader(network);
+        if found != expected {
+            return Err(CheckpointError::WrongGenesis {
+                network,
+                expected,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that every checkpoint of `base` is also in this list, e.g. that
+    /// a refreshed list keeps the built-in ones.
+    pub fn extends(&self, base: &Checkpoints) -> Result<(), CheckpointError> {
+        for &(height, expected) in &base.0 {
+            let found = self.0.iter().find(|(h, _)| *h == height).map(|(_, c)| *c);
+            if found != Some(expected) {
+                return Err(CheckpointError::Dropped {
+                    height,
+                    expected,
+                    found,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a list in text form: a `network <name>` line, then one
+    /// `<height> <rolling cfheader>` line per checkpoint. Blank lines and
+    /// `#` comments are ignored. The list is not [validated](Self::validate).
+    pub fn from_text(text: &str) -> anyhow::Result<(Network, Self)> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.split('#').next().unwrap_or("").trim()))
+            .filter(|(_, l)| !l.is_empty());
+        let network = match lines.next() {
+            Some((_, line)) => match line.split_once(char::is_whitespace) {
+                Some(("network", name)) => Network::from_str(name.trim())
+                    .with_context(|| format!("unknown network {:?}", name.trim()))?,
+                _ => anyhow::bail!("checkpoint list must start with a `network` line"),
+            },
+            None => anyhow::bail!("checkpoint list is empty"),
+        };
+        let mut checkpoints = vec![];
+        for (n, line) in lines {
+            let (height, hash) = line
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("line {n}: expected `<height> <cfheader>`"))?;
+            let height = height
+                .parse()
+                .with_context(|| format!("line {n}: bad height {height:?}"))?;
+            let hash = BlockHash::from_str(hash.trim())
+                .with_context(|| format!("line {n}: bad cfheader {:?}", hash.trim()))?;
+            checkpoints.push((height, hash));
+        }
+        Ok((network, Self(checkpoints)))
+    }
+
+    /// The list in the text form read by [`from_text`](Self::from_text).
+    pub fn to_text(&self, network: Network) -> String {
+        let mut text = format!("network {network}\n");
+        for (height, hash) in &self.0 {
+            text.push_str(&format!("{height} {hash}\n"));
+        }
+        text
+    }
+}
+
+impl From<Vec<(u32, BlockHash)>> for Checkpoints {
+    fn from(v: Vec<(u32, BlockHash)>) -> Self {
+        Self(v)
+    }
+}
+
+/// Rolling basic-filter header of `network`'s genesis block.
+pub fn genesis_cfheader(network: Network) -> BlockHash {
+    let genesis = genesis_block(network);
+    // The genesis block only spends its coinbase, whose input is not indexed.
+    let filter = BlockFilter::new_script_filter(&genesis, |_| {
+        Err::<bitcoin::ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(Default::default()))
+    })
+    .expect("genesis filter needs no prevouts");
+    BlockHash::from_byte_array(
+        filter
+            .filter_header(&FilterHeader::all_zeros())
+            .to_byte_array(),
+    )
+}
+
+/// What is wrong with a checkpoint list, as reported by [`Checkpoints::validate`]
+/// and, at the start of a run, by
+/// [`EngineError::InvalidCheckpoints`](crate::EngineError::InvalidCheckpoints).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CheckpointError {
+    /// Two checkpoints share `height`.
+    Duplicate {
+        /// The repeated height.
+        height: u32,
+    },
+    /// The checkpoint at `height` comes after one at a greater height.
+    OutOfOrder {
+        /// Height of the misplaced checkpoint.
+        height: u32,
+        /// Height of the checkpoint before it.
+        previous: u32,
+    },
+    /// The checkpoint at height 0 is not the network's genesis cfheader.
+    WrongGenesis {
+        /// The network checked against.
+        network: Network,
+        /// Its genesis cfheader.
+        expected: BlockHash,
+        /// The checkpoint's.
+        found: BlockHash,
+    },
+    /// A checkpoint the list should keep (see [`Checkpoints::extends`]) is
+    /// missing or different.
+    Dropped {
+        /// Height of the checkpoint.
+        height: u32,
+        /// The checkpoint to keep.
+        expected: BlockHash,
+        /// The list's checkpoint at `height`, if any.
+        found: Option<BlockHash>,
+    },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duplicate { height } => write!(f, "two checkpoints at height {height}"),
+            Self::OutOfOrder { height, previous } => write!(
+                f,
+                "checkpoint at height {height} follows one at height {previous}"
+            ),
+            Self::WrongGenesis {
+                network,
+                expected,
+                found,
+            } => write!(
+                f,
+                "checkpoint at height 0 is {found}, not the {network} genesis cfheader {expected}"
+            ),
+            Self::Dropped {
+                height,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "checkpoint at height {height} is {found}, not {expected}"
+            ),
+            Self::Dropped { height, .. } => write!(f, "no checkpoint at height {height}"),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
@@ -0,0 +1,147 @@
+//! Checkpoint lists refreshed from a URL and verified against minisign
+//! (Ed25519) keys, so long-lived deployments can pick up newer checkpoints
+//! without a new binary.
+//!
+//! The list is served in [text form](Checkpoints::from_text), its signature
+//! next to it as `<url>.minisig` (what `minisign -S` writes). Only prehashed
+//! signatures, minisign's default, are accepted. No maintainer keys ship yet
+//! ([`MAINTAINER_KEYS`] is empty), so trust the list's signer explicitly:
+//!
+//! ```rust,ignore
+//! let checkpoints = RemoteCheckpoints::new("https://example.com/mainnet.txt", Network::Bitcoin)
+//!     .with_trusted_key(include_str!("minisign.pub"))?
+//!     .fetch()
+//!     .await?;
+//! let engine = Niebla158::new(store, wallet, source, headers)
+//!     .with_network(Network::Bitcoin)
+//!     .with_checkpoints(checkpoints.0);
+//! ```
+use super::{for_network, Checkpoints};
+use crate::filter_source::FilterType;
+use anyhow::Context;
+use bitcoin::Network;
+use minisign_verify::Signature;
+
+pub use minisign_verify::PublicKey;
+
+/// Minisign public keys (base64) of the maintainers, trusted by
+/// [`RemoteCheckpoints::new`]. Empty for now, like the built-in lists: add
+/// keys with [`RemoteCheckpoints::with_trusted_key`], or
+/// [`fetch`](RemoteCheckpoints::fetch) fails.
+pub const MAINTAINER_KEYS: &[&str] = &[];
+
+/// Fetches a signed checkpoint list for one network.
+#[derive(Clone)]
+pub struct RemoteCheckpoints {
+    url: String,
+    signature_url: String,
+    network: Network,
+    keys: Vec<PublicKey>,
+    known: Checkpoints,
+    client: reqwest::Client,
+}
+
+impl RemoteCheckpoints {
+    /// Fetch `network`'s list from `url`, trusting the [`MAINTAINER_KEYS`].
+    pub fn new(url: impl Into<String>, network: Network) -> Self {
+        Self::with_client(url, network, reqwest::Client::new())
+    }
+
+    /// Same as [`RemoteCheckpoints::new`] but with a preconfigured `reqwest::Client`.
+    pub fn with_client(url: impl Into<String>, network: Network, client: reqwest::Client) -> Self {
+        let url = url.into();
+        Self {
+            signature_url: format!("{url}.minisig"),
+            url,
+            network,
+            keys: MAINTAINER_KEYS
+                .iter()
+                .map(|k| parse_key(k).expect("maintainer keys are valid"))
+                .collect(),
+            known: Checkpoints(for_network(network)),
+            client,
+        }
+    }
+
+    /// Fetch the signature from `url` instead of `<list url>.minisig`.
+    pub fn with_signature_url(mut self, url: impl Into<String>) -> Self {
+        self.signature_url = url.into();
+        self
+    }
+
+    /// Also trust `key`: a minisign public key, either its base64 line or a
+    /// whole `minisign.pub` file.
+    pub fn with_trusted_key(mut self, key: &str) -> anyhow::Result<Self> {
+        self.keys.push(parse_key(key)?);
+        Ok(self)
+    }
+
+    /// Require fetched lists to keep `checkpoints` (e.g. the list in use), on
+    /// top of the network's built-in ones, so a replayed older list cannot
+    /// drop anchors.
+    pub fn with_known(mut self, checkpoints: &Checkpoints) -> Self {
+        self.known.0.extend(checkpoints.0.iter().copied());
+        self
+    }
+
+    /// Download the list and its signature, and return the list if a trusted
+    /// key signed it, it is for this network, it is
+    /// [valid](Checkpoints::validate_for) for basic filters and it
+    /// [keeps](Checkpoints::extends) the known checkpoints. Fails before any
+    /// request without a trusted key.
+    pub async fn fetch(&self) -> anyhow::Result<Checkpoints> {
+        if self.keys.is_empty() {
+            anyhow::bail!("no trusted keys configured for {}", self.url);
+        }
+        let body = self.get(&self.url).await?;
+        let signature = self.get(&self.signature_url).await?;
+        let signature = String::from_utf8(signature).context("checkpoint signature is not text")?;
+        let checkpoints = verify_list(&body, &signature, &self.keys, self.network)?;
+        checkpoints
+            .extends(&self.known)
+            .context("checkpoint list drops a known checkpoint")?;
+        Ok(checkpoints)
+    }
+
+    async fn get(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("GET {url}"))?
+            .error_for_status()
+            .with_context(|| format!("GET {url}"))?;
+        Ok(resp.bytes().await?.to_vec())
+    }
+}
+
+/// Check that one of `keys` made `signature` (a `.minisig` file) over `body`,
+/// and parse `body` as a valid basic-filter checkpoint list for `network`.
+pub fn verify_list(
+    body: &[u8],
+    signature: &str,
+    keys: &[PublicKey],
+    network: Network,
+) -> anyhow::Result<Checkpoints> {
+    let signature = Signature::decode(signature).context("malformed checkpoint signature")?;
+    if !keys
+        .iter()
+        .any(|k| k.verify(body, &signature, false).is_ok())
+    {
+        anyhow::bail!("checkpoint list is not signed by a trusted key");
+    }
+    let text = std::str::from_utf8(body).context("checkpoint list is not text")?;
+    let (listed, checkpoints) = Checkpoints::from_text(text)?;
+    if listed != network {
+        anyhow::bail!("checkpoint list is for {listed}, not {network}");
+    }
+    checkpoints.validate_for(network, FilterType::BASIC)?;
+    Ok(checkpoints)
+}
+
+/// Parse a minisign public key from its base64 line or a `minisign.pub` file.
+pub fn parse_key(key: &str) -> anyhow::Result<PublicKey> {
+    let line = key.lines().map(str::trim).rev().find(|l| !l.is_empty());
+    PublicKey::from_base64(line.unwrap_or_default()).context("malformed minisign public key")
+}
@@ -0,0 +1,34 @@
+//! Thread-safety bounds that relax on single-threaded targets.
+//!
+//! On native targets [`MaybeSend`]/[`MaybeSync`] are just `Send`/`Sync`. On
+//! `wasm32`, or with the `local` feature, they are implemented for every type and
+//! the crate's async traits use `#[async_trait(?Send)]`, so implementations may
+//! hold JS handles, `Rc`s or other non-`Send` state and the engine can run on a
+//! `tokio::task::LocalSet`.
+//!
+//! `local` changes trait signatures: with it enabled, implement the traits with
+//! `#[async_trait(?Send)]`.
+
+/// `Send` on multi-threaded builds; no requirement on wasm or with `local`.
+#[cfg(not(niebla_unsend))]
+pub trait MaybeSend: Send {}
+#[cfg(not(niebla_unsend))]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// `Send` on multi-threaded builds; no requirement on wasm or with `local`.
+#[cfg(niebla_unsend)]
+pub trait MaybeSend {}
+#[cfg(niebla_unsend)]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// `Sync` on multi-threaded builds; no requirement on wasm or with `local`.
+#[cfg(not(niebla_unsend))]
+pub trait MaybeSync: Sync {}
+#[cfg(not(niebla_unsend))]
+impl<T: Sync + ?Sized> MaybeSync for T {}
+
+/// `Sync` on multi-threaded builds; no requirement on wasm or with `local`.
+#[cfg(niebla_unsend)]
+pub trait MaybeSync {}
+#[cfg(niebla_unsend)]
+impl<T: ?Sized> MaybeSync for T {}
@@ -0,0 +1,106 @@
+use crate::filter_source::{FilterType, GolombParams};
+use crate::hooks::Delivery;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::{BlockHash, Network};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The settings that shape what an engine writes to its stores, recorded in
+/// each store on the first run (see [`Store::set_config`](crate::Store::set_config))
+/// and compared on later ones.
+///
+/// Settings that would mix incompatible data into the same database fail the
+/// run with [`EngineError::IncompatibleConfig`](crate::EngineError::IncompatibleConfig):
+/// other Golomb parameters for the same filter type, another network, checkpoints
+/// that contradict the stored cfheaders, or UTXO tracking turned on after
+/// scanning began (its earlier outputs would be missing). Other changes are
+/// recorded and reported as [`EngineEvent::ConfigChanged`](crate::events::EngineEvent::ConfigChanged).
+/// [`Niebla158::reset`](crate::Niebla158::reset) clears the record.
+///
+/// Records also name the rules stored cfheaders were rolled by
+/// ([`CFHEADER_RULES`]). Records without them predate BIP-157 rolling
+/// (operands in the other order, no genesis filter): those stores' cfheaders
+/// are rewound to their anchor, or to before genesis, and verified again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineConfig {
+    /// See [`Niebla158::with_filter_type`](crate::Niebla158::with_filter_type).
+    pub filter_type: FilterType,
+    /// The Golomb parameters filters are decoded with.
+    pub golomb: GolombParams,
+    /// See [`Niebla158::with_network`](crate::Niebla158::with_network).
+    pub network: Option<Network>,
+    /// Hash of the checkpoint set, in height order.
+    pub checkpoints: sha256::Hash,
+    /// See [`Niebla158::with_utxo_tracking`](crate::Niebla158::with_utxo_tracking).
+    pub utxo_tracking: bool,
+    /// See [`Niebla158::with_delivery`](crate::Niebla158::with_delivery).
+    pub delivery: Delivery,
+    /// See [`Niebla158::with_parallel_cfheaders`](crate::Niebla158::with_parallel_cfheaders).
+    pub parallel_cfheaders: Option<usize>,
+    /// See [`Niebla158::with_watchlist_shards`](crate::Niebla158::with_watchlist_shards).
+    pub watchlist_shards: Option<usize>,
+}
+
+impl EngineConfig {
+    /// Hash a checkpoint set as recorded in [`checkpoints`](Self::checkpoints).
+    pub fn checkpoints_hash(checkpoints: &[(u32, BlockHash)]) -> sha256::Hash {
+        let mut sorted = checkpoints.to_vec();
+        sorted.sort();
+        let mut engine = sha256::Hash::engine();
+        for (height, header) in sorted {
+            engine.input(&height.to_le_bytes());
+            engine.input(header.as_byte_array());
+        }
+        sha256::Hash::from_engine(engine)
+    }
+
+    /// `(name, value)` of every setting, in record order.
+    pub(crate) fn settings(&self) -> Vec<(&'static str, String)> {
+        let opt = |v: Option<usize>| v.map_or_else(|| "none".to_string(), |v| v.to_string());
+        vec![
+            // First, so older cfheaders are rewound before anything checks them.
+            ("cfheaders", CFHEADER_RULES.to_string()),
+            ("filter_type", self.filter_type.to_string()),
+            ("golomb", format!("{}/{}", self.golomb.p, self.golomb.m)),
+            (
+                "network",
+                self.network
+                    .map_or_else(|| "none".to_string(), |n| n.to_string()),
+            ),
+            ("checkpoints", self.checkpoints.to_string()),
+            ("utxo_tracking", self.utxo_tracking.to_string()),
+            ("delivery", format!("{:?}", self.delivery)),
+            ("parallel_cfheaders", opt(self.parallel_cfheaders)),
+            ("watchlist_shards", opt(self.watchlist_shards)),
+        ]
+    }
+}
+
+/// One `name=value` line per setting.
+impl fmt::Display for EngineConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.settings() {
+            writeln!(f, "{name}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// How cfheaders are rolled: as BIP-157 specifies, from the genesis filter.
+pub const CFHEADER_RULES: &str = "bip157";
+
+/// The filter type as written in a record, if it is one.
+pub(crate) fn parse_filter_type(value: &str) -> Option<FilterType> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok().map(FilterType),
+        None => (value == FilterType::BASIC.to_string()).then_some(FilterType::BASIC),
+    }
+}
+
+/// The settings in a record written by [`EngineConfig`]'s `Display`.
+pub(crate) fn parse_record(record: &str) -> BTreeMap<&str, &str> {
+    record
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect()
+}
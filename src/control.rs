@@ -0,0 +1,392 @@
+//! Runtime controls for a running engine (pause / resume / status / health).
+use bitcoin::Network;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+
+/// What the engine is doing right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RunState {
+    /// Not inside `run_to_tip`.
+    Idle,
+    /// Actively verifying cfheaders or scanning filters.
+    Running,
+    /// Parked at a safe point, waiting for [`EngineHandle::resume`].
+    Paused,
+}
+
+/// The step the engine is at, in a run or any other call that reads the
+/// chain (e.g. [`check_block`](crate::Niebla158::check_block)), finer than
+/// [`RunState`]: returned by
+/// [`EngineHandle::phase`] and, with
+/// [`Niebla158::with_phase_events`](crate::Niebla158::with_phase_events),
+/// reported on every change as
+/// [`EngineEvent::PhaseChanged`](crate::events::EngineEvent::PhaseChanged).
+///
+/// A paused engine keeps the phase it paused in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EnginePhase {
+    /// No engine call in progress.
+    #[default]
+    Idle,
+    /// Checking the configuration and verifying cfheaders up to the chain tip
+    /// (or, in an [audit](crate::Niebla158::audit), the stored ones).
+    VerifyingHeaders,
+    /// Fetching and matching filters, at `height` (the lowest range's start in
+    /// a [parallel scan](crate::Niebla158::run_parallel), the window's first
+    /// height in [`find_transaction`](crate::Niebla158::find_transaction) and
+    /// [`scan_deposits`](crate::Niebla158::scan_deposits)).
+    Scanning {
+        /// Height being scanned.
+        height: u32,
+    },
+    /// Fetching the block (or transactions) whose filter matched at `height`.
+    FetchingBlock {
+        /// Height of the matched block.
+        height: u32,
+    },
+    /// Handing the match at `height` to the wallet hooks.
+    Delivering {
+        /// Height of the matched block.
+        height: u32,
+    },
+}
+
+/// Snapshot returned by [`EngineHandle::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EngineStatus {
+    /// Current run state.
+    pub state: RunState,
+    /// Current step of the run.
+    pub phase: EnginePhase,
+    /// Height of the latest verified cfheader.
+    pub cf_tip_height: u32,
+    /// Last height whose filter was scanned.
+    pub scanned_height: u32,
+    /// Best height reported by the `HeaderSource` at the start of the run.
+    pub chain_tip_height: u32,
+}
+
+/// Liveness snapshot returned by [`EngineHandle::health`].
+///
+/// Meant for daemons to surface through their own health checks: a scanner
+/// whose sources keep failing, or whose chain tip stopped moving, shows up here
+/// even while `run_to_tip` is quietly retrying or sleeping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Health {
+    /// Current run state.
+    pub state: RunState,
+    /// When a [`FilterSource`](crate::FilterSource) call last succeeded.
+    pub filter_source_ok_at: Option<SystemTime>,
+    /// When a [`HeaderSource`](crate::headers::HeaderSource) call last succeeded.
+    pub header_source_ok_at: Option<SystemTime>,
+    /// When a [`BlockSource`](crate::BlockSource) (or [`TxSource`](crate::tx_source::TxSource))
+    /// call last succeeded.
+    pub block_source_ok_at: Option<SystemTime>,
+    /// `run_to_tip` calls that failed in a row; reset by the next successful run.
+    pub consecutive_failures: u32,
+    /// Last height whose filter was scanned.
+    pub scanned_height: u32,
+    /// Best height reported by the `HeaderSource`.
+    pub chain_tip_height: u32,
+    /// When the engine last saw the chain tip advance.
+    pub new_block_at: Option<SystemTime>,
+    /// Seconds since [`new_block_at`](Self::new_block_at), as of this snapshot.
+    pub secs_since_new_block: Option<u64>,
+}
+
+/// Time spent in each part of a sync, summed over every run of the engine.
+/// Returned by [`EngineHandle::timings`].
+///
+/// Compare the source phases with [`hooks`](Self::hooks) and
+/// [`store`](Self::store) to see whether the backend or the wallet is the
+/// bottleneck; diff two snapshots to time a single run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PhaseTimings {
+    /// Waiting for the filter source to serve cfheaders.
+    pub cfheaders_fetch: Duration,
+    /// Rolling and checking cfheaders against checkpoints.
+    pub cfheaders_verify: Duration,
+    /// Waiting for the filter source to serve filters.
+    pub filter_fetch: Duration,
+    /// Validating filters and matching them against watchlists.
+    pub matching: Duration,
+    /// Waiting for the block source to serve matching blocks or transactions.
+    pub block_fetch: Duration,
+    /// Inside [`WalletHooks`](crate::WalletHooks) calls (watchlists, matches, rollbacks).
+    pub hooks: Duration,
+    /// Inside [`Store`](crate::Store) calls made while syncing.
+    pub store: Duration,
+}
+
+/// One of the [`PhaseTimings`] buckets.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Phase {
+    CfHeadersFetch,
+    CfHeadersVerify,
+    FilterFetch,
+    Matching,
+    BlockFetch,
+    Hooks,
+    Store,
+}
+
+/// Which source a call went to, in [`EngineEvent::SourceFailed`](crate::events::EngineEvent::SourceFailed).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// The [`FilterSource`](crate::FilterSource) in use (the primary or a fallback).
+    Filters,
+    /// The [`HeaderSource`](crate::headers::HeaderSource).
+    Headers,
+    /// The [`BlockSource`](crate::BlockSource).
+    Blocks,
+    /// The [`TxSource`](crate::tx_source::TxSource).
+    Transactions,
+}
+
+impl fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Filters => "filter",
+            Self::Headers => "header",
+            Self::Blocks => "block",
+            Self::Transactions => "transaction",
+        })
+    }
+}
+
+#[derive(Default)]
+struct HealthState {
+    filters_ok: Option<SystemTime>,
+    headers_ok: Option<SystemTime>,
+    blocks_ok: Option<SystemTime>,
+    consecutive_failures: u32,
+    best_tip: Option<u32>,
+    new_block_at: Option<SystemTime>,
+}
+
+/// A public engine call in progress (see [`Control::call`]).
+pub(crate) struct Call<'a>(&'a Control);
+
+impl Call<'_> {
+    /// End the call; returns whether no other call is still in progress.
+    pub(crate) fn finish(self) -> bool {
+        let control = self.0;
+        drop(self);
+        *control.calls.lock().unwrap() == 0
+    }
+}
+
+impl Drop for Call<'_> {
+    fn drop(&mut self) {
+        *self.0.calls.lock().unwrap() -= 1;
+    }
+}
+
+/// Shared state between the engine and its handles.
+pub(crate) struct Control {
+    paused: watch::Sender<bool>,
+    status: Mutex<EngineStatus>,
+    health: Mutex<HealthState>,
+    rescan_from: Mutex<Option<u32>>,
+    timings: Mutex<PhaseTimings>,
+    network: Mutex<Option<Network>>,
+    calls: Mutex<usize>,
+}
+
+impl Control {
+    pub(crate) fn new() -> Self {
+        let (paused, _) = watch::channel(false);
+        Self {
+            paused,
+            status: Mutex::new(EngineStatus {
+                state: RunState::Idle,
+                phase: EnginePhase::Idle,
+                cf_tip_height: 0,
+                scanned_height: 0,
+                chain_tip_height: 0,
+            }),
+            health: Mutex::new(HealthState::default()),
+            rescan_from: Mutex::new(None),
+            timings: Mutex::new(PhaseTimings::default()),
+            network: Mutex::new(None),
+            calls: Mutex::new(0),
+        }
+    }
+
+    /// Safe point: if a pause was requested, park here until resumed.
+    pub(crate) async fn checkpoint(&self) {
+        let mut rx = self.paused.subscribe();
+        if !*rx.borrow() {
+            return;
+        }
+        self.set_state(RunState::Paused);
+        // Sender lives as long as `self`, so this only fails if we are being dropped.
+        let _ = rx.wait_for(|paused| !*paused).await;
+        self.set_state(RunState::Running);
+    }
+
+    pub(crate) fn set_state(&self, state: RunState) {
+        self.status.lock().unwrap().state = state;
+    }
+
+    /// Move to `phase`; returns whether that changed it.
+    pub(crate) fn set_phase(&self, phase: EnginePhase) -> bool {
+        let mut status = self.status.lock().unwrap();
+        let changed = status.phase != phase;
+        status.phase = phase;
+        changed
+    }
+
+    /// Count a public engine call as in progress until the returned guard is
+    /// finished or dropped (a cancelled call).
+    pub(crate) fn call(&self) -> Call<'_> {
+        *self.calls.lock().unwrap() += 1;
+        Call(self)
+    }
+
+    pub(crate) fn update(&self, f: impl FnOnce(&mut EngineStatus)) {
+        f(&mut self.status.lock().unwrap());
+    }
+
+    /// Note a successful source call for [`EngineHandle::health`].
+    pub(crate) fn source_ok(&self, kind: SourceKind) {
+        let mut h = self.health.lock().unwrap();
+        let now = Some(crate::rt::now());
+        match kind {
+            SourceKind::Filters => h.filters_ok = now,
+            SourceKind::Headers => h.headers_ok = now,
+            SourceKind::Blocks | SourceKind::Transactions => h.blocks_ok = now,
+        }
+    }
+
+    /// Note how a `run_to_tip` call ended.
+    pub(crate) fn run_finished(&self, ok: bool) {
+        let mut h = self.health.lock().unwrap();
+        h.consecutive_failures = if ok {
+            0
+        } else {
+            h.consecutive_failures.saturating_add(1)
+        };
+    }
+
+    /// Add the time since `started` to `phase`.
+    pub(crate) fn spent(&self, phase: Phase, started: SystemTime) {
+        let elapsed = crate::rt::now().duration_since(started).unwrap_or_default();
+        let mut t = self.timings.lock().unwrap();
+        let bucket = match phase {
+            Phase::CfHeadersFetch => &mut t.cfheaders_fetch,
+            Phase::CfHeadersVerify => &mut t.cfheaders_verify,
+            Phase::FilterFetch => &mut t.filter_fetch,
+            Phase::Matching => &mut t.matching,
+            Phase::BlockFetch => &mut t.block_fetch,
+            Phase::Hooks => &mut t.hooks,
+            Phase::Store => &mut t.store,
+        };
+        *bucket += elapsed;
+    }
+
+    /// Take the lowest height a rescan was requested from since the last call.
+    pub(crate) fn take_rescan(&self) -> Option<u32> {
+        self.rescan_from.lock().unwrap().take()
+    }
+
+    /// Label the engine with its network.
+    pub(crate) fn set_network(&self, network: Network) {
+        *self.network.lock().unwrap() = Some(network);
+    }
+
+    /// Record the header chain tip, stamping the time whenever it advances.
+    pub(crate) fn saw_tip(&self, height: u32) {
+        self.update(|s| s.chain_tip_height = height);
+        let mut h = self.health.lock().unwrap();
+        if h.best_tip.is_none_or(|best| height > best) {
+            h.best_tip = Some(height);
+            h.new_block_at = Some(crate::rt::now());
+        }
+    }
+}
+
+/// Cloneable handle to pause, resume, and observe an engine from another task.
+///
+/// Pausing takes effect at the next safe point (between cfheaders batches or
+/// between scanned heights), after progress for the previous step has been
+/// persisted, so a resumed engine continues exactly where it stopped.
+#[derive(Clone)]
+pub struct EngineHandle {
+    pub(crate) control: Arc<Control>,
+}
+
+impl EngineHandle {
+    /// Request the engine to pause at its next safe point.
+    pub fn pause(&self) {
+        self.control.paused.send_replace(true);
+    }
+
+    /// Resume a paused engine (no-op if it is not paused).
+    pub fn resume(&self) {
+        self.control.paused.send_replace(false);
+    }
+
+    /// Whether a pause has been requested (the engine may still be finishing its current step).
+    pub fn is_pause_requested(&self) -> bool {
+        *self.control.paused.borrow()
+    }
+
+    /// Rescan every wallet from `height` on the next `run_to_tip` (e.g. after
+    /// adding scripts with history). Requests made before that run merge into
+    /// the lowest height; wallets that have not reached `height` are unaffected.
+    pub fn rescan_from(&self, height: u32) {
+        let mut from = self.control.rescan_from.lock().unwrap();
+        *from = Some(from.map_or(height, |h| h.min(height)));
+    }
+
+    /// Current state and progress.
+    pub fn status(&self) -> EngineStatus {
+        *self.control.status.lock().unwrap()
+    }
+
+    /// Current step of the run (also in [`status`](Self::status)).
+    pub fn phase(&self) -> EnginePhase {
+        self.control.status.lock().unwrap().phase
+    }
+
+    /// The engine's [network](crate::Niebla158::with_network), if set: a label
+    /// for telling engines of several networks apart in shared metrics.
+    pub fn network(&self) -> Option<Network> {
+        *self.control.network.lock().unwrap()
+    }
+
+    /// Where sync time went so far, per phase.
+    pub fn timings(&self) -> PhaseTimings {
+        *self.control.timings.lock().unwrap()
+    }
+
+    /// Source liveness and chain lag, for health checks.
+    pub fn health(&self) -> Health {
+        let status = self.status();
+        let h = self.control.health.lock().unwrap();
+        let secs_since_new_block = h.new_block_at.map(|at| {
+            crate::rt::now()
+                .duration_since(at)
+                .map_or(0, |d| d.as_secs())
+        });
+        Health {
+            state: status.state,
+            filter_source_ok_at: h.filters_ok,
+            header_source_ok_at: h.headers_ok,
+            block_source_ok_at: h.blocks_ok,
+            consecutive_failures: h.consecutive_failures,
+            scanned_height: status.scanned_height,
+            chain_tip_height: status.chain_tip_height,
+            new_block_at: h.new_block_at,
+            secs_since_new_block,
+        }
+    }
+}
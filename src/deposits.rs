@@ -0,0 +1,22 @@
+//! Deposit detection for exchanges and payment processors, from
+//! [`Niebla158::scan_deposits`](crate::Niebla158::scan_deposits): one call
+//! matching a large, fixed set of deposit scripts (say one address per
+//! customer) against a range of blocks, without wallet hooks or stored progress.
+use bitcoin::{BlockHash, OutPoint, TxOut};
+
+/// An output paying one of the scanned scripts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deposit {
+    /// Where the output lives.
+    pub outpoint: OutPoint,
+    /// Its value and script.
+    pub txout: TxOut,
+    /// Height of the block confirming it.
+    pub height: u32,
+    /// That block.
+    pub block: BlockHash,
+    /// Confirmations at the header tip seen by the scan (1 in the tip block).
+    pub confirmations: u32,
+    /// Whether it is a coinbase output, spendable only after 100 confirmations.
+    pub coinbase: bool,
+}
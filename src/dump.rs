@@ -0,0 +1,110 @@
+//! Dump the cfheaders and filters the engine receives for a window of heights
+//! to a file, to compare them with what a backend operator's node serves when
+//! the two disagree (a checkpoint mismatch, an invalid filter, a missed
+//! payment).
+//!
+//! ```rust,ignore
+//! let dump = PayloadDump::create("payloads.log", 812_000..=812_010)?;
+//! let engine = Niebla158::new(store, wallet, source, headers).with_payload_dump(dump);
+//! ```
+//!
+//! The file gets one line per payload, fields separated by tabs:
+//!
+//! ```text
+//! cfheader  <filter type>  <height>  -        <source>  <filter hash hex>
+//! cfilter   <filter type>  <height>  <block>  <source>  <filter hex>
+//! ```
+//!
+//! `<source>` is the [label](crate::FilterSource::label) of the filter source
+//! that served the payload, `-` if it has none. Filter hashes are written as
+//! served (the byte order of `getcfheaders`, not reversed like block hashes).
+//! A height fetched several times (retries, rescans, audits) is dumped each
+//! time; payloads outside the window are not dumped at all.
+use crate::filter_source::{CfHeadersBatch, FilterType};
+use anyhow::Context;
+use bitcoin::BlockHash;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Appends source payloads for a window of heights to a file, for
+/// [`Niebla158::with_payload_dump`](crate::Niebla158::with_payload_dump).
+pub struct PayloadDump {
+    heights: RangeInclusive<u32>,
+    file: Mutex<File>,
+}
+
+impl PayloadDump {
+    /// Dump payloads for `heights`, appending to `path`.
+    pub fn create(path: impl AsRef<Path>, heights: RangeInclusive<u32>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open {}", path.display()))?;
+        Ok(Self {
+            heights,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Heights whose payloads are dumped.
+    pub fn heights(&self) -> &RangeInclusive<u32> {
+        &self.heights
+    }
+
+    /// Dump the filter hashes of `batch` in the window.
+    pub(crate) fn cfheaders(
+        &self,
+        filter_type: FilterType,
+        batch: &CfHeadersBatch,
+        source: Option<String>,
+    ) -> anyhow::Result<()> {
+        let source = source.unwrap_or_else(|| "-".into());
+        let mut lines = String::new();
+        for (height, hash) in (batch.start_height..).zip(&batch.headers) {
+            if self.heights.contains(&height) {
+                lines += &format!(
+                    "cfheader\t{}\t{height}\t-\t{source}\t{}\n",
+                    filter_type.0,
+                    hex::encode(hash)
+                );
+            }
+        }
+        self.write(&lines)
+    }
+
+    /// Dump the filter of `block` at `height`, if it is in the window.
+    pub(crate) fn filter(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+        block: BlockHash,
+        raw: &[u8],
+        source: Option<String>,
+    ) -> anyhow::Result<()> {
+        if !self.heights.contains(&height) {
+            return Ok(());
+        }
+        let source = source.unwrap_or_else(|| "-".into());
+        self.write(&format!(
+            "cfilter\t{}\t{height}\t{block}\t{source}\t{}\n",
+            filter_type.0,
+            hex::encode(raw)
+        ))
+    }
+
+    fn write(&self, lines: &str) -> anyhow::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(lines.as_bytes())
+            .context("write payload dump")
+    }
+}
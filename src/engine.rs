@@ -1,16 +1,70 @@
 //! Orchestrator for BIP-158 light client flow:
 //! 1) verify cfheaders with optional checkpoints,
-//! 2) scan per-block filters against a wallet watchlist,
+//! 2) scan per-block filters against one or more wallet watchlists,
 //! 3) fetch matching blocks and deliver transactions.
 use crate::{
-    cfheaders::CfHeaderChain, filter_source::FilterSource, headers::HeaderSource,
-    hooks::WalletHooks, matcher::filter_matches_any, store::Store,
+    adaptive::Window,
+    audit::{AuditReport, Discrepancy},
+    block_source::{BitcoinCodec, BlockCodec, BlockSource},
+    cfheaders::{next_header, CfHeaderChain},
+    checkpoints::Checkpoints,
+    config::{parse_filter_type, parse_record, EngineConfig},
+    control::{Control, EngineHandle, EnginePhase, Phase, RunState, SourceKind},
+    deposits::Deposit,
+    dump::PayloadDump,
+    error::{CheckpointMismatch, EngineError, InvalidFilter},
+    events::{EmptyFilterKind, EngineEvent, EventSink},
+    filter_source::{CfHeadersBatch, FilterSource, FilterType, GolombParams},
+    headers::HeaderSource,
+    hooks::{
+        match_id, watchlist_fingerprint, Delivery, MatchDetails, TxSummary, WalletHooks, WatchItem,
+    },
+    layers::Bounded,
+    matcher::{validate_filter, QuerySet, MAX_SHARD_SCRIPTS},
+    parse,
+    policy::{AlwaysSync, PolicyDecision, SyncContext, SyncPhase, SyncPolicy},
+    session::{ScanDirection, ScanSession},
+    store::Store,
+    tx_source::TxSource,
+    utxo::{BalanceDelta, Utxo},
 };
 use anyhow::Context;
-use bitcoin::{consensus, Block, BlockHash};
+use bitcoin::{
+    block::Header,
+    constants::genesis_block,
+    hashes::{sha256, Hash, HashEngine},
+    Amount, BlockHash, Network, OutPoint, ScriptBuf, Transaction, TxOut, Txid,
+};
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet, VecDeque},
+    future::Future,
+    ops::RangeInclusive,
+    slice,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+/// cfheaders per request: BIP-157 caps `getcfheaders` at 2000, so the window
+/// starts there and only shrinks (down to 100) while the source is slow.
+const CFHEADERS_WINDOW: Window = Window::new(2_000, 100, 2_000, Duration::from_secs(5));
+
+/// Heights between BIP-157 `cfcheckpt` entries (and so per parallel segment).
+const CFCHECKPT_INTERVAL: u32 = 1_000;
 
-/// How many cfheaders to advance per request window.
-const CFHEADERS_BATCH: u32 = 2_000;
+/// Filters fetched per request window (BIP-157 caps `getcfilters` at 1000).
+const CFILTER_WINDOW: Window = Window::new(8, 1, 1_000, Duration::from_secs(2));
+
+/// Verified cfheaders are persisted every this many heights within a batch, so a
+/// crash mid-batch only loses this much work.
+const CFHEADERS_PERSIST_CHUNK: usize = 500;
+
+/// Watched outputs remembered (across all wallets) to value their spends when
+/// the store does not track UTXOs; the oldest are forgotten first.
+const PREVOUT_CACHE: usize = 100_000;
 
 /// Core engine. `S` = store, `W` = wallet hooks, `F` = network filter source, `H` = header iterator/stream.
 pub struct Niebla158<S, W, F, H> {
@@ -19,6 +73,138 @@ pub struct Niebla158<S, W, F, H> {
     source: F,
     headers: H,
     checkpoints: Vec<(u32, BlockHash)>,
+    anchor: Option<(u32, BlockHash)>,
+    /// Anchor recorded in (or picked for) the primary store, without an explicit one.
+    store_anchor: Mutex<Option<(u32, BlockHash)>>,
+    control: Arc<Control>,
+    policy: Arc<dyn SyncPolicy>,
+    wallets: Vec<(Box<dyn Store>, Box<dyn WalletHooks>)>,
+    blocks: Option<Box<dyn BlockSource>>,
+    tx_source: Option<Box<dyn TxSource>>,
+    prevouts_wanted: bool,
+    codec: Box<dyn BlockCodec>,
+    source_wait: Option<(Duration, Duration)>,
+    delivery: Delivery,
+    filter_type: FilterType,
+    golomb: Option<GolombParams>,
+    windows: Mutex<Windows>,
+    queued: bool,
+    pending: Notify,
+    parallel_segments: Option<usize>,
+    events: Option<Arc<dyn EventSink>>,
+    phase_events: bool,
+    payload_dump: Option<PayloadDump>,
+    cross_check: Option<u32>,
+    /// Last height compared by the source cross-check in this run.
+    cross_checked: Mutex<Option<u32>>,
+    fallbacks: Vec<Box<dyn FallbackSource>>,
+    active_source: AtomicUsize,
+    /// Delivery numbers for stores that do not keep a sequence.
+    sequence: AtomicU64,
+    reanchor_retries: u32,
+    assume_fresh: AtomicBool,
+    /// Unspent watched outputs seen paid, by wallet, without UTXO tracking.
+    /// Only delivered matches change it (see [`PrevoutUpdate`]).
+    prevouts: Mutex<Bounded<(usize, OutPoint), TxOut>>,
+    /// Last `(height, balance)` reported per wallet with UTXO tracking.
+    balances: Mutex<HashMap<usize, (u32, Amount)>>,
+    utxo_tracking: bool,
+    strict_filters: bool,
+    shard_size: Option<usize>,
+    indexed_matching: bool,
+    verdict_cache: bool,
+    auto_rescan: bool,
+    network: Option<Network>,
+    network_checked: AtomicBool,
+    config_checked: AtomicBool,
+    opaque_blocks: bool,
+    raw_blocks: bool,
+    watchtower: bool,
+}
+
+/// An engine over trait objects, for applications that pick their store,
+/// hooks and sources at runtime (from config, say) instead of naming every
+/// combination. `Box<T>` and `Arc<T>` implement each trait `T` does.
+pub type DynEngine =
+    Niebla158<Box<dyn Store>, Box<dyn WalletHooks>, Box<dyn FilterSource>, Box<dyn HeaderSource>>;
+
+/// A boxed filter source usable both as [`FilterSource`] and as [`BlockSource`].
+trait FallbackSource: FilterSource + BlockSource {}
+
+impl<T: FilterSource> FallbackSource for T {}
+
+/// Request sizes learned from the sources, kept across runs.
+struct Windows {
+    cfheaders: Window,
+    cfilters: Window,
+}
+
+/// How the cfheaders phase of a run ended.
+enum CfPhase {
+    /// Verified up to the chain tip.
+    Synced,
+    /// The sync policy ended the run.
+    Stopped,
+    /// The filter source could not serve up to the tip (and waiting did not help).
+    SourceBehind(EngineError),
+}
+
+/// A downloaded block, decoded once per height for every wallet it matched.
+#[derive(Clone, Default)]
+struct DecodedBlock {
+    header: Option<Header>,
+    txs: Vec<Transaction>,
+    /// The block as served; empty unless it is delivered too.
+    raw: Vec<u8>,
+}
+
+/// The block downloaded at the height a scan is on, if any.
+#[derive(Default)]
+struct BlockCache {
+    block: Option<DecodedBlock>,
+    /// Wallets after the current one still to scan this height: when none
+    /// are left, the block is moved into the match rather than cloned.
+    wallets_left: usize,
+}
+
+/// What a match changes in the prevout cache, applied once it is delivered
+/// so a failed delivery can be retried with the same amounts.
+#[derive(Default)]
+struct PrevoutUpdate {
+    /// Outputs its transactions spend.
+    spent: Vec<OutPoint>,
+    /// Watched outputs its transactions pay.
+    paid: Vec<(OutPoint, TxOut)>,
+}
+
+/// A scan of a height range for scripts outside the wallets' watchlists
+/// ([`Niebla158::find_transaction`], [`Niebla158::scan_deposits`]).
+struct AdhocScan {
+    query: QuerySet,
+    golomb: GolombParams,
+    /// Header tip when the scan started.
+    tip: u32,
+    /// Start of the next filter window; `None` past `u32::MAX`.
+    next: Option<u32>,
+    end: u32,
+}
+
+/// One wallet taking part in a scan: its store/hooks, watchlist and progress.
+struct Lane<'a> {
+    /// Index in [`Niebla158::all_wallets`].
+    wallet: usize,
+    store: &'a dyn Store,
+    hooks: &'a dyn WalletHooks,
+    items: Vec<WatchItem>,
+    watch: Vec<ScriptBuf>,
+    query: QuerySet,
+    last_scanned: u32,
+    /// Key of this watchlist's cached misses, with the verdict cache on.
+    miss_key: Option<sha256::Hash>,
+    /// Heights of the current window already known to miss.
+    known_misses: HashSet<u32>,
+    /// Misses seen since they were last saved.
+    new_misses: Vec<(u32, BlockHash)>,
 }
 
 impl<S, W, F, H> Niebla158<S, W, F, H>
@@ -36,15 +222,411 @@ where
             source,
             headers,
             checkpoints: vec![],
+            anchor: None,
+            store_anchor: Mutex::new(None),
+            control: Arc::new(Control::new()),
+            policy: Arc::new(AlwaysSync),
+            wallets: vec![],
+            blocks: None,
+            tx_source: None,
+            prevouts_wanted: false,
+            codec: Box::new(BitcoinCodec),
+            source_wait: None,
+            delivery: Delivery::AtLeastOnce,
+            filter_type: FilterType::BASIC,
+            golomb: None,
+            windows: Mutex::new(Windows {
+                cfheaders: CFHEADERS_WINDOW,
+                cfilters: CFILTER_WINDOW,
+            }),
+            queued: false,
+            pending: Notify::new(),
+            parallel_segments: None,
+            events: None,
+            phase_events: false,
+            payload_dump: None,
+            cross_check: None,
+            cross_checked: Mutex::new(None),
+            fallbacks: vec![],
+            active_source: AtomicUsize::new(0),
+            sequence: AtomicU64::new(0),
+            reanchor_retries: 0,
+            assume_fresh: AtomicBool::new(false),
+            prevouts: Mutex::new(Bounded::new(PREVOUT_CACHE)),
+            balances: Mutex::new(HashMap::new()),
+            utxo_tracking: false,
+            strict_filters: false,
+            shard_size: None,
+            indexed_matching: false,
+            verdict_cache: false,
+            auto_rescan: false,
+            network: None,
+            network_checked: AtomicBool::new(false),
+            config_checked: AtomicBool::new(false),
+            opaque_blocks: false,
+            raw_blocks: false,
+            watchtower: false,
         }
     }
 
-    /// Provide compact-filter header checkpoints `(height, rolling_cfheader_hash)` for defense-in-depth
+    /// Provide compact-filter header checkpoints `(height, rolling_cfheader_hash)` for defense-in-depth.
+    /// They apply to the engine's [filter type](Self::with_filter_type).
     pub fn with_checkpoints(mut self, v: Vec<(u32, BlockHash)>) -> Self {
         self.checkpoints = v;
         self
     }
 
+    /// Start the cfheaders chain at a trusted rolling header `cfheader` at
+    /// `height` (e.g. a checkpoint just below the wallets' birth) instead of
+    /// at genesis, so history nobody scans is not verified.
+    ///
+    /// Applies while the store's cfheaders are below `height`; sync continues
+    /// from the anchor, and re-anchoring or reorg recovery never rewinds below
+    /// it. Heights at or below the anchor are not scanned either: wallets whose
+    /// progress is lower resume above it.
+    ///
+    /// Without one, the first sync of wallets that all have a
+    /// [birth height](Store::get_birth_height) anchors at the highest
+    /// [checkpoint](Self::with_checkpoints) below the earliest of them, and
+    /// records that in the primary store ([`Store::set_cf_anchor`]).
+    pub fn with_cfheader_anchor(mut self, height: u32, cfheader: BlockHash) -> Self {
+        self.anchor = Some((height, cfheader));
+        self
+    }
+
+    /// Tie the engine to `network`. The first run checks that the header
+    /// source's genesis block is the network's and records the network in
+    /// every wallet's store ([`Store::set_network`]), failing with
+    /// [`EngineError::WrongNetwork`] on a mismatch, so engines for several
+    /// networks can share a process without mixing up sources or stores.
+    ///
+    /// Without [explicit checkpoints](Self::with_checkpoints), the network's
+    /// built-in ones are used.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self.control.set_network(network);
+        if self.checkpoints.is_empty() {
+            self.checkpoints = crate::checkpoints::for_network(network);
+        }
+        self
+    }
+
+    /// Deliver matching blocks undecoded, in [`MatchDetails::raw_block`],
+    /// instead of parsing them as Bitcoin blocks.
+    ///
+    /// For chains whose filters follow BIP-158 but whose blocks are encoded
+    /// differently, such as Liquid and other Elements sidechains: cfheaders
+    /// verification and filter matching only need filter bytes and block
+    /// hashes, so the engine scans them as is and the wallet decodes the block
+    /// (e.g. with the `elements` crate). The block source's
+    /// [`get_relevant_txs`](BlockSource::get_relevant_txs) is not used, and
+    /// runs fail if [UTXO tracking](Self::with_utxo_tracking) is on.
+    pub fn with_opaque_blocks(mut self) -> Self {
+        self.opaque_blocks = true;
+        self
+    }
+
+    /// Also deliver matching blocks as served, in [`MatchDetails::raw_block`],
+    /// next to their decoded transactions: for wallets that forward or archive
+    /// the consensus bytes and would otherwise serialize the block again.
+    ///
+    /// Matching blocks are then always downloaded whole; the block source's
+    /// [`get_relevant_txs`](BlockSource::get_relevant_txs) is not used.
+    /// To skip decoding altogether, see [`with_opaque_blocks`](Self::with_opaque_blocks).
+    pub fn with_raw_blocks(mut self) -> Self {
+        self.raw_blocks = true;
+        self
+    }
+
+    /// Run as a watchtower (see [`watchtower`](crate::watchtower)): matching
+    /// blocks are always downloaded whole and passed in
+    /// [`MatchDetails::raw_block`] next to their transactions, and each hit is
+    /// queued in the wallet's [`Store`] before its hooks are called and dequeued
+    /// once they return, so a breach the hooks did not handle is delivered
+    /// again at the start of the next run even if its block is no longer
+    /// matched (a reorg, a filter source gone missing).
+    ///
+    /// Hits are delivered during the scan, so runs fail if a
+    /// [delivery queue](Self::with_delivery_queue) is on. Requires a store
+    /// that implements the queue methods (both bundled stores do).
+    pub fn with_watchtower(mut self) -> Self {
+        self.watchtower = true;
+        self
+    }
+
+    /// Install a [`SyncPolicy`] consulted before every cfheaders batch and scanned height.
+    pub fn with_policy(mut self, policy: impl SyncPolicy + 'static) -> Self {
+        self.policy = Arc::new(policy);
+        self
+    }
+
+    /// Register an additional wallet that shares this engine's cfheaders verification
+    /// and filter downloads. Matching and scan progress are tracked per wallet in its
+    /// own `store`; the verified cfheaders tip lives in the primary store only.
+    pub fn with_wallet(
+        mut self,
+        store: impl Store + 'static,
+        hooks: impl WalletHooks + 'static,
+    ) -> Self {
+        self.wallets.push((Box::new(store), Box::new(hooks)));
+        self
+    }
+
+    /// Fetch matching blocks from `blocks` instead of the filter source (e.g. filters
+    /// from an HTTP mirror, blocks from your own node).
+    pub fn with_block_source(mut self, blocks: impl BlockSource + 'static) -> Self {
+        self.blocks = Some(Box::new(blocks));
+        self
+    }
+
+    /// Pass the outputs spent by matched transactions in
+    /// [`MatchDetails::prevouts`], for coin control and fee accounting.
+    ///
+    /// They are resolved for the transactions that involve the watchlist (pay
+    /// a watched script or spend a known watched output; every transaction a
+    /// block source returned as relevant) from the store's [UTXO
+    /// set](Self::with_utxo_tracking), else the unspent watched outputs paid in
+    /// matches this engine delivered (the 100 000 latest, across wallets),
+    /// and, with one set, the [`with_tx_source`](Self::with_tx_source) lookup.
+    /// The [summaries](MatchDetails::summaries) use them too.
+    pub fn with_prevouts(mut self) -> Self {
+        self.prevouts_wanted = true;
+        self
+    }
+
+    /// Look up outputs the engine does not know in `txs` (implies
+    /// [`with_prevouts`](Self::with_prevouts)); every input of a transaction
+    /// involving the watchlist is then resolved, so fees can be computed.
+    pub fn with_tx_source(mut self, txs: impl TxSource + 'static) -> Self {
+        self.tx_source = Some(Box::new(txs));
+        self.prevouts_wanted = true;
+        self
+    }
+
+    /// Read raw blocks with `codec` instead of Bitcoin's consensus encoding
+    /// (see [`BlockCodec`]).
+    pub fn with_block_codec(mut self, codec: impl BlockCodec + 'static) -> Self {
+        self.codec = Box::new(codec);
+        self
+    }
+
+    /// When the filter source lags the header chain (e.g. its node is still
+    /// indexing), poll it every `poll` for up to `deadline` before giving up with
+    /// [`EngineError::SourceBehindTip`]. Without this the run fails right away.
+    pub fn with_source_wait(mut self, deadline: Duration, poll: Duration) -> Self {
+        self.source_wait = Some((deadline, poll));
+        self
+    }
+
+    /// Choose whether matches may be re-delivered after a crash (see [`Delivery`]).
+    pub fn with_delivery(mut self, delivery: Delivery) -> Self {
+        self.delivery = delivery;
+        self
+    }
+
+    /// Sync and scan filters of `filter_type` instead of BIP-158 basic filters.
+    ///
+    /// The verified cfheaders chain is kept per filter type in the store, so
+    /// engines with different types can share one store. Scan progress
+    /// (`last_scanned`) is not namespaced; give each engine its own wallet store.
+    pub fn with_filter_type(mut self, filter_type: FilterType) -> Self {
+        self.filter_type = filter_type;
+        self
+    }
+
+    /// Decode filters with custom Golomb-Rice parameters (e.g. an in-house filter
+    /// type). Without this the filter type's standard parameters are used, and
+    /// BIP-158's for types that have none. Checked against the filter type when
+    /// a run starts.
+    pub fn with_golomb_params(mut self, params: GolombParams) -> Self {
+        self.golomb = Some(params);
+        self
+    }
+
+    fn golomb_params(&self) -> GolombParams {
+        self.golomb
+            .or_else(|| self.filter_type.standard_params())
+            .unwrap_or(GolombParams::BASIC)
+    }
+
+    /// Queue filter hits in each wallet's [`Store`] instead of delivering them
+    /// during the scan, so a slow or failing wallet callback does not hold up
+    /// scanning and undelivered matches survive restarts.
+    ///
+    /// Blocks are then fetched and handed to the hooks by
+    /// [`deliver_pending`](Self::deliver_pending), typically from a separate task
+    /// waiting on [`wait_for_pending`](Self::wait_for_pending). Requires a store
+    /// that implements the queue methods (both bundled stores do). Queued
+    /// matches whose block a reorg removes are dropped undelivered, each
+    /// reported as an [`EngineEvent::MatchDropped`].
+    pub fn with_delivery_queue(mut self) -> Self {
+        self.queued = true;
+        self
+    }
+
+    /// Verify cfheaders in 1000-height segments, `segments` at a time, each
+    /// anchored at the filter source's `cfcheckpt` header where it starts and
+    /// required to end on the next one.
+    ///
+    /// Segments are downloaded concurrently and stitched in order, which cuts
+    /// initial sync time on high-latency sources. Falls back to sequential
+    /// batches when the source serves no checkpoints.
+    pub fn with_parallel_cfheaders(mut self, segments: usize) -> Self {
+        self.parallel_segments = Some(segments.max(1));
+        self
+    }
+
+    /// Report [`EngineEvent`]s (e.g. checkpoint mismatches) to `sink`, for
+    /// alerting or banning misbehaving sources.
+    pub fn with_events(mut self, sink: impl EventSink + 'static) -> Self {
+        self.events = Some(Arc::new(sink));
+        self
+    }
+
+    /// Also report every change of [phase](EngineHandle::phase) to the event
+    /// sink as [`EngineEvent::PhaseChanged`], e.g. to drive a progress display.
+    /// Off by default: scanning changes phase at every height.
+    pub fn with_phase_events(mut self) -> Self {
+        self.phase_events = true;
+        self
+    }
+
+    /// Write the cfheaders and filters received for the heights of `dump` to
+    /// its file (see [`dump`](crate::dump)), to diagnose disagreements with a
+    /// backend. Meant for debugging: the dump is written as payloads arrive.
+    pub fn with_payload_dump(mut self, dump: PayloadDump) -> Self {
+        self.payload_dump = Some(dump);
+        self
+    }
+
+    /// Compare the block hashes of the filter source
+    /// ([`FilterSource::block_hash_at`]) and the header source at the top of
+    /// the first cfheaders batch or filter window of each run, then whenever
+    /// the run has moved `interval` heights away from the last height
+    /// compared. A difference fails the run with
+    /// [`EngineError::SourcesDiverged`] before that batch is verified or
+    /// anything from that window is matched or delivered.
+    ///
+    /// For filters and headers from different backends. Filter sources that
+    /// cannot tell their chain are not checked; one that fails to answer (e.g.
+    /// because it is behind) is asked again at the next batch or window.
+    pub fn with_source_cross_check(mut self, interval: u32) -> Self {
+        self.cross_check = Some(interval.max(1));
+        self
+    }
+
+    /// Add a filter source to switch to when re-anchoring (see
+    /// [`with_reanchor`](Self::with_reanchor)). Sources are tried in the order
+    /// added, after the primary one, and the engine keeps using the one it
+    /// switched to.
+    pub fn with_fallback_source(mut self, source: impl FilterSource + 'static) -> Self {
+        self.fallbacks.push(Box::new(source));
+        self
+    }
+
+    /// Recover from up to `retries` checkpoint mismatches per run instead of
+    /// failing on the first one.
+    ///
+    /// Each time, stored cfheaders are discarded back to the last configured
+    /// checkpoint below the mismatch (or genesis), wallets that scanned past it
+    /// rescan from there, the next fallback source (if any) takes over, and
+    /// verification resumes. An [`EngineEvent::Reanchored`] is emitted for each.
+    pub fn with_reanchor(mut self, retries: u32) -> Self {
+        self.reanchor_retries = retries;
+        self
+    }
+
+    /// Ignore whatever progress the stores hold: the first run
+    /// [`reset`](Self::reset)s the engine before syncing. Useful when a
+    /// database may belong to another network or be corrupted.
+    pub fn with_assume_fresh(self) -> Self {
+        self.assume_fresh.store(true, Ordering::Relaxed);
+        self
+    }
+
+    /// Keep each wallet's confirmed UTXO set in its store (see [`crate::utxo`]):
+    /// outputs paying watched scripts are recorded as they are delivered, along
+    /// with the height they are spent at. On a reorg, outputs from blocks that
+    /// left the chain are marked orphaned and their spends undone; a transaction
+    /// confirming again on the new branch is delivered and recorded anew.
+    ///
+    /// Every store must implement the `Store` UTXO methods.
+    pub fn with_utxo_tracking(mut self) -> Self {
+        self.utxo_tracking = true;
+        self
+    }
+
+    /// Fail with [`EngineError::InvalidFilter`] on a zero-length filter instead
+    /// of treating it as a miss. A source returning empty bytes would otherwise
+    /// hide every payment in those blocks.
+    ///
+    /// Legitimately empty filters (no elements) are still accepted; malformed
+    /// ones fail in either mode.
+    pub fn with_strict_filters(mut self) -> Self {
+        self.strict_filters = true;
+        self
+    }
+
+    /// Split each wallet's watchlist into shards of at most `scripts_per_shard`
+    /// scripts and match every filter against the shards in parallel, on at
+    /// most one thread per core kept for the scan (one after another on wasm);
+    /// the first shard that hits ends the block's match. For registries of
+    /// millions of scripts.
+    ///
+    /// Matching a shard allocates 8 bytes per script on top of the script bytes
+    /// themselves, so the shard size bounds per-thread memory; runs fail if it
+    /// is 0 or above 2^20 (8 MiB per shard).
+    pub fn with_watchlist_shards(mut self, scripts_per_shard: usize) -> Self {
+        self.shard_size = Some(scripts_per_shard);
+        self
+    }
+
+    /// Match by decoding each filter's elements and looking every hashed script
+    /// up in them, instead of hashing and sorting the whole watchlist for a
+    /// sort-merge against the filter. Same results; faster on watchlists of
+    /// tens of thousands of scripts and more (see `benches/filters.rs`).
+    ///
+    /// BIP-158 keys the script hashes with the block hash, so the watchlist
+    /// itself cannot be indexed ahead of time; the filter side can.
+    pub fn with_indexed_matching(mut self) -> Self {
+        self.indexed_matching = true;
+        self
+    }
+
+    /// Remember in each wallet's store which filters had no hit for its
+    /// watchlist ([`Store::save_misses`]), keyed by the watchlist's
+    /// [fingerprint](crate::hooks::watchlist_fingerprint), and skip fetching
+    /// and matching them again while the watchlist is unchanged: rescans and
+    /// runs after a rollback of progress then only download filters of blocks
+    /// that changed or that matched.
+    ///
+    /// Costs one stored block hash per scanned height. Zero-length filters are
+    /// never cached, so a source that withheld one is asked again.
+    pub fn with_verdict_cache(mut self) -> Self {
+        self.verdict_cache = true;
+        self
+    }
+
+    /// Save each wallet's watchlist in its store ([`Store::set_watchlist`]) and,
+    /// when a run finds scripts added since, rescan that wallet from the
+    /// earliest height one of them could have been paid at: the item's
+    /// [`birth_height`](WatchItem::birth_height), else the store's birth
+    /// height, else genesis. Added scripts born above the wallet's progress
+    /// need no rescan, so give fresh addresses a birth height.
+    ///
+    /// Each change is reported as [`EngineEvent::WatchlistChanged`].
+    pub fn with_auto_rescan(mut self) -> Self {
+        self.auto_rescan = true;
+        self
+    }
+
+    /// Handle for pausing/resuming this engine and reading its status from another task.
+    pub fn handle(&self) -> EngineHandle {
+        EngineHandle {
+            control: self.control.clone(),
+        }
+    }
+
     /// Verify/advance compact-filter headers to the given tip and then
     /// scan each block's BIP-158 filter against the wallet watchlist.
     /// For every hit, fetch and decode the block and forward its txs to `WalletHooks`.
@@ -55,80 +637,2754 @@ where
     /// # Errors
     /// Returns an error if cfheader verification fails, the network source fails to
     /// provide data, block decoding fails, or the store cannot persist progress.
+    /// If the filter source is behind the header chain, heights it could serve
+    /// are still scanned and the error downcasts to [`EngineError::SourceBehindTip`].
     pub async fn run_to_tip(&self) -> anyhow::Result<()> {
-        let cf_tip = self.store.load_cf_tip().await?;
-        let mut cfchain = CfHeaderChain::new_from_store(cf_tip);
+        self.control.set_state(RunState::Running);
+        let res = self.phased(self.sync()).await;
+        self.control.run_finished(res.is_ok());
+        self.control.set_state(RunState::Idle);
+        res
+    }
 
-        let chain_tip = self.headers.tip_height().await?;
+    /// Verify and persist cfheaders up to the chain tip without scanning any
+    /// filters, e.g. to pre-warm header verification in the background before
+    /// a wallet exists. Returns the verified height; the next
+    /// [`run_to_tip`](Self::run_to_tip) picks up from there.
+    ///
+    /// # Errors
+    /// As [`run_to_tip`](Self::run_to_tip); a filter source behind the header
+    /// chain fails with [`EngineError::SourceBehindTip`] after persisting what
+    /// it could verify.
+    pub async fn sync_cfheaders_only(&self) -> anyhow::Result<u32> {
+        self.control.set_state(RunState::Running);
+        let res = match self.phased(self.verify_to_tip()).await {
+            Ok(Some((verified, _, None))) => Ok(verified),
+            Ok(Some((_, _, Some(behind)))) => Err(behind.into()),
+            // The sync policy ended the run.
+            Ok(None) => Ok(self.handle().status().cf_tip_height),
+            Err(e) => Err(e),
+        };
+        self.control.run_finished(res.is_ok());
+        self.control.set_state(RunState::Idle);
+        res
+    }
 
-        let mut next = cfchain.tip_height.saturating_add(1);
-        while next <= chain_tip {
-            let stop_h = (next + CFHEADERS_BATCH - 1).min(chain_tip);
-            let stop_hash = self.headers.hash_at_height(stop_h).await?;
+    /// Scan `ranges` of heights with one worker per source in `workers`, each
+    /// taking the next range not yet scanned and fetching its filters from its
+    /// own source (e.g. its own connection to a local node), for the initial
+    /// recovery over long stretches of history.
+    ///
+    /// cfheaders are verified up to the tip first, as by
+    /// [`run_to_tip`](Self::run_to_tip), and every range must lie within them.
+    /// Workers run concurrently and only match filters: their hits are queued in
+    /// each wallet's store (which needs a delivery queue), and delivered lowest
+    /// height first once every range is done, unless
+    /// [queued delivery](Self::with_delivery_queue) leaves that to
+    /// [`deliver_pending`](Self::deliver_pending). A failed delivery leaves the
+    /// remaining hits queued for `deliver_pending`.
+    ///
+    /// Each range's progress is kept in the stores, so calling again with the
+    /// same ranges after an error resumes where the workers stopped. When all
+    /// ranges are done, each wallet's scan progress moves over the ranges that
+    /// continue it without a gap; the next `run_to_tip` scans whatever they left out.
+    pub async fn run_parallel<G: FilterSource>(
+        &self,
+        ranges: Vec<RangeInclusive<u32>>,
+        workers: Vec<G>,
+    ) -> anyhow::Result<()> {
+        if workers.is_empty() {
+            anyhow::bail!("a parallel scan needs at least one worker source");
+        }
+        let mut ranges = ranges;
+        ranges.sort_by_key(|r| *r.start());
+        if let Some(r) = ranges.iter().find(|r| r.is_empty() || *r.start() == 0) {
+            anyhow::bail!("scan range {r:?} is empty or starts at genesis");
+        }
+        if let Some(pair) = ranges.windows(2).find(|p| p[0].end() >= p[1].start()) {
+            anyhow::bail!("scan ranges {:?} and {:?} overlap", pair[0], pair[1]);
+        }
+
+        self.control.set_state(RunState::Running);
+        let res = self.phased(self.sync_parallel(&ranges, &workers)).await;
+        self.control.run_finished(res.is_ok());
+        self.control.set_state(RunState::Idle);
+        res
+    }
+
+    /// Scan `heights` against only the primary wallet's
+    /// [priority](crate::WatchItem::with_priority) items and return the matches.
+    ///
+    /// Meant to run before the full historical scan (e.g. the last few thousand
+    /// blocks for the main receive addresses) to show an approximate balance
+    /// quickly. Filters are not checked against verified cfheaders, hooks are
+    /// not called and no progress is persisted: the regular
+    /// [`run_to_tip`](Self::run_to_tip) still delivers every match. Heights past
+    /// the header tip are ignored.
+    pub async fn quick_check(
+        &self,
+        heights: RangeInclusive<u32>,
+    ) -> anyhow::Result<Vec<MatchDetails>> {
+        self.phased(self.priority_scan(heights)).await
+    }
+
+    async fn priority_scan(
+        &self,
+        heights: RangeInclusive<u32>,
+    ) -> anyhow::Result<Vec<MatchDetails>> {
+        let golomb = self.golomb_params();
+        golomb.validate_for(self.filter_type)?;
+        let items: Vec<WatchItem> = self
+            .hooks
+            .watch_items()
+            .await?
+            .into_iter()
+            .filter(|i| i.priority)
+            .collect();
+        if items.is_empty() {
+            return Ok(vec![]);
+        }
+        let watch: Vec<ScriptBuf> = items.iter().map(|i| i.script.clone()).collect();
+        let query = QuerySet::new(&items, self.shard_size, self.indexed_matching);
+
+        let tip = self.observe(SourceKind::Headers, self.headers.tip_height().await)?;
+        let mut found = vec![];
+        for h in *heights.start()..=(*heights.end()).min(tip) {
+            self.enter(EnginePhase::Scanning { height: h });
+            let block_hash = self.hash_at(h).await?;
+            let raw_filter = self
+                .observe(
+                    SourceKind::Filters,
+                    self.timed(
+                        Phase::FilterFetch,
+                        self.filters()
+                            .get_cfilter_typed(self.filter_type, block_hash),
+                    )
+                    .await,
+                )
+                .with_context(|| format!("get_cfilter({block_hash})"))?;
+            if self.match_filter(h, block_hash, &raw_filter, &query, golomb)? {
+                self.enter(EnginePhase::FetchingBlock { height: h });
+                found.push(
+                    self.fetch_match(0, h, block_hash, &items, &watch, &mut BlockCache::default())
+                        .await?,
+                );
+            }
+        }
+        Ok(found)
+    }
+
+    /// Match the one block `block_hash` against the primary wallet's watchlist,
+    /// e.g. to confirm a payment a user says landed in it, without a scan.
+    ///
+    /// Fetches the block's filter and, on a hit, the block (or its relevant
+    /// transactions) as a scan would, and returns the match; `None` if the
+    /// filter misses. Filter hits can be false positives: check the match's
+    /// `items`. As with [`quick_check`](Self::quick_check), the filter is not
+    /// checked against verified cfheaders, hooks are not called and nothing is
+    /// persisted.
+    ///
+    /// # Errors
+    /// Fails if the header source does not have `block_hash` on its chain, as
+    /// well as on source errors and malformed filters.
+    pub async fn check_block(&self, block_hash: BlockHash) -> anyhow::Result<Option<MatchDetails>> {
+        self.phased(self.match_block(block_hash)).await
+    }
+
+    async fn match_block(&self, block_hash: BlockHash) -> anyhow::Result<Option<MatchDetails>> {
+        let golomb = self.golomb_params();
+        golomb.validate_for(self.filter_type)?;
+        let height = self
+            .observe(
+                SourceKind::Headers,
+                self.headers.height_of(block_hash).await,
+            )?
+            .with_context(|| format!("block {block_hash} is not on the header source's chain"))?;
+        let items = self.hooks.watch_items().await?;
+        if items.is_empty() {
+            return Ok(None);
+        }
+        let watch: Vec<ScriptBuf> = items.iter().map(|i| i.script.clone()).collect();
+        let query = QuerySet::new(&items, self.shard_size, self.indexed_matching);
+
+        self.enter(EnginePhase::Scanning { height });
+        let raw_filter = self
+            .observe(
+                SourceKind::Filters,
+                self.timed(
+                    Phase::FilterFetch,
+                    self.filters()
+                        .get_cfilter_typed(self.filter_type, block_hash),
+                )
+                .await,
+            )
+            .with_context(|| format!("get_cfilter({block_hash})"))?;
+        if !self.match_filter(height, block_hash, &raw_filter, &query, golomb)? {
+            return Ok(None);
+        }
+        self.enter(EnginePhase::FetchingBlock { height });
+        let details = self
+            .fetch_match(
+                0,
+                height,
+                block_hash,
+                &items,
+                &watch,
+                &mut BlockCache::default(),
+            )
+            .await?;
+        Ok(Some(details))
+    }
+
+    /// Find where transaction `txid` confirmed, searching `heights` (clamped to
+    /// the header tip) for blocks whose filters match any of `scripts`, the
+    /// transaction's output (or spent) scripts as the caller knows them.
+    ///
+    /// Independent of the wallets' watchlists: for support requests like "did
+    /// my payment confirm?". Candidate blocks are downloaded (or asked for
+    /// their relevant transactions) in height order, and the first containing
+    /// the transaction is returned as `(height, block hash, transaction)`;
+    /// `None` if no block in `heights` has it. Nothing is delivered or persisted.
+    pub async fn find_transaction(
+        &self,
+        txid: Txid,
+        scripts: &[ScriptBuf],
+        heights: RangeInclusive<u32>,
+    ) -> anyhow::Result<Option<(u32, BlockHash, Transaction)>> {
+        self.phased(self.search_transaction(txid, scripts, heights))
+            .await
+    }
+
+    async fn search_transaction(
+        &self,
+        txid: Txid,
+        scripts: &[ScriptBuf],
+        heights: RangeInclusive<u32>,
+    ) -> anyhow::Result<Option<(u32, BlockHash, Transaction)>> {
+        if self.opaque_blocks {
+            anyhow::bail!("transactions cannot be found in opaque blocks");
+        }
+        if scripts.is_empty() {
+            return Ok(None);
+        }
+        let mut scan = self
+            .adhoc_scan(scripts, heights, self.indexed_matching)
+            .await?;
+        while let Some(hits) = self.next_adhoc_hits(&mut scan).await? {
+            for (h, block) in hits {
+                self.enter(EnginePhase::FetchingBlock { height: h });
+                let relevant = self
+                    .observe(
+                        SourceKind::Blocks,
+                        self.timed(
+                            Phase::BlockFetch,
+                            self.block_source().get_relevant_txs(block, scripts),
+                        )
+                        .await,
+                    )
+                    .with_context(|| format!("get_relevant_txs({block})"))?;
+                let found = match relevant {
+                    Some(txs) => txs.into_iter().find(|tx| tx.compute_txid() == txid),
+                    None => {
+                        let raw = self.fetch_block(block).await?;
+                        let mut found = None;
+                        self.codec.visit_txs(&raw, &mut |tx| {
+                            if tx.compute_txid() == txid {
+                                found = Some(tx);
+                            }
+                            found.is_none()
+                        })?;
+                        found
+                    }
+                };
+                if let Some(tx) = found {
+                    return Ok(Some((h, block, tx)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Find every output paying one of `scripts` in `heights` (clamped to the
+    /// header tip), e.g. the deposit addresses of all of an exchange's customers.
+    ///
+    /// Independent of the wallets' watchlists, like
+    /// [`find_transaction`](Self::find_transaction): nothing is delivered or
+    /// persisted. Filters are matched with [indexed
+    /// matching](Self::with_indexed_matching), whatever the engine's setting,
+    /// and matching blocks are downloaded whole rather than asking the block
+    /// source for the transactions of millions of scripts. Deposits come lowest
+    /// height first, in block order, with their confirmations at the tip seen
+    /// when the scan started.
+    pub async fn scan_deposits(
+        &self,
+        scripts: &[ScriptBuf],
+        heights: RangeInclusive<u32>,
+    ) -> anyhow::Result<Vec<Deposit>> {
+        self.phased(self.collect_deposits(scripts, heights)).await
+    }
+
+    async fn collect_deposits(
+        &self,
+        scripts: &[ScriptBuf],
+        heights: RangeInclusive<u32>,
+    ) -> anyhow::Result<Vec<Deposit>> {
+        if self.opaque_blocks {
+            anyhow::bail!("deposits cannot be found in opaque blocks");
+        }
+        if scripts.is_empty() {
+            return Ok(vec![]);
+        }
+        let wanted: HashSet<&ScriptBuf> = scripts.iter().collect();
+        let mut scan = self.adhoc_scan(scripts, heights, true).await?;
+        let tip = scan.tip;
+        let mut deposits = vec![];
+        while let Some(hits) = self.next_adhoc_hits(&mut scan).await? {
+            for (h, block) in hits {
+                self.enter(EnginePhase::FetchingBlock { height: h });
+                let raw = self.fetch_block(block).await?;
+                self.codec.visit_txs(&raw, &mut |tx| {
+                    let txid = tx.compute_txid();
+                    let coinbase = tx.is_coinbase();
+                    for (vout, txout) in tx.output.into_iter().enumerate() {
+                        if wanted.contains(&txout.script_pubkey) {
+                            deposits.push(Deposit {
+                                outpoint: OutPoint::new(txid, vout as u32),
+                                txout,
+                                height: h,
+                                block,
+                                confirmations: tip - h + 1,
+                                coinbase,
+                            });
+                        }
+                    }
+                    true
+                })?;
+            }
+        }
+        Ok(deposits)
+    }
+
+    /// Start an [`AdhocScan`] of `heights` (clamped to the header tip) for `scripts`.
+    async fn adhoc_scan(
+        &self,
+        scripts: &[ScriptBuf],
+        heights: RangeInclusive<u32>,
+        indexed: bool,
+    ) -> anyhow::Result<AdhocScan> {
+        let golomb = self.golomb_params();
+        golomb.validate_for(self.filter_type)?;
+        let items: Vec<WatchItem> = scripts.iter().cloned().map(WatchItem::new).collect();
+        let tip = self.observe(SourceKind::Headers, self.headers.tip_height().await)?;
+        Ok(AdhocScan {
+            query: QuerySet::new(&items, self.shard_size, indexed),
+            golomb,
+            tip,
+            next: Some(*heights.start()),
+            end: (*heights.end()).min(tip),
+        })
+    }
+
+    /// The blocks of the next filter window of `scan` whose filters match,
+    /// lowest first; `None` once the whole range is scanned.
+    async fn next_adhoc_hits(
+        &self,
+        scan: &mut AdhocScan,
+    ) -> anyhow::Result<Option<Vec<(u32, BlockHash)>>> {
+        let Some(from) = scan.next.filter(|&from| from <= scan.end) else {
+            return Ok(None);
+        };
+        let window = self.windows.lock().unwrap().cfilters.size();
+        let to = from.saturating_add(window - 1).min(scan.end);
+        scan.next = to.checked_add(1);
+        self.enter(EnginePhase::Scanning { height: from });
+        let hashes = self.hashes_in_range(from, to).await?;
+        let filters = self.cfilters_in_range(from, to, &hashes).await?;
+        let mut hits = vec![];
+        for ((h, block), raw) in (from..=to).zip(hashes).zip(&filters) {
+            if self.match_filter(h, block, raw, &scan.query, scan.golomb)? {
+                hits.push((h, block));
+            }
+        }
+        Ok(Some(hits))
+    }
+
+    /// Check the stored state over `range` against the filter source and the
+    /// checkpoints, without changing anything, and report every discrepancy.
+    ///
+    /// Each rolling cfheader the primary store keeps in the range is recomputed
+    /// from the source's filter hash and the stored header below it, and compared
+    /// with any checkpoint there. With the [verdict cache](Self::with_verdict_cache)
+    /// on, each wallet's cached misses in the range (for its current watchlist)
+    /// are fetched and matched again. Heights above the verified cfheaders are
+    /// left out. Source and store failures are errors, not discrepancies.
+    pub async fn audit(&self, range: RangeInclusive<u32>) -> anyhow::Result<AuditReport> {
+        self.phased(self.audit_range(range)).await
+    }
+
+    async fn audit_range(&self, range: RangeInclusive<u32>) -> anyhow::Result<AuditReport> {
+        let filter_type = self.filter_type;
+        let cf_tip = self
+            .store
+            .load_cf_tip_typed(filter_type)
+            .await?
+            .map(|(h, header)| CfHeaderChain::new(filter_type, h, header))
+            .filter(|chain| !chain.before_genesis());
+        // Nothing up to the anchor is in the rolling chain.
+        let anchor = match self.anchor {
+            Some(anchor) => Some(anchor),
+            None => self.store.get_cf_anchor(filter_type).await?,
+        };
+        let from = anchor.map_or(*range.start(), |(h, _)| (*range.start()).max(h + 1));
+        let (from, to) = match cf_tip {
+            Some(tip) => (from, (*range.end()).min(tip.tip_height)),
+            None => (from.max(1), 0),
+        };
+        let mut report = AuditReport {
+            filter_type,
+            range: from..=to,
+            cfheaders_checked: 0,
+            checkpoints_checked: 0,
+            misses_checked: 0,
+            discrepancies: vec![],
+        };
+        if from > to {
+            return Ok(report);
+        }
 
+        // (a) Stored cfheaders against the source's filter hashes.
+        self.enter(EnginePhase::VerifyingHeaders);
+        let mut prev = match (from.checked_sub(1), anchor) {
+            // The genesis filter rolls over the all-zero header.
+            (None, _) => Some(BlockHash::all_zeros()),
+            (Some(below), Some((h, header))) if below == h => Some(header),
+            (Some(below), _) => self.store.load_cf_header_typed(filter_type, below).await?,
+        };
+        let mut start = from;
+        while start <= to {
+            let window = self.windows.lock().unwrap().cfheaders.size();
+            let end = (start + window - 1).min(to);
+            let stop = self.hash_at(end).await?;
             let batch = self
-                .source
-                .get_cfheaders(next, stop_hash)
-                .await
-                .with_context(|| format!("get_cfheaders(start={next}, stop_h={stop_h})"))?;
+                .observe(
+                    SourceKind::Filters,
+                    self.filters()
+                        .get_cfheaders_typed(filter_type, start, stop)
+                        .await,
+                )
+                .with_context(|| format!("get_cfheaders({start}, {stop})"))?;
+            self.dump_cfheaders(filter_type, &batch)?;
+            let short = parse::cfheaders_batch(&batch, start, end)
+                .with_context(|| format!("get_cfheaders({start}, {stop})"))?;
+            if short {
+                anyhow::bail!(
+                    "get_cfheaders({start}, {stop}) returned {} filter hashes, expected {}",
+                    batch.headers.len(),
+                    end - start + 1
+                );
+            }
+            for (height, filter_hash) in (start..=end).zip(&batch.headers) {
+                let stored = self.store.load_cf_header_typed(filter_type, height).await?;
+                match (stored, prev) {
+                    (None, _) => report
+                        .discrepancies
+                        .push(Discrepancy::MissingCfHeader { height }),
+                    (Some(stored), Some(prev)) => {
+                        report.cfheaders_checked += 1;
+                        let source = next_header(filter_hash, prev);
+                        if stored != source {
+                            report.discrepancies.push(Discrepancy::CfHeader {
+                                height,
+                                stored,
+                                source,
+                            });
+                        }
+                    }
+                    // Nothing stored below to chain from; that gap is reported itself.
+                    (Some(_), None) => {}
+                }
+                prev = stored;
+            }
+            start = end + 1;
+        }
 
-            cfchain
-                .apply_batch(batch.start_height, &batch.headers, &self.checkpoints)
-                .with_context(|| format!("apply cfheaders batch @{}", batch.start_height))?;
+        // (b) Checkpoints.
+        for (height, checkpoint) in self
+            .checkpoints
+            .iter()
+            .filter(|(h, _)| (from..=to).contains(h))
+        {
+            if let Some(stored) = self
+                .store
+                .load_cf_header_typed(filter_type, *height)
+                .await?
+            {
+                report.checkpoints_checked += 1;
+                if stored != *checkpoint {
+                    report.discrepancies.push(Discrepancy::Checkpoint {
+                        height: *height,
+                        stored,
+                        checkpoint: *checkpoint,
+                    });
+                }
+            }
+        }
 
-            self.store
-                .save_cf_tip(cfchain.tip_height, cfchain.tip_hash)
-                .await?;
+        // (c) Cached misses, matched again.
+        if self.verdict_cache {
+            let golomb = self.golomb_params();
+            for (wallet, (store, hooks)) in self.all_wallets().enumerate() {
+                let items = hooks.watch_items().await?;
+                if items.is_empty() {
+                    continue;
+                }
+                let watch: Vec<ScriptBuf> = items.iter().map(|i| i.script.clone()).collect();
+                let query = QuerySet::new(&items, self.shard_size, self.indexed_matching);
+                let key = self.miss_key(&items, &watch);
+                for (height, block) in store.load_misses(key, from, to).await? {
+                    self.enter(EnginePhase::Scanning { height });
+                    // Misses of blocks a reorg replaced are never used.
+                    let on_chain = self.hash_at(height).await?;
+                    if on_chain != block {
+                        continue;
+                    }
+                    let raw = self
+                        .observe(
+                            SourceKind::Filters,
+                            self.filters().get_cfilter_typed(filter_type, block).await,
+                        )
+                        .with_context(|| format!("get_cfilter({block})"))?;
+                    report.misses_checked += 1;
+                    match validate_filter(&raw, golomb) {
+                        Err(reason) => report.discrepancies.push(Discrepancy::InvalidFilter {
+                            height,
+                            block,
+                            reason,
+                        }),
+                        Ok(()) => {
+                            if query
+                                .matches(height, block, &raw, golomb)
+                                .with_context(|| format!("filter match @height {height}"))?
+                            {
+                                report.discrepancies.push(Discrepancy::CachedMissMatches {
+                                    wallet,
+                                    height,
+                                    block,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        report.discrepancies.sort_by_key(Discrepancy::height);
+        Ok(report)
+    }
 
-            next = cfchain.tip_height.saturating_add(1);
+    /// Start over: [`wipe`](Store::wipe) the primary store and every added
+    /// wallet's store, so the next run re-verifies cfheaders and rescans from
+    /// each wallet's birth height. Fails while a run is in progress.
+    pub async fn reset(&self) -> anyhow::Result<()> {
+        if self.handle().status().state != RunState::Idle {
+            anyhow::bail!("cannot reset the engine during a run");
         }
+        for (store, _) in self.all_wallets() {
+            store.wipe().await?;
+        }
+        self.prevouts.lock().unwrap().clear();
+        self.balances.lock().unwrap().clear();
+        self.active_source.store(0, Ordering::Relaxed);
+        self.config_checked.store(false, Ordering::Relaxed);
+        self.control.update(|s| {
+            s.cf_tip_height = 0;
+            s.scanned_height = 0;
+        });
+        Ok(())
+    }
 
-        // 4) Scan filters from last_scanned+1 ..= cfheaders tip
-        let last_scanned = self.store.get_last_scanned().await?;
-        let end_h = cfchain.tip_height;
+    /// Deliver every queued match (see [`with_delivery_queue`](Self::with_delivery_queue)),
+    /// lowest height first, and return how many reached the hooks.
+    ///
+    /// Stops at the first failure, leaving that match and later ones queued for
+    /// the next call, except that a match whose block left the header chain
+    /// and can no longer be fetched is dropped (see [`EngineEvent::MatchDropped`]).
+    /// Safe to run concurrently with [`run_to_tip`](Self::run_to_tip).
+    pub async fn deliver_pending(&self) -> anyhow::Result<usize> {
+        self.phased(self.deliver_queue()).await
+    }
 
-        let watch = self.hooks.watchlist().await?;
-        if watch.is_empty() {
-            // Nothing to match; mark up-to-date and exit.
-            self.store.set_last_scanned(end_h).await?;
-            return Ok(());
+    async fn deliver_queue(&self) -> anyhow::Result<usize> {
+        let mut delivered = 0;
+        for (wallet, (store, hooks)) in self.all_wallets().enumerate() {
+            let queue = store.pending_matches().await?;
+            if queue.is_empty() {
+                continue;
+            }
+            let items = hooks.watch_items().await?;
+            let watch: Vec<ScriptBuf> = items.iter().map(|i| i.script.clone()).collect();
+            for (h, block_hash) in queue {
+                let redelivery = self.delivery == Delivery::ExactlyOnce
+                    && store.get_delivered(h).await? == Some(block_hash);
+                if !redelivery {
+                    self.enter(EnginePhase::FetchingBlock { height: h });
+                    let fetched = self
+                        .fetch_match(
+                            wallet,
+                            h,
+                            block_hash,
+                            &items,
+                            &watch,
+                            &mut BlockCache::default(),
+                        )
+                        .await;
+                    let details = match fetched {
+                        Ok(details) => details,
+                        // A block that left the chain may not be served anywhere any more.
+                        Err(_) if !self.on_chain(h, block_hash).await? => {
+                            store.dequeue_match(h).await?;
+                            self.emit(EngineEvent::MatchDropped {
+                                wallet,
+                                height: h,
+                                block: block_hash,
+                            });
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    self.enter(EnginePhase::Delivering { height: h });
+                    if self.utxo_tracking {
+                        let safe = self.fork_safe_height(store, h, block_hash).await?;
+                        self.timed(
+                            Phase::Store,
+                            self.record_utxos(wallet, &details, &watch, safe),
+                        )
+                        .await?;
+                    }
+                    let update = self.prevout_update(&details.txs, &watch);
+                    let details = self.numbered(store, details).await?;
+                    self.timed(Phase::Hooks, hooks.on_match(details))
+                        .await
+                        .with_context(|| format!("on_block_match @height {h}"))?;
+                    self.remember_prevouts(wallet, update);
+                    if self.delivery == Delivery::ExactlyOnce {
+                        store.set_delivered(h, block_hash).await?;
+                    }
+                    delivered += 1;
+                }
+                store.dequeue_match(h).await?;
+            }
         }
+        Ok(delivered)
+    }
 
-        for h in (last_scanned + 1)..=end_h {
-            let block_hash = self.headers.hash_at_height(h).await?;
+    /// Resolve once a scan has queued a match since the last call (or right away
+    /// if one was queued while nobody was waiting).
+    pub async fn wait_for_pending(&self) {
+        self.pending.notified().await
+    }
 
-            // (a) Pull filter and test
-            let raw_filter = self
-                .source
-                .get_cfilter(block_hash)
-                .await
-                .with_context(|| format!("get_cfilter({block_hash})"))?;
+    /// Save `session` in the primary wallet's store for
+    /// [`run_session`](Self::run_session), replacing any session with the same
+    /// id (and its progress).
+    pub async fn add_session(&self, session: &ScanSession) -> anyhow::Result<()> {
+        session.validate()?;
+        self.store
+            .save_scan_session(&session.id, &session.to_string())
+            .await
+    }
 
-            let hit = filter_matches_any(block_hash, &raw_filter, watch.clone().into_iter())
-                .with_context(|| format!("filter match @height {h}"))?;
+    /// Every saved [scan session](crate::session), ordered by id.
+    pub async fn sessions(&self) -> anyhow::Result<Vec<ScanSession>> {
+        let mut sessions = self
+            .store
+            .scan_sessions()
+            .await?
+            .iter()
+            .map(|record| record.parse())
+            .collect::<anyhow::Result<Vec<ScanSession>>>()?;
+        sessions.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(sessions)
+    }
 
-            // (b) On hit, download block and callback
-            if hit {
-                let raw_block = self
-                    .source
-                    .get_block(block_hash)
-                    .await
-                    .with_context(|| format!("get_block({block_hash})"))?;
+    /// The saved scan session `id`, if any.
+    pub async fn session(&self, id: &str) -> anyhow::Result<Option<ScanSession>> {
+        Ok(self.sessions().await?.into_iter().find(|s| s.id == id))
+    }
 
-                let block: Block =
-                    consensus::encode::deserialize(&raw_block).context("block deserialize")?;
-                let txs = block.txdata.clone();
+    /// Forget scan session `id`, finished or not.
+    pub async fn remove_session(&self, id: &str) -> anyhow::Result<()> {
+        self.store.remove_scan_session(id).await
+    }
 
-                self.hooks
-                    .on_block_match(h, block_hash, txs)
-                    .await
-                    .with_context(|| format!("on_block_match @height {h}"))?;
+    /// Advance scan session `id` as far as the verified cfheaders go, matching
+    /// the primary wallet's current watchlist and delivering hits to its hooks
+    /// (or its [delivery queue](Self::with_delivery_queue)) in the session's
+    /// direction. Returns the session with its progress.
+    ///
+    /// Progress is saved after every height, apart from the wallet's own scan
+    /// progress, so sessions and [`run_to_tip`](Self::run_to_tip) can take turns
+    /// or run side by side, and an interrupted session resumes where it
+    /// stopped. Heights above the verified cfheaders are left for a later call
+    /// (a backward session waits until its whole range is verified). A changed
+    /// watchlist starts the session over. Balances from
+    /// [UTXO tracking](Self::with_utxo_tracking) are left to the main scan.
+    pub async fn run_session(&self, id: &str) -> anyhow::Result<ScanSession> {
+        self.phased(self.advance_session(id)).await
+    }
+
+    async fn advance_session(&self, id: &str) -> anyhow::Result<ScanSession> {
+        let mut session = self
+            .session(id)
+            .await?
+            .with_context(|| format!("no scan session {id:?}"))?;
+        let golomb = self.golomb_params();
+        golomb.validate_for(self.filter_type)?;
+        let verified = self
+            .store
+            .load_cf_tip_typed(self.filter_type)
+            .await?
+            .map_or(0, |(h, _)| h);
+
+        let items = self.timed(Phase::Hooks, self.hooks.watch_items()).await?;
+        let watch: Vec<ScriptBuf> = items.iter().map(|i| i.script.clone()).collect();
+        let fingerprint = watchlist_fingerprint(&watch);
+        if session.fingerprint != Some(fingerprint) {
+            if session.fingerprint.is_some() {
+                session.scanned = None;
             }
+            session.fingerprint = Some(fingerprint);
+            self.add_session(&session).await?;
+        }
+        let query = QuerySet::new(&items, self.shard_size, self.indexed_matching);
 
-            // (c) Persist progress every height
-            self.store.set_last_scanned(h).await?;
+        let (start, end) = (*session.range.start(), *session.range.end());
+        // A backward session starts at the top of its range.
+        if session.direction == ScanDirection::Backward && end > verified {
+            return Ok(session);
+        }
+        while let Some(next) = session.next_height() {
+            if next > verified {
+                break;
+            }
+            let window = self.windows.lock().unwrap().cfilters.size();
+            let (from, to) = match session.direction {
+                ScanDirection::Forward => (next, (next + window - 1).min(end).min(verified)),
+                ScanDirection::Backward => (next.saturating_sub(window - 1).max(start), next),
+            };
+            let hashes = self.hashes_in_range(from, to).await?;
+            let filters = self.cfilters_in_range(from, to, &hashes).await?;
+            let mut window: Vec<_> = (from..=to).zip(hashes).zip(filters).collect();
+            if session.direction == ScanDirection::Backward {
+                window.reverse();
+            }
+            for ((h, block_hash), raw) in window {
+                self.enter(EnginePhase::Scanning { height: h });
+                self.control.checkpoint().await;
+                if !items.is_empty() && self.match_filter(h, block_hash, &raw, &query, golomb)? {
+                    self.deliver_session_hit(h, block_hash, &items, &watch)
+                        .await?;
+                }
+                session.scanned = Some(h);
+                self.add_session(&session).await?;
+            }
         }
+        Ok(session)
+    }
 
+    /// Deliver (or queue) a scan session's hit at `h` to the primary wallet.
+    async fn deliver_session_hit(
+        &self,
+        h: u32,
+        block_hash: BlockHash,
+        items: &[WatchItem],
+        watch: &[ScriptBuf],
+    ) -> anyhow::Result<()> {
+        let store: &dyn Store = &self.store;
+        if self.delivery == Delivery::ExactlyOnce
+            && self.timed(Phase::Store, store.get_delivered(h)).await? == Some(block_hash)
+        {
+            return Ok(());
+        }
+        if self.queued {
+            self.timed(Phase::Store, store.enqueue_match(h, block_hash))
+                .await?;
+            self.pending.notify_one();
+            return Ok(());
+        }
+        self.enter(EnginePhase::FetchingBlock { height: h });
+        let details = self
+            .fetch_match(0, h, block_hash, items, watch, &mut BlockCache::default())
+            .await?;
+        self.enter(EnginePhase::Delivering { height: h });
+        let details = self.numbered(store, details).await?;
+        self.timed(Phase::Hooks, self.hooks.on_match(details))
+            .await
+            .with_context(|| format!("on_block_match @height {h}"))?;
+        if self.delivery == Delivery::ExactlyOnce {
+            self.timed(Phase::Store, store.set_delivered(h, block_hash))
+                .await?;
+        }
         Ok(())
     }
+
+    /// With a [network](Self::with_network) set, make sure the header source
+    /// and every wallet's store belong to it (once per engine).
+    async fn check_network(&self) -> anyhow::Result<()> {
+        let Some(network) = self.network else {
+            return Ok(());
+        };
+        if self.network_checked.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let genesis = self.hash_at(0).await?;
+        if genesis != genesis_block(network).block_hash() {
+            return Err(EngineError::WrongNetwork {
+                network,
+                what: "header source",
+                found: format!("genesis {genesis}"),
+            }
+            .into());
+        }
+        for (store, _) in self.all_wallets() {
+            match store.get_network().await? {
+                None => store.set_network(network).await?,
+                Some(n) if n == network => {}
+                Some(n) => {
+                    return Err(EngineError::WrongNetwork {
+                        network,
+                        what: "store",
+                        found: n.to_string(),
+                    }
+                    .into())
+                }
+            }
+        }
+        self.network_checked.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The settings recorded in the stores (see [`EngineConfig`]).
+    pub fn config(&self) -> EngineConfig {
+        EngineConfig {
+            filter_type: self.filter_type,
+            golomb: self.golomb_params(),
+            network: self.network,
+            checkpoints: EngineConfig::checkpoints_hash(&self.checkpoints),
+            utxo_tracking: self.utxo_tracking,
+            delivery: self.delivery,
+            parallel_cfheaders: self.parallel_segments,
+            watchlist_shards: self.shard_size,
+        }
+    }
+
+    /// Compare every wallet's store's configuration record with this engine's
+    /// settings and record them (once per engine).
+    async fn check_config(&self) -> anyhow::Result<()> {
+        if self.config_checked.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let config = self.config();
+        let current = config.to_string();
+        for (store, _) in self.all_wallets() {
+            let Some(record) = store.get_config().await? else {
+                store.set_config(&current).await?;
+                continue;
+            };
+            if record == current {
+                continue;
+            }
+            let stored = parse_record(&record);
+            let mut changed = vec![];
+            for (setting, now) in config.settings() {
+                let Some(&was) = stored.get(setting) else {
+                    if setting == "cfheaders" {
+                        let mut types = vec![self.filter_type];
+                        types.extend(
+                            stored
+                                .get("filter_type")
+                                .and_then(|t| parse_filter_type(t))
+                                .filter(|t| *t != self.filter_type),
+                        );
+                        for filter_type in types {
+                            self.rewind_cfheaders(store, filter_type).await?;
+                        }
+                        changed.push(setting.to_string());
+                    }
+                    continue;
+                };
+                if was == now {
+                    continue;
+                }
+                let incompatible = match setting {
+                    // Stores keep a cfheaders chain per filter type, but not per parameter set.
+                    "golomb" => {
+                        stored.get("filter_type").copied()
+                            == Some(config.filter_type.to_string().as_str())
+                    }
+                    "network" => was != "none" && now != "none",
+                    "utxo_tracking" => config.utxo_tracking && store.get_last_scanned().await? > 0,
+                    "checkpoints" => {
+                        self.check_stored_cfheaders(store).await?;
+                        false
+                    }
+                    _ => false,
+                };
+                if incompatible {
+                    return Err(EngineError::IncompatibleConfig {
+                        setting,
+                        stored: was.to_string(),
+                        current: now,
+                    }
+                    .into());
+                }
+                changed.push(setting.to_string());
+            }
+            store.set_config(&current).await?;
+            if !changed.is_empty() {
+                self.emit(EngineEvent::ConfigChanged { settings: changed });
+            }
+        }
+        self.config_checked.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Rewind `store`'s cfheaders of `filter_type` to its anchor, or to before
+    /// genesis, so the next sync verifies them again.
+    async fn rewind_cfheaders(
+        &self,
+        store: &dyn Store,
+        filter_type: FilterType,
+    ) -> anyhow::Result<()> {
+        if store.load_cf_tip_typed(filter_type).await?.is_none() {
+            return Ok(());
+        }
+        let anchor = match self.anchor.filter(|_| filter_type == self.filter_type) {
+            Some(anchor) => Some(anchor),
+            None => store.get_cf_anchor(filter_type).await?,
+        };
+        let (height, header) = anchor.unwrap_or((0, BlockHash::all_zeros()));
+        store.save_cf_tip_typed(filter_type, height, header).await
+    }
+
+    /// Fail if `store` holds a cfheader that contradicts a checkpoint.
+    async fn check_stored_cfheaders(&self, store: &dyn Store) -> anyhow::Result<()> {
+        // Headers above the tip are left over from a rewind and not trusted.
+        let Some(tip) = store
+            .load_cf_tip_typed(self.filter_type)
+            .await?
+            .map(|(h, header)| CfHeaderChain::new(self.filter_type, h, header))
+            .filter(|chain| !chain.before_genesis())
+            .map(|chain| chain.tip_height)
+        else {
+            return Ok(());
+        };
+        for (height, checkpoint) in self.checkpoints.iter().filter(|(h, _)| *h <= tip) {
+            let stored = store
+                .load_cf_header_typed(self.filter_type, *height)
+                .await?;
+            if let Some(stored) = stored.filter(|s| s != checkpoint) {
+                return Err(EngineError::IncompatibleConfig {
+                    setting: "checkpoints",
+                    stored: format!("cfheader {stored} at height {height}"),
+                    current: format!("checkpoint {checkpoint}"),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    async fn sync(&self) -> anyhow::Result<()> {
+        let Some((verified, chain_tip, behind)) = self.verify_to_tip().await? else {
+            return Ok(());
+        };
+        // Scan whatever was verified, even if the source could not reach the tip.
+        self.scan(verified, chain_tip).await?;
+        match behind {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the configuration, apply pending resets and rescans, and verify
+    /// cfheaders up to the chain tip. Returns the verified height, the chain
+    /// tip and, if the source could not reach it, the error to report after
+    /// scanning; `None` if the sync policy ended the run.
+    async fn verify_to_tip(&self) -> anyhow::Result<Option<(u32, u32, Option<EngineError>)>> {
+        self.enter(EnginePhase::VerifyingHeaders);
+        *self.cross_checked.lock().unwrap() = None;
+        self.golomb_params().validate_for(self.filter_type)?;
+        let checkpoints = Checkpoints(self.checkpoints.clone());
+        match self.network {
+            Some(network) => checkpoints.validate_for(network, self.filter_type),
+            None => checkpoints.validate(),
+        }
+        .map_err(EngineError::InvalidCheckpoints)?;
+        if let Some(size) = self.shard_size {
+            if !(1..=MAX_SHARD_SCRIPTS).contains(&size) {
+                anyhow::bail!("watchlist shard size {size} is outside 1..={MAX_SHARD_SCRIPTS}");
+            }
+        }
+        if self.opaque_blocks && self.utxo_tracking {
+            anyhow::bail!(
+                "UTXO tracking needs decoded blocks; it cannot be used with opaque blocks"
+            );
+        }
+        if self.watchtower && self.queued {
+            anyhow::bail!("watchtower mode delivers hits during the scan; it cannot be used with a delivery queue");
+        }
+        self.check_network().await?;
+        if self.assume_fresh.load(Ordering::Relaxed) {
+            for (store, _) in self.all_wallets() {
+                store.wipe().await?;
+            }
+            self.assume_fresh.store(false, Ordering::Relaxed);
+        }
+        self.check_config().await?;
+        if let Some(from) = self.control.take_rescan() {
+            for (store, _) in self.all_wallets() {
+                if store.get_last_scanned().await? >= from {
+                    store.set_last_scanned(from.saturating_sub(1)).await?;
+                }
+            }
+        }
+
+        let mut cf_tip = self.store.load_cf_tip_typed(self.filter_type).await?;
+        self.resolve_anchor(cf_tip).await?;
+        if let Some(anchor) = self.anchor() {
+            if cf_tip.is_none_or(|(h, _)| h < anchor.0) {
+                cf_tip = Some(anchor);
+            }
+        }
+        let mut cfchain = CfHeaderChain::new_from_store(self.filter_type, cf_tip);
+
+        let chain_tip = self.observe(SourceKind::Headers, self.headers.tip_height().await)?;
+        self.control.saw_tip(chain_tip);
+        self.roll_back_stale(&mut cfchain, chain_tip).await?;
+        self.control
+            .update(|s| s.cf_tip_height = cfchain.tip_height);
+
+        let mut retries = self.reanchor_retries;
+        let phase = loop {
+            match self.sync_cfheaders(&mut cfchain, chain_tip).await {
+                Err(e) if retries > 0 => match e.downcast_ref::<EngineError>() {
+                    Some(EngineError::CheckpointMismatch(m)) => {
+                        retries -= 1;
+                        self.reanchor(&mut cfchain, m.height).await?;
+                    }
+                    _ => return Err(e),
+                },
+                res => break res?,
+            }
+        };
+        Ok(match phase {
+            CfPhase::Stopped => None,
+            CfPhase::Synced => Some((cfchain.tip_height, chain_tip, None)),
+            CfPhase::SourceBehind(e) => Some((cfchain.tip_height, chain_tip, Some(e))),
+        })
+    }
+
+    async fn sync_parallel<G: FilterSource>(
+        &self,
+        ranges: &[RangeInclusive<u32>],
+        workers: &[G],
+    ) -> anyhow::Result<()> {
+        let Some((verified, _, behind)) = self.verify_to_tip().await? else {
+            return Ok(());
+        };
+        if let Some(r) = ranges.iter().find(|r| *r.end() > verified) {
+            return Err(match behind {
+                Some(e) => e.into(),
+                None => anyhow::anyhow!("scan range {r:?} is past the chain tip {verified}"),
+            });
+        }
+        let lanes = self.lanes(verified).await?;
+        if lanes.is_empty() {
+            return Ok(());
+        }
+        self.enter(EnginePhase::Scanning {
+            height: *ranges[0].start(),
+        });
+
+        let queue = Mutex::new(ranges.iter().cloned().collect::<VecDeque<_>>());
+        futures_util::future::try_join_all(
+            workers
+                .iter()
+                .map(|source| self.scan_worker(source, &queue, &lanes)),
+        )
+        .await?;
+
+        // Merge: move each wallet's progress over the ranges that continue it.
+        for lane in &lanes {
+            let mut progress = lane.last_scanned;
+            for r in ranges {
+                if *r.start() <= progress + 1 && *r.end() > progress {
+                    progress = *r.end();
+                }
+            }
+            if progress > lane.last_scanned {
+                let block = self.hash_at(progress).await?;
+                self.timed(
+                    Phase::Store,
+                    lane.store.set_last_scanned_block(progress, block),
+                )
+                .await?;
+            }
+            lane.store.clear_range_progress().await?;
+        }
+        if self.queued {
+            self.pending.notify_one();
+        } else {
+            self.deliver_pending().await?;
+        }
+        Ok(())
+    }
+
+    /// Roll back wallets whose last scanned block left the header chain (a reorg
+    /// while offline or between runs) to the fork point, and `cfchain` with them
+    /// or on its own if the block at its tip left the chain.
+    async fn roll_back_stale(
+        &self,
+        cfchain: &mut CfHeaderChain,
+        chain_tip: u32,
+    ) -> anyhow::Result<()> {
+        let mut lowest_fork: Option<u32> = None;
+        for (wallet, (store, hooks)) in self.all_wallets().enumerate() {
+            let Some((stale_height, stale)) = store.get_last_scanned_block().await? else {
+                continue;
+            };
+            let (fork_height, fork) = self
+                .find_fork(stale_height, stale, chain_tip)
+                .await
+                .with_context(|| {
+                    format!("find fork below scanned block {stale} @{stale_height}")
+                })?;
+            if fork_height == stale_height {
+                continue;
+            }
+            // A watchtower still owes the hooks its breaches, reorged out or not.
+            for (h, block) in store.pending_matches().await? {
+                if h > fork_height && !self.watchtower {
+                    store.dequeue_match(h).await?;
+                    self.emit(EngineEvent::MatchDropped {
+                        wallet,
+                        height: h,
+                        block,
+                    });
+                }
+            }
+            store.set_last_scanned_block(fork_height, fork).await?;
+            if self.utxo_tracking {
+                store.rollback_utxos(fork_height).await?;
+                self.balances.lock().unwrap().clear();
+            }
+            // Outputs remembered from the stale branch may not exist on the new one.
+            self.prevouts.lock().unwrap().clear();
+            self.timed(Phase::Hooks, hooks.on_rollback(fork_height))
+                .await
+                .with_context(|| format!("on_rollback @height {fork_height}"))?;
+            self.emit(EngineEvent::RolledBack {
+                stale_height,
+                fork_height,
+            });
+            lowest_fork = Some(lowest_fork.map_or(fork_height, |f| f.min(fork_height)));
+        }
+
+        // cfheaders verified past every wallet's progress can be stale on their own.
+        let cf_tip_block = self.store.get_cf_tip_block(self.filter_type).await?;
+        if let Some((stale_height, stale)) = cf_tip_block.filter(|(h, _)| *h == cfchain.tip_height)
+        {
+            let fork_height = match self.find_fork(stale_height, stale, chain_tip).await {
+                Ok((fork_height, _)) => fork_height,
+                // The stale branch may be pruned everywhere: fall back to the
+                // scan progress, whose blocks were just checked.
+                Err(_) => {
+                    let mut progress = stale_height.saturating_sub(1);
+                    for (store, _) in self.all_wallets() {
+                        progress = progress.min(store.get_last_scanned().await?);
+                    }
+                    progress
+                }
+            };
+            if fork_height < stale_height {
+                lowest_fork = Some(lowest_fork.map_or(fork_height, |f| f.min(fork_height)));
+            }
+        }
+
+        // cfheaders above the fork were built from the stale branch's filters.
+        if let Some(fork_height) = lowest_fork.filter(|f| cfchain.tip_height > *f) {
+            let header = match self
+                .store
+                .load_cf_header_typed(self.filter_type, fork_height)
+                .await?
+            {
+                Some(header) => (fork_height, header),
+                None => self.trusted_header(fork_height),
+            };
+            *cfchain = CfHeaderChain::new_from_store(self.filter_type, Some(header));
+            self.store
+                .save_cf_tip_typed(self.filter_type, header.0, header.1)
+                .await?;
+            if header.0 == fork_height {
+                let fork = self.hash_at(fork_height).await?;
+                self.store
+                    .set_cf_tip_block(self.filter_type, fork_height, fork)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `block` is still the header chain's block at `height`.
+    async fn on_chain(&self, height: u32, block: BlockHash) -> anyhow::Result<bool> {
+        let tip = self.observe(SourceKind::Headers, self.headers.tip_height().await)?;
+        Ok(height <= tip && self.hash_at(height).await? == block)
+    }
+
+    /// The highest height `block` at `height` shares with the header chain:
+    /// `height` while it is on it, else the fork point, or `store`'s scan
+    /// progress below it if the stale branch cannot be walked.
+    async fn fork_safe_height(
+        &self,
+        store: &dyn Store,
+        height: u32,
+        block: BlockHash,
+    ) -> anyhow::Result<u32> {
+        let tip = self.observe(SourceKind::Headers, self.headers.tip_height().await)?;
+        match self.find_fork(height, block, tip).await {
+            Ok((fork_height, _)) => Ok(fork_height),
+            Err(_) => Ok(store
+                .get_last_scanned()
+                .await?
+                .min(height.saturating_sub(1))),
+        }
+    }
+
+    /// Walk back from `block` at `height` along its parents until a block the
+    /// header chain also has, and return that `(height, hash)`.
+    async fn find_fork(
+        &self,
+        mut height: u32,
+        mut block: BlockHash,
+        chain_tip: u32,
+    ) -> anyhow::Result<(u32, BlockHash)> {
+        loop {
+            if height <= chain_tip && self.hash_at(height).await? == block {
+                return Ok((height, block));
+            }
+            if height == 0 {
+                anyhow::bail!("scanned blocks share no history with the header chain");
+            }
+            let raw = self.fetch_block(block).await?;
+            block = self.codec.prev_block_hash(&raw)?;
+            height -= 1;
+        }
+    }
+
+    /// Rewind `cfchain` (and wallets that scanned past it) to the last trusted
+    /// checkpoint below `bad_height`, then move on to the next filter source.
+    async fn reanchor(&self, cfchain: &mut CfHeaderChain, bad_height: u32) -> anyhow::Result<()> {
+        let (height, header) = self.trusted_header(bad_height.saturating_sub(1));
+        if cfchain.tip_height > height {
+            *cfchain = CfHeaderChain::new_from_store(self.filter_type, Some((height, header)));
+            self.store
+                .save_cf_tip_typed(self.filter_type, height, header)
+                .await?;
+            self.control.update(|s| s.cf_tip_height = height);
+        }
+        for (store, _) in self.all_wallets() {
+            if store.get_last_scanned().await? > height {
+                store.set_last_scanned(height).await?;
+            }
+        }
+
+        if !self.fallbacks.is_empty() {
+            let next =
+                (self.active_source.load(Ordering::Relaxed) + 1) % (1 + self.fallbacks.len());
+            self.active_source.store(next, Ordering::Relaxed);
+        }
+        self.emit(EngineEvent::Reanchored {
+            height,
+            source: self.filters().label(),
+        });
+        Ok(())
+    }
+
+    /// The cfheaders chain's anchor: the [explicit](Self::with_cfheader_anchor)
+    /// one, else the one the primary store recorded.
+    fn anchor(&self) -> Option<(u32, BlockHash)> {
+        self.anchor.or(*self.store_anchor.lock().unwrap())
+    }
+
+    /// Without an explicit anchor, load the one the primary store recorded or,
+    /// before any cfheaders are stored, anchor at the highest checkpoint below
+    /// every wallet's birth height and record that in the store.
+    async fn resolve_anchor(&self, cf_tip: Option<(u32, BlockHash)>) -> anyhow::Result<()> {
+        if self.anchor.is_some() {
+            return Ok(());
+        }
+        let mut anchor = self.store.get_cf_anchor(self.filter_type).await?;
+        if anchor.is_none() && cf_tip.is_none() {
+            let mut births = vec![];
+            for (store, _) in self.all_wallets() {
+                births.push(store.get_birth_height().await?);
+            }
+            // Every wallet needs a birth height, and the birth block itself is scanned.
+            if let Some(birth) = births.into_iter().collect::<Option<Vec<u32>>>() {
+                let below = birth.into_iter().min().unwrap_or(0);
+                anchor = self
+                    .checkpoints
+                    .iter()
+                    .filter(|(h, _)| *h < below)
+                    .max_by_key(|(h, _)| *h)
+                    .copied();
+            }
+            if let Some((height, header)) = anchor {
+                self.store
+                    .set_cf_anchor(self.filter_type, height, header)
+                    .await?;
+            }
+        }
+        *self.store_anchor.lock().unwrap() = anchor;
+        Ok(())
+    }
+
+    /// The highest rolling header trusted without verification at or below
+    /// `max_height`: a checkpoint, the [anchor](Self::with_cfheader_anchor), or
+    /// else the all-zero header the genesis filter rolls over (never below the
+    /// anchor).
+    fn trusted_header(&self, max_height: u32) -> (u32, BlockHash) {
+        let anchor = self.anchor();
+        let floor = anchor.unwrap_or((0, BlockHash::all_zeros()));
+        self.checkpoints
+            .iter()
+            .chain(anchor.as_ref())
+            .filter(|(h, _)| (floor.0..=max_height).contains(h))
+            .max_by_key(|(h, _)| *h)
+            .copied()
+            .unwrap_or(floor)
+    }
+
+    /// Verify and persist cfheaders from the stored tip up to `chain_tip`.
+    async fn sync_cfheaders(
+        &self,
+        cfchain: &mut CfHeaderChain,
+        chain_tip: u32,
+    ) -> anyhow::Result<CfPhase> {
+        if let Some(segments) = self.parallel_segments {
+            if !self
+                .sync_cfheader_segments(cfchain, chain_tip, segments)
+                .await?
+            {
+                return Ok(CfPhase::Stopped);
+            }
+        }
+
+        let mut waited = Duration::ZERO;
+        let mut next = cfchain.next_height();
+        while next <= chain_tip {
+            if !self
+                .safe_point(SyncPhase::CfHeaders, next, chain_tip, 0)
+                .await?
+            {
+                return Ok(CfPhase::Stopped);
+            }
+
+            let window = self.windows.lock().unwrap().cfheaders.size();
+            let stop_h = (next + window - 1).min(chain_tip);
+            let stop_hash = self.hash_at(stop_h).await?;
+            self.cross_check(stop_h, stop_hash).await?;
+
+            let started = crate::rt::now();
+            let batch = self.observe(
+                SourceKind::Filters,
+                self.timed(
+                    Phase::CfHeadersFetch,
+                    self.filters()
+                        .get_cfheaders_typed(self.filter_type, next, stop_hash),
+                )
+                .await,
+            );
+            self.windows.lock().unwrap().cfheaders.record(started);
+            let batch = match batch {
+                Ok(batch) => batch,
+                // A source that has not indexed `stop_h` yet typically just fails;
+                // ask it how far it got to tell "behind" from "broken".
+                Err(e) => {
+                    match self.observe(
+                        SourceKind::Filters,
+                        self.filters().filter_tip_height().await,
+                    ) {
+                        Ok(Some(source_tip)) if source_tip < stop_h => CfHeadersBatch {
+                            start_height: next,
+                            headers: vec![],
+                        },
+                        _ => {
+                            return Err(
+                                e.context(format!("get_cfheaders(start={next}, stop_h={stop_h})"))
+                            )
+                        }
+                    }
+                }
+            };
+            self.dump_cfheaders(self.filter_type, &batch)?;
+            let short = parse::cfheaders_batch(&batch, next, stop_h)
+                .with_context(|| format!("get_cfheaders(start={next}, stop_h={stop_h})"))?;
+
+            let mut start = batch.start_height;
+            for chunk in batch.headers.chunks(CFHEADERS_PERSIST_CHUNK) {
+                let verifying = crate::rt::now();
+                let rolled = cfchain.apply_batch(start, chunk, &self.checkpoints);
+                self.control.spent(Phase::CfHeadersVerify, verifying);
+                let rolled = rolled
+                    .map_err(|e| self.on_apply_error(e))
+                    .with_context(|| format!("apply cfheaders batch @{start}"))?;
+                self.save_cf_headers(cfchain, start, &rolled).await?;
+                self.control
+                    .update(|s| s.cf_tip_height = cfchain.tip_height);
+                start = cfchain.next_height();
+            }
+
+            next = cfchain.next_height();
+
+            if short {
+                match self.source_wait {
+                    Some((deadline, poll)) if waited < deadline => {
+                        crate::rt::sleep(poll).await;
+                        waited += poll;
+                    }
+                    _ => {
+                        return Ok(CfPhase::SourceBehind(EngineError::SourceBehindTip {
+                            source_height: cfchain.tip_height,
+                            chain_height: chain_tip,
+                        }))
+                    }
+                }
+            }
+        }
+        Ok(CfPhase::Synced)
+    }
+
+    /// Advance `cfchain` over whole `cfcheckpt` segments, `parallel` downloads at a
+    /// time. Returns `false` if the sync policy ended the run; anything past the
+    /// last checkpoint is left to the sequential loop.
+    async fn sync_cfheader_segments(
+        &self,
+        cfchain: &mut CfHeaderChain,
+        chain_tip: u32,
+        parallel: usize,
+    ) -> anyhow::Result<bool> {
+        if chain_tip < cfchain.tip_height.saturating_add(CFCHECKPT_INTERVAL) {
+            return Ok(true);
+        }
+        let stop_hash = self.hash_at(chain_tip).await?;
+        let Ok(checkpoints) = self.observe(
+            SourceKind::Filters,
+            self.filters()
+                .get_cfcheckpt_typed(self.filter_type, stop_hash)
+                .await,
+        ) else {
+            // Not fatal: the sequential loop reports real source problems.
+            return Ok(true);
+        };
+
+        // (start, end, expected rolling header at end), each starting above the previous end.
+        let mut segments = Vec::new();
+        let mut next = cfchain.next_height();
+        for (i, cp) in checkpoints.iter().enumerate() {
+            let end = (i as u32 + 1) * CFCHECKPT_INTERVAL;
+            if end > chain_tip {
+                break;
+            }
+            if end == cfchain.tip_height && *cp != cfchain.tip_hash {
+                anyhow::bail!(
+                    "{} cfcheckpt @{end} disagrees with the verified cfheaders chain",
+                    self.filter_type
+                );
+            }
+            if end >= next {
+                segments.push((next, end, *cp));
+                next = end + 1;
+            }
+        }
+
+        for group in segments.chunks(parallel) {
+            let (first, ..) = group[0];
+            if !self
+                .safe_point(SyncPhase::CfHeaders, first, chain_tip, 0)
+                .await?
+            {
+                return Ok(false);
+            }
+            let mut stops = Vec::with_capacity(group.len());
+            for (_, end, _) in group {
+                stops.push(self.hash_at(*end).await?);
+            }
+            let started = crate::rt::now();
+            let batches = self.observe(
+                SourceKind::Filters,
+                self.timed(
+                    Phase::CfHeadersFetch,
+                    futures_util::future::try_join_all(group.iter().zip(&stops).map(
+                        |((start, _, _), stop)| {
+                            self.filters()
+                                .get_cfheaders_typed(self.filter_type, *start, *stop)
+                        },
+                    )),
+                )
+                .await,
+            );
+            self.windows.lock().unwrap().cfheaders.record(started);
+            let batches = batches
+                .with_context(|| format!("get_cfheaders for segments from {first} (parallel)"))?;
+
+            // Stitch in order; each segment must land exactly on its checkpoint.
+            for ((start, end, expected), batch) in group.iter().zip(batches) {
+                self.dump_cfheaders(self.filter_type, &batch)?;
+                let short = parse::cfheaders_batch(&batch, *start, *end)
+                    .with_context(|| format!("get_cfheaders({start}..={end})"))?;
+                if short {
+                    anyhow::bail!(
+                        "get_cfheaders({start}..={end}) returned {} headers",
+                        batch.headers.len()
+                    );
+                }
+                let verifying = crate::rt::now();
+                let rolled = cfchain.apply_batch(*start, &batch.headers, &self.checkpoints);
+                self.control.spent(Phase::CfHeadersVerify, verifying);
+                let rolled = rolled
+                    .map_err(|e| self.on_apply_error(e))
+                    .with_context(|| format!("apply cfheaders segment @{start}"))?;
+                if cfchain.tip_hash != *expected {
+                    let err = self.on_apply_error(
+                        EngineError::CheckpointMismatch(CheckpointMismatch {
+                            filter_type: self.filter_type,
+                            height: *end,
+                            expected: *expected,
+                            computed: cfchain.tip_hash,
+                            batch_start: *start,
+                            source: None,
+                        })
+                        .into(),
+                    );
+                    return Err(err.context(format!(
+                        "cfheaders segment {start}..={end} does not reach its cfcheckpt"
+                    )));
+                }
+                self.save_cf_headers(cfchain, *start, &rolled).await?;
+                self.control
+                    .update(|s| s.cf_tip_height = cfchain.tip_height);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Persist verified cfheaders from `start` and the new tip of `cfchain`,
+    /// with the block at the tip.
+    async fn save_cf_headers(
+        &self,
+        cfchain: &CfHeaderChain,
+        start: u32,
+        rolled: &[BlockHash],
+    ) -> anyhow::Result<()> {
+        let block = self.hash_at(cfchain.tip_height).await?;
+        self.timed(Phase::Store, async {
+            self.store
+                .save_cf_headers_typed(cfchain.filter_type, start, rolled)
+                .await?;
+            self.store
+                .save_cf_tip_typed(cfchain.filter_type, cfchain.tip_height, cfchain.tip_hash)
+                .await?;
+            self.store
+                .set_cf_tip_block(cfchain.filter_type, cfchain.tip_height, block)
+                .await
+        })
+        .await
+    }
+
+    /// Scan filters for every wallet up to the verified cfheaders tip `end_h`.
+    async fn scan(&self, end_h: u32, chain_tip: u32) -> anyhow::Result<()> {
+        let golomb = self.golomb_params();
+        if self.watchtower {
+            // Breaches left over from a failed or interrupted run come first.
+            self.deliver_pending().await?;
+        }
+        let mut lanes = self.lanes(end_h).await?;
+
+        // Scan from the least-advanced wallet's last_scanned+1 ..= cfheaders tip
+        let Some(start_h) = lanes.iter().map(|l| l.last_scanned).min() else {
+            self.control.update(|s| s.scanned_height = end_h);
+            return Ok(());
+        };
+        self.control.update(|s| s.scanned_height = start_h);
+
+        let mut prefetched = VecDeque::new();
+        for h in (start_h + 1)..=end_h {
+            self.enter(EnginePhase::Scanning { height: h });
+            if !self
+                .safe_point(SyncPhase::Scan, h, chain_tip, h - start_h - 1)
+                .await?
+            {
+                break;
+            }
+
+            // (a) Pull filter once and test it against every wallet still behind `h`
+            if prefetched.is_empty() {
+                self.save_misses(&mut lanes).await?;
+                let window = self.windows.lock().unwrap().cfilters.size();
+                prefetched = self
+                    .fetch_filters(h, (h + window - 1).min(end_h), &mut lanes)
+                    .await?;
+            }
+            let (block_hash, raw_filter) =
+                prefetched.pop_front().expect("window covers at least `h`");
+            if let Some(kind) = raw_filter.as_deref().and_then(empty_filter) {
+                self.emit(EngineEvent::EmptyFilter {
+                    height: h,
+                    block: block_hash,
+                    kind,
+                    source: self.filters().label(),
+                });
+            }
+
+            let mut block = BlockCache {
+                block: None,
+                wallets_left: lanes.iter().filter(|l| l.last_scanned < h).count(),
+            };
+            for lane in lanes.iter_mut().filter(|l| l.last_scanned < h) {
+                block.wallets_left -= 1;
+                let hit = match &raw_filter {
+                    Some(raw) if !lane.known_misses.contains(&h) => {
+                        let hit = self.match_filter(h, block_hash, raw, &lane.query, golomb)?;
+                        if !hit && !raw.is_empty() && lane.miss_key.is_some() {
+                            lane.new_misses.push((h, block_hash));
+                        }
+                        hit
+                    }
+                    // A cached miss: the filter was not even fetched for it.
+                    _ => false,
+                };
+
+                let redelivery = hit
+                    && self.delivery == Delivery::ExactlyOnce
+                    && self
+                        .timed(Phase::Store, lane.store.get_delivered(h))
+                        .await?
+                        == Some(block_hash);
+
+                // (b) On hit, ask for the relevant txs, else download the block (once per height)
+                if hit && !redelivery && self.queued {
+                    self.timed(Phase::Store, lane.store.enqueue_match(h, block_hash))
+                        .await?;
+                    self.pending.notify_one();
+                } else if hit && !redelivery {
+                    if self.watchtower {
+                        self.timed(Phase::Store, lane.store.enqueue_match(h, block_hash))
+                            .await?;
+                    }
+                    self.enter(EnginePhase::FetchingBlock { height: h });
+                    let details = self
+                        .fetch_match(
+                            lane.wallet,
+                            h,
+                            block_hash,
+                            &lane.items,
+                            &lane.watch,
+                            &mut block,
+                        )
+                        .await?;
+                    self.enter(EnginePhase::Delivering { height: h });
+                    if self.utxo_tracking {
+                        self.timed(
+                            Phase::Store,
+                            self.record_utxos(lane.wallet, &details, &lane.watch, h),
+                        )
+                        .await?;
+                    }
+                    let update = self.prevout_update(&details.txs, &lane.watch);
+                    let details = self.numbered(lane.store, details).await?;
+                    self.timed(Phase::Hooks, lane.hooks.on_match(details))
+                        .await
+                        .with_context(|| format!("on_block_match @height {h}"))?;
+                    self.remember_prevouts(lane.wallet, update);
+                    if self.delivery == Delivery::ExactlyOnce {
+                        self.timed(Phase::Store, lane.store.set_delivered(h, block_hash))
+                            .await?;
+                    }
+                    if self.watchtower {
+                        self.timed(Phase::Store, lane.store.dequeue_match(h))
+                            .await?;
+                    }
+                }
+
+                // (c) Persist progress every height
+                self.timed(
+                    Phase::Store,
+                    lane.store.set_last_scanned_block(h, block_hash),
+                )
+                .await?;
+                lane.last_scanned = h;
+            }
+            self.control.update(|s| s.scanned_height = h);
+        }
+        self.save_misses(&mut lanes).await?;
+
+        Ok(())
+    }
+
+    /// The wallets taking part in a scan up to `end_h`: those with an empty
+    /// watchlist are marked scanned up to it instead (and with
+    /// [auto rescan](Self::with_auto_rescan), added scripts move progress back).
+    async fn lanes(&self, end_h: u32) -> anyhow::Result<Vec<Lane<'_>>> {
+        let mut lanes = Vec::with_capacity(1 + self.wallets.len());
+        for (wallet, (store, hooks)) in self.all_wallets().enumerate() {
+            let mut last_scanned = self.timed(Phase::Store, store.get_last_scanned()).await?;
+            let items = self.timed(Phase::Hooks, hooks.watch_items()).await?;
+            if items.is_empty() {
+                // Nothing to match; mark up-to-date.
+                let end_hash = self.hash_at(end_h).await?;
+                store.set_last_scanned_block(end_h, end_hash).await?;
+                continue;
+            }
+            if let Some((anchor, _)) = self.anchor() {
+                last_scanned = last_scanned.max(anchor);
+            }
+            let watch: Vec<ScriptBuf> = items.iter().map(|i| i.script.clone()).collect();
+            if self.auto_rescan {
+                last_scanned = self
+                    .timed(
+                        Phase::Store,
+                        self.rescan_added(store, &items, &watch, last_scanned),
+                    )
+                    .await?;
+            }
+            lanes.push(Lane {
+                wallet,
+                store,
+                hooks,
+                query: QuerySet::new(&items, self.shard_size, self.indexed_matching),
+                miss_key: self.verdict_cache.then(|| self.miss_key(&items, &watch)),
+                known_misses: HashSet::new(),
+                new_misses: vec![],
+                watch,
+                items,
+                last_scanned,
+            });
+        }
+        Ok(lanes)
+    }
+
+    /// Fetch the filters for `from..=to` in one window, adapting the window size.
+    ///
+    /// Loads the lanes' cached misses for the window first; heights every lane
+    /// behind them already knows to miss are not fetched (`None`).
+    async fn fetch_filters(
+        &self,
+        from: u32,
+        to: u32,
+        lanes: &mut [Lane<'_>],
+    ) -> anyhow::Result<VecDeque<(BlockHash, Option<Vec<u8>>)>> {
+        let hashes = self.hashes_in_range(from, to).await?;
+        for lane in lanes.iter_mut() {
+            lane.known_misses.clear();
+            let Some(key) = lane.miss_key else { continue };
+            let misses = self
+                .timed(Phase::Store, lane.store.load_misses(key, from, to))
+                .await?;
+            // Only misses of blocks still on the header chain count.
+            lane.known_misses
+                .extend(misses.into_iter().filter_map(|(h, block)| {
+                    let at = h.checked_sub(from).and_then(|i| hashes.get(i as usize));
+                    (at == Some(&block)).then_some(h)
+                }));
+        }
+        let needed: Vec<bool> = (from..=to)
+            .map(|h| {
+                lanes
+                    .iter()
+                    .any(|l| l.last_scanned < h && !l.known_misses.contains(&h))
+            })
+            .collect();
+        let wanted: Vec<BlockHash> = hashes
+            .iter()
+            .zip(&needed)
+            .filter_map(|(block, needed)| needed.then_some(*block))
+            .collect();
+
+        let mut filters = Vec::new();
+        if !wanted.is_empty() {
+            let started = crate::rt::now();
+            filters = self
+                .observe(
+                    SourceKind::Filters,
+                    self.timed(
+                        Phase::FilterFetch,
+                        self.filters().get_cfilter_range(self.filter_type, &wanted),
+                    )
+                    .await,
+                )
+                .with_context(|| format!("get_cfilter_range({from}..={to})"))?;
+            self.windows.lock().unwrap().cfilters.record(started);
+            if filters.len() != wanted.len() {
+                anyhow::bail!(
+                    "get_cfilter_range({from}..={to}) returned {} filters, expected {}",
+                    filters.len(),
+                    wanted.len()
+                );
+            }
+        }
+        let mut filters = filters.into_iter();
+        Ok(hashes
+            .into_iter()
+            .zip(needed)
+            .map(|(block, needed)| (block, needed.then(|| filters.next().expect("counted"))))
+            .collect())
+    }
+
+    /// One [parallel scan](Self::run_parallel) worker: take ranges off `queue`
+    /// until it is empty, match their filters from `source` against every lane
+    /// still behind them and queue the hits, saving each range's progress.
+    async fn scan_worker(
+        &self,
+        source: &dyn FilterSource,
+        queue: &Mutex<VecDeque<RangeInclusive<u32>>>,
+        lanes: &[Lane<'_>],
+    ) -> anyhow::Result<()> {
+        let golomb = self.golomb_params();
+        loop {
+            let Some(range) = queue.lock().unwrap().pop_front() else {
+                return Ok(());
+            };
+            let (start, end) = (*range.start(), *range.end());
+            let mut done = end;
+            for lane in lanes {
+                let saved = self
+                    .timed(Phase::Store, lane.store.get_range_progress(start, end))
+                    .await?;
+                done = done.min(saved.unwrap_or(start - 1));
+            }
+            while done < end {
+                self.control.checkpoint().await;
+                let window = self.windows.lock().unwrap().cfilters.size();
+                let (from, to) = (done + 1, (done + window).min(end));
+                let hashes = self.hashes_in_range(from, to).await?;
+                let filters = self
+                    .timed(
+                        Phase::FilterFetch,
+                        source.get_cfilter_range(self.filter_type, &hashes),
+                    )
+                    .await
+                    .with_context(|| format!("get_cfilter_range({from}..={to})"))?;
+                if filters.len() != hashes.len() {
+                    anyhow::bail!(
+                        "get_cfilter_range({from}..={to}) returned {} filters, expected {}",
+                        filters.len(),
+                        hashes.len()
+                    );
+                }
+                for ((h, block), raw) in (from..=to).zip(hashes).zip(&filters) {
+                    for lane in lanes.iter().filter(|l| l.last_scanned < h) {
+                        if self.match_filter(h, block, raw, &lane.query, golomb)? {
+                            self.timed(Phase::Store, lane.store.enqueue_match(h, block))
+                                .await?;
+                        }
+                    }
+                }
+                for lane in lanes {
+                    self.timed(Phase::Store, lane.store.set_range_progress(start, end, to))
+                        .await?;
+                }
+                done = to;
+            }
+        }
+    }
+
+    /// Save the misses the lanes saw since the last call.
+    async fn save_misses(&self, lanes: &mut [Lane<'_>]) -> anyhow::Result<()> {
+        for lane in lanes.iter_mut() {
+            let Some(key) = lane.miss_key else { continue };
+            if !lane.new_misses.is_empty() {
+                self.timed(Phase::Store, lane.store.save_misses(key, &lane.new_misses))
+                    .await?;
+                lane.new_misses.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Compare `watch` with the watchlist `store` saved and, if scripts were
+    /// added that could have been paid at or below `last_scanned`, move the
+    /// wallet's progress back below the earliest such height. Saves `watch`
+    /// and returns the progress to scan from.
+    async fn rescan_added(
+        &self,
+        store: &dyn Store,
+        items: &[WatchItem],
+        watch: &[ScriptBuf],
+        last_scanned: u32,
+    ) -> anyhow::Result<u32> {
+        let fingerprint = watchlist_fingerprint(watch);
+        match store.get_watchlist_fingerprint().await? {
+            Some(saved) if saved == fingerprint => return Ok(last_scanned),
+            // First run with a saved watchlist: the scan from birth covers it.
+            None => {
+                store.set_watchlist(fingerprint, watch).await?;
+                return Ok(last_scanned);
+            }
+            Some(_) => {}
+        }
+
+        let old: HashSet<ScriptBuf> = store.watched_scripts().await?.into_iter().collect();
+        let new: HashSet<&ScriptBuf> = watch.iter().collect();
+        let added: Vec<&WatchItem> = items.iter().filter(|i| !old.contains(&i.script)).collect();
+        let birth = store.get_birth_height().await?.unwrap_or(0);
+        let rescan_from = added
+            .iter()
+            .map(|i| i.birth_height.unwrap_or(birth).max(1))
+            .min()
+            .filter(|&from| from <= last_scanned);
+
+        let mut progress = last_scanned;
+        if let Some(from) = rescan_from {
+            progress = from - 1;
+            store.set_last_scanned(progress).await?;
+        }
+        store.set_watchlist(fingerprint, watch).await?;
+        let added = added
+            .iter()
+            .map(|i| &i.script)
+            .collect::<HashSet<_>>()
+            .len();
+        self.emit(EngineEvent::WatchlistChanged {
+            added,
+            removed: old.iter().filter(|s| !new.contains(s)).count(),
+            rescan_from,
+        });
+        Ok(progress)
+    }
+
+    /// Key of the misses cached for `items`: the watchlist's fingerprint plus
+    /// the birth heights, filter type and Golomb parameters, which a verdict
+    /// also depends on.
+    fn miss_key(&self, items: &[WatchItem], watch: &[ScriptBuf]) -> sha256::Hash {
+        let golomb = self.golomb_params();
+        let mut engine = sha256::Hash::engine();
+        engine.input(watchlist_fingerprint(watch).as_byte_array());
+        let births: BTreeSet<(&ScriptBuf, u32)> = items
+            .iter()
+            .filter_map(|i| Some((&i.script, i.birth_height?)))
+            .collect();
+        for (script, birth) in births {
+            engine.input(&(script.len() as u64).to_le_bytes());
+            engine.input(script.as_bytes());
+            engine.input(&birth.to_le_bytes());
+        }
+        engine.input(&[self.filter_type.0, golomb.p]);
+        engine.input(&golomb.m.to_le_bytes());
+        sha256::Hash::from_engine(engine)
+    }
+
+    /// Build `wallet`'s [`MatchDetails`] for a filter hit: ask the block source for the
+    /// relevant txs, else download the block (once per height, kept in `cache`).
+    async fn fetch_match(
+        &self,
+        wallet: usize,
+        height: u32,
+        block_hash: BlockHash,
+        items: &[WatchItem],
+        watch: &[ScriptBuf],
+        cache: &mut BlockCache,
+    ) -> anyhow::Result<MatchDetails> {
+        let (store, _) = self.wallet(wallet);
+        let blocks = self.block_source();
+        if self.opaque_blocks {
+            let raw_block = self
+                .observe(
+                    SourceKind::Blocks,
+                    self.timed(Phase::BlockFetch, blocks.get_block(block_hash))
+                        .await,
+                )
+                .with_context(|| format!("get_block({block_hash})"))?;
+            let header = self.header(block_hash).await?;
+            return Ok(MatchDetails {
+                height,
+                block: block_hash,
+                header,
+                median_time_past: self.median_time_past(height, header).await?,
+                txs: vec![],
+                items: vec![],
+                summaries: vec![],
+                prevouts: vec![],
+                raw_block: Some(raw_block),
+                match_id: match_id(height, block_hash, []),
+                sequence: 0,
+            });
+        }
+        // Block sources look up scriptPubKeys; raw elements and raw delivery need the whole block.
+        let relevant = if self.watchtower || self.raw_blocks || items.iter().any(|i| i.element) {
+            None
+        } else {
+            self.observe(
+                SourceKind::Blocks,
+                self.timed(
+                    Phase::BlockFetch,
+                    blocks.get_relevant_txs(block_hash, watch),
+                )
+                .await,
+            )
+            .with_context(|| format!("get_relevant_txs({block_hash})"))?
+        };
+        let vouched = relevant.is_some();
+        let (header, txs, raw_block) = match relevant {
+            Some(txs) => (self.header(block_hash).await?, txs, None),
+            None => {
+                if cache.block.is_none() {
+                    let raw_block = self.fetch_block(block_hash).await?;
+                    cache.block = Some(DecodedBlock {
+                        header: self.codec.decode_header(&raw_block)?,
+                        txs: self.codec.decode_txs(&raw_block)?,
+                        raw: if self.watchtower || self.raw_blocks {
+                            raw_block
+                        } else {
+                            vec![]
+                        },
+                    });
+                }
+                let decoded = match cache.wallets_left {
+                    0 => cache.block.take(),
+                    _ => cache.block.clone(),
+                };
+                let decoded = decoded.unwrap_or_default();
+                let header = match decoded.header {
+                    Some(header) => Some(header),
+                    None => self.header(block_hash).await?,
+                };
+                (header, decoded.txs, Some(decoded.raw))
+            }
+        };
+
+        let items: Vec<WatchItem> = items
+            .iter()
+            .filter(|i| i.involved_in(&txs))
+            .cloned()
+            .collect();
+        let resolved = if self.prevouts_wanted || self.utxo_tracking {
+            self.resolve_prevouts(wallet, store, &txs, watch, vouched)
+                .await?
+        } else {
+            vec![]
+        };
+        let summaries = self.summarize(wallet, &txs, watch, &items, &resolved, vouched);
+        let prevouts = if self.prevouts_wanted {
+            resolved
+        } else {
+            vec![]
+        };
+        let txids: Vec<Txid> = txs.iter().map(Transaction::compute_txid).collect();
+        Ok(MatchDetails {
+            height,
+            block: block_hash,
+            header,
+            median_time_past: self.median_time_past(height, header).await?,
+            match_id: match_id(height, block_hash, &txids),
+            txs,
+            items,
+            summaries,
+            prevouts,
+            raw_block: raw_block.filter(|_| self.watchtower || self.raw_blocks),
+            sequence: 0,
+        })
+    }
+
+    /// Give `details` the next number of `store`'s delivery sequence.
+    async fn numbered(
+        &self,
+        store: &dyn Store,
+        mut details: MatchDetails,
+    ) -> anyhow::Result<MatchDetails> {
+        details.sequence = match self
+            .timed(Phase::Store, store.next_delivery_sequence())
+            .await?
+        {
+            Some(n) => n,
+            None => self.sequence.fetch_add(1, Ordering::Relaxed) + 1,
+        };
+        Ok(details)
+    }
+
+    /// Header of `block` from the header source, if it serves headers.
+    async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+        self.observe(SourceKind::Headers, self.headers.header(block).await)
+            .with_context(|| format!("header({block})"))
+    }
+
+    /// Median timestamp of the block at `height` (with `header`) and its
+    /// predecessors: one [`headers_from`](HeaderSource::headers_from) batch
+    /// where the header source serves them, else following parent hashes.
+    async fn median_time_past(
+        &self,
+        height: u32,
+        header: Option<Header>,
+    ) -> anyhow::Result<Option<u32>> {
+        const SPAN: u32 = 11;
+        let Some(mut header) = header else {
+            return Ok(None);
+        };
+        let from = height.saturating_sub(SPAN - 1);
+        let mut times: Vec<u32> = self
+            .header_run(from, height - from + 1)
+            .await?
+            .filter(|run| run.last().map(Header::block_hash) == Some(header.block_hash()))
+            .map(|run| run.iter().map(|h| h.time).collect())
+            .unwrap_or_default();
+        if times.is_empty() {
+            times.push(header.time);
+            for _ in from..height {
+                let Some(parent) = self.header(header.prev_blockhash).await? else {
+                    return Ok(None);
+                };
+                header = parent;
+                times.push(header.time);
+            }
+        }
+        times.sort_unstable();
+        Ok(Some(times[times.len() / 2]))
+    }
+
+    /// The `count` linked headers from `height` up, if the header source serves
+    /// them in one batch.
+    async fn header_run(&self, height: u32, count: u32) -> anyhow::Result<Option<Vec<Header>>> {
+        if count < 2 {
+            return Ok(None);
+        }
+        // Below a source's lowest height: leave it to the parent walk.
+        let Ok(start) = self.headers.hash_at_height(height).await else {
+            return Ok(None);
+        };
+        let run = self
+            .observe(
+                SourceKind::Headers,
+                self.headers.headers_from(start, count).await,
+            )
+            .with_context(|| format!("headers_from({start}, {count})"))?;
+        let linked = run.first().map(Header::block_hash) == Some(start)
+            && run
+                .windows(2)
+                .all(|w| w[1].prev_blockhash == w[0].block_hash());
+        Ok((run.len() == count as usize && linked).then_some(run))
+    }
+
+    /// Download `block` from the block source, checking that it is that block.
+    async fn fetch_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        let raw = self
+            .observe(
+                SourceKind::Blocks,
+                self.timed(Phase::BlockFetch, self.block_source().get_block(block))
+                    .await,
+            )
+            .with_context(|| format!("get_block({block})"))?;
+        let served = self.codec.block_hash(&raw)?;
+        if served != block {
+            anyhow::bail!("get_block({block}) served block {served}");
+        }
+        Ok(raw)
+    }
+
+    /// Update `wallet`'s UTXO set with a match about to be delivered: record
+    /// outputs paying `watch`, mark recorded outputs spent by its txs, and
+    /// report the block's [`BalanceDelta`]. Idempotent, so a failed delivery can simply be retried.
+    ///
+    /// Only outputs funded at or below `safe`, the highest height the match's
+    /// block shares with the header chain, are marked spent: a block delivered
+    /// after it was reorged out must not spend the new branch's outputs.
+    async fn record_utxos(
+        &self,
+        wallet: usize,
+        details: &MatchDetails,
+        watch: &[ScriptBuf],
+        safe: u32,
+    ) -> anyhow::Result<()> {
+        let (store, _) = self.wallet(wallet);
+        let height = details.height;
+        let watch: HashSet<&ScriptBuf> = watch.iter().collect();
+        let mut wanted = Vec::new();
+        for tx in &details.txs {
+            let txid = tx.compute_txid();
+            wanted.extend(tx.input.iter().map(|i| i.previous_output));
+            wanted.extend(
+                (0..tx.output.len() as u32)
+                    .filter(|&vout| watch.contains(&tx.output[vout as usize].script_pubkey))
+                    .map(|vout| OutPoint::new(txid, vout)),
+            );
+        }
+        let mut known: HashMap<OutPoint, Utxo> = store
+            .load_utxos(&wanted)
+            .await
+            .with_context(|| format!("load_utxos @height {height}"))?
+            .into_iter()
+            .map(|u| (u.outpoint, u))
+            .collect();
+        let (mut received, mut sent) = (Amount::ZERO, Amount::ZERO);
+        for tx in &details.txs {
+            let txid = tx.compute_txid();
+            for (vout, out) in tx.output.iter().enumerate() {
+                if !watch.contains(&out.script_pubkey) {
+                    continue;
+                }
+                received += out.value;
+                let outpoint = OutPoint::new(txid, vout as u32);
+                // A rescan re-delivers the funding block: keep any later spend.
+                // An orphaned output confirming again on the new branch lands here too.
+                let utxo = Utxo {
+                    outpoint,
+                    txout: out.clone(),
+                    height,
+                    spent_at: known.get(&outpoint).and_then(|u| u.spent_at),
+                    orphaned: false,
+                };
+                store
+                    .save_utxo(&utxo)
+                    .await
+                    .with_context(|| format!("save_utxo @height {height}"))?;
+                known.insert(outpoint, utxo);
+            }
+            for input in &tx.input {
+                let known = known.get_mut(&input.previous_output);
+                // Spends on a stale branch of outputs above the fork are left to rollback_utxos.
+                if let Some(utxo) = known.filter(|u| u.height <= safe) {
+                    sent += utxo.txout.value;
+                    utxo.spent_at = Some(height);
+                    store.save_utxo(utxo).await?;
+                }
+            }
+        }
+        if received > Amount::ZERO || sent > Amount::ZERO {
+            let balance = self
+                .balance_after(wallet, store, height, received, sent)
+                .await?;
+            self.emit(EngineEvent::BalanceChanged(BalanceDelta {
+                height,
+                received,
+                sent,
+                balance,
+            }));
+        }
+        Ok(())
+    }
+
+    /// `wallet`'s balance right after the block at `height`: the last balance
+    /// reported plus the block's change, or read from `store` when there is no
+    /// lower height to build on (first block, rescan, rollback, retry).
+    async fn balance_after(
+        &self,
+        wallet: usize,
+        store: &dyn Store,
+        height: u32,
+        received: Amount,
+        sent: Amount,
+    ) -> anyhow::Result<Amount> {
+        let last = self.balances.lock().unwrap().get(&wallet).copied();
+        let balance = match last {
+            Some((at, balance)) if at < height => (balance + received)
+                .checked_sub(sent)
+                .unwrap_or(Amount::ZERO),
+            _ => crate::utxo::balance_at(store, height).await?,
+        };
+        self.balances
+            .lock()
+            .unwrap()
+            .insert(wallet, (height, balance));
+        Ok(balance)
+    }
+
+    /// Amounts of `txs` relative to `watch`, and whether each involves it or an
+    /// element of `items` (all of them if the block source `vouched` for their
+    /// relevance). Without UTXO tracking, spent outputs are looked up earlier in
+    /// `txs` and in the prevout cache, which this leaves alone.
+    fn summarize(
+        &self,
+        wallet: usize,
+        txs: &[Transaction],
+        watch: &[ScriptBuf],
+        items: &[WatchItem],
+        resolved: &[(OutPoint, TxOut)],
+        vouched: bool,
+    ) -> Vec<TxSummary> {
+        let watch: HashSet<&ScriptBuf> = watch.iter().collect();
+        let elements: Vec<&WatchItem> = items.iter().filter(|i| i.element).collect();
+        let resolved: HashMap<&OutPoint, &TxOut> = resolved.iter().map(|(o, t)| (o, t)).collect();
+        let prevouts = self.prevouts.lock().unwrap();
+        let mut in_block: HashMap<OutPoint, &TxOut> = HashMap::new();
+        txs.iter()
+            .map(|tx| {
+                let txid = tx.compute_txid();
+                let spent: Vec<Option<TxOut>> = tx
+                    .input
+                    .iter()
+                    .map(|i| {
+                        let op = i.previous_output;
+                        let known = match self.utxo_tracking {
+                            true => None,
+                            false => in_block
+                                .get(&op)
+                                .map(|&o| o.clone())
+                                .or_else(|| prevouts.peek(&(wallet, op)).cloned()),
+                        };
+                        known.or_else(|| resolved.get(&op).map(|&o| o.clone()))
+                    })
+                    .collect();
+                let ours: Vec<&TxOut> = spent
+                    .iter()
+                    .flatten()
+                    .filter(|o| watch.contains(&o.script_pubkey))
+                    .collect();
+                let sent = ours.iter().map(|o| o.value).sum();
+                let fee = spent
+                    .iter()
+                    .map(|o| o.as_ref().map(|o| o.value))
+                    .sum::<Option<Amount>>()
+                    .and_then(|inputs| inputs.checked_sub(tx.output.iter().map(|o| o.value).sum()));
+                let mut received = Amount::ZERO;
+                let mut pays_watched = false;
+                for (vout, out) in tx.output.iter().enumerate() {
+                    if watch.contains(&out.script_pubkey) {
+                        received += out.value;
+                        pays_watched = true;
+                        if !self.utxo_tracking {
+                            in_block.insert(OutPoint::new(txid, vout as u32), out);
+                        }
+                    }
+                }
+                let involved = vouched
+                    || pays_watched
+                    || !ours.is_empty()
+                    || elements.iter().any(|i| i.involved_in(slice::from_ref(tx)));
+                TxSummary {
+                    txid,
+                    received,
+                    sent,
+                    fee,
+                    involved,
+                }
+            })
+            .collect()
+    }
+
+    /// The prevout cache changes of delivering `txs` (none with UTXO tracking,
+    /// where the store keeps the outputs instead).
+    fn prevout_update(&self, txs: &[Transaction], watch: &[ScriptBuf]) -> PrevoutUpdate {
+        if self.utxo_tracking {
+            return PrevoutUpdate::default();
+        }
+        let watch: HashSet<&ScriptBuf> = watch.iter().collect();
+        let mut update = PrevoutUpdate::default();
+        for tx in txs {
+            update
+                .spent
+                .extend(tx.input.iter().map(|i| i.previous_output));
+            let txid = tx.compute_txid();
+            for (vout, out) in tx.output.iter().enumerate() {
+                if watch.contains(&out.script_pubkey) {
+                    update
+                        .paid
+                        .push((OutPoint::new(txid, vout as u32), out.clone()));
+                }
+            }
+        }
+        update
+    }
+
+    /// Apply `update` for `wallet`, whose match has been delivered.
+    fn remember_prevouts(&self, wallet: usize, update: PrevoutUpdate) {
+        let mut prevouts = self.prevouts.lock().unwrap();
+        for (outpoint, out) in update.paid {
+            prevouts.insert((wallet, outpoint), out);
+        }
+        for outpoint in update.spent {
+            prevouts.remove(&(wallet, outpoint));
+        }
+    }
+
+    /// Outputs spent by the transactions of `txs` involving `watch` (all of
+    /// them if the block source `vouched` for their relevance), in input order.
+    async fn resolve_prevouts(
+        &self,
+        wallet: usize,
+        store: &dyn Store,
+        txs: &[Transaction],
+        watch: &[ScriptBuf],
+        vouched: bool,
+    ) -> anyhow::Result<Vec<(OutPoint, TxOut)>> {
+        let watch: HashSet<&ScriptBuf> = watch.iter().collect();
+        let mut in_block: HashMap<OutPoint, &TxOut> = HashMap::new();
+        let mut fetched: HashMap<Txid, Option<Transaction>> = HashMap::new();
+        let mut resolved = vec![];
+        let mut recorded: HashMap<OutPoint, TxOut> = HashMap::new();
+        if self.utxo_tracking {
+            let inputs: Vec<OutPoint> = txs
+                .iter()
+                .flat_map(|tx| tx.input.iter().map(|i| i.previous_output))
+                .collect();
+            let utxos = self.timed(Phase::Store, store.load_utxos(&inputs)).await?;
+            recorded.extend(utxos.into_iter().map(|u| (u.outpoint, u.txout)));
+        }
+        for tx in txs {
+            let mut spent = Vec::with_capacity(tx.input.len());
+            for input in &tx.input {
+                let op = input.previous_output;
+                let mut out = in_block.get(&op).map(|&o| o.clone());
+                if out.is_none() && self.utxo_tracking {
+                    out = recorded.get(&op).cloned();
+                } else if out.is_none() {
+                    out = self.prevouts.lock().unwrap().peek(&(wallet, op)).cloned();
+                }
+                spent.push((op, out));
+            }
+            let ours = vouched
+                || tx.output.iter().any(|o| watch.contains(&o.script_pubkey))
+                || spent
+                    .iter()
+                    .any(|(_, o)| o.as_ref().is_some_and(|o| watch.contains(&o.script_pubkey)));
+            let txid = tx.compute_txid();
+            for (vout, out) in tx.output.iter().enumerate() {
+                in_block.insert(OutPoint::new(txid, vout as u32), out);
+            }
+            if !ours {
+                continue;
+            }
+            for (op, out) in spent {
+                let out = match (out, &self.tx_source) {
+                    (None, Some(source)) if !op.is_null() => {
+                        let tx = match fetched.entry(op.txid) {
+                            Entry::Occupied(e) => e.into_mut(),
+                            Entry::Vacant(e) => {
+                                let tx = self.get_transaction(source.as_ref(), op.txid).await?;
+                                e.insert(tx)
+                            }
+                        };
+                        tx.as_ref()
+                            .and_then(|tx| tx.output.get(op.vout as usize))
+                            .cloned()
+                    }
+                    (out, _) => out,
+                };
+                if let Some(out) = out {
+                    resolved.push((op, out));
+                }
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// `txid` from the transaction lookup, which must serve that very
+    /// transaction: its outputs end up in prevouts and amounts.
+    async fn get_transaction(
+        &self,
+        source: &dyn TxSource,
+        txid: Txid,
+    ) -> anyhow::Result<Option<Transaction>> {
+        let tx = self
+            .timed(Phase::BlockFetch, source.get_transaction(txid))
+            .await
+            .and_then(|tx| match tx {
+                Some(tx) if tx.compute_txid() != txid => {
+                    anyhow::bail!("served transaction {} instead", tx.compute_txid())
+                }
+                tx => Ok(tx),
+            });
+        self.observe(SourceKind::Transactions, tx)
+            .with_context(|| format!("get_transaction({txid})"))
+    }
+
+    /// Store and hooks of wallet `i` of [`all_wallets`](Self::all_wallets).
+    fn wallet(&self, i: usize) -> (&dyn Store, &dyn WalletHooks) {
+        self.all_wallets().nth(i).expect("wallet index in range")
+    }
+
+    /// The primary wallet followed by those added with [`with_wallet`](Self::with_wallet).
+    fn all_wallets(&self) -> impl Iterator<Item = (&dyn Store, &dyn WalletHooks)> {
+        std::iter::once((&self.store as &dyn Store, &self.hooks as &dyn WalletHooks))
+            .chain(self.wallets.iter().map(|(s, w)| (s.as_ref(), w.as_ref())))
+    }
+
+    /// Await `fut`, adding the time it took to `phase` in [`EngineHandle::timings`].
+    async fn timed<T>(&self, phase: Phase, fut: impl Future<Output = T>) -> T {
+        let started = crate::rt::now();
+        let out = fut.await;
+        self.control.spent(phase, started);
+        out
+    }
+
+    /// Pass a source call's result through, noting successes for [`EngineHandle::health`]
+    /// and attributing failures to the source's label (see [`EngineEvent::SourceFailed`]).
+    fn observe<T>(&self, kind: SourceKind, res: anyhow::Result<T>) -> anyhow::Result<T> {
+        let err = match res {
+            Ok(out) => {
+                self.control.source_ok(kind);
+                return Ok(out);
+            }
+            Err(err) => err,
+        };
+        let source = match kind {
+            SourceKind::Filters => self.filters().label(),
+            SourceKind::Headers => self.headers.label(),
+            SourceKind::Blocks => self.block_source().label(),
+            SourceKind::Transactions => self.tx_source.as_ref().and_then(|t| t.label()),
+        };
+        self.emit(EngineEvent::SourceFailed {
+            kind,
+            source: source.clone(),
+            error: format!("{err:#}"),
+        });
+        match source {
+            Some(label) => Err(err.context(format!("{kind} source {label}"))),
+            None => Err(err),
+        }
+    }
+
+    /// Attribute a checkpoint mismatch to the filter source and emit it as an
+    /// event; other errors pass through unchanged.
+    fn on_apply_error(&self, err: anyhow::Error) -> anyhow::Error {
+        match err.downcast::<EngineError>() {
+            Ok(EngineError::CheckpointMismatch(mut m)) => {
+                m.source = self.filters().label();
+                self.emit(EngineEvent::CheckpointMismatch(m.clone()));
+                EngineError::CheckpointMismatch(m).into()
+            }
+            Ok(other) => other.into(),
+            Err(err) => err,
+        }
+    }
+
+    /// Test `raw_filter` against `query`. A malformed filter (or, in
+    /// [strict](Self::with_strict_filters) mode, an empty one) is an
+    /// [`InvalidFilter`], also emitted as an event so the source can be banned.
+    fn match_filter(
+        &self,
+        height: u32,
+        block: BlockHash,
+        raw_filter: &[u8],
+        query: &QuerySet,
+        golomb: GolombParams,
+    ) -> anyhow::Result<bool> {
+        if let Some(dump) = &self.payload_dump {
+            dump.filter(
+                self.filter_type,
+                height,
+                block,
+                raw_filter,
+                self.filters().label(),
+            )?;
+        }
+        if raw_filter.is_empty() && !self.strict_filters {
+            return Ok(false);
+        }
+        let started = crate::rt::now();
+        let hit = validate_filter(raw_filter, golomb)
+            .map(|()| query.matches(height, block, raw_filter, golomb));
+        self.control.spent(Phase::Matching, started);
+        match hit {
+            Ok(hit) => hit.with_context(|| format!("filter match @height {height}")),
+            Err(reason) => {
+                let invalid = InvalidFilter {
+                    height,
+                    block,
+                    reason,
+                    source: self.filters().label(),
+                };
+                self.emit(EngineEvent::InvalidFilter(invalid.clone()));
+                Err(EngineError::InvalidFilter(invalid).into())
+            }
+        }
+    }
+
+    /// Dump `batch` if a [payload dump](Self::with_payload_dump) is set.
+    fn dump_cfheaders(
+        &self,
+        filter_type: FilterType,
+        batch: &CfHeadersBatch,
+    ) -> anyhow::Result<()> {
+        match &self.payload_dump {
+            Some(dump) => dump.cfheaders(filter_type, batch, self.filters().label()),
+            None => Ok(()),
+        }
+    }
+
+    fn emit(&self, event: EngineEvent) {
+        if let Some(sink) = &self.events {
+            sink.on_event(&event);
+        }
+    }
+
+    /// Move to `phase`, reporting it if it changed.
+    fn enter(&self, phase: EnginePhase) {
+        if self.control.set_phase(phase) && self.phase_events {
+            self.emit(EngineEvent::PhaseChanged(phase));
+        }
+    }
+
+    /// Run the body of a public call, which enters its own phases, and go back
+    /// to [`EnginePhase::Idle`] when it ends unless another call (e.g.
+    /// `deliver_pending` beside a run) is still going.
+    async fn phased<T>(&self, call: impl Future<Output = T>) -> T {
+        let call_guard = self.control.call();
+        let res = call.await;
+        if call_guard.finish() {
+            self.enter(EnginePhase::Idle);
+        }
+        res
+    }
+
+    /// The filter source in use: the primary one until a re-anchor switched to a fallback.
+    fn filters(&self) -> &dyn FilterSource {
+        match self.active_source.load(Ordering::Relaxed) {
+            0 => &self.source,
+            i => self.fallbacks[i - 1].as_ref(),
+        }
+    }
+
+    /// Where matching blocks come from: the configured `BlockSource`, else the filter source.
+    fn block_source(&self) -> &dyn BlockSource {
+        match (&self.blocks, self.active_source.load(Ordering::Relaxed)) {
+            (Some(b), _) => b.as_ref(),
+            (None, 0) => &self.source,
+            (None, i) => self.fallbacks[i - 1].as_ref(),
+        }
+    }
+
+    /// Hash of the block at `height` on the header chain. A height the header
+    /// source does not have fails as [`EngineError::HeightOutOfRange`], whatever
+    /// error the source itself returned.
+    async fn hash_at(&self, height: u32) -> anyhow::Result<BlockHash> {
+        match self.headers.hash_at_height(height).await {
+            Err(e) => match self.out_of_range(&[height]).await {
+                Some(out_of_range) => Err(out_of_range),
+                None => self.observe(SourceKind::Headers, Err(e)),
+            },
+            ok => self.observe(SourceKind::Headers, ok),
+        }
+    }
+
+    /// The filters of `hashes`, the blocks at `from..=to`, one per block.
+    async fn cfilters_in_range(
+        &self,
+        from: u32,
+        to: u32,
+        hashes: &[BlockHash],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let filters = self
+            .observe(
+                SourceKind::Filters,
+                self.timed(
+                    Phase::FilterFetch,
+                    self.filters().get_cfilter_range(self.filter_type, hashes),
+                )
+                .await,
+            )
+            .with_context(|| format!("get_cfilter_range({from}..={to})"))?;
+        if filters.len() != hashes.len() {
+            anyhow::bail!(
+                "get_cfilter_range({from}..={to}) returned {} filters, expected {}",
+                filters.len(),
+                hashes.len()
+            );
+        }
+        Ok(filters)
+    }
+
+    /// Hashes of the blocks at `from..=to`, like [`hash_at`](Self::hash_at).
+    async fn hashes_in_range(&self, from: u32, to: u32) -> anyhow::Result<Vec<BlockHash>> {
+        let hashes = match self.headers.hashes_in_range(from, to).await {
+            Err(e) => match self.out_of_range(&[from, to]).await {
+                Some(out_of_range) => return Err(out_of_range),
+                None => self.observe(SourceKind::Headers, Err(e))?,
+            },
+            ok => self.observe(SourceKind::Headers, ok)?,
+        };
+        if hashes.len() != (to - from + 1) as usize {
+            anyhow::bail!(
+                "hashes_in_range({from}..={to}) returned {} hashes, expected {}",
+                hashes.len(),
+                to - from + 1
+            );
+        }
+        if let Some(&top) = hashes.last() {
+            self.cross_check(to, top).await?;
+        }
+        Ok(hashes)
+    }
+
+    /// With a [source cross-check](Self::with_source_cross_check) due at
+    /// `height`, compare the header source's `block` there with the filter source's.
+    async fn cross_check(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+        let Some(interval) = self.cross_check else {
+            return Ok(());
+        };
+        let last = *self.cross_checked.lock().unwrap();
+        if last.is_some_and(|last| height.abs_diff(last) < interval) {
+            return Ok(());
+        }
+        match self.filters().block_hash_at(height).await {
+            Ok(Some(theirs)) if theirs != block => Err(EngineError::SourcesDiverged {
+                height,
+                header_source: block,
+                filter_source: theirs,
+                source: self.filters().label(),
+            }
+            .into()),
+            Ok(_) => {
+                *self.cross_checked.lock().unwrap() = Some(height);
+                Ok(())
+            }
+            // Not fatal: asked again next time.
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// After a failed lookup: the typed error for the first of `heights` the
+    /// header source does not have, if any.
+    async fn out_of_range(&self, heights: &[u32]) -> Option<anyhow::Error> {
+        for &height in heights {
+            if let Ok(false) = self.headers.has_height(height).await {
+                return Some(EngineError::HeightOutOfRange { height }.into());
+            }
+        }
+        None
+    }
+
+    /// Safe point between steps: honour pause requests and the sync policy.
+    /// Returns `false` when the policy asks to end this run.
+    async fn safe_point(
+        &self,
+        phase: SyncPhase,
+        next_height: u32,
+        chain_tip: u32,
+        scanned_this_run: u32,
+    ) -> anyhow::Result<bool> {
+        loop {
+            self.control.checkpoint().await;
+            let ctx = SyncContext {
+                phase,
+                next_height,
+                chain_tip,
+                scanned_this_run,
+            };
+            match self.policy.check(&ctx).await.context("sync policy")? {
+                PolicyDecision::Continue => return Ok(true),
+                PolicyDecision::Stop => return Ok(false),
+                PolicyDecision::Wait(d) => crate::rt::sleep(d).await,
+            }
+        }
+    }
+}
+
+/// Whether `raw_filter` has nothing to match: no bytes at all, or an element
+/// count of zero.
+fn empty_filter(raw_filter: &[u8]) -> Option<EmptyFilterKind> {
+    match raw_filter.first() {
+        None => Some(EmptyFilterKind::Missing),
+        Some(0) => Some(EmptyFilterKind::NoElements),
+        Some(_) => None,
+    }
 }
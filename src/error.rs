@@ -0,0 +1,185 @@
+//! Typed engine failures.
+//!
+//! The engine returns `anyhow::Error`; conditions a caller may want to react to
+//! are raised as an [`EngineError`] inside it:
+//!
+//! ```rust,ignore
+//! match engine.run_to_tip().await {
+//!     Err(e) => match e.downcast_ref::<EngineError>() {
+//!         Some(EngineError::SourceBehindTip { source_height, chain_height }) => { /* retry later */ }
+//!         _ => return Err(e),
+//!     },
+//!     Ok(()) => {}
+//! }
+//! ```
+use crate::checkpoints::CheckpointError;
+use crate::filter_source::FilterType;
+use bitcoin::{BlockHash, Network};
+use std::fmt;
+
+/// Conditions the engine reports in a form callers can match on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EngineError {
+    /// The filter source cannot serve filters up to the header chain's tip yet
+    /// (e.g. its node is still building the filter index). Everything up to
+    /// `source_height` has been verified and scanned.
+    SourceBehindTip {
+        /// Last height the filter source could serve.
+        source_height: u32,
+        /// Tip reported by the header source.
+        chain_height: u32,
+    },
+    /// A rolling cfheader disagreed with a checkpoint. Nothing from the
+    /// offending batch was persisted.
+    CheckpointMismatch(CheckpointMismatch),
+    /// A filter is malformed (bad element count, truncated or out-of-range
+    /// elements, trailing bytes), or empty under
+    /// [`Niebla158::with_strict_filters`](crate::Niebla158::with_strict_filters).
+    /// Nothing from that height was scanned.
+    InvalidFilter(InvalidFilter),
+    /// The header source or a store belongs to another network than the one
+    /// set with [`Niebla158::with_network`](crate::Niebla158::with_network).
+    /// Nothing was synced.
+    WrongNetwork {
+        /// The engine's network.
+        network: Network,
+        /// What disagreed: `"header source"` or `"store"`.
+        what: &'static str,
+        /// What it is on instead (a genesis hash or a network name).
+        found: String,
+    },
+    /// A store was written with settings this engine cannot continue from
+    /// (see [`EngineConfig`](crate::config::EngineConfig)). Nothing was synced;
+    /// [`Niebla158::reset`](crate::Niebla158::reset) starts the store over.
+    IncompatibleConfig {
+        /// Name of the setting, as in the configuration record.
+        setting: &'static str,
+        /// Its value in the store's record.
+        stored: String,
+        /// Its value now.
+        current: String,
+    },
+    /// The engine needed a block at a height the header source does not have
+    /// (see [`HeaderSource::has_height`](crate::headers::HeaderSource::has_height)),
+    /// e.g. genesis from a source that starts at a birth height.
+    HeightOutOfRange {
+        /// The height asked for.
+        height: u32,
+    },
+    /// The engine's [checkpoints](crate::Niebla158::with_checkpoints) are
+    /// malformed (see [`Checkpoints::validate`](crate::checkpoints::Checkpoints::validate)).
+    /// Nothing was synced.
+    InvalidCheckpoints(CheckpointError),
+    /// The filter source and the header source have different blocks at
+    /// `height` (see
+    /// [`Niebla158::with_source_cross_check`](crate::Niebla158::with_source_cross_check)):
+    /// they are on different chains, so the filters cannot be trusted to
+    /// describe the header source's blocks. Nothing at or above the window
+    /// being scanned was matched.
+    SourcesDiverged {
+        /// The height compared.
+        height: u32,
+        /// Block at `height` according to the header source.
+        header_source: BlockHash,
+        /// Block at `height` according to the filter source.
+        filter_source: BlockHash,
+        /// [`FilterSource::label`](crate::FilterSource::label) of the filter source.
+        source: Option<String>,
+    },
+}
+
+/// Forensics for [`EngineError::CheckpointMismatch`], also emitted as
+/// [`EngineEvent::CheckpointMismatch`](crate::events::EngineEvent::CheckpointMismatch).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointMismatch {
+    /// Filter type of the cfheaders chain.
+    pub filter_type: FilterType,
+    /// Height of the checkpoint.
+    pub height: u32,
+    /// Rolling header the checkpoint requires.
+    pub expected: BlockHash,
+    /// Rolling header computed from the source's data.
+    pub computed: BlockHash,
+    /// First height of the batch that contained it.
+    pub batch_start: u32,
+    /// [`FilterSource::label`](crate::FilterSource::label) of the source that served the batch.
+    pub source: Option<String>,
+}
+
+/// Details of an [`EngineError::InvalidFilter`], also emitted as
+/// [`EngineEvent::InvalidFilter`](crate::events::EngineEvent::InvalidFilter).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFilter {
+    /// Height of the block.
+    pub height: u32,
+    /// The block the filter was served for.
+    pub block: BlockHash,
+    /// What is wrong with it.
+    pub reason: String,
+    /// [`FilterSource::label`](crate::FilterSource::label) of the source that served it.
+    pub source: Option<String>,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SourceBehindTip {
+                source_height,
+                chain_height,
+            } => write!(
+                f,
+                "filter source is behind the chain tip (source at {source_height}, chain at {chain_height})"
+            ),
+            Self::CheckpointMismatch(m) => {
+                write!(
+                    f,
+                    "{} cfheaders checkpoint mismatch @{}: expected {}, computed {} (batch from {}",
+                    m.filter_type, m.height, m.expected, m.computed, m.batch_start
+                )?;
+                match &m.source {
+                    Some(source) => write!(f, ", source {source})"),
+                    None => write!(f, ")"),
+                }
+            }
+            Self::InvalidFilter(i) => {
+                write!(f, "invalid filter for {} @{}: {}", i.block, i.height, i.reason)?;
+                match &i.source {
+                    Some(source) => write!(f, " (source {source})"),
+                    None => Ok(()),
+                }
+            }
+            Self::WrongNetwork {
+                network,
+                what,
+                found,
+            } => write!(f, "{what} is not on {network} ({found})"),
+            Self::IncompatibleConfig {
+                setting,
+                stored,
+                current,
+            } => write!(f, "store was synced with {setting} {stored}, not {current}"),
+            Self::HeightOutOfRange { height } => {
+                write!(f, "header source has no block at height {height}")
+            }
+            Self::InvalidCheckpoints(e) => write!(f, "invalid checkpoints: {e}"),
+            Self::SourcesDiverged {
+                height,
+                header_source,
+                filter_source,
+                source,
+            } => {
+                write!(
+                    f,
+                    "filter and header sources diverged @{height}: header source has {header_source}, filter source has {filter_source}"
+                )?;
+                match source {
+                    Some(source) => write!(f, " (source {source})"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
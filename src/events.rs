@@ -0,0 +1,128 @@
+//! Machine-readable notifications about what the engine observed, for
+//! monitoring and automated responses (alerting, banning a source).
+use crate::compat::{MaybeSend, MaybeSync};
+use crate::control::{EnginePhase, SourceKind};
+use crate::error::{CheckpointMismatch, InvalidFilter};
+use crate::utxo::BalanceDelta;
+use bitcoin::BlockHash;
+
+/// Something worth telling an operator about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EngineEvent {
+    /// A source served cfheaders that contradict a checkpoint; the run fails
+    /// with the same details as [`EngineError::CheckpointMismatch`](crate::EngineError::CheckpointMismatch).
+    CheckpointMismatch(CheckpointMismatch),
+    /// Stored cfheaders were discarded back to `height` after a mismatch and
+    /// verification resumes from there (see
+    /// [`Niebla158::with_reanchor`](crate::Niebla158::with_reanchor)).
+    Reanchored {
+        /// Height of the trusted checkpoint (0 for genesis) now at the cfheaders tip.
+        height: u32,
+        /// [`label`](crate::FilterSource::label) of the source used from now on.
+        source: Option<String>,
+    },
+    /// A wallet's last scanned block at `stale_height` is no longer on the header
+    /// chain; its progress was rolled back to the fork point `fork_height`.
+    RolledBack {
+        /// Height of the stale last scanned block.
+        stale_height: u32,
+        /// Last height both branches share; scanning resumes above it.
+        fork_height: u32,
+    },
+    /// A queued match (see
+    /// [`Niebla158::with_delivery_queue`](crate::Niebla158::with_delivery_queue))
+    /// was dropped without reaching the wallet's hooks: its block left the
+    /// header chain. A [watchtower](crate::Niebla158::with_watchtower) keeps
+    /// such breaches until no source serves their block any more. Reconcile
+    /// it if the application tracks queued matches.
+    MatchDropped {
+        /// Index of the wallet: 0 for the primary one, then in
+        /// [`with_wallet`](crate::Niebla158::with_wallet) order.
+        wallet: usize,
+        /// Height the match was queued at.
+        height: u32,
+        /// The block that left the chain.
+        block: BlockHash,
+    },
+    /// A delivered block changed a wallet's tracked balance (see
+    /// [`Niebla158::with_utxo_tracking`](crate::Niebla158::with_utxo_tracking)).
+    /// Rescans deliver these again for the blocks they revisit.
+    BalanceChanged(BalanceDelta),
+    /// A source served a malformed filter; the run fails with the same details
+    /// as [`EngineError::InvalidFilter`](crate::EngineError::InvalidFilter).
+    InvalidFilter(InvalidFilter),
+    /// The filter for `block` held nothing to match, so the height was skipped.
+    EmptyFilter {
+        /// Height of the block.
+        height: u32,
+        /// The block.
+        block: BlockHash,
+        /// Whether the block really has no filter elements.
+        kind: EmptyFilterKind,
+        /// [`label`](crate::FilterSource::label) of the source that served it.
+        source: Option<String>,
+    },
+    /// Settings that do not affect stored data changed since a store's last
+    /// run; its [configuration record](crate::config::EngineConfig) was updated.
+    /// `cfheaders` means the store's cfheaders predate BIP-157 rolling and
+    /// were rewound to be verified again.
+    ConfigChanged {
+        /// Names of the changed settings.
+        settings: Vec<String>,
+    },
+    /// A wallet's watchlist differs from the one it last scanned with (see
+    /// [`Niebla158::with_auto_rescan`](crate::Niebla158::with_auto_rescan)).
+    WatchlistChanged {
+        /// Scripts added since.
+        added: usize,
+        /// Scripts no longer watched.
+        removed: usize,
+        /// Height the wallet rescans from, when an added script could have
+        /// been paid at or below its last scanned height.
+        rescan_from: Option<u32>,
+    },
+    /// A source call failed. Retried and non-fatal calls report here too, so
+    /// this is the place to count failures per backend.
+    SourceFailed {
+        /// Which source was called.
+        kind: SourceKind,
+        /// Its `label()`, e.g. [`FilterSource::label`](crate::FilterSource::label).
+        source: Option<String>,
+        /// The error, with its causes.
+        error: String,
+    },
+    /// The engine moved to another step of its run (see
+    /// [`EngineHandle::phase`](crate::EngineHandle::phase)). Only reported with
+    /// [`Niebla158::with_phase_events`](crate::Niebla158::with_phase_events).
+    PhaseChanged(EnginePhase),
+}
+
+/// What an [`EngineEvent::EmptyFilter`] looked like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyFilterKind {
+    /// A well-formed filter with no elements: the block has no scripts to index.
+    /// Rare outside test chains, since every coinbase pays somewhere.
+    NoElements,
+    /// Zero bytes: the source returned nothing. Matched as a miss, so a source
+    /// doing this can hide payments.
+    Missing,
+}
+
+/// Receiver of [`EngineEvent`]s, installed with
+/// [`Niebla158::with_events`](crate::Niebla158::with_events).
+///
+/// Called inline from the engine, so keep it quick (e.g. push to a channel).
+pub trait EventSink: MaybeSend + MaybeSync {
+    /// Handle one event.
+    fn on_event(&self, event: &EngineEvent);
+}
+
+impl<F> EventSink for F
+where
+    F: Fn(&EngineEvent) + MaybeSend + MaybeSync,
+{
+    fn on_event(&self, event: &EngineEvent) {
+        self(event)
+    }
+}
@@ -0,0 +1,333 @@
+//! Write matches to a directory instead of handing them to Rust callbacks, so
+//! an offline (air-gapped) signer or accounting system can ingest them later,
+//! e.g. from a removable disk.
+//!
+//! [`ExportWallet`] is a [`WalletHooks`] that writes one file per match,
+//! keeping only the [relevant](MatchDetails::relevant_txids) transactions
+//! (blocks with none, e.g. filter false positives, are not written):
+//!
+//! ```text
+//! 0000800123-<block hash>.match
+//! ```
+//!
+//! Each file is one `name=value` line per field, see [`ExportedMatch`]:
+//!
+//! ```text
+//! height=800123
+//! block=…
+//! match_id=…
+//! sequence=42
+//! tx=<hex>
+//! raw_block=<hex>
+//! ```
+//!
+//! with a `tx` line per transaction and `raw_block` only
+//! [with blocks](ExportWallet::with_blocks). With the `serde` feature,
+//! [`ExportWallet::with_json`] writes the webhook's JSON schema instead, in
+//! `.json` files. Files are written under a temporary name and renamed, so
+//! readers never see half a match. A match delivered again (after a failed
+//! run, a reorg or a rescan) overwrites its file. On the offline side,
+//! [`read_dir`] loads them back:
+//!
+//! ```rust,ignore
+//! // Online, watch-only:
+//! let wallet = ExportWallet::new("/mnt/transfer", items)?.with_blocks();
+//! Niebla158::new(store, wallet, source, headers).with_raw_blocks().run_to_tip().await?;
+//!
+//! // Offline:
+//! for exported in export::read_dir("/mnt/transfer")? { /* … */ }
+//! ```
+use crate::config::parse_record;
+use crate::hooks::{match_id, MatchDetails, SharedWatchlist, WalletHooks, WatchItem};
+#[cfg(feature = "serde")]
+use crate::record::MatchRecordV1;
+use anyhow::Context;
+use async_trait::async_trait;
+use bitcoin::{
+    consensus::encode::{deserialize_hex, serialize_hex},
+    hashes::sha256,
+    BlockHash, ScriptBuf, Transaction, Txid,
+};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Extension of the files written by [`ExportWallet`].
+pub const EXTENSION: &str = "match";
+
+/// Extension of the files written by [`ExportWallet::with_json`].
+pub const JSON_EXTENSION: &str = "json";
+
+/// A match as written by [`ExportWallet`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportedMatch {
+    /// Height of the matching block.
+    pub height: u32,
+    /// Hash of the matching block.
+    pub block: BlockHash,
+    /// [`MatchDetails::match_id`], to deduplicate on.
+    pub match_id: sha256::Hash,
+    /// [`MatchDetails::sequence`]; 0 for matches delivered without details.
+    pub sequence: u64,
+    /// The block's transactions involving the watchlist.
+    pub txs: Vec<Transaction>,
+    /// The whole block as served, with [`ExportWallet::with_blocks`].
+    pub raw_block: Option<Vec<u8>>,
+}
+
+impl ExportedMatch {
+    /// Name of the file holding this match: its height, zero-padded so names
+    /// sort in chain order, and block.
+    pub fn file_name(&self) -> String {
+        format!("{:010}-{}.{EXTENSION}", self.height, self.block)
+    }
+}
+
+impl fmt::Display for ExportedMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "height={}", self.height)?;
+        writeln!(f, "block={}", self.block)?;
+        writeln!(f, "match_id={}", self.match_id)?;
+        writeln!(f, "sequence={}", self.sequence)?;
+        for tx in &self.txs {
+            writeln!(f, "tx={}", serialize_hex(tx))?;
+        }
+        if let Some(raw) = &self.raw_block {
+            writeln!(f, "raw_block={}", hex::encode(raw))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ExportedMatch {
+    type Err = anyhow::Error;
+
+    fn from_str(record: &str) -> anyhow::Result<Self> {
+        let fields = parse_record(record);
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .copied()
+                .with_context(|| format!("exported match has no {name}"))
+        };
+        let txs = record
+            .lines()
+            .filter_map(|line| line.strip_prefix("tx="))
+            .map(|hex| deserialize_hex(hex).context("exported match has a malformed tx"))
+            .collect::<anyhow::Result<_>>()?;
+        let raw_block = match fields.get("raw_block") {
+            Some(raw) => Some(hex::decode(raw).context("exported match raw_block")?),
+            None => None,
+        };
+        Ok(Self {
+            height: field("height")?.parse().context("exported match height")?,
+            block: field("block")?.parse().context("exported match block")?,
+            match_id: field("match_id")?
+                .parse()
+                .context("exported match match_id")?,
+            sequence: field("sequence")?
+                .parse()
+                .context("exported match sequence")?,
+            txs,
+            raw_block,
+        })
+    }
+}
+
+/// Load every match exported to `dir`, text or [JSON](ExportWallet::with_json),
+/// lowest height first. JSON files fail to load without the `serde` feature.
+/// Other files (including the temporary ones of writes in progress) are ignored.
+pub fn read_dir(dir: impl AsRef<Path>) -> anyhow::Result<Vec<ExportedMatch>> {
+    let dir = dir.as_ref();
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|e| e == EXTENSION || e == JSON_EXTENSION)
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("read {}", path.display()))?;
+            let exported = match path.extension().is_some_and(|e| e == JSON_EXTENSION) {
+                true => from_json(&text),
+                false => text.parse(),
+            };
+            exported.with_context(|| format!("parse {}", path.display()))
+        })
+        .collect()
+}
+
+#[cfg(feature = "serde")]
+fn from_json(text: &str) -> anyhow::Result<ExportedMatch> {
+    let record = MatchRecordV1::from_json(text)?;
+    Ok(ExportedMatch {
+        height: record.height,
+        block: record.block,
+        match_id: record.match_id,
+        sequence: record.sequence.unwrap_or(0),
+        txs: record.transactions()?,
+        raw_block: record.raw_block_bytes()?,
+    })
+}
+
+#[cfg(not(feature = "serde"))]
+fn from_json(_text: &str) -> anyhow::Result<ExportedMatch> {
+    anyhow::bail!("reading JSON exports needs the `serde` feature")
+}
+
+/// [`WalletHooks`] watching a fixed set of items and writing matches to a directory.
+pub struct ExportWallet {
+    dir: PathBuf,
+    items: SharedWatchlist,
+    blocks: bool,
+    #[cfg(feature = "serde")]
+    json: bool,
+}
+
+impl ExportWallet {
+    /// Write matches for `items` to `dir`, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>, items: Vec<WatchItem>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            items: SharedWatchlist::new(items),
+            blocks: false,
+            #[cfg(feature = "serde")]
+            json: false,
+        })
+    }
+
+    /// The watched items, for adding scripts while the engine runs.
+    pub fn watchlist_handle(&self) -> SharedWatchlist {
+        self.items.clone()
+    }
+
+    /// Also write each matching block whole ([`ExportedMatch::raw_block`]).
+    /// The engine must run with
+    /// [`Niebla158::with_raw_blocks`](crate::Niebla158::with_raw_blocks);
+    /// matches without their raw block fail to export.
+    pub fn with_blocks(mut self) -> Self {
+        self.blocks = true;
+        self
+    }
+
+    /// Write each match as a [`MatchRecordV1`](crate::record::MatchRecordV1)
+    /// in a `.json` file (same name otherwise) instead, for consumers that
+    /// already read the webhook's or the command line's JSON. [`read_dir`]
+    /// loads these too, without the amounts and watch items; parse them with
+    /// [`MatchRecordV1::from_json`](crate::record::MatchRecordV1::from_json)
+    /// for those.
+    #[cfg(feature = "serde")]
+    pub fn with_json(mut self) -> Self {
+        self.json = true;
+        self
+    }
+
+    /// Write `record` with only the `relevant` transactions, as JSON.
+    #[cfg(feature = "serde")]
+    fn write_json(
+        &self,
+        mut record: MatchRecordV1,
+        relevant: &HashSet<Txid>,
+    ) -> anyhow::Result<()> {
+        record.retain_txs(|txid| relevant.contains(txid));
+        if !self.blocks {
+            record.raw_block = None;
+        }
+        let name = format!("{:010}-{}.{JSON_EXTENSION}", record.height, record.block);
+        self.write(&name, record.to_json() + "\n")
+    }
+
+    fn write(&self, name: &str, contents: String) -> anyhow::Result<()> {
+        let path = self.dir.join(name);
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, contents).with_context(|| format!("write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("rename to {}", path.display()))
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl WalletHooks for ExportWallet {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(self.items.items().into_iter().map(|i| i.script).collect())
+    }
+
+    async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+        Ok(self.items.items())
+    }
+
+    /// Without amounts to tell them apart, writes all of `txs`.
+    async fn on_block_match(
+        &self,
+        height: u32,
+        block: BlockHash,
+        txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        if self.blocks {
+            anyhow::bail!("block {height} ({block}) was delivered without its raw block");
+        }
+        let relevant: HashSet<Txid> = txs.iter().map(Transaction::compute_txid).collect();
+        if relevant.is_empty() {
+            return Ok(());
+        }
+        #[cfg(feature = "serde")]
+        if self.json {
+            let record = MatchRecordV1::from_block_match(height, block, &txs);
+            return self.write_json(record, &relevant);
+        }
+        let txids: Vec<_> = txs.iter().map(Transaction::compute_txid).collect();
+        let exported = ExportedMatch {
+            height,
+            block,
+            match_id: match_id(height, block, &txids),
+            sequence: 0,
+            txs: keep(txs, &relevant),
+            raw_block: None,
+        };
+        self.write(&exported.file_name(), exported.to_string())
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        if self.blocks && details.raw_block.is_none() {
+            anyhow::bail!(
+                "block {} ({}) was delivered without its raw block; run the engine with_raw_blocks",
+                details.height,
+                details.block
+            );
+        }
+        let relevant = details.relevant_txids();
+        if relevant.is_empty() {
+            return Ok(());
+        }
+        #[cfg(feature = "serde")]
+        if self.json {
+            return self.write_json(MatchRecordV1::from_details(&details), &relevant);
+        }
+        let exported = ExportedMatch {
+            height: details.height,
+            block: details.block,
+            match_id: details.match_id,
+            sequence: details.sequence,
+            txs: keep(details.txs, &relevant),
+            raw_block: details.raw_block.filter(|_| self.blocks),
+        };
+        self.write(&exported.file_name(), exported.to_string())
+    }
+}
+
+/// The transactions of `txs` in `relevant`, in order.
+fn keep(txs: Vec<Transaction>, relevant: &HashSet<Txid>) -> Vec<Transaction> {
+    txs.into_iter()
+        .filter(|tx| relevant.contains(&tx.compute_txid()))
+        .collect()
+}
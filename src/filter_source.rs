@@ -1,18 +1,137 @@
 //! Abstractions for fetching compact filter data from the network (HTTP or P2P).
+use crate::compat::{MaybeSend, MaybeSync};
 use async_trait::async_trait;
 use bitcoin::BlockHash;
+use std::fmt;
+
+/// BIP-157 filter type byte. Only [`FilterType::BASIC`] is defined today; other
+/// values let deployments track future or private filter classes side by side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilterType(pub u8);
+
+impl FilterType {
+    /// BIP-158 basic filter (`0x00`).
+    pub const BASIC: FilterType = FilterType(0x00);
+
+    /// Golomb-Rice parameters this filter type is defined with, if it is a standard one.
+    pub fn standard_params(self) -> Option<GolombParams> {
+        match self {
+            Self::BASIC => Some(GolombParams::BASIC),
+            _ => None,
+        }
+    }
+}
+
+impl Default for FilterType {
+    fn default() -> Self {
+        Self::BASIC
+    }
+}
+
+impl fmt::Display for FilterType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::BASIC => f.write_str("basic"),
+            FilterType(t) => write!(f, "0x{t:02x}"),
+        }
+    }
+}
+
+/// Golomb-coded set parameters: `p` bits of remainder per element and the
+/// false-positive rate `1/m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GolombParams {
+    /// Golomb-Rice coding parameter (remainder bits).
+    pub p: u8,
+    /// Inverse false-positive rate.
+    pub m: u64,
+}
+
+impl GolombParams {
+    /// BIP-158 basic filter parameters (`P = 19`, `M = 784931`).
+    pub const BASIC: GolombParams = GolombParams { p: 19, m: 784_931 };
+
+    /// Check these parameters can decode filters of `filter_type`.
+    ///
+    /// Standard types only accept their own parameters; custom types accept any
+    /// `1 <= p <= 32` and `m >= 1`.
+    pub fn validate_for(&self, filter_type: FilterType) -> anyhow::Result<()> {
+        if let Some(standard) = filter_type.standard_params() {
+            if *self != standard {
+                anyhow::bail!(
+                    "{filter_type} filters use P={} M={}, not P={} M={}",
+                    standard.p,
+                    standard.m,
+                    self.p,
+                    self.m
+                );
+            }
+        }
+        if !(1..=32).contains(&self.p) {
+            anyhow::bail!("Golomb parameter P={} is outside 1..=32", self.p);
+        }
+        if self.m == 0 {
+            anyhow::bail!("Golomb parameter M must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+impl Default for GolombParams {
+    fn default() -> Self {
+        Self::BASIC
+    }
+}
 
 /// A batch of rolling compact-filter headers returned by the source.
+///
+/// With the `serde` feature, `headers` serialize as hex strings of their bytes
+/// in order (not reversed like block hashes).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CfHeadersBatch {
     /// Height of the first header in `headers`.
     pub start_height: u32,
     /// Consecutive rolling cfheader hashes (each is a 32-byte array).
+    #[cfg_attr(feature = "serde", serde(with = "hex_headers"))]
     pub headers: Vec<[u8; 32]>,
 }
 
+/// [`CfHeadersBatch::headers`] as a list of hex strings.
+#[cfg(feature = "serde")]
+mod hex_headers {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(headers: &[[u8; 32]], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(headers.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<[u8; 32]>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .iter()
+            .map(|h| {
+                let mut header = [0; 32];
+                hex::decode_to_slice(h, &mut header).map_err(D::Error::custom)?;
+                Ok(header)
+            })
+            .collect()
+    }
+}
+
 /// Network provider for compact-filter sync.
-#[async_trait]
-pub trait FilterSource: Send + Sync {
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+pub trait FilterSource: MaybeSend + MaybeSync {
+    /// Name of this source (e.g. its URL or peer address) for errors and events.
+    fn label(&self) -> Option<String> {
+        None
+    }
+
     /// Fetch a batch of rolling cfheaders starting at `start_h` and ending at the block `stop_hash`.
     async fn get_cfheaders(
         &self,
@@ -20,8 +139,103 @@ pub trait FilterSource: Send + Sync {
         stop_hash: BlockHash,
     ) -> anyhow::Result<CfHeadersBatch>;
 
+    /// Fetch the filter headers at every 1000th height up to `stop_hash` (BIP-157 `cfcheckpt`).
+    ///
+    /// Sources that cannot serve checkpoints return an empty list (the default).
+    async fn get_cfcheckpt(&self, _stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+        Ok(vec![])
+    }
+
+    /// Highest height this source can serve filters for, if it knows.
+    ///
+    /// Consulted when `get_cfheaders` fails, to tell a lagging source (reported as
+    /// [`EngineError::SourceBehindTip`](crate::EngineError::SourceBehindTip)) from a
+    /// broken one. Sources that cannot tell return `None` (the default). Sources
+    /// that are behind may also just return a shorter `get_cfheaders` batch.
+    async fn filter_tip_height(&self) -> anyhow::Result<Option<u32>> {
+        Ok(None)
+    }
+
+    /// Hash of the block at `height` on this source's best chain, if it knows.
+    ///
+    /// Compared with the [`HeaderSource`](crate::headers::HeaderSource) under
+    /// [`Niebla158::with_source_cross_check`](crate::Niebla158::with_source_cross_check),
+    /// to notice filters and headers coming from different chains. Sources
+    /// that cannot tell return `None` (the default).
+    async fn block_hash_at(&self, _height: u32) -> anyhow::Result<Option<BlockHash>> {
+        Ok(None)
+    }
+
     /// Fetch the raw BIP-158 filter bytes for a given `block` hash.
     async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>>;
+
+    /// [`get_cfheaders`](Self::get_cfheaders) for an explicit filter type.
+    ///
+    /// The default serves [`FilterType::BASIC`] only; sources offering other
+    /// types override the three `*_typed` methods.
+    async fn get_cfheaders_typed(
+        &self,
+        filter_type: FilterType,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        ensure_basic(filter_type)?;
+        self.get_cfheaders(start_h, stop_hash).await
+    }
+
+    /// [`get_cfcheckpt`](Self::get_cfcheckpt) for an explicit filter type.
+    async fn get_cfcheckpt_typed(
+        &self,
+        filter_type: FilterType,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<Vec<BlockHash>> {
+        ensure_basic(filter_type)?;
+        self.get_cfcheckpt(stop_hash).await
+    }
+
+    /// [`get_cfilter`](Self::get_cfilter) for an explicit filter type.
+    async fn get_cfilter_typed(
+        &self,
+        filter_type: FilterType,
+        block: BlockHash,
+    ) -> anyhow::Result<Vec<u8>> {
+        ensure_basic(filter_type)?;
+        self.get_cfilter(block).await
+    }
+
+    /// Filters of `filter_type` for consecutive `blocks`, in order.
+    ///
+    /// The engine asks for windows sized to the source's observed latency. The
+    /// default issues one [`get_cfilter_typed`](Self::get_cfilter_typed) per
+    /// block, all in flight at once; sources with a range request override it.
+    async fn get_cfilter_range(
+        &self,
+        filter_type: FilterType,
+        blocks: &[BlockHash],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        futures_util::future::try_join_all(
+            blocks
+                .iter()
+                .map(|block| self.get_cfilter_typed(filter_type, *block)),
+        )
+        .await
+    }
+
     /// Fetch the raw consensus-encoded block bytes for `block` (used after a filter hit).
-    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>>;
+    ///
+    /// Filter-only sources may leave the default, which fails; pair them with a
+    /// [`BlockSource`](crate::block_source::BlockSource) via
+    /// [`Niebla158::with_block_source`](crate::Niebla158::with_block_source).
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        anyhow::bail!(
+            "this filter source does not serve blocks (wanted {block}); configure a BlockSource"
+        )
+    }
+}
+
+fn ensure_basic(filter_type: FilterType) -> anyhow::Result<()> {
+    if filter_type != FilterType::BASIC {
+        anyhow::bail!("this filter source does not serve {filter_type} filters");
+    }
+    Ok(())
 }
@@ -0,0 +1,346 @@
+//! `Box<T>` and `Arc<T>` implement each trait `T` does, forwarding every method
+//! (defaults included, so overrides are kept), so engines can be built from
+//! trait objects chosen at runtime. See [`DynEngine`](crate::engine::DynEngine).
+use crate::filter_source::{CfHeadersBatch, FilterSource, FilterType};
+use crate::headers::HeaderSource;
+use crate::hooks::{MatchDetails, WalletHooks, WatchItem};
+use crate::store::Store;
+use crate::utxo::Utxo;
+use async_trait::async_trait;
+use bitcoin::{
+    block::Header, hashes::sha256, BlockHash, Network, OutPoint, ScriptBuf, Transaction,
+};
+use std::sync::Arc;
+
+macro_rules! forward {
+    ($ptr:ident) => {
+        #[cfg_attr(niebla_unsend, async_trait(?Send))]
+        #[cfg_attr(not(niebla_unsend), async_trait)]
+        impl<T: Store + ?Sized> Store for $ptr<T> {
+            async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+                (**self).load_cf_tip().await
+            }
+            async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
+                (**self).save_cf_tip(height, cfheader).await
+            }
+            async fn save_cf_headers(
+                &self,
+                start_height: u32,
+                headers: &[BlockHash],
+            ) -> anyhow::Result<()> {
+                (**self).save_cf_headers(start_height, headers).await
+            }
+            async fn load_cf_header(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+                (**self).load_cf_header(height).await
+            }
+            async fn load_cf_tip_typed(
+                &self,
+                filter_type: FilterType,
+            ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+                (**self).load_cf_tip_typed(filter_type).await
+            }
+            async fn save_cf_tip_typed(
+                &self,
+                filter_type: FilterType,
+                height: u32,
+                cfheader: BlockHash,
+            ) -> anyhow::Result<()> {
+                (**self)
+                    .save_cf_tip_typed(filter_type, height, cfheader)
+                    .await
+            }
+            async fn save_cf_headers_typed(
+                &self,
+                filter_type: FilterType,
+                start_height: u32,
+                headers: &[BlockHash],
+            ) -> anyhow::Result<()> {
+                (**self)
+                    .save_cf_headers_typed(filter_type, start_height, headers)
+                    .await
+            }
+            async fn load_cf_header_typed(
+                &self,
+                filter_type: FilterType,
+                height: u32,
+            ) -> anyhow::Result<Option<BlockHash>> {
+                (**self).load_cf_header_typed(filter_type, height).await
+            }
+            async fn get_cf_anchor(
+                &self,
+                filter_type: FilterType,
+            ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+                (**self).get_cf_anchor(filter_type).await
+            }
+            async fn set_cf_anchor(
+                &self,
+                filter_type: FilterType,
+                height: u32,
+                cfheader: BlockHash,
+            ) -> anyhow::Result<()> {
+                (**self).set_cf_anchor(filter_type, height, cfheader).await
+            }
+            async fn get_cf_tip_block(
+                &self,
+                filter_type: FilterType,
+            ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+                (**self).get_cf_tip_block(filter_type).await
+            }
+            async fn set_cf_tip_block(
+                &self,
+                filter_type: FilterType,
+                height: u32,
+                block: BlockHash,
+            ) -> anyhow::Result<()> {
+                (**self).set_cf_tip_block(filter_type, height, block).await
+            }
+            async fn get_last_scanned(&self) -> anyhow::Result<u32> {
+                (**self).get_last_scanned().await
+            }
+            async fn set_last_scanned(&self, height: u32) -> anyhow::Result<()> {
+                (**self).set_last_scanned(height).await
+            }
+            async fn get_last_scanned_block(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+                (**self).get_last_scanned_block().await
+            }
+            async fn set_last_scanned_block(
+                &self,
+                height: u32,
+                block: BlockHash,
+            ) -> anyhow::Result<()> {
+                (**self).set_last_scanned_block(height, block).await
+            }
+            async fn get_delivered(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+                (**self).get_delivered(height).await
+            }
+            async fn set_delivered(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+                (**self).set_delivered(height, block).await
+            }
+            async fn enqueue_match(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+                (**self).enqueue_match(height, block).await
+            }
+            async fn pending_matches(&self) -> anyhow::Result<Vec<(u32, BlockHash)>> {
+                (**self).pending_matches().await
+            }
+            async fn dequeue_match(&self, height: u32) -> anyhow::Result<()> {
+                (**self).dequeue_match(height).await
+            }
+            async fn save_utxo(&self, utxo: &Utxo) -> anyhow::Result<()> {
+                (**self).save_utxo(utxo).await
+            }
+            async fn load_utxo(&self, outpoint: OutPoint) -> anyhow::Result<Option<Utxo>> {
+                (**self).load_utxo(outpoint).await
+            }
+            async fn load_utxos(&self, outpoints: &[OutPoint]) -> anyhow::Result<Vec<Utxo>> {
+                (**self).load_utxos(outpoints).await
+            }
+            async fn utxos(&self) -> anyhow::Result<Vec<Utxo>> {
+                (**self).utxos().await
+            }
+            async fn rollback_utxos(&self, height: u32) -> anyhow::Result<()> {
+                (**self).rollback_utxos(height).await
+            }
+            async fn load_misses(
+                &self,
+                fingerprint: sha256::Hash,
+                from: u32,
+                to: u32,
+            ) -> anyhow::Result<Vec<(u32, BlockHash)>> {
+                (**self).load_misses(fingerprint, from, to).await
+            }
+            async fn save_misses(
+                &self,
+                fingerprint: sha256::Hash,
+                misses: &[(u32, BlockHash)],
+            ) -> anyhow::Result<()> {
+                (**self).save_misses(fingerprint, misses).await
+            }
+            async fn get_watchlist_fingerprint(&self) -> anyhow::Result<Option<sha256::Hash>> {
+                (**self).get_watchlist_fingerprint().await
+            }
+            async fn watched_scripts(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+                (**self).watched_scripts().await
+            }
+            async fn set_watchlist(
+                &self,
+                fingerprint: sha256::Hash,
+                scripts: &[ScriptBuf],
+            ) -> anyhow::Result<()> {
+                (**self).set_watchlist(fingerprint, scripts).await
+            }
+            async fn get_range_progress(
+                &self,
+                start: u32,
+                end: u32,
+            ) -> anyhow::Result<Option<u32>> {
+                (**self).get_range_progress(start, end).await
+            }
+            async fn set_range_progress(
+                &self,
+                start: u32,
+                end: u32,
+                scanned: u32,
+            ) -> anyhow::Result<()> {
+                (**self).set_range_progress(start, end, scanned).await
+            }
+            async fn clear_range_progress(&self) -> anyhow::Result<()> {
+                (**self).clear_range_progress().await
+            }
+            async fn next_delivery_sequence(&self) -> anyhow::Result<Option<u64>> {
+                (**self).next_delivery_sequence().await
+            }
+            async fn scan_sessions(&self) -> anyhow::Result<Vec<String>> {
+                (**self).scan_sessions().await
+            }
+            async fn save_scan_session(&self, id: &str, record: &str) -> anyhow::Result<()> {
+                (**self).save_scan_session(id, record).await
+            }
+            async fn remove_scan_session(&self, id: &str) -> anyhow::Result<()> {
+                (**self).remove_scan_session(id).await
+            }
+            async fn get_birth_height(&self) -> anyhow::Result<Option<u32>> {
+                (**self).get_birth_height().await
+            }
+            async fn set_birth_height(&self, h: u32) -> anyhow::Result<()> {
+                (**self).set_birth_height(h).await
+            }
+            async fn get_network(&self) -> anyhow::Result<Option<Network>> {
+                (**self).get_network().await
+            }
+            async fn set_network(&self, network: Network) -> anyhow::Result<()> {
+                (**self).set_network(network).await
+            }
+            async fn get_config(&self) -> anyhow::Result<Option<String>> {
+                (**self).get_config().await
+            }
+            async fn set_config(&self, record: &str) -> anyhow::Result<()> {
+                (**self).set_config(record).await
+            }
+            async fn wipe(&self) -> anyhow::Result<()> {
+                (**self).wipe().await
+            }
+        }
+
+        #[cfg_attr(niebla_unsend, async_trait(?Send))]
+        #[cfg_attr(not(niebla_unsend), async_trait)]
+        impl<T: FilterSource + ?Sized> FilterSource for $ptr<T> {
+            fn label(&self) -> Option<String> {
+                (**self).label()
+            }
+            async fn get_cfheaders(
+                &self,
+                start_h: u32,
+                stop_hash: BlockHash,
+            ) -> anyhow::Result<CfHeadersBatch> {
+                (**self).get_cfheaders(start_h, stop_hash).await
+            }
+            async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+                (**self).get_cfcheckpt(stop_hash).await
+            }
+            async fn filter_tip_height(&self) -> anyhow::Result<Option<u32>> {
+                (**self).filter_tip_height().await
+            }
+            async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+                (**self).block_hash_at(height).await
+            }
+            async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+                (**self).get_cfilter(block).await
+            }
+            async fn get_cfheaders_typed(
+                &self,
+                filter_type: FilterType,
+                start_h: u32,
+                stop_hash: BlockHash,
+            ) -> anyhow::Result<CfHeadersBatch> {
+                (**self)
+                    .get_cfheaders_typed(filter_type, start_h, stop_hash)
+                    .await
+            }
+            async fn get_cfcheckpt_typed(
+                &self,
+                filter_type: FilterType,
+                stop_hash: BlockHash,
+            ) -> anyhow::Result<Vec<BlockHash>> {
+                (**self).get_cfcheckpt_typed(filter_type, stop_hash).await
+            }
+            async fn get_cfilter_typed(
+                &self,
+                filter_type: FilterType,
+                block: BlockHash,
+            ) -> anyhow::Result<Vec<u8>> {
+                (**self).get_cfilter_typed(filter_type, block).await
+            }
+            async fn get_cfilter_range(
+                &self,
+                filter_type: FilterType,
+                blocks: &[BlockHash],
+            ) -> anyhow::Result<Vec<Vec<u8>>> {
+                (**self).get_cfilter_range(filter_type, blocks).await
+            }
+            async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+                FilterSource::get_block(&**self, block).await
+            }
+        }
+
+        #[cfg_attr(niebla_unsend, async_trait(?Send))]
+        #[cfg_attr(not(niebla_unsend), async_trait)]
+        impl<T: HeaderSource + ?Sized> HeaderSource for $ptr<T> {
+            fn label(&self) -> Option<String> {
+                (**self).label()
+            }
+            async fn tip_height(&self) -> anyhow::Result<u32> {
+                (**self).tip_height().await
+            }
+            async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+                (**self).hash_at_height(height).await
+            }
+            async fn has_height(&self, height: u32) -> anyhow::Result<bool> {
+                (**self).has_height(height).await
+            }
+            async fn hashes_in_range(&self, from: u32, to: u32) -> anyhow::Result<Vec<BlockHash>> {
+                (**self).hashes_in_range(from, to).await
+            }
+            async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+                (**self).header(block).await
+            }
+            async fn headers_from(
+                &self,
+                start: BlockHash,
+                count: u32,
+            ) -> anyhow::Result<Vec<Header>> {
+                (**self).headers_from(start, count).await
+            }
+            async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+                (**self).height_of(block).await
+            }
+        }
+
+        #[cfg_attr(niebla_unsend, async_trait(?Send))]
+        #[cfg_attr(not(niebla_unsend), async_trait)]
+        impl<T: WalletHooks + ?Sized> WalletHooks for $ptr<T> {
+            async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+                (**self).watchlist().await
+            }
+            async fn on_block_match(
+                &self,
+                height: u32,
+                block: BlockHash,
+                txs: Vec<Transaction>,
+            ) -> anyhow::Result<()> {
+                (**self).on_block_match(height, block, txs).await
+            }
+            async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+                (**self).watch_items().await
+            }
+            async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+                (**self).on_match(details).await
+            }
+            async fn on_rollback(&self, height: u32) -> anyhow::Result<()> {
+                (**self).on_rollback(height).await
+            }
+        }
+    };
+}
+
+forward!(Box);
+forward!(Arc);
@@ -0,0 +1,163 @@
+//! gRPC transport for the `niebla.v1.Filters` service (`proto/niebla.proto`).
+//!
+//! [`GrpcSource`] is a client implementing [`FilterSource`] and [`HeaderSource`];
+//! `GrpcService` exposes any such source as a server, so an indexer can serve
+//! the same service from its own backend (not available with the `local` feature).
+use crate::{
+    filter_source::{CfHeadersBatch, FilterSource},
+    headers::HeaderSource,
+};
+use anyhow::Context;
+use async_trait::async_trait;
+use bitcoin::{hashes::Hash, BlockHash};
+use tonic::transport::Channel;
+
+/// Generated protobuf messages, client and server stubs.
+#[allow(missing_docs, clippy::all)]
+pub mod proto {
+    tonic::include_proto!("niebla.v1");
+}
+
+use proto::filters_client::FiltersClient;
+
+// The tonic server needs `Send` futures, so it is unavailable with `local`/wasm.
+#[cfg(not(niebla_unsend))]
+mod service;
+#[cfg(not(niebla_unsend))]
+pub use service::GrpcService;
+
+fn hash_from_bytes(bytes: &[u8]) -> anyhow::Result<BlockHash> {
+    let arr: [u8; 32] = bytes
+        .try_into()
+        .with_context(|| format!("expected 32-byte hash, got {} bytes", bytes.len()))?;
+    Ok(BlockHash::from_byte_array(arr))
+}
+
+/// gRPC client implementing [`FilterSource`] and [`HeaderSource`].
+#[derive(Clone)]
+pub struct GrpcSource {
+    client: FiltersClient<Channel>,
+    endpoint: Option<String>,
+}
+
+impl GrpcSource {
+    /// Connect to a `niebla.v1.Filters` endpoint, e.g. `http://indexer:50051`.
+    pub async fn connect(endpoint: impl Into<String>) -> anyhow::Result<Self> {
+        let endpoint = endpoint.into();
+        let client = FiltersClient::connect(endpoint.clone())
+            .await
+            .with_context(|| format!("connect {endpoint}"))?;
+        Ok(Self {
+            client,
+            endpoint: Some(endpoint),
+        })
+    }
+
+    /// Wrap an already configured channel (TLS, timeouts, interceptors).
+    pub fn from_channel(channel: Channel) -> Self {
+        Self {
+            client: FiltersClient::new(channel),
+            endpoint: None,
+        }
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl FilterSource for GrpcSource {
+    fn label(&self) -> Option<String> {
+        self.endpoint.clone()
+    }
+
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        let resp = self
+            .client
+            .clone()
+            .get_cf_headers(proto::GetCfHeadersRequest {
+                start_height: start_h,
+                stop_hash: stop_hash.to_byte_array().to_vec(),
+            })
+            .await?
+            .into_inner();
+        let headers = resp
+            .filter_hashes
+            .iter()
+            .map(|h| Ok(hash_from_bytes(h)?.to_byte_array()))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(CfHeadersBatch {
+            start_height: resp.start_height,
+            headers,
+        })
+    }
+
+    async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+        let resp = self
+            .client
+            .clone()
+            .get_cf_checkpt(proto::GetCfCheckptRequest {
+                stop_hash: stop_hash.to_byte_array().to_vec(),
+            })
+            .await?
+            .into_inner();
+        resp.filter_headers
+            .iter()
+            .map(|h| hash_from_bytes(h))
+            .collect()
+    }
+
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        let resp = self
+            .client
+            .clone()
+            .get_cfilter(proto::GetCfilterRequest {
+                block_hash: block.to_byte_array().to_vec(),
+            })
+            .await?;
+        Ok(resp.into_inner().filter)
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        let resp = self
+            .client
+            .clone()
+            .get_block(proto::GetBlockRequest {
+                block_hash: block.to_byte_array().to_vec(),
+            })
+            .await?;
+        Ok(resp.into_inner().block)
+    }
+
+    async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        HeaderSource::hash_at_height(self, height).await.map(Some)
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl HeaderSource for GrpcSource {
+    fn label(&self) -> Option<String> {
+        self.endpoint.clone()
+    }
+
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        let resp = self
+            .client
+            .clone()
+            .tip_height(proto::TipHeightRequest {})
+            .await?;
+        Ok(resp.into_inner().height)
+    }
+
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        let resp = self
+            .client
+            .clone()
+            .hash_at_height(proto::HashAtHeightRequest { height })
+            .await?;
+        hash_from_bytes(&resp.into_inner().block_hash)
+    }
+}
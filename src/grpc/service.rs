@@ -0,0 +1,117 @@
+//! tonic server adapter for the `niebla.v1.Filters` service.
+use super::{hash_from_bytes, proto, proto::filters_server::Filters};
+use crate::{filter_source::FilterSource, headers::HeaderSource};
+use async_trait::async_trait;
+use bitcoin::{hashes::Hash, BlockHash};
+use tonic::{Request, Response, Status};
+
+/// Server adapter exposing any `FilterSource + HeaderSource` over gRPC.
+///
+/// ```rust,ignore
+/// tonic::transport::Server::builder()
+///     .add_service(GrpcService::new(my_source).into_server())
+///     .serve(addr)
+///     .await?;
+/// ```
+pub struct GrpcService<T> {
+    inner: T,
+}
+
+impl<T> GrpcService<T>
+where
+    T: FilterSource + HeaderSource + 'static,
+{
+    /// Wrap a source.
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Turn into a tonic service ready for `Server::add_service`.
+    pub fn into_server(self) -> proto::filters_server::FiltersServer<Self> {
+        proto::filters_server::FiltersServer::new(self)
+    }
+}
+
+fn internal(e: anyhow::Error) -> Status {
+    Status::internal(format!("{e:#}"))
+}
+
+// `Status` is tonic's error type for every handler, large or not.
+#[allow(clippy::result_large_err)]
+fn request_hash(bytes: &[u8]) -> Result<BlockHash, Status> {
+    hash_from_bytes(bytes).map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+#[async_trait]
+impl<T> Filters for GrpcService<T>
+where
+    T: FilterSource + HeaderSource + 'static,
+{
+    async fn tip_height(
+        &self,
+        _req: Request<proto::TipHeightRequest>,
+    ) -> Result<Response<proto::TipHeightResponse>, Status> {
+        let height = self.inner.tip_height().await.map_err(internal)?;
+        Ok(Response::new(proto::TipHeightResponse { height }))
+    }
+
+    async fn hash_at_height(
+        &self,
+        req: Request<proto::HashAtHeightRequest>,
+    ) -> Result<Response<proto::HashAtHeightResponse>, Status> {
+        let h = self
+            .inner
+            .hash_at_height(req.into_inner().height)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::HashAtHeightResponse {
+            block_hash: h.to_byte_array().to_vec(),
+        }))
+    }
+
+    async fn get_cf_headers(
+        &self,
+        req: Request<proto::GetCfHeadersRequest>,
+    ) -> Result<Response<proto::GetCfHeadersResponse>, Status> {
+        let req = req.into_inner();
+        let stop = request_hash(&req.stop_hash)?;
+        let batch = self
+            .inner
+            .get_cfheaders(req.start_height, stop)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::GetCfHeadersResponse {
+            start_height: batch.start_height,
+            filter_hashes: batch.headers.iter().map(|h| h.to_vec()).collect(),
+        }))
+    }
+
+    async fn get_cf_checkpt(
+        &self,
+        req: Request<proto::GetCfCheckptRequest>,
+    ) -> Result<Response<proto::GetCfCheckptResponse>, Status> {
+        let stop = request_hash(&req.into_inner().stop_hash)?;
+        let v = self.inner.get_cfcheckpt(stop).await.map_err(internal)?;
+        Ok(Response::new(proto::GetCfCheckptResponse {
+            filter_headers: v.iter().map(|h| h.to_byte_array().to_vec()).collect(),
+        }))
+    }
+
+    async fn get_cfilter(
+        &self,
+        req: Request<proto::GetCfilterRequest>,
+    ) -> Result<Response<proto::GetCfilterResponse>, Status> {
+        let block = request_hash(&req.into_inner().block_hash)?;
+        let filter = self.inner.get_cfilter(block).await.map_err(internal)?;
+        Ok(Response::new(proto::GetCfilterResponse { filter }))
+    }
+
+    async fn get_block(
+        &self,
+        req: Request<proto::GetBlockRequest>,
+    ) -> Result<Response<proto::GetBlockResponse>, Status> {
+        let block = request_hash(&req.into_inner().block_hash)?;
+        let block = self.inner.get_block(block).await.map_err(internal)?;
+        Ok(Response::new(proto::GetBlockResponse { block }))
+    }
+}
@@ -1,12 +1,229 @@
+use crate::compat::{MaybeSend, MaybeSync};
+use anyhow::Context;
 use async_trait::async_trait;
-use bitcoin::BlockHash;
+use bitcoin::{
+    block::Header, consensus, constants::genesis_block, params::Params, BlockHash, CompactTarget,
+    Network,
+};
+use std::collections::HashMap;
+use std::path::Path;
 
 /// Source of block header information (height ↔ hash).
-#[async_trait]
-pub trait HeaderSource: Send + Sync {
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+pub trait HeaderSource: MaybeSend + MaybeSync {
+    /// Name of this source (e.g. its URL) for errors and events.
+    fn label(&self) -> Option<String> {
+        None
+    }
+
     /// Current best height.
     async fn tip_height(&self) -> anyhow::Result<u32>;
 
     /// Block hash at an exact height.
+    ///
+    /// Heights the source does not have (see [`has_height`](Self::has_height))
+    /// may fail with any error: the engine reports them as
+    /// [`EngineError::HeightOutOfRange`](crate::EngineError::HeightOutOfRange).
     async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash>;
+
+    /// Whether the source has a block at `height`.
+    ///
+    /// The default covers genesis up to [`tip_height`](Self::tip_height);
+    /// sources that start higher (e.g. at a wallet's birth height) override it.
+    async fn has_height(&self, height: u32) -> anyhow::Result<bool> {
+        Ok(height <= self.tip_height().await?)
+    }
+
+    /// Block hashes at `from..=to`, in order.
+    ///
+    /// The default asks [`hash_at_height`](Self::hash_at_height) for each
+    /// height; sources with a range lookup override it.
+    async fn hashes_in_range(&self, from: u32, to: u32) -> anyhow::Result<Vec<BlockHash>> {
+        let mut hashes = Vec::with_capacity(to.saturating_sub(from) as usize + 1);
+        for height in from..=to {
+            hashes.push(self.hash_at_height(height).await?);
+        }
+        Ok(hashes)
+    }
+
+    /// The header of `block` (its time, bits and version), or `None` if this
+    /// source only knows hashes (the default).
+    ///
+    /// Consulted for [`MatchDetails::header`](crate::hooks::MatchDetails::header)
+    /// when the matching block was not downloaded.
+    async fn header(&self, _block: BlockHash) -> anyhow::Result<Option<Header>> {
+        Ok(None)
+    }
+
+    /// Up to `count` consecutive headers of this source's chain starting at
+    /// `start`, oldest first.
+    ///
+    /// The default serves none, so callers fall back to one
+    /// [`header`](Self::header) per block; sources that serve headers in
+    /// batches override it.
+    async fn headers_from(&self, _start: BlockHash, _count: u32) -> anyhow::Result<Vec<Header>> {
+        Ok(vec![])
+    }
+
+    /// Height of `block` on this source's chain, or `None` if it is not on it.
+    ///
+    /// The default walks back from the tip through
+    /// [`hashes_in_range`](Self::hashes_in_range), so recent blocks are found
+    /// first; sources with a hash index override it.
+    async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+        const WINDOW: u32 = 2_000;
+        let mut to = self.tip_height().await?;
+        loop {
+            let from = to.saturating_sub(WINDOW - 1);
+            if !self.has_height(from).await? {
+                // Near the source's lowest height: go one block at a time.
+                let mut h = to;
+                while self.has_height(h).await? {
+                    if self.hash_at_height(h).await? == block {
+                        return Ok(Some(h));
+                    }
+                    let Some(below) = h.checked_sub(1) else { break };
+                    h = below;
+                }
+                return Ok(None);
+            }
+            let hashes = self.hashes_in_range(from, to).await?;
+            if let Some(i) = hashes.iter().rposition(|h| *h == block) {
+                return Ok(Some(from + i as u32));
+            }
+            let Some(below) = from.checked_sub(1) else {
+                return Ok(None);
+            };
+            to = below;
+        }
+    }
+}
+
+/// [`HeaderSource`] over a flat file of consecutive 80-byte block headers
+/// starting at genesis (the `headers.bin` layout), held in memory, for fully
+/// offline rescans.
+///
+/// Loading checks that the first header is the network's genesis block, that
+/// every header links to the one before it, and that its proof of work meets
+/// both its own target and the network's limit. On networks without
+/// minimum-difficulty blocks, each header's target must also follow the
+/// 2016-block retarget rule.
+#[derive(Debug, Clone)]
+pub struct FileHeaderSource {
+    headers: Vec<Header>,
+    hashes: Vec<BlockHash>,
+    heights: HashMap<BlockHash, u32>,
+}
+
+impl FileHeaderSource {
+    /// Load and validate the headers in `path`.
+    pub fn open(path: impl AsRef<Path>, network: Network) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let raw = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        Self::from_bytes(&raw, network).with_context(|| format!("headers file {}", path.display()))
+    }
+
+    /// Validate headers already in memory (same layout as [`open`](Self::open)).
+    pub fn from_bytes(raw: &[u8], network: Network) -> anyhow::Result<Self> {
+        if raw.is_empty() || !raw.len().is_multiple_of(80) {
+            anyhow::bail!(
+                "{} bytes is not a whole number of 80-byte headers",
+                raw.len()
+            );
+        }
+        let params = Params::new(network);
+        let retarget = !params.allow_min_difficulty_blocks && !params.no_pow_retargeting;
+        let interval = params.difficulty_adjustment_interval() as usize;
+        let genesis = genesis_block(network).block_hash();
+
+        let mut headers: Vec<Header> = Vec::with_capacity(raw.len() / 80);
+        let mut hashes = Vec::with_capacity(raw.len() / 80);
+        for (height, bytes) in raw.chunks(80).enumerate() {
+            let header: Header =
+                consensus::deserialize(bytes).with_context(|| format!("header {height}"))?;
+            let hash = header.block_hash();
+            match hashes.last() {
+                None if hash != genesis => {
+                    anyhow::bail!("header 0 is {hash}, not the {network} genesis block")
+                }
+                Some(prev) if header.prev_blockhash != *prev => {
+                    anyhow::bail!("header {height} does not connect to header {}", height - 1)
+                }
+                _ => {}
+            }
+            if header.target() > params.max_attainable_target {
+                anyhow::bail!("header {height} has a target above the {network} limit");
+            }
+            header
+                .validate_pow(header.target())
+                .map_err(|e| anyhow::anyhow!("header {height}: {e}"))?;
+            if retarget && height > 0 {
+                let last = &headers[height - 1];
+                let bits = if height.is_multiple_of(interval) {
+                    // Same window as Core: the previous `interval` blocks.
+                    let first = &headers[height - interval];
+                    let timespan = (i64::from(last.time) - i64::from(first.time)).max(0);
+                    CompactTarget::from_next_work_required(last.bits, timespan as u64, &params)
+                } else {
+                    last.bits
+                };
+                if header.bits != bits {
+                    anyhow::bail!("header {height} does not follow the difficulty adjustment");
+                }
+            }
+            headers.push(header);
+            hashes.push(hash);
+        }
+        let heights = (0..).zip(&hashes).map(|(h, hash)| (*hash, h)).collect();
+        Ok(Self {
+            headers,
+            hashes,
+            heights,
+        })
+    }
+
+    /// Block hashes by height.
+    pub(crate) fn hashes(&self) -> &[BlockHash] {
+        &self.hashes
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl HeaderSource for FileHeaderSource {
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        Ok(self.hashes.len() as u32 - 1)
+    }
+
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        self.hashes
+            .get(height as usize)
+            .copied()
+            .with_context(|| format!("height {height} is above the headers file tip"))
+    }
+
+    async fn hashes_in_range(&self, from: u32, to: u32) -> anyhow::Result<Vec<BlockHash>> {
+        self.hashes
+            .get(from as usize..=to as usize)
+            .map(<[BlockHash]>::to_vec)
+            .with_context(|| format!("heights {from}..={to} are above the headers file tip"))
+    }
+
+    async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+        Ok(self.heights.get(&block).map(|&h| self.headers[h as usize]))
+    }
+
+    async fn headers_from(&self, start: BlockHash, count: u32) -> anyhow::Result<Vec<Header>> {
+        let Some(&from) = self.heights.get(&start) else {
+            return Ok(vec![]);
+        };
+        let from = from as usize;
+        let to = (from + count as usize).min(self.headers.len());
+        Ok(self.headers[from..to].to_vec())
+    }
+
+    async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+        Ok(self.heights.get(&block).copied())
+    }
 }
@@ -1,10 +1,20 @@
 //! Wallet glue: provide watchlist items and receive notifications on matches.
+use crate::compat::{MaybeSend, MaybeSync};
 use async_trait::async_trait;
-use bitcoin::{BlockHash, ScriptBuf, Transaction};
+use bitcoin::{
+    block::Header,
+    hashes::{sha256, Hash, HashEngine},
+    Address, Amount, BlockHash, OutPoint, ScriptBuf, Transaction, TxOut, Txid,
+};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
 
-#[async_trait]
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
 /// Return scripts/addresses/outpoints to watch for in BIP-158 filters.
-pub trait WalletHooks: Send + Sync {
+pub trait WalletHooks: MaybeSend + MaybeSync {
     /// Return scripts/addresses/outpoints to watch for in BIP-158 filters.
     async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>>;
     /// Called when a block at `height` with hash `block` matches the watchlist.
@@ -15,4 +25,365 @@ pub trait WalletHooks: Send + Sync {
         block: BlockHash,
         txs: Vec<Transaction>,
     ) -> anyhow::Result<()>;
+
+    /// Watch items, optionally tagged. Used by the engine instead of
+    /// [`watchlist`](Self::watchlist); the default wraps it with no tags.
+    async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+        Ok(self
+            .watchlist()
+            .await?
+            .into_iter()
+            .map(WatchItem::new)
+            .collect())
+    }
+
+    /// Called for every matching block with the watch items it involves.
+    /// The default forwards to [`on_block_match`](Self::on_block_match).
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        self.on_block_match(details.height, details.block, details.txs)
+            .await
+    }
+
+    /// Called when blocks above `height` left the best chain (a reorg) and
+    /// scanning resumes from `height + 1`. Matches delivered above `height` may
+    /// no longer be confirmed; those that reconfirm are delivered again. With
+    /// [UTXO tracking](crate::Niebla158::with_utxo_tracking) their outputs are
+    /// already marked [orphaned](crate::utxo::Utxo::orphaned) in the store.
+    async fn on_rollback(&self, _height: u32) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// A script to watch, with an opaque tag echoed back in [`MatchDetails`]
+/// (e.g. a derivation path or account id).
+///
+/// With the `serde` feature, only `script` is required when deserializing;
+/// the other fields default as in [`WatchItem::new`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatchItem {
+    /// The script_pubkey to look for.
+    pub script: ScriptBuf,
+    /// Caller-defined label; the engine never interprets it.
+    pub tag: Option<String>,
+    /// Included in [`Niebla158::quick_check`](crate::Niebla158::quick_check).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub priority: bool,
+    /// First height that can pay the script (e.g. when the address was
+    /// derived). The script is not matched against filters below it, and an
+    /// [automatic rescan](crate::Niebla158::with_auto_rescan) for it starts
+    /// there instead of at the wallet's birth height.
+    pub birth_height: Option<u32>,
+    /// `script` holds raw filter element bytes (e.g. a tweaked key or a
+    /// tapleaf script under a custom [filter type](crate::Niebla158::with_filter_type))
+    /// rather than a scriptPubKey. It is matched against filters as is; on a
+    /// hit the whole block is downloaded, and the item is reported in
+    /// [`MatchDetails::items`] when its bytes appear in an output script, a
+    /// scriptSig or a witness element.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub element: bool,
+}
+
+impl WatchItem {
+    /// Untagged item for `script`.
+    pub fn new(script: ScriptBuf) -> Self {
+        Self {
+            script,
+            tag: None,
+            priority: false,
+            birth_height: None,
+            element: false,
+        }
+    }
+
+    /// Untagged item for the raw filter element `bytes` (see [`element`](Self::element)).
+    pub fn element(bytes: impl Into<Vec<u8>>) -> Self {
+        Self {
+            element: true,
+            ..Self::new(ScriptBuf::from_bytes(bytes.into()))
+        }
+    }
+
+    /// Whether `txs` involve this item: an output paying its script, or for
+    /// an [`element`](Self::element), its bytes anywhere in an output script,
+    /// scriptSig or witness.
+    pub(crate) fn involved_in(&self, txs: &[Transaction]) -> bool {
+        if !self.element {
+            return txs
+                .iter()
+                .flat_map(|tx| &tx.output)
+                .any(|o| o.script_pubkey == self.script);
+        }
+        let needle = self.script.as_bytes();
+        let contains = |haystack: &[u8]| {
+            !needle.is_empty() && haystack.windows(needle.len()).any(|w| w == needle)
+        };
+        txs.iter().any(|tx| {
+            tx.output
+                .iter()
+                .any(|o| contains(o.script_pubkey.as_bytes()))
+                || tx
+                    .input
+                    .iter()
+                    .any(|i| contains(i.script_sig.as_bytes()) || i.witness.iter().any(contains))
+        })
+    }
+
+    /// Mark this item for [`Niebla158::quick_check`](crate::Niebla158::quick_check)
+    /// (e.g. primary receive addresses).
+    pub fn with_priority(mut self) -> Self {
+        self.priority = true;
+        self
+    }
+
+    /// Attach `tag` to this item.
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Set the item's [`birth_height`](Self::birth_height).
+    pub fn with_birth_height(mut self, height: u32) -> Self {
+        self.birth_height = Some(height);
+        self
+    }
+}
+
+impl From<ScriptBuf> for WatchItem {
+    fn from(script: ScriptBuf) -> Self {
+        Self::new(script)
+    }
+}
+
+/// Watch items that can be changed while an engine runs: the hooks read them
+/// on every run and a control surface (e.g. the `rpc` module) adds to them.
+#[derive(Debug, Clone, Default)]
+pub struct SharedWatchlist {
+    items: Arc<Mutex<Vec<WatchItem>>>,
+}
+
+impl SharedWatchlist {
+    /// Start with `items`.
+    pub fn new(items: Vec<WatchItem>) -> Self {
+        Self {
+            items: Arc::new(Mutex::new(items)),
+        }
+    }
+
+    /// Watch `item` from the next run on. Returns `false` if its script was already watched.
+    pub fn add(&self, item: WatchItem) -> bool {
+        let mut items = self.items.lock().unwrap();
+        if items.iter().any(|i| i.script == item.script) {
+            return false;
+        }
+        items.push(item);
+        true
+    }
+
+    /// Snapshot of the current items.
+    pub fn items(&self) -> Vec<WatchItem> {
+        self.items.lock().unwrap().clone()
+    }
+}
+
+/// Stable hash of a watchlist: SHA-256 over its distinct scripts in byte
+/// order, each prefixed with its length as a little-endian `u64`. Neither the
+/// order nor duplicates of `scripts` change it.
+pub fn watchlist_fingerprint<'a>(scripts: impl IntoIterator<Item = &'a ScriptBuf>) -> sha256::Hash {
+    let scripts: BTreeSet<&ScriptBuf> = scripts.into_iter().collect();
+    let mut engine = sha256::Hash::engine();
+    for script in scripts {
+        engine.input(&(script.len() as u64).to_le_bytes());
+        engine.input(script.as_bytes());
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+/// Stable id of the match of `block` at `height` delivering `txids`, as in
+/// [`MatchDetails::match_id`]: the order of `txids` does not matter.
+pub fn match_id<'a>(
+    height: u32,
+    block: BlockHash,
+    txids: impl IntoIterator<Item = &'a Txid>,
+) -> sha256::Hash {
+    let txids: BTreeSet<&Txid> = txids.into_iter().collect();
+    let mut engine = sha256::Hash::engine();
+    engine.input(&height.to_le_bytes());
+    engine.input(block.as_byte_array());
+    for txid in txids {
+        engine.input(txid.as_byte_array());
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+/// A matching block as passed to [`WalletHooks::on_match`].
+#[derive(Debug, Clone)]
+pub struct MatchDetails {
+    /// Height of the matching block.
+    pub height: u32,
+    /// Hash of the matching block.
+    pub block: BlockHash,
+    /// Header of the matching block (time, bits, version): decoded from the
+    /// downloaded block, else (and always for opaque blocks) from
+    /// [`HeaderSource::header`](crate::headers::HeaderSource::header); `None`
+    /// when neither has it.
+    pub header: Option<Header>,
+    /// Median time past of the matching block: the median timestamp of it and
+    /// its 10 predecessors, as Core's `mediantime`. Transactions with a
+    /// time-based locktime (or CLTV) below it are final in the next block.
+    /// Needs `header` and the predecessors' headers from the
+    /// [`HeaderSource`](crate::headers::HeaderSource); `None` otherwise.
+    pub median_time_past: Option<u32>,
+    /// Decoded transactions (all of them, or just the relevant ones if the
+    /// [`BlockSource`](crate::BlockSource) can filter).
+    pub txs: Vec<Transaction>,
+    /// Watch items paid by an output of `txs`, plus [element](WatchItem::element)
+    /// items found in them. Spends are not attributed here: the spent output's
+    /// script is not in the block.
+    pub items: Vec<WatchItem>,
+    /// Per-transaction amounts relative to the watchlist, in the order of `txs`.
+    pub summaries: Vec<TxSummary>,
+    /// With [`Niebla158::with_prevouts`](crate::Niebla158::with_prevouts), the
+    /// outputs spent by the transactions of `txs` involving the watchlist, as
+    /// far as the engine could resolve them, in input order. Empty otherwise.
+    pub prevouts: Vec<(OutPoint, TxOut)>,
+    /// The block as served, undecoded, when the engine runs with
+    /// [`Niebla158::with_opaque_blocks`](crate::Niebla158::with_opaque_blocks)
+    /// (`txs`, `items` and `summaries` are then empty),
+    /// [`Niebla158::with_raw_blocks`](crate::Niebla158::with_raw_blocks) or
+    /// [`Niebla158::with_watchtower`](crate::Niebla158::with_watchtower).
+    pub raw_block: Option<Vec<u8>>,
+    /// [`match_id`] of `height`, `block` and the txids of `txs`: the same each
+    /// time this match is delivered, across restarts, rescans and replays, for
+    /// consumers that persist matches to deduplicate on.
+    pub match_id: sha256::Hash,
+    /// Number of this delivery in the wallet's delivery order: every call to
+    /// `on_match` gets a higher one (redeliveries included; failed deliveries
+    /// leave gaps), across restarts if the store keeps the sequence (see
+    /// [`Store::next_delivery_sequence`](crate::Store::next_delivery_sequence)).
+    /// 0 for matches returned rather than delivered, e.g. by
+    /// [`Niebla158::check_block`](crate::Niebla158::check_block).
+    pub sequence: u64,
+}
+
+impl MatchDetails {
+    /// The transactions of `txs` that involve the watchlist: their
+    /// [`summaries`](Self::summaries) entry is [`involved`](TxSummary::involved).
+    /// Leaves out filter false positives and the rest of a downloaded block.
+    pub fn relevant_txids(&self) -> HashSet<Txid> {
+        self.summaries
+            .iter()
+            .filter(|s| s.involved)
+            .map(|s| s.txid)
+            .collect()
+    }
+}
+
+/// What one transaction means for the watched scripts.
+///
+/// Spent amounts rely on knowing the spent outputs: from the store with
+/// [UTXO tracking](crate::Niebla158::with_utxo_tracking), else from having
+/// seen them paid (earlier in this block or in a match this engine delivered,
+/// up to a bound), so spends of coins received before it started are not
+/// counted unless a [`TxSource`](crate::tx_source::TxSource) looks them up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxSummary {
+    /// The transaction.
+    pub txid: Txid,
+    /// Sum of its outputs paying watched scripts.
+    pub received: Amount,
+    /// Sum of the known watched outputs it spends.
+    pub sent: Amount,
+    /// Its fee, when every spent output is known (typically our own spends).
+    pub fee: Option<Amount>,
+    /// Whether it involves the watchlist whatever the amounts: it pays a
+    /// watched script, spends a known watched output, contains a watched
+    /// [element](WatchItem::element), or the block source returned it as
+    /// relevant. A spend of an output not known (see above) is not enough.
+    pub involved: bool,
+}
+
+/// How often a match may reach [`WalletHooks::on_block_match`] across crashes.
+///
+/// Progress is persisted after the hook returns, so a crash in between
+/// re-delivers the block on restart unless deliveries are recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Delivery {
+    /// Re-deliver after a crash; hooks must tolerate duplicates (the default).
+    #[default]
+    AtLeastOnce,
+    /// Record each delivery in the wallet's [`Store`](crate::Store) and skip
+    /// blocks already delivered at that height. A crash between the hook
+    /// returning and the record being written can still repeat one delivery;
+    /// stores without delivery bookkeeping behave like `AtLeastOnce`.
+    ExactlyOnce,
+}
+
+/// Address-based alternative to [`WalletHooks`] for apps that never handle scripts.
+///
+/// Wrap an implementation in [`AddressWallet`] to hand it to the engine.
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+pub trait AddressHooks: MaybeSend + MaybeSync {
+    /// Addresses to watch for in BIP-158 filters.
+    async fn addresses(&self) -> anyhow::Result<Vec<Address>>;
+    /// Called when a block at `height` with hash `block` pays or spends from a watched address.
+    async fn on_block_match(
+        &self,
+        height: u32,
+        block: BlockHash,
+        txs: Vec<Transaction>,
+    ) -> anyhow::Result<()>;
+}
+
+/// [`WalletHooks`] over an [`AddressHooks`], converting addresses to
+/// script_pubkeys and caching them across watchlist refreshes.
+pub struct AddressWallet<A> {
+    inner: A,
+    scripts: Mutex<HashMap<Address, ScriptBuf>>,
+}
+
+impl<A: AddressHooks> AddressWallet<A> {
+    /// Wrap `inner`.
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            scripts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The wrapped hooks.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl<A: AddressHooks> WalletHooks for AddressWallet<A> {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        let addresses = self.inner.addresses().await?;
+        let mut cache = self.scripts.lock().unwrap();
+        // Only keep what is still watched so the cache tracks the current list.
+        let mut fresh = HashMap::with_capacity(addresses.len());
+        let watch = addresses
+            .into_iter()
+            .map(|addr| {
+                let script = cache.remove(&addr).unwrap_or_else(|| addr.script_pubkey());
+                fresh.insert(addr, script.clone());
+                script
+            })
+            .collect();
+        *cache = fresh;
+        Ok(watch)
+    }
+
+    async fn on_block_match(
+        &self,
+        height: u32,
+        block: BlockHash,
+        txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        self.inner.on_block_match(height, block, txs).await
+    }
 }
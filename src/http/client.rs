@@ -0,0 +1,178 @@
+//! `reqwest`-based client for the HTTP filter-server protocol.
+use super::{
+    block_path, blockhash_path, cfcheckpt_path, cfheaders_path, cfilter_path, split_hashes,
+    tip_path,
+};
+use crate::{
+    filter_source::{CfHeadersBatch, FilterSource},
+    headers::HeaderSource,
+    layers::Bounded,
+};
+use anyhow::Context;
+use async_trait::async_trait;
+use bitcoin::{hashes::Hash, BlockHash};
+use reqwest::{header, StatusCode};
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// Bodies of immutable responses by path, with the `ETag` they were served with.
+type EtagCache = Arc<Mutex<Bounded<String, (String, Vec<u8>)>>>;
+
+/// [`FilterSource`] (and optional [`HeaderSource`]) speaking the [`crate::http`] protocol.
+///
+/// Only use it as a `HeaderSource` if you trust the server for the header chain;
+/// otherwise pair it with an independent headers provider.
+///
+/// Responses may be gzip- or deflate-encoded; they are decoded transparently.
+#[derive(Clone)]
+pub struct HttpFilterSource {
+    base: String,
+    client: reqwest::Client,
+    etags: Option<EtagCache>,
+}
+
+impl HttpFilterSource {
+    /// Client for a server rooted at `base_url` (e.g. `https://filters.example.com`).
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_client(base_url, reqwest::Client::new())
+    }
+
+    /// Same as [`HttpFilterSource::new`] but with a preconfigured `reqwest::Client`
+    /// (proxies such as Tor, timeouts, custom roots).
+    pub fn with_client(base_url: impl Into<String>, client: reqwest::Client) -> Self {
+        let base = base_url.into().trim_end_matches('/').to_owned();
+        Self {
+            base,
+            client,
+            etags: None,
+        }
+    }
+
+    /// Keep up to `capacity` cfcheckpt, cfheaders and cfilter responses in memory
+    /// along with their `ETag`, evicting the oldest first, and revalidate them with
+    /// `If-None-Match` instead of downloading them again: a `304 Not Modified`
+    /// reuses the cached body. Meant for clients that rescan the same ranges, such
+    /// as a wallet syncing many accounts.
+    pub fn with_etag_cache(mut self, capacity: usize) -> Self {
+        self.etags = Some(Arc::new(Mutex::new(Bounded::new(capacity))));
+        self
+    }
+
+    async fn get_bytes(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let url = format!("{}{path}", self.base);
+        let resp = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("GET {url}"))?
+            .error_for_status()
+            .with_context(|| format!("GET {url}"))?;
+        Ok(resp.bytes().await?.to_vec())
+    }
+
+    /// [`Self::get_bytes`] for immutable objects, going through the `ETag` cache.
+    async fn get_cached(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let Some(cache) = &self.etags else {
+            return self.get_bytes(path).await;
+        };
+        let url = format!("{}{path}", self.base);
+        let cached = cache.lock().unwrap().peek(&path.to_owned()).cloned();
+        let mut req = self.client.get(&url);
+        if let Some((etag, _)) = &cached {
+            req = req.header(header::IF_NONE_MATCH, etag);
+        }
+        let resp = req.send().await.with_context(|| format!("GET {url}"))?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return cached
+                .map(|(_, body)| body)
+                .with_context(|| format!("GET {url}: 304 for an object never fetched"));
+        }
+        let resp = resp
+            .error_for_status()
+            .with_context(|| format!("GET {url}"))?;
+        let etag = resp
+            .headers()
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+        let body = resp.bytes().await?.to_vec();
+        if let Some(etag) = etag {
+            cache
+                .lock()
+                .unwrap()
+                .insert(path.to_owned(), (etag, body.clone()));
+        }
+        Ok(body)
+    }
+
+    async fn get_text(&self, path: &str) -> anyhow::Result<String> {
+        let bytes = self.get_bytes(path).await?;
+        Ok(String::from_utf8(bytes)
+            .context("non-UTF-8 text response")?
+            .trim()
+            .to_owned())
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl FilterSource for HttpFilterSource {
+    fn label(&self) -> Option<String> {
+        Some(self.base.clone())
+    }
+
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        let body = self.get_cached(&cfheaders_path(start_h, stop_hash)).await?;
+        Ok(CfHeadersBatch {
+            start_height: start_h,
+            headers: split_hashes(&body)?,
+        })
+    }
+
+    async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+        let body = self.get_cached(&cfcheckpt_path(stop_hash)).await?;
+        Ok(split_hashes(&body)?
+            .into_iter()
+            .map(BlockHash::from_byte_array)
+            .collect())
+    }
+
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.get_cached(&cfilter_path(block)).await
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.get_bytes(&block_path(block)).await
+    }
+
+    async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        HeaderSource::hash_at_height(self, height).await.map(Some)
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl HeaderSource for HttpFilterSource {
+    fn label(&self) -> Option<String> {
+        Some(self.base.clone())
+    }
+
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        self.get_text(&tip_path())
+            .await?
+            .parse()
+            .context("parse tip height")
+    }
+
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        let text = self.get_text(&blockhash_path(height)).await?;
+        BlockHash::from_str(&text).context("parse block hash")
+    }
+}
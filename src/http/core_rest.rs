@@ -0,0 +1,270 @@
+//! Source backed by Bitcoin Core's REST interface (`-rest -blockfilterindex`).
+use crate::{
+    filter_source::{CfHeadersBatch, FilterSource},
+    headers::HeaderSource,
+    tx_source::TxSource,
+};
+use anyhow::Context;
+use async_trait::async_trait;
+use bitcoin::{
+    block::Header,
+    consensus,
+    hashes::{sha256d, Hash},
+    BlockHash, Transaction, Txid,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+type FilterHashCache = Arc<Mutex<HashMap<BlockHash, [u8; 32]>>>;
+
+/// Spacing of BIP-157 filter-header checkpoints.
+const CFCHECKPT_INTERVAL: u32 = 1_000;
+
+/// [`FilterSource`] + [`HeaderSource`] (and, with `-txindex`, [`TxSource`]) talking to a
+/// local Bitcoin Core node over REST.
+///
+/// Core's REST API exposes filter *headers* but not filter *hashes*, so
+/// [`FilterSource::get_cfheaders`] downloads each filter in the range and hashes it.
+/// That is fine against your own node; for remote clients put `niebla-serve` in front.
+#[derive(Clone)]
+pub struct CoreRestSource {
+    base: String,
+    client: reqwest::Client,
+    filter_hashes: Option<FilterHashCache>,
+}
+
+impl CoreRestSource {
+    /// Node REST root, e.g. `http://127.0.0.1:8332` (without the `/rest` suffix).
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let base = base_url.into().trim_end_matches('/').to_owned();
+        Self {
+            base,
+            client: reqwest::Client::new(),
+            filter_hashes: None,
+        }
+    }
+
+    /// Remember computed filter hashes in memory (~64 bytes per block) so repeated
+    /// `get_cfheaders` calls only download filters once. Meant for long-lived servers.
+    pub fn with_filter_hash_cache(mut self) -> Self {
+        self.filter_hashes = Some(Arc::default());
+        self
+    }
+
+    async fn filter_hash(&self, block: BlockHash) -> anyhow::Result<[u8; 32]> {
+        if let Some(cache) = &self.filter_hashes {
+            if let Some(h) = cache.lock().unwrap().get(&block) {
+                return Ok(*h);
+            }
+        }
+        let filter = self.get_cfilter(block).await?;
+        let h = sha256d::Hash::hash(&filter).to_byte_array();
+        if let Some(cache) = &self.filter_hashes {
+            cache.lock().unwrap().insert(block, h);
+        }
+        Ok(h)
+    }
+
+    async fn get(&self, path: &str) -> anyhow::Result<reqwest::Response> {
+        let url = format!("{}/rest/{path}", self.base);
+        self.client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("GET {url}"))?
+            .error_for_status()
+            .with_context(|| format!("GET {url}"))
+    }
+
+    async fn get_json(&self, path: &str) -> anyhow::Result<Value> {
+        let body = self.get(path).await?.bytes().await?;
+        serde_json::from_slice(&body).with_context(|| format!("decode JSON from /rest/{path}"))
+    }
+
+    /// Height of `block` in the node's active chain.
+    pub async fn height_of(&self, block: BlockHash) -> anyhow::Result<u32> {
+        let v = self
+            .get_json(&format!("headers/{block}.json?count=1"))
+            .await?;
+        let h = v[0]["height"]
+            .as_u64()
+            .with_context(|| format!("no header for {block}"))?;
+        Ok(u32::try_from(h)?)
+    }
+
+    /// Block hashes for `count` consecutive blocks starting at `start`.
+    pub async fn hashes_from(
+        &self,
+        start: BlockHash,
+        count: u32,
+    ) -> anyhow::Result<Vec<BlockHash>> {
+        let v = self
+            .get_json(&format!("headers/{start}.json?count={count}"))
+            .await?;
+        v.as_array()
+            .context("headers: expected array")?
+            .iter()
+            .map(|h| {
+                let s = h["hash"].as_str().context("headers: missing hash")?;
+                Ok(BlockHash::from_str(s)?)
+            })
+            .collect()
+    }
+
+    /// BIP-157 filter header committed for `block`.
+    pub async fn filter_header(&self, block: BlockHash) -> anyhow::Result<BlockHash> {
+        let v = self
+            .get_json(&format!("blockfilterheaders/basic/{block}.json?count=1"))
+            .await?;
+        let s = v[0].as_str().context("blockfilterheaders: expected hex")?;
+        Ok(BlockHash::from_str(s)?)
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl FilterSource for CoreRestSource {
+    fn label(&self) -> Option<String> {
+        Some(self.base.clone())
+    }
+
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        let stop_h = self.height_of(stop_hash).await?;
+        if stop_h < start_h {
+            anyhow::bail!("stop height {stop_h} below start height {start_h}");
+        }
+        let start_hash = self.hash_at_height(start_h).await?;
+        let blocks = self.hashes_from(start_hash, stop_h - start_h + 1).await?;
+
+        let mut headers = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            headers.push(self.filter_hash(block).await?);
+        }
+        Ok(CfHeadersBatch {
+            start_height: start_h,
+            headers,
+        })
+    }
+
+    async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+        let stop_h = self.height_of(stop_hash).await?;
+        let mut out = Vec::with_capacity((stop_h / CFCHECKPT_INTERVAL) as usize);
+        let mut h = CFCHECKPT_INTERVAL;
+        while h <= stop_h {
+            let block = self.hash_at_height(h).await?;
+            out.push(self.filter_header(block).await?);
+            h += CFCHECKPT_INTERVAL;
+        }
+        Ok(out)
+    }
+
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        let v = self
+            .get_json(&format!("blockfilter/basic/{block}.json"))
+            .await?;
+        let hex = v["filter"]
+            .as_str()
+            .context("blockfilter: missing filter")?;
+        Ok(hex::decode(hex)?)
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        Ok(self
+            .get(&format!("block/{block}.bin"))
+            .await?
+            .bytes()
+            .await?
+            .to_vec())
+    }
+
+    async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        HeaderSource::hash_at_height(self, height).await.map(Some)
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl HeaderSource for CoreRestSource {
+    fn label(&self) -> Option<String> {
+        Some(self.base.clone())
+    }
+
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        let v = self.get_json("chaininfo.json").await?;
+        let h = v["blocks"].as_u64().context("chaininfo: missing blocks")?;
+        Ok(u32::try_from(h)?)
+    }
+
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        let v = self
+            .get_json(&format!("blockhashbyheight/{height}.json"))
+            .await?;
+        let s = v["blockhash"]
+            .as_str()
+            .context("blockhashbyheight: missing blockhash")?;
+        Ok(BlockHash::from_str(s)?)
+    }
+
+    async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+        let raw = self
+            .get(&format!("headers/{block}.bin?count=1"))
+            .await?
+            .bytes()
+            .await?;
+        if raw.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(
+            consensus::deserialize(&raw).context("header deserialize")?,
+        ))
+    }
+
+    async fn headers_from(&self, start: BlockHash, count: u32) -> anyhow::Result<Vec<Header>> {
+        let raw = self
+            .get(&format!("headers/{start}.bin?count={count}"))
+            .await?
+            .bytes()
+            .await?;
+        raw.chunks(80)
+            .map(|h| consensus::deserialize(h).context("header deserialize"))
+            .collect()
+    }
+}
+
+/// Needs `-txindex` for transactions outside the mempool; unknown ones are `None`.
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl TxSource for CoreRestSource {
+    fn label(&self) -> Option<String> {
+        Some(self.base.clone())
+    }
+
+    async fn get_transaction(&self, txid: Txid) -> anyhow::Result<Option<Transaction>> {
+        let url = format!("{}/rest/tx/{txid}.bin", self.base);
+        let resp = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("GET {url}"))?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let raw = resp
+            .error_for_status()
+            .with_context(|| format!("GET {url}"))?
+            .bytes()
+            .await?;
+        Ok(Some(
+            consensus::deserialize(&raw).context("tx deserialize")?,
+        ))
+    }
+}
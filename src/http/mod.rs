@@ -0,0 +1,83 @@
+//! HTTP(S) filter-server protocol.
+//!
+//! A deliberately small, CDN-friendly API. Everything addressed by block hash is
+//! immutable and may be cached forever; only the tip moves.
+//!
+//! | Method & path                               | Body                                                        |
+//! |---------------------------------------------|-------------------------------------------------------------|
+//! | `GET /v1/tip`                               | best height, decimal text                                   |
+//! | `GET /v1/blockhash/{height}`                | block hash at `height`, hex text                            |
+//! | `GET /v1/cfcheckpt/{stop_hash}`             | concatenated 32-byte filter headers at every 1000th height  |
+//! | `GET /v1/cfheaders/{start_height}/{stop_hash}` | concatenated 32-byte filter hashes, `start_height ..= stop` |
+//! | `GET /v1/cfilter/{block_hash}`              | raw BIP-158 basic filter bytes                              |
+//! | `GET /v1/block/{block_hash}`                | raw consensus-encoded block                                 |
+//!
+//! Hashes in paths use the usual (byte-reversed) hex display form; hashes in
+//! binary bodies use internal byte order. Unknown objects return `404`.
+//!
+//! Servers may compress bodies (`Content-Encoding: gzip` or `deflate`) and tag
+//! immutable ones with an `ETag`, answering a matching `If-None-Match` with `304`.
+use bitcoin::BlockHash;
+
+#[cfg(feature = "http")]
+mod client;
+#[cfg(feature = "http")]
+mod core_rest;
+#[cfg(feature = "http")]
+mod tx;
+#[cfg(feature = "http")]
+pub use client::HttpFilterSource;
+#[cfg(feature = "http")]
+pub use core_rest::CoreRestSource;
+#[cfg(feature = "http")]
+pub use tx::{CoreRpcTxSource, EsploraTxSource};
+
+/// Protocol version prefix shared by all endpoints.
+pub const PREFIX: &str = "/v1";
+
+/// Size in bytes of every hash in a binary response body.
+pub const HASH_LEN: usize = 32;
+
+/// Path for the best-height endpoint.
+pub fn tip_path() -> String {
+    format!("{PREFIX}/tip")
+}
+
+/// Path for the height → block hash endpoint.
+pub fn blockhash_path(height: u32) -> String {
+    format!("{PREFIX}/blockhash/{height}")
+}
+
+/// Path for the filter-header checkpoints endpoint.
+pub fn cfcheckpt_path(stop: BlockHash) -> String {
+    format!("{PREFIX}/cfcheckpt/{stop}")
+}
+
+/// Path for the filter-hashes range endpoint.
+pub fn cfheaders_path(start_height: u32, stop: BlockHash) -> String {
+    format!("{PREFIX}/cfheaders/{start_height}/{stop}")
+}
+
+/// Path for the per-block filter endpoint.
+pub fn cfilter_path(block: BlockHash) -> String {
+    format!("{PREFIX}/cfilter/{block}")
+}
+
+/// Path for the raw block endpoint.
+pub fn block_path(block: BlockHash) -> String {
+    format!("{PREFIX}/block/{block}")
+}
+
+/// Split a binary body into 32-byte hashes, rejecting trailing bytes.
+pub fn split_hashes(body: &[u8]) -> anyhow::Result<Vec<[u8; HASH_LEN]>> {
+    if !body.len().is_multiple_of(HASH_LEN) {
+        anyhow::bail!(
+            "hash list body is {} bytes, not a multiple of {HASH_LEN}",
+            body.len()
+        );
+    }
+    Ok(body
+        .chunks_exact(HASH_LEN)
+        .map(|c| c.try_into().expect("chunk is HASH_LEN bytes"))
+        .collect())
+}
@@ -0,0 +1,129 @@
+//! [`TxSource`]s over Bitcoin Core's JSON-RPC and Esplora's HTTP API.
+use crate::tx_source::TxSource;
+use anyhow::Context;
+use async_trait::async_trait;
+use bitcoin::{consensus, Transaction, Txid};
+use serde_json::{json, Value};
+
+/// Core's RPC error for an unknown transaction.
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+
+/// [`TxSource`] calling `getrawtransaction` on a Bitcoin Core node.
+///
+/// Transactions outside the mempool need `-txindex`; unknown ones are `None`.
+#[derive(Clone)]
+pub struct CoreRpcTxSource {
+    url: String,
+    auth: Option<(String, String)>,
+    client: reqwest::Client,
+}
+
+impl CoreRpcTxSource {
+    /// Node RPC endpoint, e.g. `http://127.0.0.1:8332`.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            auth: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Authenticate as `user` (`-rpcuser`/`-rpcauth`, or the cookie file's `__cookie__`).
+    pub fn with_basic_auth(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some((user.into(), password.into()));
+        self
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl TxSource for CoreRpcTxSource {
+    fn label(&self) -> Option<String> {
+        Some(self.url.clone())
+    }
+
+    async fn get_transaction(&self, txid: Txid) -> anyhow::Result<Option<Transaction>> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": "niebla",
+            "method": "getrawtransaction",
+            "params": [txid.to_string(), false],
+        });
+        let mut req = self
+            .client
+            .post(&self.url)
+            .header("content-type", "application/json")
+            .body(body.to_string());
+        if let Some((user, password)) = &self.auth {
+            req = req.basic_auth(user, Some(password));
+        }
+        // Core answers RPC errors with a non-2xx status and a JSON body.
+        let body = req
+            .send()
+            .await
+            .with_context(|| format!("POST {}", self.url))?
+            .bytes()
+            .await?;
+        let resp: Value =
+            serde_json::from_slice(&body).context("getrawtransaction: invalid JSON")?;
+        if let Some(err) = resp.get("error").filter(|e| !e.is_null()) {
+            if err["code"].as_i64() == Some(RPC_INVALID_ADDRESS_OR_KEY) {
+                return Ok(None);
+            }
+            anyhow::bail!("getrawtransaction({txid}): {err}");
+        }
+        let hex = resp["result"]
+            .as_str()
+            .context("getrawtransaction: expected hex")?;
+        let raw = hex::decode(hex).context("getrawtransaction: invalid hex")?;
+        Ok(Some(
+            consensus::deserialize(&raw).context("tx deserialize")?,
+        ))
+    }
+}
+
+/// [`TxSource`] over an Esplora HTTP API (`GET /tx/{txid}/raw`).
+#[derive(Clone)]
+pub struct EsploraTxSource {
+    base: String,
+    client: reqwest::Client,
+}
+
+impl EsploraTxSource {
+    /// API root, e.g. `https://blockstream.info/api`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base: base_url.into().trim_end_matches('/').to_owned(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl TxSource for EsploraTxSource {
+    fn label(&self) -> Option<String> {
+        Some(self.base.clone())
+    }
+
+    async fn get_transaction(&self, txid: Txid) -> anyhow::Result<Option<Transaction>> {
+        let url = format!("{}/tx/{txid}/raw", self.base);
+        let resp = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("GET {url}"))?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let raw = resp
+            .error_for_status()
+            .with_context(|| format!("GET {url}"))?
+            .bytes()
+            .await?;
+        Ok(Some(
+            consensus::deserialize(&raw).context("tx deserialize")?,
+        ))
+    }
+}
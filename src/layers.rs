@@ -0,0 +1,657 @@
+//! Wrappers that add retries, timeouts, caching, rate limiting and labels to any
+//! [`FilterSource`] (and [`HeaderSource`]), composed through [`SourceExt`]:
+//!
+//! ```no_run
+//! # use niebla_158::layers::SourceExt;
+//! # use std::time::Duration;
+//! # fn wrap(source: niebla_158::testing::MockFilterSource) {
+//! let source = source
+//!     .with_rate_limit(20)
+//!     .with_timeout(Duration::from_secs(10))
+//!     .with_retry(3, Duration::from_millis(500))
+//!     .with_cache(10_000);
+//! # }
+//! ```
+//!
+//! Each layer wraps the ones before it: above, every retry attempt gets its own
+//! timeout and rate-limit slot, and cached filters skip all three.
+//!
+//! [`SourceExt::with_fair_share`] shares one source (e.g. a peer pool) between
+//! several engines, each through its own [`SharedHandle`]:
+//!
+//! ```no_run
+//! # use niebla_158::layers::SourceExt;
+//! # fn wrap(source: niebla_158::testing::MockFilterSource) {
+//! let pool = source.with_fair_share(8);
+//! let (alice, bob) = (pool.handle(), pool.handle());
+//! // Niebla158::new(store_a, wallet_a, alice, headers_a), and so on.
+//! # }
+//! ```
+use crate::filter_source::{CfHeadersBatch, FilterSource, FilterType};
+use crate::headers::HeaderSource;
+use async_trait::async_trait;
+use bitcoin::{block::Header, BlockHash};
+use futures_util::future::{select, Either};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::oneshot;
+
+/// Builder-style layering for sources; implemented for every [`FilterSource`].
+pub trait SourceExt: Sized {
+    /// Retry failed requests up to `attempts` times in all, waiting `backoff`
+    /// after the first failure and twice as long after each one after that.
+    fn with_retry(self, attempts: u32, backoff: Duration) -> Retry<Self> {
+        Retry {
+            inner: self,
+            attempts: attempts.max(1),
+            backoff,
+        }
+    }
+
+    /// Fail requests that take longer than `limit`.
+    fn with_timeout(self, limit: Duration) -> Timeout<Self> {
+        Timeout { inner: self, limit }
+    }
+
+    /// Keep up to `capacity` filters in memory, evicting the oldest first, so
+    /// rescans and repeated runs do not fetch them again.
+    fn with_cache(self, capacity: usize) -> Cache<Self> {
+        Cache {
+            inner: self,
+            filters: Mutex::new(Bounded::new(capacity)),
+        }
+    }
+
+    /// Start at most `per_second` requests a second, evenly spaced.
+    fn with_rate_limit(self, per_second: u32) -> RateLimit<Self> {
+        RateLimit {
+            inner: self,
+            interval: Duration::from_secs(1) / per_second.max(1),
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Name this source `label` in errors and events, whatever it calls itself
+    /// (see [`FilterSource::label`]). Useful to tell a primary from its fallbacks.
+    fn with_label(self, label: impl Into<String>) -> Labeled<Self> {
+        Labeled {
+            inner: self,
+            label: label.into(),
+        }
+    }
+
+    /// Share this source between engines: each takes a [`SharedHandle`] from
+    /// the returned [`Shared`], and at most `max_in_flight` requests run at
+    /// once. When requests queue up, handles take turns, so an engine deep in
+    /// an initial sync cannot starve the others.
+    fn with_fair_share(self, max_in_flight: usize) -> Shared<Self> {
+        Shared {
+            inner: Arc::new(self),
+            fair: Arc::new(Mutex::new(Fair {
+                limit: max_in_flight.max(1),
+                ..Fair::default()
+            })),
+        }
+    }
+}
+
+impl<S: FilterSource> SourceExt for S {}
+
+/// See [`SourceExt::with_retry`].
+pub struct Retry<S> {
+    inner: S,
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl<S> Retry<S> {
+    /// The wrapped source.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    async fn layer<T, Fut>(&self, call: impl Fn() -> Fut) -> anyhow::Result<T>
+    where
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut backoff = self.backoff;
+        for _ in 1..self.attempts {
+            if let Ok(out) = call().await {
+                return Ok(out);
+            }
+            crate::rt::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+        }
+        call().await
+    }
+}
+
+/// See [`SourceExt::with_timeout`].
+pub struct Timeout<S> {
+    inner: S,
+    limit: Duration,
+}
+
+impl<S> Timeout<S> {
+    /// The wrapped source.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    async fn layer<T, Fut>(&self, call: impl Fn() -> Fut) -> anyhow::Result<T>
+    where
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let (call, timer) = (
+            std::pin::pin!(call()),
+            std::pin::pin!(crate::rt::sleep(self.limit)),
+        );
+        match select(call, timer).await {
+            Either::Left((out, _)) => out,
+            Either::Right(((), _)) => anyhow::bail!("request timed out after {:?}", self.limit),
+        }
+    }
+}
+
+/// See [`SourceExt::with_rate_limit`].
+pub struct RateLimit<S> {
+    inner: S,
+    interval: Duration,
+    /// When the next request may start.
+    next: Mutex<Option<SystemTime>>,
+}
+
+impl<S> RateLimit<S> {
+    /// The wrapped source.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    async fn layer<T, Fut>(&self, call: impl Fn() -> Fut) -> anyhow::Result<T>
+    where
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let now = crate::rt::now();
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = next.map_or(now, |n| n.max(now));
+            *next = Some(slot + self.interval);
+            slot
+        };
+        if let Ok(wait) = slot.duration_since(now) {
+            if !wait.is_zero() {
+                crate::rt::sleep(wait).await;
+            }
+        }
+        call().await
+    }
+}
+
+/// See [`SourceExt::with_label`].
+pub struct Labeled<S> {
+    inner: S,
+    label: String,
+}
+
+impl<S> Labeled<S> {
+    /// The wrapped source.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    async fn layer<T, Fut>(&self, call: impl Fn() -> Fut) -> anyhow::Result<T>
+    where
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        call().await
+    }
+}
+
+/// See [`SourceExt::with_fair_share`]. Cloning it shares the same source and
+/// request budget.
+pub struct Shared<S> {
+    inner: Arc<S>,
+    fair: Arc<Mutex<Fair>>,
+}
+
+impl<S> Clone for Shared<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            fair: self.fair.clone(),
+        }
+    }
+}
+
+impl<S> Shared<S> {
+    /// The wrapped source.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// A new handle with its own request queue, for one engine.
+    pub fn handle(&self) -> SharedHandle<S> {
+        let mut fair = self.fair.lock().unwrap();
+        fair.next_id += 1;
+        SharedHandle {
+            inner: self.inner.clone(),
+            fair: self.fair.clone(),
+            id: fair.next_id,
+        }
+    }
+
+    /// Requests running now, and waiting for a turn.
+    pub fn load(&self) -> (usize, usize) {
+        let fair = self.fair.lock().unwrap();
+        let waiting = fair.queues.values().map(VecDeque::len).sum();
+        (fair.in_flight, waiting)
+    }
+}
+
+/// One engine's share of a [`Shared`] source.
+pub struct SharedHandle<S> {
+    inner: Arc<S>,
+    fair: Arc<Mutex<Fair>>,
+    id: u64,
+}
+
+/// Scheduler state behind a [`Shared`] source.
+#[derive(Default)]
+struct Fair {
+    limit: usize,
+    in_flight: usize,
+    next_id: u64,
+    /// Waiting requests per handle.
+    queues: HashMap<u64, VecDeque<oneshot::Sender<()>>>,
+    /// Handles with waiting requests, next turn first.
+    turns: VecDeque<u64>,
+}
+
+impl Fair {
+    /// Hand a finished request's slot to the next handle in turn, or free it.
+    fn release(&mut self) {
+        while let Some(id) = self.turns.pop_front() {
+            let queue = self.queues.get_mut(&id).expect("turns have queues");
+            let waiter = queue.pop_front().expect("turns have waiters");
+            if queue.is_empty() {
+                self.queues.remove(&id);
+            } else {
+                self.turns.push_back(id);
+            }
+            // Waiters whose request was dropped no longer want the slot.
+            if waiter.send(()).is_ok() {
+                return;
+            }
+        }
+        self.in_flight -= 1;
+    }
+}
+
+/// A running request's slot; handed on when dropped.
+struct Slot<'a>(&'a Mutex<Fair>);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().release();
+    }
+}
+
+/// A queued request's turn. If the request is dropped after being woken but
+/// before it ran, the slot it was handed is passed on.
+struct Turn<'a>(oneshot::Receiver<()>, &'a Mutex<Fair>);
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        if self.0.try_recv().is_ok() {
+            self.1.lock().unwrap().release();
+        }
+    }
+}
+
+impl<S> SharedHandle<S> {
+    /// The wrapped source.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    async fn layer<T, Fut>(&self, call: impl Fn() -> Fut) -> anyhow::Result<T>
+    where
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let turn = {
+            let mut fair = self.fair.lock().unwrap();
+            if fair.in_flight < fair.limit && fair.turns.is_empty() {
+                fair.in_flight += 1;
+                None
+            } else {
+                let (tx, rx) = oneshot::channel();
+                let queue = fair.queues.entry(self.id).or_default();
+                queue.push_back(tx);
+                if queue.len() == 1 {
+                    fair.turns.push_back(self.id);
+                }
+                Some(Turn(rx, &self.fair))
+            }
+        };
+        if let Some(mut turn) = turn {
+            (&mut turn.0)
+                .await
+                .expect("queued requests are woken before the scheduler is dropped");
+        }
+        let _slot = Slot(&self.fair);
+        call().await
+    }
+}
+
+/// Forward every [`FilterSource`] and [`HeaderSource`] method through the
+/// wrapper's `layer`, and its label through `$label` (the inner source's by default).
+macro_rules! layered {
+    ($layer:ident) => {
+        layered!($layer, |this| this.inner.label());
+    };
+    ($layer:ident, |$this:ident| $label:expr) => {
+        #[cfg_attr(niebla_unsend, async_trait(?Send))]
+        #[cfg_attr(not(niebla_unsend), async_trait)]
+        impl<S: FilterSource> FilterSource for $layer<S> {
+            fn label(&self) -> Option<String> {
+                let $this = self;
+                $label
+            }
+            async fn get_cfheaders(
+                &self,
+                start_h: u32,
+                stop_hash: BlockHash,
+            ) -> anyhow::Result<CfHeadersBatch> {
+                self.layer(|| self.inner.get_cfheaders(start_h, stop_hash))
+                    .await
+            }
+            async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+                self.layer(|| self.inner.get_cfcheckpt(stop_hash)).await
+            }
+            async fn filter_tip_height(&self) -> anyhow::Result<Option<u32>> {
+                self.layer(|| self.inner.filter_tip_height()).await
+            }
+            async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+                self.layer(|| self.inner.block_hash_at(height)).await
+            }
+            async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+                self.layer(|| self.inner.get_cfilter(block)).await
+            }
+            async fn get_cfheaders_typed(
+                &self,
+                filter_type: FilterType,
+                start_h: u32,
+                stop_hash: BlockHash,
+            ) -> anyhow::Result<CfHeadersBatch> {
+                self.layer(|| {
+                    self.inner
+                        .get_cfheaders_typed(filter_type, start_h, stop_hash)
+                })
+                .await
+            }
+            async fn get_cfcheckpt_typed(
+                &self,
+                filter_type: FilterType,
+                stop_hash: BlockHash,
+            ) -> anyhow::Result<Vec<BlockHash>> {
+                self.layer(|| self.inner.get_cfcheckpt_typed(filter_type, stop_hash))
+                    .await
+            }
+            async fn get_cfilter_typed(
+                &self,
+                filter_type: FilterType,
+                block: BlockHash,
+            ) -> anyhow::Result<Vec<u8>> {
+                self.layer(|| self.inner.get_cfilter_typed(filter_type, block))
+                    .await
+            }
+            async fn get_cfilter_range(
+                &self,
+                filter_type: FilterType,
+                blocks: &[BlockHash],
+            ) -> anyhow::Result<Vec<Vec<u8>>> {
+                self.layer(|| self.inner.get_cfilter_range(filter_type, blocks))
+                    .await
+            }
+            async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+                self.layer(|| FilterSource::get_block(&self.inner, block))
+                    .await
+            }
+        }
+
+        #[cfg_attr(niebla_unsend, async_trait(?Send))]
+        #[cfg_attr(not(niebla_unsend), async_trait)]
+        impl<S: HeaderSource> HeaderSource for $layer<S> {
+            fn label(&self) -> Option<String> {
+                let $this = self;
+                $label
+            }
+            async fn tip_height(&self) -> anyhow::Result<u32> {
+                self.layer(|| self.inner.tip_height()).await
+            }
+            async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+                self.layer(|| self.inner.hash_at_height(height)).await
+            }
+            async fn has_height(&self, height: u32) -> anyhow::Result<bool> {
+                self.layer(|| self.inner.has_height(height)).await
+            }
+            async fn hashes_in_range(&self, from: u32, to: u32) -> anyhow::Result<Vec<BlockHash>> {
+                self.layer(|| self.inner.hashes_in_range(from, to)).await
+            }
+            async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+                self.layer(|| self.inner.header(block)).await
+            }
+            async fn headers_from(
+                &self,
+                start: BlockHash,
+                count: u32,
+            ) -> anyhow::Result<Vec<Header>> {
+                self.layer(|| self.inner.headers_from(start, count)).await
+            }
+            async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+                self.layer(|| self.inner.height_of(block)).await
+            }
+        }
+    };
+}
+
+layered!(Retry);
+layered!(Timeout);
+layered!(RateLimit);
+layered!(Labeled, |this| Some(this.label.clone()));
+layered!(SharedHandle);
+
+/// See [`SourceExt::with_cache`].
+pub struct Cache<S> {
+    inner: S,
+    filters: Mutex<Bounded<(FilterType, BlockHash), Vec<u8>>>,
+}
+
+impl<S> Cache<S> {
+    /// The wrapped source.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn get(&self, key: (FilterType, BlockHash)) -> Option<Vec<u8>> {
+        self.filters.lock().unwrap().peek(&key).cloned()
+    }
+
+    fn insert(&self, key: (FilterType, BlockHash), filter: &[u8]) {
+        self.filters.lock().unwrap().insert(key, filter.to_vec());
+    }
+}
+
+/// A map of at most `capacity` entries that evicts the least recently
+/// inserted first.
+pub(crate) struct Bounded<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (u64, V)>,
+    /// Keys by insertion, oldest first.
+    order: BTreeMap<u64, K>,
+    inserts: u64,
+}
+
+impl<K: Eq + Hash + Clone, V> Bounded<K, V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            inserts: 0,
+        }
+    }
+
+    /// The value of `key`.
+    pub(crate) fn peek(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|(_, value)| value)
+    }
+
+    /// Insert or replace `key`, evicting the oldest entry if full.
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.inserts += 1;
+        if let Some((used, _)) = self.entries.insert(key.clone(), (self.inserts, value)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.inserts, key);
+        while self.entries.len() > self.capacity {
+            let (_, oldest) = self.order.pop_first().expect("over capacity");
+            self.entries.remove(&oldest);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &K) -> Option<V> {
+        let (used, value) = self.entries.remove(key)?;
+        self.order.remove(&used);
+        Some(value)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl<S: FilterSource> FilterSource for Cache<S> {
+    fn label(&self) -> Option<String> {
+        self.inner.label()
+    }
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        self.inner.get_cfheaders(start_h, stop_hash).await
+    }
+    async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+        self.inner.get_cfcheckpt(stop_hash).await
+    }
+    async fn filter_tip_height(&self) -> anyhow::Result<Option<u32>> {
+        self.inner.filter_tip_height().await
+    }
+    async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        self.inner.block_hash_at(height).await
+    }
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.get_cfilter_typed(FilterType::BASIC, block).await
+    }
+    async fn get_cfheaders_typed(
+        &self,
+        filter_type: FilterType,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        self.inner
+            .get_cfheaders_typed(filter_type, start_h, stop_hash)
+            .await
+    }
+    async fn get_cfcheckpt_typed(
+        &self,
+        filter_type: FilterType,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<Vec<BlockHash>> {
+        self.inner.get_cfcheckpt_typed(filter_type, stop_hash).await
+    }
+    async fn get_cfilter_typed(
+        &self,
+        filter_type: FilterType,
+        block: BlockHash,
+    ) -> anyhow::Result<Vec<u8>> {
+        if let Some(filter) = self.get((filter_type, block)) {
+            return Ok(filter);
+        }
+        let filter = self.inner.get_cfilter_typed(filter_type, block).await?;
+        self.insert((filter_type, block), &filter);
+        Ok(filter)
+    }
+    async fn get_cfilter_range(
+        &self,
+        filter_type: FilterType,
+        blocks: &[BlockHash],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut filters: Vec<Option<Vec<u8>>> =
+            blocks.iter().map(|b| self.get((filter_type, *b))).collect();
+        let missing: Vec<BlockHash> = blocks
+            .iter()
+            .zip(&filters)
+            .filter(|(_, f)| f.is_none())
+            .map(|(b, _)| *b)
+            .collect();
+        if !missing.is_empty() {
+            let fetched = self.inner.get_cfilter_range(filter_type, &missing).await?;
+            anyhow::ensure!(
+                fetched.len() == missing.len(),
+                "get_cfilter_range returned {} filters, expected {}",
+                fetched.len(),
+                missing.len()
+            );
+            let mut fetched = missing.iter().zip(fetched);
+            for slot in filters.iter_mut().filter(|f| f.is_none()) {
+                let (block, filter) = fetched.next().expect("one per missing block");
+                self.insert((filter_type, *block), &filter);
+                *slot = Some(filter);
+            }
+        }
+        Ok(filters.into_iter().map(|f| f.unwrap_or_default()).collect())
+    }
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        FilterSource::get_block(&self.inner, block).await
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl<S: HeaderSource> HeaderSource for Cache<S> {
+    fn label(&self) -> Option<String> {
+        self.inner.label()
+    }
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        self.inner.tip_height().await
+    }
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        self.inner.hash_at_height(height).await
+    }
+    async fn has_height(&self, height: u32) -> anyhow::Result<bool> {
+        self.inner.has_height(height).await
+    }
+    async fn hashes_in_range(&self, from: u32, to: u32) -> anyhow::Result<Vec<BlockHash>> {
+        self.inner.hashes_in_range(from, to).await
+    }
+    async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+        self.inner.header(block).await
+    }
+    async fn headers_from(&self, start: BlockHash, count: u32) -> anyhow::Result<Vec<Header>> {
+        self.inner.headers_from(start, count).await
+    }
+    async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+        self.inner.height_of(block).await
+    }
+}
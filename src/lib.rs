@@ -4,9 +4,29 @@
 //!
 //! ## What you implement
 //! - [`FilterSource`]: fetch cfheaders batches, per-block filters, and raw blocks.
-//! - [`WalletHooks`]: provide a **watchlist** and handle **on_block_match** callbacks.
-//! - [`Store`]: keep a couple of integers (verified tip + last scanned).
+//! - [`WalletHooks`]: provide a **watchlist** and handle **on_block_match** callbacks
+//!   (or [`AddressHooks`] wrapped in [`AddressWallet`] to watch addresses instead of scripts).
+//! - [`Store`]: keep the verified cfheaders tip and the last scanned height;
+//!   every other method is optional, but some features need one implemented
+//!   (their defaults fail):
+//!   - the pending queue (`enqueue_match` and friends) for
+//!     [`with_delivery_queue`](Niebla158::with_delivery_queue),
+//!     [`with_watchtower`](Niebla158::with_watchtower) and
+//!     [`run_parallel`](Niebla158::run_parallel);
+//!   - the UTXO methods (`save_utxo` and friends) for
+//!     [`with_utxo_tracking`](Niebla158::with_utxo_tracking);
+//!   - the scan session methods for [`add_session`](Niebla158::add_session);
+//!   - the typed cfheaders methods for filter types other than basic;
+//!   - `wipe` for [`reset`](Niebla158::reset) and
+//!     [`with_assume_fresh`](Niebla158::with_assume_fresh).
+//!
+//!   The rest degrade quietly when left out: without the last scanned block
+//!   hash reorgs go unnoticed, without delivered records
+//!   [`Delivery::ExactlyOnce`](hooks::Delivery::ExactlyOnce) is at least once.
+//!   [`MemoryStore`](testing::MemoryStore) and the SQLite store implement them all.
 //! - [`HeaderSource`]: return block header info by height (used to scan ranges).
+//! - Optionally a [`BlockSource`] to fetch matching blocks from somewhere other
+//!   than the filter source.
 //!
 //! ## What the engine does
 //! - Validates **cfheaders** against optional checkpoints (defense-in-depth).
@@ -67,34 +87,138 @@
 //!     Ok(())
 //! }
 //! ```
+/// Integrity audits of stored cfheaders and cached verdicts.
+pub mod audit;
+
+/// One-call deposit detection over a large, fixed script set.
+pub mod deposits;
+
+/// Debug dumps of the cfheaders and filters received for a window of heights.
+pub mod dump;
+
+/// BIP-157 rolling filter-header chain and checkpoint checks, for verifying
+/// cfheaders outside the engine (e.g. in a proxy).
+pub mod cfheaders;
+
+/// Block provider used after filter hits (defaults to the filter source).
+pub mod block_source;
+
+/// Record of the settings an engine ran with, checked against later runs.
+pub mod config;
+
+/// Pause/resume handle and status snapshots for a running engine.
+pub mod control;
+
 /// Engine that verifies cfheaders, scans filters, and fetches matching blocks.
 pub mod engine;
 
+/// Typed errors raised inside the engine's `anyhow::Error` results.
+pub mod error;
+
 /// Traits and types for fetching cfheaders, cfilters, and blocks from the network.
 pub mod filter_source;
 
+/// gRPC client and server adapter for `proto/niebla.proto`.
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
+/// HTTP(S) filter-server protocol; the `HttpFilterSource` client needs the `http` feature.
+pub mod http;
+
 /// Wallet callbacks: provide a watchlist and receive matches.
 pub mod hooks;
 
-/// Block header lookup abstraction (height → hash).
+/// Webhook delivery: POST matches as signed JSON to an HTTP endpoint.
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+/// Matches written to files for offline (air-gapped) processing.
+pub mod export;
+
+/// Versioned JSON schema for matches (`MatchRecordV1`).
+#[cfg(feature = "serde")]
+pub mod record;
+
+/// JSON-RPC control interface (`getprogress`, `addwatchscript`, `rescan`, ...).
+#[cfg(feature = "rpc")]
+pub mod rpc;
+
+/// Pure parsers for untrusted source data (filters, cfheaders, blocks), fuzzed in `fuzz/`.
+pub mod parse;
+
+/// Filter matching outside the engine: script, address and outpoint queries
+/// against a raw BIP-158 filter, and filter validation.
+pub mod matcher;
+
+/// Retry, timeout, cache and rate-limit wrappers for sources, composed with `SourceExt`.
+pub mod layers;
+
+/// Transaction lookup by txid, for prevouts the matched blocks do not contain.
+pub mod tx_source;
+
+/// Lightning watchtower mode: breach callbacks with whole blocks, delivered at least once.
+pub mod watchtower;
+
+/// Zero-conf watching: double-spend, replacement and eviction alerts for unconfirmed payments.
+pub mod mempool;
+
+/// Scheduling rules (throttling, time windows, session budgets) for the sync loop.
+pub mod policy;
+
+/// Block header lookup abstraction (height → hash) and a headers-file source.
 pub mod headers;
 
+/// Machine-readable engine events (checkpoint mismatches, ...).
+pub mod events;
+
+/// Import cfheaders from an existing LND/Neutrino filter-header database.
+pub mod neutrino;
+
+/// Air-gapped scanning from filters, blocks and headers in a local directory.
+pub mod offline;
+
+/// Record source responses to a file and replay them deterministically.
+pub mod replay;
+
+/// Named scans of a height range with their own stored progress.
+pub mod session;
+
+/// `Send`/`Sync` bounds that relax on single-threaded (wasm) targets.
+pub mod compat;
+
+/// Built-in cfheader checkpoints and validation of checkpoint lists.
+pub mod checkpoints;
+
+/// In-memory mocks (`MockFilterSource`, `MemoryStore`, ...) for testing integrations.
+pub mod testing;
+
 // Internal helpers:
-mod cfheaders;
-mod checkpoints;
-mod matcher;
+mod adaptive;
+mod forward;
+mod rt;
+
+/// Confirmed UTXO tracking (balance and coin listing) on top of the `Store`.
+pub mod utxo;
 
 /// Persistence layer (traits and SQLite implementation).
 pub mod store;
 
 // Public re-exports
-pub use engine::Niebla158;
+pub use block_source::BlockSource;
+pub use control::{EngineHandle, EnginePhase, EngineStatus, Health, PhaseTimings, RunState};
+pub use engine::{DynEngine, Niebla158};
+pub use error::{CheckpointMismatch, EngineError, InvalidFilter};
 pub use filter_source::FilterSource;
-pub use hooks::WalletHooks;
-pub use store::{sqlite_store::SqliteStore, Store};
+pub use hooks::{
+    AddressHooks, AddressWallet, MatchDetails, SharedWatchlist, TxSummary, WalletHooks, WatchItem,
+};
+#[cfg(feature = "sqlite")]
+pub use store::sqlite_store::SqliteStore;
+pub use store::Store;
 
 /// Convenience prelude for end users.
 pub mod prelude {
-    pub use crate::{FilterSource, Niebla158, SqliteStore, Store, WalletHooks};
+    #[cfg(feature = "sqlite")]
+    pub use crate::SqliteStore;
+    pub use crate::{EngineHandle, FilterSource, Niebla158, Store, WalletHooks};
 }
-
@@ -1,31 +1,396 @@
-use bitcoin::{bip158::BlockFilter, Address, BlockHash, ScriptBuf};
+use crate::filter_source::GolombParams;
+use crate::hooks::WatchItem;
+use crate::parse::filter_elements;
+use bitcoin::{
+    bip158::{GcsFilterReader, GcsFilterWriter},
+    hashes::{siphash24, Hash},
+    Address, BlockHash, OutPoint, ScriptBuf,
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{mpsc, Mutex};
 
-pub fn filter_matches_any<I>(
+/// Whether any of `scripts` is in the filter `raw_filter` of block `block_hash`.
+///
+/// Filters have false positives (about 1 in 784931 per script for basic
+/// filters) but no false negatives: `false` means the block neither pays nor
+/// spends any of `scripts`.
+pub fn filter_matches_any(
+    raw_filter: &[u8],
     block_hash: BlockHash,
+    scripts: &[ScriptBuf],
+    params: GolombParams,
+) -> Result<bool, bitcoin::bip158::Error> {
+    if scripts.is_empty() {
+        return Ok(false);
+    }
+    let (_, _, reader) = filter_reader(block_hash, params);
+    reader.match_any(
+        &mut &raw_filter[..],
+        &mut scripts.iter().map(|s| s.as_bytes()),
+    )
+}
+
+/// [`filter_matches_any`] for the script_pubkeys of `addresses`.
+pub fn filter_matches_any_address(
     raw_filter: &[u8],
-    scripts: I,
-) -> Result<bool, bitcoin::bip158::Error>
-where
-    I: IntoIterator<Item = ScriptBuf>,
-{
-    let filter = BlockFilter::new(raw_filter);
+    block_hash: BlockHash,
+    addresses: &[Address],
+    params: GolombParams,
+) -> Result<bool, bitcoin::bip158::Error> {
+    let scripts: Vec<ScriptBuf> = addresses.iter().map(|a| a.script_pubkey()).collect();
+    filter_matches_any(raw_filter, block_hash, &scripts, params)
+}
+
+/// Which of `outpoints`, each with the script_pubkey of the output it names,
+/// the block may spend.
+///
+/// Basic filters hold the scripts of spent outputs, not the outpoints, so a
+/// hit means the block spends some output locked to that script (or is a
+/// false positive); fetch the block to tell.
+pub fn outpoints_maybe_spent(
+    raw_filter: &[u8],
+    block_hash: BlockHash,
+    outpoints: &[(OutPoint, ScriptBuf)],
+    params: GolombParams,
+) -> Result<Vec<OutPoint>, bitcoin::bip158::Error> {
+    let (_, _, reader) = filter_reader(block_hash, params);
+    let mut spent = Vec::new();
+    for (outpoint, script) in outpoints {
+        let mut query = std::iter::once(script.as_bytes());
+        if reader.match_any(&mut &raw_filter[..], &mut query)? {
+            spent.push(*outpoint);
+        }
+    }
+    Ok(spent)
+}
+
+/// Code `elements` (scripts or raw element bytes) as the filter of block
+/// `block_hash` with `params`, as a filter server would. Empty elements and
+/// duplicates are dropped, as BIP-158 does.
+pub fn build_filter<'a>(
+    block_hash: BlockHash,
+    elements: impl IntoIterator<Item = &'a [u8]>,
+    params: GolombParams,
+) -> Vec<u8> {
+    let (k0, k1, _) = filter_reader(block_hash, params);
+    let mut out = vec![];
+    let mut writer = GcsFilterWriter::new(&mut out, k0, k1, params.m, params.p);
+    for element in elements {
+        writer.add_element(element);
+    }
+    writer.finish().expect("writing to a Vec cannot fail");
+    out
+}
+
+/// The SipHash keys for `block_hash`'s filter (its first 16 bytes, BIP-158,
+/// whatever P/M are) and a reader using them.
+fn filter_reader(block_hash: BlockHash, params: GolombParams) -> (u64, u64, GcsFilterReader) {
+    let key = block_hash.as_byte_array();
+    let k0 = u64::from_le_bytes(key[0..8].try_into().expect("8 byte slice"));
+    let k1 = u64::from_le_bytes(key[8..16].try_into().expect("8 byte slice"));
+    (k0, k1, GcsFilterReader::new(k0, k1, params.m, params.p))
+}
 
-    // Own the bytes, then iterate by &[…]
-    let query_bytes: Vec<Vec<u8>> = scripts.into_iter().map(|s| s.as_bytes().to_vec()).collect();
-    let mut it = query_bytes.iter().map(|v| v.as_slice());
+/// Scripts per shard at most: matching a shard allocates and sorts 8 bytes per
+/// script, so this caps that at 8 MiB.
+pub const MAX_SHARD_SCRIPTS: usize = 1 << 20;
 
-    filter.match_any(&block_hash, &mut it)
+/// A watchlist prepared for matching against many filters: query bytes are
+/// copied once, ordered by birth height and split into shards of bounded size.
+///
+/// Each script is queried once, however many items (addresses, descriptors,
+/// raw scripts) name it, from the earliest birth height among them.
+pub struct QuerySet {
+    shards: Arc<[Shard]>,
+    /// [Birth height](WatchItem::birth_height) of each script, ascending.
+    births: Vec<u32>,
+    indexed: bool,
+    /// Threads matching the shards, kept for the set's lifetime.
+    #[cfg(not(target_arch = "wasm32"))]
+    pool: Option<ShardPool>,
 }
 
-#[allow(dead_code)]
-pub fn filter_matches_any_address<I>(
+impl QuerySet {
+    /// One shard per `shard_size` scripts, or a single shard when `None`. With
+    /// `indexed`, scripts are looked up in an [`ElementIndex`] of each filter.
+    pub fn new(items: &[WatchItem], shard_size: Option<usize>, indexed: bool) -> Self {
+        let mut births: HashMap<&ScriptBuf, u32> = HashMap::with_capacity(items.len());
+        for item in items {
+            let birth = item.birth_height.unwrap_or(0);
+            births
+                .entry(&item.script)
+                .and_modify(|b| *b = (*b).min(birth))
+                .or_insert(birth);
+        }
+        let mut scripts: Vec<(u32, &ScriptBuf)> = births.into_iter().map(|(s, b)| (b, s)).collect();
+        scripts.sort_unstable();
+        let births = scripts.iter().map(|(b, _)| *b).collect();
+        let shard_size = shard_size.unwrap_or(usize::MAX).max(1);
+        let shards: Arc<[Shard]> = scripts
+            .chunks(shard_size.min(scripts.len().max(1)))
+            .map(|chunk| Shard::new(chunk.iter().map(|(_, s)| s.as_bytes())))
+            .collect();
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            pool: (shards.len() > 1).then(|| ShardPool::new(shards.clone())),
+            shards,
+            births,
+            indexed,
+        }
+    }
+
+    /// Number of distinct scripts queried.
+    pub fn len(&self) -> usize {
+        self.births.len()
+    }
+
+    /// Whether there are no scripts to query.
+    pub fn is_empty(&self) -> bool {
+        self.births.is_empty()
+    }
+
+    /// Whether any script born at or below `height` is in `raw_filter`; a
+    /// script cannot be paid before its birth. Shards are matched in parallel
+    /// on threads kept for the set's lifetime (in turn on wasm), and the
+    /// first hit stops the others.
+    pub fn matches(
+        &self,
+        height: u32,
+        block_hash: BlockHash,
+        raw_filter: &[u8],
+        params: GolombParams,
+    ) -> Result<bool, bitcoin::bip158::Error> {
+        // The born scripts are a prefix of the shards.
+        let born = self.births.partition_point(|&b| b <= height);
+        if born == 0 {
+            return Ok(false);
+        }
+        // (shard, born scripts in it), in shard order.
+        let mut left = born;
+        let born_shards = self.shards.iter().enumerate().map_while(move |(i, shard)| {
+            let n = left.min(shard.len());
+            left -= n;
+            (n > 0).then_some((i, n))
+        });
+
+        let probe = Probe {
+            block_hash,
+            params,
+            index: match self.indexed {
+                true => ElementIndex::new(raw_filter, params),
+                false => None,
+            },
+            hit: AtomicBool::new(false),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(pool) = self.pool.as_ref().filter(|_| born > self.shards[0].len()) {
+            return pool.matches(born_shards, probe, raw_filter);
+        }
+        for (i, n) in born_shards {
+            if probe.shard_matches(&self.shards[i], n, raw_filter)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// One filter being matched against the shards.
+struct Probe {
     block_hash: BlockHash,
-    raw_filter: &[u8],
-    addrs: I,
-) -> Result<bool, bitcoin::bip158::Error>
-where
-    I: IntoIterator<Item = Address>,
-{
-    let scripts = addrs.into_iter().map(|a| a.script_pubkey());
-    filter_matches_any(block_hash, raw_filter, scripts)
+    params: GolombParams,
+    index: Option<ElementIndex>,
+    /// Set by the first shard that hits; the others stop early.
+    hit: AtomicBool,
+}
+
+impl Probe {
+    /// Whether any of the first `n` scripts of `shard` is in `raw_filter`.
+    /// Gives up (with an answer that no longer matters) once another shard hit.
+    fn shard_matches(
+        &self,
+        shard: &Shard,
+        n: usize,
+        raw_filter: &[u8],
+    ) -> Result<bool, bitcoin::bip158::Error> {
+        let (k0, k1, reader) = filter_reader(self.block_hash, self.params);
+        let mut scripts = shard
+            .scripts(n)
+            .take_while(|_| !self.hit.load(Ordering::Relaxed));
+        let hit = match &self.index {
+            Some(index) => scripts.any(|s| index.contains(k0, k1, s)),
+            None => reader.match_any(&mut &raw_filter[..], &mut scripts)?,
+        };
+        if hit {
+            self.hit.store(true, Ordering::Relaxed);
+        }
+        Ok(hit)
+    }
+}
+
+/// A shard to match: its index, how many of its scripts are born, the filter
+/// and where to send the answer.
+#[cfg(not(target_arch = "wasm32"))]
+type ShardJob = (
+    usize,
+    usize,
+    Arc<(Probe, Vec<u8>)>,
+    mpsc::Sender<Result<bool, bitcoin::bip158::Error>>,
+);
+
+/// Worker threads matching shards, at most one per core. Spawning a thread
+/// per shard and block would cost more than matching a small shard does.
+#[cfg(not(target_arch = "wasm32"))]
+struct ShardPool {
+    jobs: Option<mpsc::Sender<ShardJob>>,
+    workers: Vec<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ShardPool {
+    fn new(shards: Arc<[Shard]>) -> Self {
+        let threads = std::thread::available_parallelism()
+            .map_or(1, usize::from)
+            .min(shards.len());
+        let (jobs, queue) = mpsc::channel::<ShardJob>();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..threads)
+            .map(|_| {
+                let (shards, queue) = (shards.clone(), queue.clone());
+                std::thread::spawn(move || loop {
+                    let job = queue.lock().unwrap().recv();
+                    let Ok((i, n, probe, reply)) = job else {
+                        return;
+                    };
+                    let (probe, raw_filter) = &*probe;
+                    // The caller may have stopped listening after another hit.
+                    let _ = reply.send(probe.shard_matches(&shards[i], n, raw_filter));
+                })
+            })
+            .collect();
+        Self {
+            jobs: Some(jobs),
+            workers,
+        }
+    }
+
+    /// Match `born_shards` in parallel; the first hit answers without waiting
+    /// for the rest.
+    fn matches(
+        &self,
+        born_shards: impl Iterator<Item = (usize, usize)>,
+        probe: Probe,
+        raw_filter: &[u8],
+    ) -> Result<bool, bitcoin::bip158::Error> {
+        let jobs = self.jobs.as_ref().expect("pool is running");
+        let probe = Arc::new((probe, raw_filter.to_vec()));
+        let (reply, answers) = mpsc::channel();
+        let mut sent = 0;
+        for (i, n) in born_shards {
+            jobs.send((i, n, probe.clone(), reply.clone()))
+                .expect("shard workers outlive the pool");
+            sent += 1;
+        }
+        drop(reply);
+        let (mut received, mut error) = (0, None);
+        for answer in answers.iter() {
+            received += 1;
+            match answer {
+                Ok(true) => return Ok(true),
+                Ok(false) => {}
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        assert_eq!(received, sent, "shard matcher panicked");
+        error.map_or(Ok(false), Err)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for ShardPool {
+    fn drop(&mut self) {
+        // Closing the queue ends the workers once they finish their shard.
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A shard's scripts, back to back in one buffer: built once per scan and
+/// borrowed by every height's match, with no per-script allocations.
+struct Shard {
+    bytes: Vec<u8>,
+    /// End offset of each script in `bytes`.
+    ends: Vec<usize>,
+}
+
+impl Shard {
+    fn new<'a>(scripts: impl Iterator<Item = &'a [u8]>) -> Self {
+        let (mut bytes, mut ends) = (Vec::new(), Vec::new());
+        for script in scripts {
+            bytes.extend_from_slice(script);
+            ends.push(bytes.len());
+        }
+        Self { bytes, ends }
+    }
+
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// The first `n` scripts.
+    fn scripts(&self, n: usize) -> impl Iterator<Item = &[u8]> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        starts
+            .zip(&self.ends[..n])
+            .map(|(start, &end)| &self.bytes[start..end])
+    }
+}
+
+/// One block filter's decoded elements, sorted for lookups.
+///
+/// BIP-158 hashes scripts with keys taken from the block hash, so a watchlist
+/// cannot be hashed ahead of time; GCS matching hashes and sorts the whole
+/// watchlist for every block. Indexing the block's few thousand elements
+/// instead lets each hashed script be looked up directly, skipping the sort.
+/// Lookups are exact: a script found here is a filter match.
+pub struct ElementIndex {
+    values: Vec<u64>,
+    range: u64,
+}
+
+impl ElementIndex {
+    /// Index `raw_filter`'s elements, or `None` if it cannot be (malformed, or
+    /// `N * M` beyond 64 bits), leaving it to the GCS match.
+    fn new(raw_filter: &[u8], params: GolombParams) -> Option<Self> {
+        let mut values = Vec::new();
+        let range = filter_elements(raw_filter, params, |v| values.push(v as u64)).ok()?;
+        let range = u64::try_from(range).ok()?;
+        // Decoded in ascending order already.
+        Some(Self { values, range })
+    }
+
+    /// Whether `script`, hashed with the block's SipHash keys, is an element.
+    fn contains(&self, k0: u64, k1: u64, script: &[u8]) -> bool {
+        let hash = siphash24::Hash::hash_to_u64_with_keys(k0, k1, script);
+        // Same mapping into `0..N * M` as BIP-158.
+        let value = ((u128::from(hash) * u128::from(self.range)) >> 64) as u64;
+        self.values.binary_search(&value).is_ok()
+    }
+}
+
+/// Check that `raw_filter` is a well-formed GCS filter: a canonical CompactSize
+/// element count `N`, then exactly `N` Golomb-Rice coded deltas whose sum stays
+/// below `N * M`, then only zero padding up to the next byte.
+///
+/// `match_any` stops at the first hit and treats a short count as "no
+/// elements", so a malformed filter could otherwise pass as a miss.
+pub fn validate_filter(raw_filter: &[u8], params: GolombParams) -> Result<(), String> {
+    filter_elements(raw_filter, params, |_| ()).map(drop)
 }
@@ -0,0 +1,218 @@
+//! Zero-conf watching: track unconfirmed transactions paying the wallet and
+//! alert when they are double-spent, replaced (RBF) or dropped.
+//!
+//! The crate has no mempool source of its own; feed [`MempoolWatcher`] from
+//! whatever the app already has (ZMQ `rawtx`, an Esplora/Electrum mempool
+//! feed, `getrawmempool` polling): every new mempool transaction goes to
+//! [`observe`](MempoolWatcher::observe), mempool snapshots to
+//! [`retain`](MempoolWatcher::retain), and mined transactions to
+//! [`confirmed`](MempoolWatcher::confirmed).
+use crate::compat::{MaybeSend, MaybeSync};
+use async_trait::async_trait;
+use bitcoin::{OutPoint, ScriptBuf, Transaction, Txid};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+/// Why a watched unconfirmed transaction can no longer be relied on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZeroConfAlert {
+    /// `conflicting` spends an input of `watched` and pays none of the
+    /// watched scripts: the payment is being double-spent.
+    DoubleSpend {
+        /// The unconfirmed payment to the wallet.
+        watched: Txid,
+        /// The transaction spending the same input(s).
+        conflicting: Transaction,
+    },
+    /// `replacement` spends an input of `watched` but still pays a watched
+    /// script (typically an RBF fee bump); it is tracked from now on.
+    Replaced {
+        /// The replaced payment.
+        watched: Txid,
+        /// The transaction replacing it.
+        replacement: Transaction,
+    },
+    /// `watched` left the mempool without confirming or a seen conflict
+    /// (expiry, eviction, or a replacement we never saw).
+    Evicted {
+        /// The dropped payment.
+        watched: Txid,
+    },
+}
+
+/// Receives [`ZeroConfAlert`]s from a [`MempoolWatcher`].
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+pub trait ZeroConfHooks: MaybeSend + MaybeSync {
+    /// A newly seen unconfirmed transaction pays a watched script. Optional.
+    async fn on_unconfirmed(&self, _tx: &Transaction) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// A watched unconfirmed transaction was double-spent, replaced or dropped.
+    async fn on_zero_conf_alert(&self, alert: ZeroConfAlert) -> anyhow::Result<()>;
+}
+
+#[derive(Default)]
+struct Tracked {
+    txs: HashMap<Txid, Transaction>,
+    /// Inputs of tracked transactions -> the tracked tx spending them.
+    spends: HashMap<OutPoint, Txid>,
+}
+
+impl Tracked {
+    fn insert(&mut self, tx: Transaction) {
+        let txid = tx.compute_txid();
+        for input in &tx.input {
+            self.spends.insert(input.previous_output, txid);
+        }
+        self.txs.insert(txid, tx);
+    }
+
+    /// Stop tracking (and return) the tracked txs that `tx` conflicts with.
+    fn take_conflicts(&mut self, tx: &Transaction) -> Vec<Txid> {
+        let mut conflicts: Vec<Txid> = tx
+            .input
+            .iter()
+            .filter_map(|i| self.spends.get(&i.previous_output).copied())
+            .collect();
+        conflicts.sort();
+        conflicts.dedup();
+        for txid in &conflicts {
+            self.remove(txid);
+        }
+        conflicts
+    }
+
+    fn remove(&mut self, txid: &Txid) -> Option<Transaction> {
+        let tx = self.txs.remove(txid)?;
+        for input in &tx.input {
+            self.spends.remove(&input.previous_output);
+        }
+        Some(tx)
+    }
+}
+
+/// Tracks unconfirmed payments to a set of scripts and reports conflicts to `H`.
+pub struct MempoolWatcher<H> {
+    hooks: H,
+    scripts: HashSet<ScriptBuf>,
+    tracked: Mutex<Tracked>,
+}
+
+impl<H: ZeroConfHooks> MempoolWatcher<H> {
+    /// Watch for unconfirmed payments to `scripts`.
+    pub fn new(hooks: H, scripts: impl IntoIterator<Item = ScriptBuf>) -> Self {
+        Self {
+            hooks,
+            scripts: scripts.into_iter().collect(),
+            tracked: Mutex::new(Tracked::default()),
+        }
+    }
+
+    /// The wrapped hooks.
+    pub fn hooks(&self) -> &H {
+        &self.hooks
+    }
+
+    /// Txids of the unconfirmed payments currently tracked.
+    pub fn tracked(&self) -> Vec<Txid> {
+        self.tracked.lock().unwrap().txs.keys().copied().collect()
+    }
+
+    /// Feed one transaction seen entering the mempool.
+    pub async fn observe(&self, tx: Transaction) -> anyhow::Result<()> {
+        let pays = tx
+            .output
+            .iter()
+            .any(|o| self.scripts.contains(&o.script_pubkey));
+        let conflicts = {
+            let mut tracked = self.tracked.lock().unwrap();
+            if tracked.txs.contains_key(&tx.compute_txid()) {
+                return Ok(());
+            }
+            let conflicts = tracked.take_conflicts(&tx);
+            if pays {
+                tracked.insert(tx.clone());
+            }
+            conflicts
+        };
+        self.alert_conflicts(conflicts, &tx, pays).await?;
+        if pays {
+            self.hooks.on_unconfirmed(&tx).await?;
+        }
+        Ok(())
+    }
+
+    /// Feed the transactions of a newly mined block (e.g. a
+    /// [`MatchDetails`](crate::MatchDetails)): tracked ones confirmed and stop
+    /// being tracked, and any that spend a tracked payment's inputs are
+    /// reported as confirmed conflicts.
+    pub async fn confirmed(&self, txs: &[Transaction]) -> anyhow::Result<()> {
+        for tx in txs {
+            let conflicts = {
+                let mut tracked = self.tracked.lock().unwrap();
+                if tracked.remove(&tx.compute_txid()).is_some() {
+                    continue;
+                }
+                tracked.take_conflicts(tx)
+            };
+            let pays = tx
+                .output
+                .iter()
+                .any(|o| self.scripts.contains(&o.script_pubkey));
+            self.alert_conflicts(conflicts, tx, pays).await?;
+        }
+        Ok(())
+    }
+
+    async fn alert_conflicts(
+        &self,
+        conflicts: Vec<Txid>,
+        tx: &Transaction,
+        pays: bool,
+    ) -> anyhow::Result<()> {
+        for watched in conflicts {
+            let alert = if pays {
+                ZeroConfAlert::Replaced {
+                    watched,
+                    replacement: tx.clone(),
+                }
+            } else {
+                ZeroConfAlert::DoubleSpend {
+                    watched,
+                    conflicting: tx.clone(),
+                }
+            };
+            self.hooks.on_zero_conf_alert(alert).await?;
+        }
+        Ok(())
+    }
+
+    /// Feed a full mempool snapshot: tracked transactions missing from
+    /// `mempool` are reported as [`ZeroConfAlert::Evicted`] and dropped.
+    /// Report mined ones with [`confirmed`](Self::confirmed) first.
+    pub async fn retain(&self, mempool: &HashSet<Txid>) -> anyhow::Result<()> {
+        let gone: Vec<Txid> = {
+            let mut tracked = self.tracked.lock().unwrap();
+            let gone: Vec<Txid> = tracked
+                .txs
+                .keys()
+                .filter(|txid| !mempool.contains(*txid))
+                .copied()
+                .collect();
+            for txid in &gone {
+                tracked.remove(txid);
+            }
+            gone
+        };
+        for watched in gone {
+            self.hooks
+                .on_zero_conf_alert(ZeroConfAlert::Evicted { watched })
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! Seed a [`Store`] from an existing LND/Neutrino (btcd) filter-header database.
+//!
+//! Neutrino keeps BIP-157 filter headers in a flat file, `reg_filter_headers.bin`,
+//! holding one 32-byte header (internal byte order) per height starting at genesis.
+//! These are rolled the way the engine rolls them, genesis included
+//! (`H_0 = HASH256(F_0 || 0^32)`), so importing them lets a migrating wallet
+//! skip re-verifying cfheaders from scratch.
+use crate::checkpoints::genesis_cfheader;
+use crate::store::Store;
+use anyhow::{bail, Context};
+use bitcoin::{hashes::Hash, BlockHash, Network};
+use std::path::{Path, PathBuf};
+
+/// File name of the basic-filter header store inside a Neutrino data directory.
+pub const FILTER_HEADERS_FILE: &str = "reg_filter_headers.bin";
+
+/// How many headers to hand to the store per `save_cf_headers` call.
+const IMPORT_CHUNK: usize = 10_000;
+
+/// Importer for Neutrino's flat filter-header file.
+pub struct NeutrinoImport {
+    path: PathBuf,
+    checkpoints: Vec<(u32, BlockHash)>,
+    network: Option<Network>,
+}
+
+impl NeutrinoImport {
+    /// Import from a Neutrino data directory (e.g. `~/.lnd/data/chain/bitcoin/mainnet`).
+    pub fn from_dir(dir: impl AsRef<Path>) -> Self {
+        Self::from_file(dir.as_ref().join(FILTER_HEADERS_FILE))
+    }
+
+    /// Import from an explicit `reg_filter_headers.bin` path.
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            checkpoints: vec![],
+            network: None,
+        }
+    }
+
+    /// Require the imported headers to match these `(height, cfheader)` checkpoints.
+    pub fn with_checkpoints(mut self, v: Vec<(u32, BlockHash)>) -> Self {
+        self.checkpoints = v;
+        self
+    }
+
+    /// Require the file's first header to be `network`'s
+    /// [genesis cfheader](genesis_cfheader), as the engine rolls it.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Read the file, check it against the configured checkpoints, and seed `store`
+    /// with every header plus the cf tip. Returns the imported tip.
+    ///
+    /// # Errors
+    /// Fails if the file is missing or truncated, a checkpoint or the
+    /// [network](Self::with_network)'s genesis cfheader disagrees, or the
+    /// store already has a verified cfheaders tip (import only seeds fresh stores).
+    pub async fn import_into<S: Store + ?Sized>(
+        &self,
+        store: &S,
+    ) -> anyhow::Result<(u32, BlockHash)> {
+        if let Some((h, _)) = store.load_cf_tip().await? {
+            if h > 0 {
+                bail!("store already has a cfheaders tip at {h}; refusing to import over it");
+            }
+        }
+
+        let headers = read_filter_headers(&self.path)?;
+        let Some(tip) = headers.last().copied() else {
+            bail!("{} holds no headers", self.path.display());
+        };
+        let tip_height = u32::try_from(headers.len() - 1).context("too many headers")?;
+
+        if let Some(network) = self.network {
+            let expected = genesis_cfheader(network);
+            if headers[0] != expected {
+                bail!(
+                    "neutrino import: header at 0 is {}, not the {network} genesis cfheader {expected}",
+                    headers[0]
+                );
+            }
+        }
+
+        for (h, expected) in &self.checkpoints {
+            match headers.get(*h as usize) {
+                Some(got) if got == expected => {}
+                Some(got) => bail!("neutrino import: checkpoint mismatch @{h}: file has {got}, expected {expected}"),
+                None => {} // beyond the imported range; the engine verifies it later
+            }
+        }
+
+        for (i, chunk) in headers.chunks(IMPORT_CHUNK).enumerate() {
+            store
+                .save_cf_headers((i * IMPORT_CHUNK) as u32, chunk)
+                .await?;
+        }
+        store.save_cf_tip(tip_height, tip).await?;
+        Ok((tip_height, tip))
+    }
+}
+
+fn read_filter_headers(path: &Path) -> anyhow::Result<Vec<BlockHash>> {
+    let bytes = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    if !bytes.len().is_multiple_of(32) {
+        bail!(
+            "{} is {} bytes, not a whole number of 32-byte headers (truncated write?)",
+            path.display(),
+            bytes.len()
+        );
+    }
+    Ok(bytes
+        .chunks_exact(32)
+        .map(|c| BlockHash::from_byte_array(c.try_into().expect("32-byte chunk")))
+        .collect())
+}
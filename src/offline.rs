@@ -0,0 +1,130 @@
+//! Fully offline scanning from a local directory, for air-gapped recovery.
+//!
+//! The directory mirrors the hash-addressed part of the [HTTP protocol](crate::http),
+//! so a copy of a `niebla-serve` mirror works as is:
+//!
+//! | Path                      | Contents                                                  |
+//! |---------------------------|-----------------------------------------------------------|
+//! | `headers.bin`             | consecutive 80-byte headers from genesis (see [`FileHeaderSource`]) |
+//! | `v1/cfilter/{block_hash}` | raw BIP-158 basic filter bytes                            |
+//! | `v1/block/{block_hash}`   | raw consensus-encoded block                               |
+//!
+//! Every height the engine scans needs its filter; blocks are only read after a
+//! filter hit, so an export can leave out the ones the wallet never matches.
+use crate::filter_source::{CfHeadersBatch, FilterSource};
+use crate::headers::{FileHeaderSource, HeaderSource};
+use crate::http::{block_path, cfilter_path};
+use anyhow::Context;
+use async_trait::async_trait;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::{block::Header, BlockHash, Network};
+use std::path::{Path, PathBuf};
+
+/// [`FilterSource`] and [`HeaderSource`] over a local directory (see the module docs).
+///
+/// Filters and blocks are read from disk on demand; only the block hashes of
+/// `headers.bin` are kept in memory.
+#[derive(Debug, Clone)]
+pub struct OfflineSource {
+    dir: PathBuf,
+    headers: FileHeaderSource,
+}
+
+impl OfflineSource {
+    /// Open `dir`, loading and validating its `headers.bin` for `network`.
+    pub fn open(dir: impl Into<PathBuf>, network: Network) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        let headers = FileHeaderSource::open(dir.join("headers.bin"), network)?;
+        Ok(Self { dir, headers })
+    }
+
+    fn path(&self, http_path: &str) -> PathBuf {
+        self.dir.join(http_path.trim_start_matches('/'))
+    }
+
+    fn read(&self, path: &Path, what: &str, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        std::fs::read(path).with_context(|| {
+            format!(
+                "offline: no {what} for {block} in {} ({})",
+                self.dir.display(),
+                path.display()
+            )
+        })
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl FilterSource for OfflineSource {
+    fn label(&self) -> Option<String> {
+        Some(self.dir.display().to_string())
+    }
+
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        let chain = self.headers.hashes();
+        let stop = chain
+            .iter()
+            .skip(start_h as usize)
+            .position(|h| *h == stop_hash)
+            .with_context(|| format!("offline: {stop_hash} is not above height {start_h}"))?;
+        let headers = chain[start_h as usize..=start_h as usize + stop]
+            .iter()
+            .map(|block| {
+                let filter = self.read(&self.path(&cfilter_path(*block)), "filter", *block)?;
+                Ok(sha256d::Hash::hash(&filter).to_byte_array())
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(CfHeadersBatch {
+            start_height: start_h,
+            headers,
+        })
+    }
+
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.read(&self.path(&cfilter_path(block)), "filter", block)
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.read(&self.path(&block_path(block)), "block", block)
+    }
+
+    async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        HeaderSource::hash_at_height(self, height).await.map(Some)
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl HeaderSource for OfflineSource {
+    fn label(&self) -> Option<String> {
+        Some(self.dir.display().to_string())
+    }
+
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        self.headers.tip_height().await
+    }
+
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        self.headers.hash_at_height(height).await
+    }
+
+    async fn hashes_in_range(&self, from: u32, to: u32) -> anyhow::Result<Vec<BlockHash>> {
+        self.headers.hashes_in_range(from, to).await
+    }
+
+    async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+        self.headers.header(block).await
+    }
+
+    async fn headers_from(&self, start: BlockHash, count: u32) -> anyhow::Result<Vec<Header>> {
+        self.headers.headers_from(start, count).await
+    }
+
+    async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+        self.headers.height_of(block).await
+    }
+}
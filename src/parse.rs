@@ -0,0 +1,185 @@
+//! Pure parsers for the data sources hand the engine: filters, cfheaders
+//! batches and messages, and blocks. None of them trust their input; the
+//! fuzz targets in `fuzz/` drive them with arbitrary bytes (the `arbitrary`
+//! feature derives [`Arbitrary`](https://docs.rs/arbitrary) for their inputs).
+use crate::filter_source::{CfHeadersBatch, FilterType, GolombParams};
+use anyhow::{bail, Context};
+use bitcoin::consensus::{deserialize, encode::deserialize_partial};
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::message_filter::CFHeaders;
+use bitcoin::{block::Header, BlockHash, Transaction, VarInt};
+
+/// Decode the GCS filter `raw_filter`, passing each element's value (in
+/// `0..N * M`, ascending) to `visit`. Returns `N * M`.
+///
+/// Fails unless the filter is well formed: a canonical CompactSize element
+/// count `N`, then exactly `N` Golomb-Rice coded deltas whose sum stays below
+/// `N * M`, then only zero padding up to the next byte.
+pub fn filter_elements(
+    raw_filter: &[u8],
+    params: GolombParams,
+    mut visit: impl FnMut(u128),
+) -> Result<u128, String> {
+    let (n, header) = compact_size(raw_filter)?;
+    let body = &raw_filter[header..];
+    let body_bits = body.len() as u64 * 8;
+
+    // Every element takes at least P + 1 bits (a unary 0 plus the remainder).
+    if n * (u64::from(params.p) + 1) > body_bits {
+        return Err(format!("{n} elements cannot fit in {} bytes", body.len()));
+    }
+
+    let range = u128::from(n) * u128::from(params.m);
+    let mut bits = BitReader { data: body, pos: 0 };
+    let mut value: u128 = 0;
+    for i in 0..n {
+        let mut quotient: u128 = 0;
+        loop {
+            match bits.read(1) {
+                Some(1) => quotient += 1,
+                Some(_) => break,
+                None => return Err(format!("element {i} of {n} is truncated")),
+            }
+        }
+        let remainder = bits
+            .read(params.p)
+            .ok_or_else(|| format!("element {i} of {n} is truncated"))?;
+        value += (quotient << params.p) + u128::from(remainder);
+        if value >= range {
+            return Err(format!("element {i} of {n} is outside the filter range"));
+        }
+        visit(value);
+    }
+
+    let left = body_bits - bits.pos;
+    if left >= 8 {
+        return Err(format!("{} trailing bytes after {n} elements", left / 8));
+    }
+    if bits.read(left as u8).is_some_and(|padding| padding != 0) {
+        return Err("non-zero padding".into());
+    }
+    Ok(range)
+}
+
+/// Decode the element count: a canonical CompactSize no larger than `u32::MAX`.
+/// Returns it with the number of bytes it took.
+pub fn compact_size(raw: &[u8]) -> Result<(u64, usize), String> {
+    let (&first, rest) = raw.split_first().ok_or("zero-length filter")?;
+    let wide = |len: usize| -> Result<u64, String> {
+        let bytes = rest.get(..len).ok_or("truncated element count")?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0u64, |acc, b| (acc << 8) | u64::from(*b)))
+    };
+    let (n, len, min) = match first {
+        0..=0xfc => return Ok((u64::from(first), 1)),
+        0xfd => (wide(2)?, 3, 0xfd),
+        0xfe => (wide(4)?, 5, 0x1_0000),
+        0xff => return Err("element count exceeds u32".into()),
+    };
+    if n < min {
+        return Err("non-canonical element count".into());
+    }
+    Ok((n, len))
+}
+
+/// MSB-first bit reader over a byte slice, as BIP-158 filters are written.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: u64,
+}
+
+impl BitReader<'_> {
+    /// Read `n <= 64` bits as a big-endian number, or `None` past the end.
+    fn read(&mut self, n: u8) -> Option<u64> {
+        if self.pos + u64::from(n) > self.data.len() as u64 * 8 {
+            return None;
+        }
+        let mut out = 0u64;
+        for _ in 0..n {
+            let byte = self.data[(self.pos / 8) as usize];
+            let bit = (byte >> (7 - self.pos % 8)) & 1;
+            out = (out << 1) | u64::from(bit);
+            self.pos += 1;
+        }
+        Some(out)
+    }
+}
+
+/// Check that `batch` answers a `get_cfheaders` request for `start..=stop`:
+/// it starts at `start` and has no more than `stop - start + 1` headers.
+/// Returns whether it has fewer (a source that is behind may stop short).
+pub fn cfheaders_batch(batch: &CfHeadersBatch, start: u32, stop: u32) -> anyhow::Result<bool> {
+    let wanted = u64::from(stop.saturating_sub(start)) + 1;
+    if batch.start_height != start {
+        bail!(
+            "cfheaders batch starts at {}, expected {start}",
+            batch.start_height
+        );
+    }
+    if batch.headers.len() as u64 > wanted {
+        bail!(
+            "cfheaders batch has {} headers, more than the {wanted} asked for",
+            batch.headers.len()
+        );
+    }
+    Ok((batch.headers.len() as u64) < wanted)
+}
+
+/// A decoded BIP-157 `cfheaders` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CfHeadersMessage {
+    /// Filter type of the headers.
+    pub filter_type: FilterType,
+    /// Block the headers run up to.
+    pub stop_hash: BlockHash,
+    /// Rolling header before the first filter hash.
+    pub previous: BlockHash,
+    /// Per-block filter hashes.
+    pub filter_hashes: Vec<[u8; 32]>,
+}
+
+/// Decode a `cfheaders` payload as received from a peer.
+pub fn cfheaders_message(payload: &[u8]) -> anyhow::Result<CfHeadersMessage> {
+    let msg: CFHeaders = deserialize(payload).context("decode cfheaders message")?;
+    Ok(CfHeadersMessage {
+        filter_type: FilterType(msg.filter_type),
+        stop_hash: msg.stop_hash,
+        previous: BlockHash::from_byte_array(msg.previous_filter_header.to_byte_array()),
+        filter_hashes: msg
+            .filter_hashes
+            .iter()
+            .map(|h| h.to_byte_array())
+            .collect(),
+    })
+}
+
+/// The header at the start of the consensus-encoded block `raw`.
+pub fn block_header(raw: &[u8]) -> anyhow::Result<Header> {
+    Ok(deserialize_partial(raw)
+        .context("block header deserialize")?
+        .0)
+}
+
+/// Hand the transactions of the consensus-encoded block `raw` to `visit` one
+/// at a time, in block order, until it returns `false`. Fails on a truncated
+/// block or trailing bytes (if every transaction was visited).
+pub fn block_txs(raw: &[u8], visit: &mut dyn FnMut(Transaction) -> bool) -> anyhow::Result<()> {
+    let (_, mut at) = deserialize_partial::<Header>(raw).context("block deserialize: header")?;
+    let (count, len) = deserialize_partial::<VarInt>(&raw[at..])
+        .context("block deserialize: transaction count")?;
+    at += len;
+    for i in 0..count.0 {
+        let (tx, len) = deserialize_partial::<Transaction>(&raw[at..])
+            .with_context(|| format!("block deserialize: transaction {i}"))?;
+        at += len;
+        if !visit(tx) {
+            return Ok(());
+        }
+    }
+    if at != raw.len() {
+        bail!("block deserialize: {} trailing bytes", raw.len() - at);
+    }
+    Ok(())
+}
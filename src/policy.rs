@@ -0,0 +1,73 @@
+//! Pluggable scheduling rules consulted by the engine between steps.
+use crate::compat::{MaybeSend, MaybeSync};
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// Which part of the sync loop is about to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPhase {
+    /// About to request/verify the next cfheaders batch.
+    CfHeaders,
+    /// About to fetch and match the filter at `next_height`.
+    Scan,
+}
+
+/// Context handed to a [`SyncPolicy`] at every safe point.
+#[derive(Debug, Clone, Copy)]
+pub struct SyncContext {
+    /// Current phase of the run.
+    pub phase: SyncPhase,
+    /// Height the engine is about to process.
+    pub next_height: u32,
+    /// Best height reported by the `HeaderSource` for this run.
+    pub chain_tip: u32,
+    /// Filters scanned so far during this `run_to_tip` call.
+    pub scanned_this_run: u32,
+}
+
+/// What the engine should do next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyDecision {
+    /// Proceed with the next step.
+    Continue,
+    /// Sleep for the given duration, then ask again.
+    Wait(Duration),
+    /// End this run cleanly; progress so far is persisted.
+    Stop,
+}
+
+/// Throttling rules (network type, time windows, per-session budgets, ...).
+///
+/// Consulted before every cfheaders batch and before every scanned height.
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+pub trait SyncPolicy: MaybeSend + MaybeSync {
+    /// Decide whether the engine may take its next step.
+    async fn check(&self, ctx: &SyncContext) -> anyhow::Result<PolicyDecision>;
+}
+
+/// Default policy: never throttle.
+pub struct AlwaysSync;
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl SyncPolicy for AlwaysSync {
+    async fn check(&self, _ctx: &SyncContext) -> anyhow::Result<PolicyDecision> {
+        Ok(PolicyDecision::Continue)
+    }
+}
+
+/// Stop a run after scanning this many filters (e.g. "max 10k blocks per session").
+pub struct MaxBlocksPerSession(pub u32);
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl SyncPolicy for MaxBlocksPerSession {
+    async fn check(&self, ctx: &SyncContext) -> anyhow::Result<PolicyDecision> {
+        if ctx.phase == SyncPhase::Scan && ctx.scanned_this_run >= self.0 {
+            Ok(PolicyDecision::Stop)
+        } else {
+            Ok(PolicyDecision::Continue)
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! The machine-readable form of a match: one versioned JSON schema shared by
+//! the webhook (`webhook::WebhookWallet`, feature `webhook`), the `niebla`
+//! command line and JSON [file export](crate::export::ExportWallet::with_json).
+//!
+//! ```text
+//! {"version":1,"height":800123,"block":"…","match_id":"…","sequence":42,
+//!  "txs":[{"txid":"…","hex":"…","received":50000,"sent":0,"fee":null}],
+//!  "items":[{"script":"0014…","tag":"acct-7"}]}
+//! ```
+//!
+//! Hashes and scripts are hex, amounts satoshis. `received`, `sent` and `fee`
+//! are `null` for transactions without a [`TxSummary`] (or, for `fee`, when it
+//! is unknown), `sequence` for matches delivered without [`MatchDetails`].
+//! `raw_block` (hex) is only present when the block was passed along.
+//!
+//! Within version 1 fields are only ever added, so consumers should ignore
+//! fields they do not know; anything else bumps [`VERSION`] and gets a new type.
+use crate::hooks::{match_id, MatchDetails, TxSummary, WatchItem};
+use anyhow::Context;
+use bitcoin::{
+    consensus::encode::{deserialize_hex, serialize_hex},
+    hashes::sha256,
+    BlockHash, ScriptBuf, Transaction, Txid,
+};
+use serde::{Deserialize, Serialize};
+
+/// Schema version written by [`MatchRecordV1`].
+pub const VERSION: u32 = 1;
+
+/// A match, as serialized to JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchRecordV1 {
+    /// Always [`VERSION`].
+    pub version: u32,
+    /// Height of the matching block.
+    pub height: u32,
+    /// Hash of the matching block.
+    pub block: BlockHash,
+    /// [`MatchDetails::match_id`].
+    pub match_id: sha256::Hash,
+    /// [`MatchDetails::sequence`]; `None` for matches delivered without details.
+    pub sequence: Option<u64>,
+    /// The transactions of the match, possibly just the relevant ones.
+    pub txs: Vec<TxRecordV1>,
+    /// [`MatchDetails::items`].
+    pub items: Vec<ItemRecordV1>,
+    /// The whole block as served, hex-encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_block: Option<String>,
+}
+
+/// A transaction of a [`MatchRecordV1`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxRecordV1 {
+    /// Its txid.
+    pub txid: Txid,
+    /// The consensus-encoded transaction, hex.
+    pub hex: String,
+    /// [`TxSummary::received`], in satoshis.
+    pub received: Option<u64>,
+    /// [`TxSummary::sent`], in satoshis.
+    pub sent: Option<u64>,
+    /// [`TxSummary::fee`], in satoshis.
+    pub fee: Option<u64>,
+}
+
+/// A watch item of a [`MatchRecordV1`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemRecordV1 {
+    /// [`WatchItem::script`].
+    pub script: ScriptBuf,
+    /// [`WatchItem::tag`].
+    pub tag: Option<String>,
+}
+
+impl MatchRecordV1 {
+    /// The record of `details`, with all its transactions and, if it was passed
+    /// along, its raw block.
+    pub fn from_details(details: &MatchDetails) -> Self {
+        Self {
+            version: VERSION,
+            height: details.height,
+            block: details.block,
+            match_id: details.match_id,
+            sequence: Some(details.sequence),
+            txs: tx_records(&details.txs, &details.summaries),
+            items: details.items.iter().map(ItemRecordV1::from).collect(),
+            raw_block: details.raw_block.as_deref().map(hex::encode),
+        }
+    }
+
+    /// The record of a match delivered without details
+    /// ([`WalletHooks::on_block_match`](crate::WalletHooks::on_block_match)).
+    pub fn from_block_match(height: u32, block: BlockHash, txs: &[Transaction]) -> Self {
+        let txids: Vec<_> = txs.iter().map(Transaction::compute_txid).collect();
+        Self {
+            version: VERSION,
+            height,
+            block,
+            match_id: match_id(height, block, &txids),
+            sequence: None,
+            txs: tx_records(txs, &[]),
+            items: vec![],
+            raw_block: None,
+        }
+    }
+
+    /// Keep only the transactions in `keep` (e.g. the relevant ones).
+    pub fn retain_txs(&mut self, keep: impl Fn(&Txid) -> bool) {
+        self.txs.retain(|tx| keep(&tx.txid));
+    }
+
+    /// The record as one line of JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("match records serialize")
+    }
+
+    /// Parse a record, checking its version.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let record: Self = serde_json::from_str(json).context("malformed match record")?;
+        if record.version != VERSION {
+            anyhow::bail!("match record is version {}, not {VERSION}", record.version);
+        }
+        Ok(record)
+    }
+
+    /// Decode the transactions.
+    pub fn transactions(&self) -> anyhow::Result<Vec<Transaction>> {
+        self.txs
+            .iter()
+            .map(|tx| {
+                deserialize_hex(&tx.hex)
+                    .with_context(|| format!("malformed transaction {}", tx.txid))
+            })
+            .collect()
+    }
+
+    /// Decode the raw block, if present.
+    pub fn raw_block_bytes(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        self.raw_block
+            .as_deref()
+            .map(|raw| hex::decode(raw).context("malformed raw_block"))
+            .transpose()
+    }
+}
+
+/// Records of `txs`, with `summaries` in the same order (or none).
+fn tx_records(txs: &[Transaction], summaries: &[TxSummary]) -> Vec<TxRecordV1> {
+    let summaries = summaries.iter().map(Some).chain(std::iter::repeat(None));
+    txs.iter()
+        .zip(summaries)
+        .map(|(tx, summary)| TxRecordV1 {
+            txid: tx.compute_txid(),
+            hex: serialize_hex(tx),
+            received: summary.map(|s| s.received.to_sat()),
+            sent: summary.map(|s| s.sent.to_sat()),
+            fee: summary.and_then(|s| s.fee).map(|f| f.to_sat()),
+        })
+        .collect()
+}
+
+impl From<&WatchItem> for ItemRecordV1 {
+    fn from(item: &WatchItem) -> Self {
+        Self {
+            script: item.script.clone(),
+            tag: item.tag.clone(),
+        }
+    }
+}
@@ -0,0 +1,466 @@
+//! Record a source's responses to a file and play them back, to reproduce a
+//! run exactly (e.g. a report that a wallet missed a payment at some height).
+//!
+//! ```rust,ignore
+//! // On the affected machine: record a run.
+//! let filters = RecordingSource::new(http_source, "session.log")?;
+//! let headers = RecordingSource::new(header_source, "session.log")?;
+//! Niebla158::new(store, wallet, filters, headers).run_to_tip().await?;
+//!
+//! // Later, anywhere: replay it, without network access.
+//! let replay = ReplaySource::open("session.log")?;
+//! Niebla158::new(MemoryStore::new(), wallet, replay.clone(), replay).run_to_tip().await?;
+//! ```
+//!
+//! The file has one line per response: the request (e.g. `cfilter 0 <block>`),
+//! `ok` or `err`, and the response or error message, separated by tabs. Filter
+//! and hash ranges are recorded per block, so a replay does not depend on how
+//! the engine batched its requests. A request made several times gets its
+//! recorded responses in order, then the last one again.
+use crate::filter_source::{CfHeadersBatch, FilterSource, FilterType};
+use crate::headers::HeaderSource;
+use anyhow::Context;
+use async_trait::async_trait;
+use bitcoin::{block::Header, consensus, BlockHash};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Wraps a [`FilterSource`] and/or [`HeaderSource`], appending every response
+/// to a file for [`ReplaySource`].
+pub struct RecordingSource<S> {
+    inner: S,
+    log: Mutex<File>,
+}
+
+impl<S> RecordingSource<S> {
+    /// Record `inner`'s responses, appending them to `path`. A filter source
+    /// and a header source may record to the same file.
+    pub fn new(inner: S, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open {}", path.display()))?;
+        Ok(Self {
+            inner,
+            log: Mutex::new(log),
+        })
+    }
+
+    /// The wrapped source.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn write(&self, key: &str, out: Result<String, String>) -> anyhow::Result<()> {
+        let line = match out {
+            Ok(value) => format!("{key}\tok\t{value}\n"),
+            Err(msg) => format!("{key}\terr\t{}\n", msg.replace(['\n', '\t'], " ")),
+        };
+        self.log.lock().unwrap().write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Log `out` under `key` and pass it on.
+    fn record<T: Payload>(&self, key: String, out: anyhow::Result<T>) -> anyhow::Result<T> {
+        let logged = match &out {
+            Ok(value) => Ok(value.encode()),
+            Err(e) => Err(format!("{e:#}")),
+        };
+        self.write(&key, logged)?;
+        out
+    }
+}
+
+/// Recorded responses (or error messages) per request, oldest first.
+type Recorded = HashMap<String, VecDeque<Result<String, String>>>;
+
+/// Plays back a file written by [`RecordingSource`]. Requests that were never
+/// recorded fail. Cloning shares the playback position.
+#[derive(Clone)]
+pub struct ReplaySource {
+    path: PathBuf,
+    responses: Arc<Mutex<Recorded>>,
+}
+
+impl ReplaySource {
+    /// Load the recording at `path`.
+    pub fn open(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let text =
+            std::fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        let mut responses = Recorded::new();
+        for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.is_empty()) {
+            let mut fields = line.splitn(3, '\t');
+            let (Some(key), Some(status), Some(value)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                anyhow::bail!("{}:{}: malformed recording", path.display(), n + 1);
+            };
+            let out = match status {
+                "ok" => Ok(value.to_owned()),
+                "err" => Err(value.to_owned()),
+                _ => anyhow::bail!("{}:{}: unknown status {status:?}", path.display(), n + 1),
+            };
+            responses.entry(key.to_owned()).or_default().push_back(out);
+        }
+        Ok(Self {
+            path,
+            responses: Arc::new(Mutex::new(responses)),
+        })
+    }
+
+    /// The next recorded response to `key`.
+    fn replay<T: Payload>(&self, key: String) -> anyhow::Result<T> {
+        let out = {
+            let mut responses = self.responses.lock().unwrap();
+            let queue = responses
+                .get_mut(&key)
+                .with_context(|| format!("replay: no recorded response to `{key}`"))?;
+            if queue.len() > 1 {
+                queue.pop_front().expect("queue is not empty")
+            } else {
+                queue[0].clone()
+            }
+        };
+        match out {
+            Ok(value) => {
+                T::decode(&value).with_context(|| format!("replay: bad response to `{key}`"))
+            }
+            Err(msg) => Err(anyhow::anyhow!(msg)),
+        }
+    }
+}
+
+/// A response as written to a recording.
+trait Payload: Sized {
+    fn encode(&self) -> String;
+    fn decode(s: &str) -> anyhow::Result<Self>;
+}
+
+impl Payload for u32 {
+    fn encode(&self) -> String {
+        self.to_string()
+    }
+    fn decode(s: &str) -> anyhow::Result<Self> {
+        Ok(s.parse()?)
+    }
+}
+
+impl Payload for bool {
+    fn encode(&self) -> String {
+        self.to_string()
+    }
+    fn decode(s: &str) -> anyhow::Result<Self> {
+        Ok(s.parse()?)
+    }
+}
+
+impl Payload for BlockHash {
+    fn encode(&self) -> String {
+        self.to_string()
+    }
+    fn decode(s: &str) -> anyhow::Result<Self> {
+        Ok(BlockHash::from_str(s)?)
+    }
+}
+
+impl Payload for Vec<u8> {
+    fn encode(&self) -> String {
+        hex::encode(self)
+    }
+    fn decode(s: &str) -> anyhow::Result<Self> {
+        Ok(hex::decode(s)?)
+    }
+}
+
+impl Payload for Vec<BlockHash> {
+    fn encode(&self) -> String {
+        let hashes: Vec<String> = self.iter().map(BlockHash::to_string).collect();
+        hashes.join(",")
+    }
+    fn decode(s: &str) -> anyhow::Result<Self> {
+        s.split(',')
+            .filter(|h| !h.is_empty())
+            .map(<BlockHash as Payload>::decode)
+            .collect()
+    }
+}
+
+impl Payload for Header {
+    fn encode(&self) -> String {
+        consensus::encode::serialize_hex(self)
+    }
+    fn decode(s: &str) -> anyhow::Result<Self> {
+        Ok(consensus::encode::deserialize_hex(s)?)
+    }
+}
+
+impl<T: Payload> Payload for Option<T> {
+    fn encode(&self) -> String {
+        self.as_ref().map_or_else(|| "-".into(), T::encode)
+    }
+    fn decode(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "-" => Ok(None),
+            _ => T::decode(s).map(Some),
+        }
+    }
+}
+
+impl Payload for CfHeadersBatch {
+    fn encode(&self) -> String {
+        let headers: Vec<String> = self.headers.iter().map(hex::encode).collect();
+        format!("{} {}", self.start_height, headers.join(","))
+    }
+    fn decode(s: &str) -> anyhow::Result<Self> {
+        let (start, headers) = s.split_once(' ').context("missing start height")?;
+        let mut batch = CfHeadersBatch {
+            start_height: start.parse()?,
+            headers: vec![],
+        };
+        for header in headers.split(',').filter(|h| !h.is_empty()) {
+            let mut bytes = [0u8; 32];
+            hex::decode_to_slice(header, &mut bytes)?;
+            batch.headers.push(bytes);
+        }
+        Ok(batch)
+    }
+}
+
+fn cfheaders_key(filter_type: FilterType, start_h: u32, stop_hash: BlockHash) -> String {
+    format!("cfheaders {} {start_h} {stop_hash}", filter_type.0)
+}
+
+fn cfcheckpt_key(filter_type: FilterType, stop_hash: BlockHash) -> String {
+    format!("cfcheckpt {} {stop_hash}", filter_type.0)
+}
+
+fn cfilter_key(filter_type: FilterType, block: BlockHash) -> String {
+    format!("cfilter {} {block}", filter_type.0)
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl<S: FilterSource> FilterSource for RecordingSource<S> {
+    fn label(&self) -> Option<String> {
+        self.inner.label()
+    }
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        let out = self.inner.get_cfheaders(start_h, stop_hash).await;
+        self.record(cfheaders_key(FilterType::BASIC, start_h, stop_hash), out)
+    }
+    async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+        let out = self.inner.get_cfcheckpt(stop_hash).await;
+        self.record(cfcheckpt_key(FilterType::BASIC, stop_hash), out)
+    }
+    async fn filter_tip_height(&self) -> anyhow::Result<Option<u32>> {
+        let out = self.inner.filter_tip_height().await;
+        self.record("filter_tip".into(), out)
+    }
+    async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        let out = self.inner.block_hash_at(height).await;
+        self.record(format!("block_hash_at {height}"), out)
+    }
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        let out = self.inner.get_cfilter(block).await;
+        self.record(cfilter_key(FilterType::BASIC, block), out)
+    }
+    async fn get_cfheaders_typed(
+        &self,
+        filter_type: FilterType,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        let out = self
+            .inner
+            .get_cfheaders_typed(filter_type, start_h, stop_hash)
+            .await;
+        self.record(cfheaders_key(filter_type, start_h, stop_hash), out)
+    }
+    async fn get_cfcheckpt_typed(
+        &self,
+        filter_type: FilterType,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<Vec<BlockHash>> {
+        let out = self.inner.get_cfcheckpt_typed(filter_type, stop_hash).await;
+        self.record(cfcheckpt_key(filter_type, stop_hash), out)
+    }
+    async fn get_cfilter_typed(
+        &self,
+        filter_type: FilterType,
+        block: BlockHash,
+    ) -> anyhow::Result<Vec<u8>> {
+        let out = self.inner.get_cfilter_typed(filter_type, block).await;
+        self.record(cfilter_key(filter_type, block), out)
+    }
+    async fn get_cfilter_range(
+        &self,
+        filter_type: FilterType,
+        blocks: &[BlockHash],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        // Recorded per block: the replayed engine may batch differently.
+        let out = self.inner.get_cfilter_range(filter_type, blocks).await;
+        for (i, block) in blocks.iter().enumerate() {
+            let logged = match &out {
+                Ok(filters) => Ok(filters.get(i).map(Payload::encode).unwrap_or_default()),
+                Err(e) => Err(format!("{e:#}")),
+            };
+            self.write(&cfilter_key(filter_type, *block), logged)?;
+        }
+        out
+    }
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        let out = FilterSource::get_block(&self.inner, block).await;
+        self.record(format!("block {block}"), out)
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl<S: HeaderSource> HeaderSource for RecordingSource<S> {
+    fn label(&self) -> Option<String> {
+        self.inner.label()
+    }
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        let out = self.inner.tip_height().await;
+        self.record("tip".into(), out)
+    }
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        let out = self.inner.hash_at_height(height).await;
+        self.record(format!("hash {height}"), out)
+    }
+    async fn has_height(&self, height: u32) -> anyhow::Result<bool> {
+        let out = self.inner.has_height(height).await;
+        self.record(format!("has_height {height}"), out)
+    }
+    async fn hashes_in_range(&self, from: u32, to: u32) -> anyhow::Result<Vec<BlockHash>> {
+        let out = self.inner.hashes_in_range(from, to).await;
+        match &out {
+            Ok(hashes) => {
+                for (height, hash) in (from..=to).zip(hashes) {
+                    self.write(&format!("hash {height}"), Ok(hash.encode()))?;
+                }
+            }
+            Err(e) => self.write(&format!("hash {from}"), Err(format!("{e:#}")))?,
+        }
+        out
+    }
+    async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+        let out = self.inner.header(block).await;
+        self.record(format!("header {block}"), out)
+    }
+    async fn headers_from(&self, start: BlockHash, count: u32) -> anyhow::Result<Vec<Header>> {
+        // Played back one `header` at a time, through the default.
+        let out = self.inner.headers_from(start, count).await;
+        if let Ok(headers) = &out {
+            for header in headers {
+                let key = format!("header {}", header.block_hash());
+                self.write(&key, Ok(Some(*header).encode()))?;
+            }
+        }
+        out
+    }
+    async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+        let out = self.inner.height_of(block).await;
+        self.record(format!("height_of {block}"), out)
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl FilterSource for ReplaySource {
+    fn label(&self) -> Option<String> {
+        Some(format!("replay of {}", self.path.display()))
+    }
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        self.get_cfheaders_typed(FilterType::BASIC, start_h, stop_hash)
+            .await
+    }
+    async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+        self.get_cfcheckpt_typed(FilterType::BASIC, stop_hash).await
+    }
+    async fn filter_tip_height(&self) -> anyhow::Result<Option<u32>> {
+        self.replay("filter_tip".into())
+    }
+    async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        self.replay(format!("block_hash_at {height}"))
+    }
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.get_cfilter_typed(FilterType::BASIC, block).await
+    }
+    async fn get_cfheaders_typed(
+        &self,
+        filter_type: FilterType,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        self.replay(cfheaders_key(filter_type, start_h, stop_hash))
+    }
+    async fn get_cfcheckpt_typed(
+        &self,
+        filter_type: FilterType,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<Vec<BlockHash>> {
+        self.replay(cfcheckpt_key(filter_type, stop_hash))
+    }
+    async fn get_cfilter_typed(
+        &self,
+        filter_type: FilterType,
+        block: BlockHash,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.replay(cfilter_key(filter_type, block))
+    }
+    async fn get_cfilter_range(
+        &self,
+        filter_type: FilterType,
+        blocks: &[BlockHash],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        // Take every block's response, as the recorded range did, before
+        // reporting the first failure.
+        let filters: Vec<anyhow::Result<Vec<u8>>> = blocks
+            .iter()
+            .map(|block| self.replay(cfilter_key(filter_type, *block)))
+            .collect();
+        filters.into_iter().collect()
+    }
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.replay(format!("block {block}"))
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl HeaderSource for ReplaySource {
+    fn label(&self) -> Option<String> {
+        Some(format!("replay of {}", self.path.display()))
+    }
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        self.replay("tip".into())
+    }
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        self.replay(format!("hash {height}"))
+    }
+    async fn has_height(&self, height: u32) -> anyhow::Result<bool> {
+        self.replay(format!("has_height {height}"))
+    }
+    async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+        self.replay(format!("header {block}"))
+    }
+    async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+        self.replay(format!("height_of {block}"))
+    }
+}
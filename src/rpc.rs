@@ -0,0 +1,165 @@
+//! JSON-RPC 2.0 control interface for a long-running engine.
+//!
+//! [`RpcService`] answers these methods over an [`EngineHandle`] and an
+//! optional [`SharedWatchlist`]:
+//!
+//! | method           | params                              | result                          |
+//! |------------------|-------------------------------------|---------------------------------|
+//! | `getprogress`    | –                                   | state, heights, health counters |
+//! | `addwatchscript` | `[script_hex, tag?, birth_height?]` | `true` if newly watched         |
+//! | `rescan`         | `[from_height]`                     | `null` (applies on next run)    |
+//! | `pause`          | –                                   | `null`                          |
+//! | `resume`         | –                                   | `null`                          |
+//!
+//! [`router`] serves it as `POST /` on axum; bind it to localhost or put it
+//! behind authentication, as it has none of its own.
+use crate::{control::EngineHandle, hooks::SharedWatchlist, WatchItem};
+use axum::{extract::State, routing::post, Router};
+use bitcoin::ScriptBuf;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// A JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    /// JSON-RPC error code (`-32601` unknown method, `-32602` bad params, ...).
+    pub code: i64,
+    /// Human-readable description.
+    pub message: String,
+}
+
+impl RpcError {
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self {
+            code: -32602,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Dispatches control methods to an engine.
+#[derive(Clone)]
+pub struct RpcService {
+    handle: EngineHandle,
+    watchlist: Option<SharedWatchlist>,
+}
+
+impl RpcService {
+    /// Control the engine behind `handle`.
+    pub fn new(handle: EngineHandle) -> Self {
+        Self {
+            handle,
+            watchlist: None,
+        }
+    }
+
+    /// Let `addwatchscript` add to `watchlist` (the one the engine's hooks read).
+    pub fn with_watchlist(mut self, watchlist: SharedWatchlist) -> Self {
+        self.watchlist = Some(watchlist);
+        self
+    }
+
+    /// Run `method` with positional `params` (`null` or an array).
+    pub fn call(&self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        let arg = |i: usize| params.get(i).filter(|v| !v.is_null());
+        match method {
+            "getprogress" => {
+                let health = self.handle.health();
+                let status = self.handle.status();
+                Ok(json!({
+                    "state": format!("{:?}", status.state).to_lowercase(),
+                    "cf_tip_height": status.cf_tip_height,
+                    "scanned_height": status.scanned_height,
+                    "chain_tip_height": status.chain_tip_height,
+                    "consecutive_failures": health.consecutive_failures,
+                    "secs_since_new_block": health.secs_since_new_block,
+                }))
+            }
+            "addwatchscript" => {
+                let Some(watchlist) = &self.watchlist else {
+                    return Err(RpcError {
+                        code: -32000,
+                        message: "this engine has no editable watchlist".into(),
+                    });
+                };
+                let script = arg(0).and_then(Value::as_str).ok_or_else(|| {
+                    RpcError::invalid_params("expected [script_hex, tag?, birth_height?]")
+                })?;
+                let script = ScriptBuf::from_hex(script)
+                    .map_err(|e| RpcError::invalid_params(format!("script_hex: {e}")))?;
+                let mut item = WatchItem::new(script);
+                if let Some(tag) = arg(1) {
+                    let tag = tag
+                        .as_str()
+                        .ok_or_else(|| RpcError::invalid_params("tag must be a string"))?;
+                    item = item.with_tag(tag);
+                }
+                if let Some(birth) = arg(2) {
+                    let birth = birth
+                        .as_u64()
+                        .and_then(|h| u32::try_from(h).ok())
+                        .ok_or_else(|| RpcError::invalid_params("birth_height must be a height"))?;
+                    item = item.with_birth_height(birth);
+                }
+                Ok(Value::Bool(watchlist.add(item)))
+            }
+            "rescan" => {
+                let from = arg(0)
+                    .and_then(Value::as_u64)
+                    .and_then(|h| u32::try_from(h).ok())
+                    .ok_or_else(|| RpcError::invalid_params("expected [from_height]"))?;
+                self.handle.rescan_from(from);
+                Ok(Value::Null)
+            }
+            "pause" => {
+                self.handle.pause();
+                Ok(Value::Null)
+            }
+            "resume" => {
+                self.handle.resume();
+                Ok(Value::Null)
+            }
+            other => Err(RpcError {
+                code: -32601,
+                message: format!("unknown method {other}"),
+            }),
+        }
+    }
+
+    /// Answer a raw JSON-RPC request body (batches are not supported).
+    pub fn handle_request(&self, body: &[u8]) -> Value {
+        let req: Value = match serde_json::from_slice(body) {
+            Ok(req) => req,
+            Err(e) => return error_response(Value::Null, -32700, &format!("parse error: {e}")),
+        };
+        let id = req.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = req.get("method").and_then(Value::as_str) else {
+            return error_response(id, -32600, "missing method");
+        };
+        match self.call(method, req.get("params").unwrap_or(&Value::Null)) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => error_response(id, e.code, &e.message),
+        }
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Serve `service` as JSON-RPC over `POST /`.
+pub fn router(service: RpcService) -> Router {
+    Router::new().route("/", post(handle)).with_state(service)
+}
+
+async fn handle(State(service): State<RpcService>, body: axum::body::Bytes) -> axum::Json<Value> {
+    axum::Json(service.handle_request(&body))
+}
@@ -0,0 +1,23 @@
+//! Runtime shims so the engine runs under tokio and in the browser.
+use std::time::{Duration, SystemTime};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn sleep(d: Duration) {
+    tokio::time::sleep(d).await
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn sleep(d: Duration) {
+    gloo_timers::future::sleep(d).await
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+// `SystemTime::now` panics on wasm32-unknown-unknown; ask the JS clock instead.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs_f64(js_sys::Date::now() / 1000.0)
+}
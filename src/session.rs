@@ -0,0 +1,160 @@
+//! Scan sessions: scans of a height range that keep their own progress in the
+//! primary wallet's store, apart from its main scan progress, so a full
+//! recovery and a targeted rescan can overlap and each resume where it stopped
+//! after a restart.
+//!
+//! ```rust,ignore
+//! engine.add_session(&ScanSession::new("recovery", 1..=tip)).await?;
+//! engine
+//!     .add_session(&ScanSession::new("account-2", 820_000..=tip).with_direction(ScanDirection::Backward))
+//!     .await?;
+//! // Later, maybe after a restart, in any order:
+//! engine.run_session("account-2").await?;
+//! engine.run_session("recovery").await?;
+//! ```
+use crate::config::parse_record;
+use anyhow::Context;
+use bitcoin::hashes::sha256;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Order a [`ScanSession`] walks its range in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanDirection {
+    /// Lowest height first.
+    #[default]
+    Forward,
+    /// Highest height first, e.g. to find recent activity before old history.
+    Backward,
+}
+
+/// A named scan of `range` and its progress, saved with
+/// [`Niebla158::add_session`](crate::Niebla158::add_session) and advanced by
+/// [`Niebla158::run_session`](crate::Niebla158::run_session).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanSession {
+    /// Name of the session, unique per store (no line breaks).
+    pub id: String,
+    /// Heights to scan.
+    pub range: RangeInclusive<u32>,
+    /// Order to scan them in.
+    pub direction: ScanDirection,
+    /// [Fingerprint](crate::hooks::watchlist_fingerprint) of the watchlist
+    /// scanned with so far; `None` before the first run. Running with another
+    /// watchlist starts the session over.
+    pub fingerprint: Option<sha256::Hash>,
+    /// Last height scanned, in [`direction`](Self::direction); `None` before the first.
+    pub scanned: Option<u32>,
+}
+
+impl ScanSession {
+    /// A session scanning `range` forward, not started yet.
+    pub fn new(id: impl Into<String>, range: RangeInclusive<u32>) -> Self {
+        Self {
+            id: id.into(),
+            range,
+            direction: ScanDirection::Forward,
+            fingerprint: None,
+            scanned: None,
+        }
+    }
+
+    /// Scan in `direction` instead.
+    pub fn with_direction(mut self, direction: ScanDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Height to scan next; `None` once the whole range is done.
+    pub fn next_height(&self) -> Option<u32> {
+        let (start, end) = (*self.range.start(), *self.range.end());
+        match (self.direction, self.scanned) {
+            (ScanDirection::Forward, None) => Some(start),
+            (ScanDirection::Forward, Some(h)) => (h < end).then(|| h + 1),
+            (ScanDirection::Backward, None) => Some(end),
+            (ScanDirection::Backward, Some(h)) => (h > start).then(|| h - 1),
+        }
+    }
+
+    /// Whether every height in the range has been scanned.
+    pub fn is_done(&self) -> bool {
+        self.next_height().is_none()
+    }
+
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if self.id.is_empty() || self.id.contains(['\n', '\r']) {
+            anyhow::bail!("scan session id {:?} is empty or spans lines", self.id);
+        }
+        if self.range.is_empty() || *self.range.start() == 0 {
+            anyhow::bail!(
+                "scan session {:?} range {:?} is empty or starts at genesis",
+                self.id,
+                self.range
+            );
+        }
+        Ok(())
+    }
+}
+
+/// One `name=value` line per field, as kept by
+/// [`Store::save_scan_session`](crate::Store::save_scan_session).
+impl fmt::Display for ScanSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            ScanDirection::Forward => "forward",
+            ScanDirection::Backward => "backward",
+        };
+        writeln!(f, "id={}", self.id)?;
+        writeln!(f, "start={}", self.range.start())?;
+        writeln!(f, "end={}", self.range.end())?;
+        writeln!(f, "direction={direction}")?;
+        match self.fingerprint {
+            Some(fp) => writeln!(f, "fingerprint={fp}")?,
+            None => writeln!(f, "fingerprint=none")?,
+        }
+        match self.scanned {
+            Some(h) => writeln!(f, "scanned={h}"),
+            None => writeln!(f, "scanned=none"),
+        }
+    }
+}
+
+impl FromStr for ScanSession {
+    type Err = anyhow::Error;
+
+    fn from_str(record: &str) -> anyhow::Result<Self> {
+        let fields = parse_record(record);
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .copied()
+                .with_context(|| format!("scan session record has no {name}"))
+        };
+        let height = |name: &str| -> anyhow::Result<u32> {
+            field(name)?
+                .parse()
+                .with_context(|| format!("scan session {name} is not a height"))
+        };
+        let direction = match field("direction")? {
+            "forward" => ScanDirection::Forward,
+            "backward" => ScanDirection::Backward,
+            other => anyhow::bail!("unknown scan direction {other:?}"),
+        };
+        let fingerprint = match field("fingerprint")? {
+            "none" => None,
+            hex => Some(hex.parse().context("scan session fingerprint")?),
+        };
+        let scanned = match field("scanned")? {
+            "none" => None,
+            _ => Some(height("scanned")?),
+        };
+        Ok(Self {
+            id: field("id")?.to_string(),
+            range: height("start")?..=height("end")?,
+            direction,
+            fingerprint,
+            scanned,
+        })
+    }
+}
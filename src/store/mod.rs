@@ -1,23 +1,322 @@
 //! Persistence interfaces and implementations used by the engine
 //! (e.g., cfheaders tip and last scanned height).
+use crate::compat::{MaybeSend, MaybeSync};
+use crate::filter_source::FilterType;
+use crate::utxo::Utxo;
 use async_trait::async_trait;
-use bitcoin::BlockHash;
+use bitcoin::{hashes::sha256, BlockHash, Network, OutPoint, ScriptBuf};
 
 /// Minimal persistence interface. No secrets — just progress markers.
-#[async_trait]
-pub trait Store: Send + Sync {
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+pub trait Store: MaybeSend + MaybeSync {
     /// Latest verified cfheaders rolling tip `(height, rolling_header_hash)`.
     async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>>;
 
     /// Save latest verified cfheaders rolling tip.
     async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()>;
 
+    /// Persist verified rolling cfheaders for consecutive heights starting at `start_height`.
+    ///
+    /// Optional: stores that only keep the tip can ignore this (the default).
+    async fn save_cf_headers(
+        &self,
+        _start_height: u32,
+        _headers: &[BlockHash],
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Rolling cfheader previously saved at `height`, if the store keeps them.
+    async fn load_cf_header(&self, _height: u32) -> anyhow::Result<Option<BlockHash>> {
+        Ok(None)
+    }
+
+    /// [`load_cf_tip`](Self::load_cf_tip) in the namespace of `filter_type`.
+    ///
+    /// The default maps [`FilterType::BASIC`] to the untyped methods and rejects
+    /// other types; stores that track several filter types override the four
+    /// `*_typed` methods.
+    async fn load_cf_tip_typed(
+        &self,
+        filter_type: FilterType,
+    ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        ensure_basic(filter_type)?;
+        self.load_cf_tip().await
+    }
+
+    /// [`save_cf_tip`](Self::save_cf_tip) in the namespace of `filter_type`.
+    async fn save_cf_tip_typed(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+        cfheader: BlockHash,
+    ) -> anyhow::Result<()> {
+        ensure_basic(filter_type)?;
+        self.save_cf_tip(height, cfheader).await
+    }
+
+    /// [`save_cf_headers`](Self::save_cf_headers) in the namespace of `filter_type`.
+    async fn save_cf_headers_typed(
+        &self,
+        filter_type: FilterType,
+        start_height: u32,
+        headers: &[BlockHash],
+    ) -> anyhow::Result<()> {
+        ensure_basic(filter_type)?;
+        self.save_cf_headers(start_height, headers).await
+    }
+
+    /// [`load_cf_header`](Self::load_cf_header) in the namespace of `filter_type`.
+    async fn load_cf_header_typed(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+    ) -> anyhow::Result<Option<BlockHash>> {
+        ensure_basic(filter_type)?;
+        self.load_cf_header(height).await
+    }
+
+    /// Rolling header the cfheaders chain of `filter_type` was anchored at
+    /// instead of genesis, recorded by the engine when it skipped history below
+    /// every wallet's birth height: no cfheaders are kept at or below it.
+    ///
+    /// Optional: the default records none, so only the first run (with no
+    /// cfheaders stored yet) anchors, and reorg recovery below the stored
+    /// cfheaders falls back to genesis.
+    async fn get_cf_anchor(
+        &self,
+        _filter_type: FilterType,
+    ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        Ok(None)
+    }
+
+    /// Record the anchor of the cfheaders chain of `filter_type`.
+    async fn set_cf_anchor(
+        &self,
+        _filter_type: FilterType,
+        _height: u32,
+        _cfheader: BlockHash,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// `(height, block_hash)` of the block at the verified cfheaders tip of
+    /// `filter_type`, if recorded with [`set_cf_tip_block`](Self::set_cf_tip_block).
+    ///
+    /// The engine compares it with the header chain at startup to notice reorgs
+    /// above every wallet's progress (cfheaders verified ahead of scanning, e.g.
+    /// by [`sync_cfheaders_only`](crate::Niebla158::sync_cfheaders_only)).
+    /// Optional: the default records none, so only wallets' last scanned blocks
+    /// reveal stale cfheaders.
+    async fn get_cf_tip_block(
+        &self,
+        _filter_type: FilterType,
+    ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        Ok(None)
+    }
+
+    /// Record that the cfheaders tip of `filter_type` was verified for `block` at `height`.
+    async fn set_cf_tip_block(
+        &self,
+        _filter_type: FilterType,
+        _height: u32,
+        _block: BlockHash,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     /// Last height whose *filter* we scanned against our watchlist.
     async fn get_last_scanned(&self) -> anyhow::Result<u32>;
 
     /// Update last scanned height.
     async fn set_last_scanned(&self, height: u32) -> anyhow::Result<()>;
 
+    /// `(height, block_hash)` of the last scanned block, if recorded with
+    /// [`set_last_scanned_block`](Self::set_last_scanned_block).
+    ///
+    /// The engine compares it with the header chain at startup to notice reorgs
+    /// below its progress. Stores that implement it forget the hash when
+    /// [`set_last_scanned`](Self::set_last_scanned) moves the height without one.
+    /// Optional: without it, progress is trusted as is (the default).
+    async fn get_last_scanned_block(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        Ok(None)
+    }
+
+    /// Update last scanned height, remembering that `block` was scanned there.
+    /// The default only records the height.
+    async fn set_last_scanned_block(&self, height: u32, _block: BlockHash) -> anyhow::Result<()> {
+        self.set_last_scanned(height).await
+    }
+
+    /// Block whose match was delivered at `height`, if recorded (see
+    /// [`Delivery::ExactlyOnce`](crate::hooks::Delivery::ExactlyOnce)).
+    ///
+    /// Optional: the default records nothing, which degrades to at-least-once.
+    async fn get_delivered(&self, _height: u32) -> anyhow::Result<Option<BlockHash>> {
+        Ok(None)
+    }
+
+    /// Record that the match for `block` at `height` reached the wallet.
+    async fn set_delivered(&self, _height: u32, _block: BlockHash) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Queue the filter hit for `block` at `height` for later delivery (see
+    /// [`Niebla158::with_delivery_queue`](crate::Niebla158::with_delivery_queue)).
+    ///
+    /// Optional: the default fails, so queued delivery needs a store that keeps them.
+    async fn enqueue_match(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+        anyhow::bail!("this store has no delivery queue (wanted {block} @{height})")
+    }
+
+    /// Queued filter hits `(height, block)`, lowest height first.
+    async fn pending_matches(&self) -> anyhow::Result<Vec<(u32, BlockHash)>> {
+        Ok(vec![])
+    }
+
+    /// Drop the queued hit at `height` once it has been delivered.
+    async fn dequeue_match(&self, _height: u32) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Insert or replace `utxo` (see [`crate::utxo`]).
+    ///
+    /// Optional: the default fails, so UTXO tracking needs a store that keeps them.
+    async fn save_utxo(&self, utxo: &Utxo) -> anyhow::Result<()> {
+        anyhow::bail!("this store does not track UTXOs (wanted {})", utxo.outpoint)
+    }
+
+    /// The recorded output at `outpoint`, spent or not.
+    async fn load_utxo(&self, _outpoint: OutPoint) -> anyhow::Result<Option<Utxo>> {
+        Ok(None)
+    }
+
+    /// The recorded outputs among `outpoints`, spent or not, in no particular
+    /// order. Outpoints with no record are left out.
+    ///
+    /// The default calls [`load_utxo`](Self::load_utxo) once per outpoint; stores
+    /// with a round trip per call should answer them together.
+    async fn load_utxos(&self, outpoints: &[OutPoint]) -> anyhow::Result<Vec<Utxo>> {
+        let mut out = Vec::new();
+        for &outpoint in outpoints {
+            out.extend(self.load_utxo(outpoint).await?);
+        }
+        Ok(out)
+    }
+
+    /// Every recorded output, spent or not.
+    async fn utxos(&self) -> anyhow::Result<Vec<Utxo>> {
+        Ok(vec![])
+    }
+
+    /// Undo UTXO history above `height` after a reorg: mark outputs created
+    /// there [orphaned](Utxo::orphaned) and outputs spent there unspent again.
+    async fn rollback_utxos(&self, _height: u32) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Heights in `from..=to` whose filter was scanned without a hit under
+    /// `fingerprint` (see [`Niebla158::with_verdict_cache`](crate::Niebla158::with_verdict_cache)),
+    /// with the block scanned there.
+    ///
+    /// Optional: the default remembers nothing, so every filter is matched again.
+    async fn load_misses(
+        &self,
+        _fingerprint: sha256::Hash,
+        _from: u32,
+        _to: u32,
+    ) -> anyhow::Result<Vec<(u32, BlockHash)>> {
+        Ok(vec![])
+    }
+
+    /// Remember that the filters of `misses` `(height, block)` had no hit under
+    /// `fingerprint`. Only one fingerprint is kept: saving under another one
+    /// forgets the misses recorded so far.
+    async fn save_misses(
+        &self,
+        _fingerprint: sha256::Hash,
+        _misses: &[(u32, BlockHash)],
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// [Fingerprint](crate::hooks::watchlist_fingerprint) of the watchlist
+    /// saved with [`set_watchlist`](Self::set_watchlist).
+    ///
+    /// Optional: the default remembers none, so watchlist changes go unnoticed
+    /// (see [`Niebla158::with_auto_rescan`](crate::Niebla158::with_auto_rescan)).
+    async fn get_watchlist_fingerprint(&self) -> anyhow::Result<Option<sha256::Hash>> {
+        Ok(None)
+    }
+
+    /// The scripts saved with [`set_watchlist`](Self::set_watchlist).
+    async fn watched_scripts(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(vec![])
+    }
+
+    /// Remember the watchlist the wallet scans with, replacing the previous one.
+    async fn set_watchlist(
+        &self,
+        _fingerprint: sha256::Hash,
+        _scripts: &[ScriptBuf],
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Last height scanned within the range `start..=end` of a
+    /// [parallel scan](crate::Niebla158::run_parallel), if it was started.
+    ///
+    /// Optional: the default remembers nothing, so an interrupted parallel scan
+    /// starts its ranges over.
+    async fn get_range_progress(&self, _start: u32, _end: u32) -> anyhow::Result<Option<u32>> {
+        Ok(None)
+    }
+
+    /// Record that the parallel scan range `start..=end` is scanned up to `scanned`.
+    async fn set_range_progress(
+        &self,
+        _start: u32,
+        _end: u32,
+        _scanned: u32,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Forget the progress of every parallel scan range.
+    async fn clear_range_progress(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Take the next number of the wallet's delivery sequence (1 for the first),
+    /// for [`MatchDetails::sequence`](crate::MatchDetails::sequence). The
+    /// sequence survives restarts and [`wipe`](Self::wipe).
+    ///
+    /// Optional: the default returns `None`, and the engine numbers deliveries
+    /// itself, from 1 again after every restart.
+    async fn next_delivery_sequence(&self) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Records of every saved [scan session](crate::session::ScanSession), in
+    /// any order.
+    ///
+    /// Optional: the default keeps none, and
+    /// [`save_scan_session`](Self::save_scan_session) fails.
+    async fn scan_sessions(&self) -> anyhow::Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    /// Save the record of scan session `id`, replacing any previous one.
+    async fn save_scan_session(&self, id: &str, _record: &str) -> anyhow::Result<()> {
+        anyhow::bail!("this store cannot keep scan sessions (wanted to save {id:?})")
+    }
+
+    /// Forget scan session `id` (no-op if there is none).
+    async fn remove_scan_session(&self, _id: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     /// (Optional) birth height to skip ancient history.
     async fn get_birth_height(&self) -> anyhow::Result<Option<u32>> {
         Ok(None)
@@ -27,8 +326,56 @@ pub trait Store: Send + Sync {
     async fn set_birth_height(&self, _h: u32) -> anyhow::Result<()> {
         Ok(())
     }
+
+    /// Network this store's progress belongs to, recorded by an engine with
+    /// [`Niebla158::with_network`](crate::Niebla158::with_network).
+    ///
+    /// Optional: the default records none, so mixing up stores of different
+    /// networks goes unnoticed.
+    async fn get_network(&self) -> anyhow::Result<Option<Network>> {
+        Ok(None)
+    }
+
+    /// Record the store's network (kept by [`wipe`](Self::wipe)).
+    async fn set_network(&self, _network: Network) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The [`EngineConfig`](crate::config::EngineConfig) record of the last run.
+    ///
+    /// Optional: the default records none, so runs with incompatible settings
+    /// against the same store go unnoticed.
+    async fn get_config(&self) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Save the engine's configuration record.
+    async fn set_config(&self, _record: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Forget all sync progress (cfheaders and anchors of every filter type,
+    /// scan progress, delivery records, queued matches, UTXOs, cached misses,
+    /// the saved watchlist, parallel scan ranges, scan sessions and the
+    /// configuration record) so the next run starts from scratch. The birth
+    /// height, network and delivery sequence are kept.
+    ///
+    /// Optional: the default fails, since the engine cannot clear a store it
+    /// does not know.
+    async fn wipe(&self) -> anyhow::Result<()> {
+        anyhow::bail!("this store cannot be wiped")
+    }
+}
+
+fn ensure_basic(filter_type: FilterType) -> anyhow::Result<()> {
+    if filter_type != FilterType::BASIC {
+        anyhow::bail!("this store does not track {filter_type} cfheaders");
+    }
+    Ok(())
 }
 
 // submodules / concrete stores live here
+#[cfg(feature = "sqlite")]
 pub mod sqlite_store;
+#[cfg(feature = "sqlite")]
 pub use sqlite_store::SqliteStore;
@@ -1,12 +1,78 @@
 //! Embedded SQLite store implementation for engine progress.
 use anyhow::Context;
 use async_trait::async_trait;
-use bitcoin::BlockHash;
+use bitcoin::{
+    hashes::{sha256, Hash},
+    Amount, BlockHash, Network, OutPoint, ScriptBuf, TxOut, Txid,
+};
 use rusqlite::{params, Connection};
 use std::{path::PathBuf, str::FromStr};
 use tokio::task;
 
+use crate::filter_source::FilterType;
 use crate::store::Store;
+use crate::utxo::Utxo;
+
+const SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS state (
+        key   TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS cf_headers (
+        height INTEGER PRIMARY KEY,
+        header BLOB NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS cf_headers_ext (
+        filter_type INTEGER NOT NULL,
+        height      INTEGER NOT NULL,
+        header      BLOB NOT NULL,
+        PRIMARY KEY (filter_type, height)
+    );
+
+    CREATE TABLE IF NOT EXISTS delivered (
+        height INTEGER PRIMARY KEY,
+        block  BLOB NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS pending (
+        height INTEGER PRIMARY KEY,
+        block  BLOB NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS utxos (
+        txid     BLOB NOT NULL,
+        vout     INTEGER NOT NULL,
+        value    INTEGER NOT NULL,
+        script   BLOB NOT NULL,
+        height   INTEGER NOT NULL,
+        spent_at INTEGER,
+        orphaned INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (txid, vout)
+    );
+
+    CREATE TABLE IF NOT EXISTS scan_misses (
+        height INTEGER PRIMARY KEY,
+        block  BLOB NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS watched (
+        script BLOB PRIMARY KEY
+    );
+
+    CREATE TABLE IF NOT EXISTS scan_ranges (
+        start   INTEGER NOT NULL,
+        end     INTEGER NOT NULL,
+        scanned INTEGER NOT NULL,
+        PRIMARY KEY (start, end)
+    );
+
+    CREATE TABLE IF NOT EXISTS scan_sessions (
+        id     TEXT PRIMARY KEY,
+        record TEXT NOT NULL
+    );
+"#;
 
 /// Simple key/value table:
 ///   state(key TEXT PRIMARY KEY, value TEXT NOT NULL)
@@ -14,8 +80,31 @@ use crate::store::Store;
 /// Keys used:
 ///  - cf_tip_height  : u32 decimal string
 ///  - cf_tip_hash    : hex BlockHash
+///  - cf_anchor_height / cf_anchor_hash : where cfheaders verification was anchored (optional)
+///  - cf_tip_block_height / cf_tip_block_hash : the block at the cfheaders tip (optional)
 ///  - last_scanned   : u32 decimal string
+///  - last_scanned_hash : hex BlockHash at `last_scanned` (optional)
 ///  - birth_height   : u32 decimal string (optional)
+///  - network        : network name, e.g. `signet` (optional)
+///  - misses_fingerprint : hex watchlist fingerprint of `scan_misses` (optional)
+///  - watchlist_fingerprint : hex fingerprint of the `watched` scripts (optional)
+///  - engine_config  : the [`EngineConfig`](crate::config::EngineConfig) record (optional)
+///  - delivery_sequence : u64 decimal string, the last delivery number handed out (optional)
+///
+/// Plus `cf_headers(height INTEGER PRIMARY KEY, header BLOB NOT NULL)` for the
+/// per-height rolling cfheaders (32 bytes, internal byte order),
+/// `delivered(height INTEGER PRIMARY KEY, block BLOB NOT NULL)` for matches
+/// already handed to the wallet, `pending` (same shape) for queued matches
+/// not yet delivered, and `utxos(txid, vout, value, script, height, spent_at, orphaned)`
+/// for [UTXO tracking](crate::utxo), and `scan_misses` (same shape as
+/// `delivered`) for [cached misses](crate::Niebla158::with_verdict_cache),
+/// `watched(script BLOB PRIMARY KEY)` for the saved watchlist, and
+/// `scan_ranges(start, end, scanned)` for [parallel scan](crate::Niebla158::run_parallel) progress,
+/// and `scan_sessions(id, record)` for [scan sessions](crate::session).
+///
+/// Filter types other than basic keep their tip under `cf_tip_height:<type>` /
+/// `cf_tip_hash:<type>` (type as two hex digits), their anchor likewise, and their headers in
+/// `cf_headers_ext(filter_type, height, header)`.
 pub struct SqliteStore {
     path: PathBuf,
 }
@@ -30,13 +119,9 @@ impl SqliteStore {
             r#"
             PRAGMA journal_mode=WAL;
             PRAGMA synchronous=NORMAL;
-
-            CREATE TABLE IF NOT EXISTS state (
-                key   TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            );
             "#,
         )?;
+        conn.execute_batch(SCHEMA)?;
         Ok(Self { path })
     }
 
@@ -47,14 +132,7 @@ impl SqliteStore {
         };
         // Ensure schema exists for in-memory (each open creates a fresh DB)
         let conn = Connection::open(&s.path)?;
-        conn.execute_batch(
-            r#"
-            CREATE TABLE IF NOT EXISTS state (
-                key   TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            );
-            "#,
-        )?;
+        conn.execute_batch(SCHEMA)?;
         Ok(s)
     }
 
@@ -74,6 +152,51 @@ impl SqliteStore {
         }
     }
 
+    /// `state` keys holding the `name` (`cf_tip`, `cf_anchor`, `cf_tip_block`)
+    /// height and hash of `filter_type`.
+    fn typed_keys(name: &str, filter_type: FilterType) -> (String, String) {
+        if filter_type == FilterType::BASIC {
+            (format!("{name}_height"), format!("{name}_hash"))
+        } else {
+            let t = filter_type.0;
+            (
+                format!("{name}_height:{t:02x}"),
+                format!("{name}_hash:{t:02x}"),
+            )
+        }
+    }
+
+    /// `utxos` rows matching `filter` (a `WHERE` clause or empty).
+    fn query_utxos(
+        conn: &Connection,
+        filter: &str,
+        params: impl rusqlite::Params,
+    ) -> anyhow::Result<Vec<Utxo>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT txid, vout, value, script, height, spent_at, orphaned FROM utxos {filter}"
+        ))?;
+        let mut rows = stmt.query(params)?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let txid: Vec<u8> = row.get(0)?;
+            let txid: [u8; 32] = txid
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("utxos txid is not 32 bytes"))?;
+            let value: i64 = row.get(2)?;
+            out.push(Utxo {
+                outpoint: OutPoint::new(Txid::from_byte_array(txid), row.get(1)?),
+                txout: TxOut {
+                    value: Amount::from_sat(value as u64),
+                    script_pubkey: ScriptBuf::from_bytes(row.get(3)?),
+                },
+                height: row.get(4)?,
+                spent_at: row.get(5)?,
+                orphaned: row.get(6)?,
+            });
+        }
+        Ok(out)
+    }
+
     fn kv_set(conn: &Connection, key: &str, val: &str) -> anyhow::Result<()> {
         conn.execute(
             "INSERT INTO state(key,value) VALUES(?1,?2)
@@ -84,14 +207,41 @@ impl SqliteStore {
     }
 }
 
-#[async_trait]
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
 impl Store for SqliteStore {
     async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        self.load_cf_tip_typed(FilterType::BASIC).await
+    }
+
+    async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
+        self.save_cf_tip_typed(FilterType::BASIC, height, cfheader)
+            .await
+    }
+
+    async fn save_cf_headers(
+        &self,
+        start_height: u32,
+        headers: &[BlockHash],
+    ) -> anyhow::Result<()> {
+        self.save_cf_headers_typed(FilterType::BASIC, start_height, headers)
+            .await
+    }
+
+    async fn load_cf_header(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        self.load_cf_header_typed(FilterType::BASIC, height).await
+    }
+
+    async fn load_cf_tip_typed(
+        &self,
+        filter_type: FilterType,
+    ) -> anyhow::Result<Option<(u32, BlockHash)>> {
         let path = self.path.clone();
         task::spawn_blocking(move || {
             let conn = Connection::open(path)?;
-            let h = Self::kv_get(&conn, "cf_tip_height")?;
-            let hh = Self::kv_get(&conn, "cf_tip_hash")?;
+            let (height_key, hash_key) = Self::typed_keys("cf_tip", filter_type);
+            let h = Self::kv_get(&conn, &height_key)?;
+            let hh = Self::kv_get(&conn, &hash_key)?;
             match (h, hh) {
                 (Some(hs), Some(hh)) => {
                     let height: u32 = hs.parse().context("parse cf_tip_height")?;
@@ -104,19 +254,89 @@ impl Store for SqliteStore {
         .await?
     }
 
-    async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
+    async fn save_cf_tip_typed(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+        cfheader: BlockHash,
+    ) -> anyhow::Result<()> {
         let path = self.path.clone();
         task::spawn_blocking(move || {
             let conn = Connection::open(path)?;
+            let (height_key, hash_key) = Self::typed_keys("cf_tip", filter_type);
             let _tx = conn.unchecked_transaction()?;
-            Self::kv_set(&conn, "cf_tip_height", &height.to_string())?;
-            Self::kv_set(&conn, "cf_tip_hash", &cfheader.to_string())?;
+            Self::kv_set(&conn, &height_key, &height.to_string())?;
+            Self::kv_set(&conn, &hash_key, &cfheader.to_string())?;
             _tx.commit()?;
             Ok(())
         })
         .await?
     }
 
+    async fn save_cf_headers_typed(
+        &self,
+        filter_type: FilterType,
+        start_height: u32,
+        headers: &[BlockHash],
+    ) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        let headers = headers.to_vec();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let tx = conn.unchecked_transaction()?;
+            {
+                // `?1` (the filter type) is unused for the basic table.
+                let sql = if filter_type == FilterType::BASIC {
+                    "INSERT INTO cf_headers(height,header) VALUES(?2,?3)
+                     ON CONFLICT(height) DO UPDATE SET header=excluded.header"
+                } else {
+                    "INSERT INTO cf_headers_ext(filter_type,height,header) VALUES(?1,?2,?3)
+                     ON CONFLICT(filter_type,height) DO UPDATE SET header=excluded.header"
+                };
+                let mut stmt = tx.prepare(sql)?;
+                for (i, h) in headers.iter().enumerate() {
+                    stmt.execute(params![
+                        filter_type.0,
+                        start_height + i as u32,
+                        h.as_byte_array()
+                    ])?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn load_cf_header_typed(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+    ) -> anyhow::Result<Option<BlockHash>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let sql = if filter_type == FilterType::BASIC {
+                "SELECT header FROM cf_headers WHERE height = ?2"
+            } else {
+                "SELECT header FROM cf_headers_ext WHERE filter_type = ?1 AND height = ?2"
+            };
+            let mut stmt = conn.prepare(sql)?;
+            let mut rows = stmt.query(params![filter_type.0, height])?;
+            match rows.next()? {
+                Some(row) => {
+                    let bytes: Vec<u8> = row.get(0)?;
+                    let arr: [u8; 32] = bytes
+                        .try_into()
+                        .map_err(|_| anyhow::anyhow!("cf_headers row {height} is not 32 bytes"))?;
+                    Ok(Some(BlockHash::from_byte_array(arr)))
+                }
+                None => Ok(None),
+            }
+        })
+        .await?
+    }
+
     async fn get_last_scanned(&self) -> anyhow::Result<u32> {
         let path = self.path.clone();
         task::spawn_blocking(move || {
@@ -134,7 +354,505 @@ impl Store for SqliteStore {
         let path = self.path.clone();
         task::spawn_blocking(move || {
             let conn = Connection::open(path)?;
-            Self::kv_set(&conn, "last_scanned", &height.to_string())
+            let _tx = conn.unchecked_transaction()?;
+            Self::kv_set(&conn, "last_scanned", &height.to_string())?;
+            conn.execute("DELETE FROM state WHERE key = 'last_scanned_hash'", [])?;
+            _tx.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn get_last_scanned_block(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let h = Self::kv_get(&conn, "last_scanned")?;
+            let hh = Self::kv_get(&conn, "last_scanned_hash")?;
+            match (h, hh) {
+                (Some(hs), Some(hh)) => {
+                    let height: u32 = hs.parse().context("parse last_scanned")?;
+                    let hash = BlockHash::from_str(&hh).context("parse last_scanned_hash")?;
+                    Ok(Some((height, hash)))
+                }
+                _ => Ok(None),
+            }
+        })
+        .await?
+    }
+
+    async fn set_last_scanned_block(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let _tx = conn.unchecked_transaction()?;
+            Self::kv_set(&conn, "last_scanned", &height.to_string())?;
+            Self::kv_set(&conn, "last_scanned_hash", &block.to_string())?;
+            _tx.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn wipe(&self) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            conn.execute_batch(
+                "BEGIN;
+                 DELETE FROM state WHERE key NOT IN ('birth_height', 'network', 'delivery_sequence');
+                 DELETE FROM cf_headers;
+                 DELETE FROM cf_headers_ext;
+                 DELETE FROM delivered;
+                 DELETE FROM pending;
+                 DELETE FROM utxos;
+                 DELETE FROM scan_misses;
+                 DELETE FROM watched;
+                 DELETE FROM scan_ranges;
+                 DELETE FROM scan_sessions;
+                 COMMIT;",
+            )?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn get_delivered(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let mut stmt = conn.prepare("SELECT block FROM delivered WHERE height = ?1")?;
+            let mut rows = stmt.query(params![height])?;
+            match rows.next()? {
+                Some(row) => {
+                    let bytes: Vec<u8> = row.get(0)?;
+                    let arr: [u8; 32] = bytes
+                        .try_into()
+                        .map_err(|_| anyhow::anyhow!("delivered row {height} is not 32 bytes"))?;
+                    Ok(Some(BlockHash::from_byte_array(arr)))
+                }
+                None => Ok(None),
+            }
+        })
+        .await?
+    }
+
+    async fn set_delivered(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            conn.execute(
+                "INSERT INTO delivered(height,block) VALUES(?1,?2)
+                 ON CONFLICT(height) DO UPDATE SET block=excluded.block",
+                params![height, block.as_byte_array()],
+            )?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn enqueue_match(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            conn.execute(
+                "INSERT INTO pending(height,block) VALUES(?1,?2)
+                 ON CONFLICT(height) DO UPDATE SET block=excluded.block",
+                params![height, block.as_byte_array()],
+            )?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn pending_matches(&self) -> anyhow::Result<Vec<(u32, BlockHash)>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let mut stmt = conn.prepare("SELECT height, block FROM pending ORDER BY height")?;
+            let mut rows = stmt.query([])?;
+            let mut out = Vec::new();
+            while let Some(row) = rows.next()? {
+                let height: u32 = row.get(0)?;
+                let bytes: Vec<u8> = row.get(1)?;
+                let arr: [u8; 32] = bytes
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("pending row {height} is not 32 bytes"))?;
+                out.push((height, BlockHash::from_byte_array(arr)));
+            }
+            Ok(out)
+        })
+        .await?
+    }
+
+    async fn dequeue_match(&self, height: u32) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            conn.execute("DELETE FROM pending WHERE height = ?1", params![height])?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn save_utxo(&self, utxo: &Utxo) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        let utxo = utxo.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            conn.execute(
+                "INSERT OR REPLACE INTO utxos(txid,vout,value,script,height,spent_at,orphaned)
+                 VALUES(?1,?2,?3,?4,?5,?6,?7)",
+                params![
+                    utxo.outpoint.txid.as_byte_array(),
+                    utxo.outpoint.vout,
+                    utxo.txout.value.to_sat() as i64,
+                    utxo.txout.script_pubkey.as_bytes(),
+                    utxo.height,
+                    utxo.spent_at,
+                    utxo.orphaned
+                ],
+            )?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn load_utxo(&self, outpoint: OutPoint) -> anyhow::Result<Option<Utxo>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            Ok(Self::query_utxos(
+                &conn,
+                "WHERE txid = ?1 AND vout = ?2",
+                params![outpoint.txid.as_byte_array(), outpoint.vout],
+            )?
+            .pop())
+        })
+        .await?
+    }
+
+    async fn load_utxos(&self, outpoints: &[OutPoint]) -> anyhow::Result<Vec<Utxo>> {
+        let path = self.path.clone();
+        let outpoints = outpoints.to_vec();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let mut out = Vec::new();
+            for o in outpoints {
+                out.extend(Self::query_utxos(
+                    &conn,
+                    "WHERE txid = ?1 AND vout = ?2",
+                    params![o.txid.as_byte_array(), o.vout],
+                )?);
+            }
+            Ok(out)
+        })
+        .await?
+    }
+
+    async fn utxos(&self) -> anyhow::Result<Vec<Utxo>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            Self::query_utxos(&conn, "", [])
+        })
+        .await?
+    }
+
+    async fn rollback_utxos(&self, height: u32) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let _tx = conn.unchecked_transaction()?;
+            conn.execute(
+                "UPDATE utxos SET orphaned = 1 WHERE height > ?1",
+                params![height],
+            )?;
+            conn.execute(
+                "UPDATE utxos SET spent_at = NULL WHERE spent_at > ?1",
+                params![height],
+            )?;
+            _tx.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn load_misses(
+        &self,
+        fingerprint: sha256::Hash,
+        from: u32,
+        to: u32,
+    ) -> anyhow::Result<Vec<(u32, BlockHash)>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            if Self::kv_get(&conn, "misses_fingerprint")? != Some(fingerprint.to_string()) {
+                return Ok(vec![]);
+            }
+            let mut stmt = conn.prepare(
+                "SELECT height, block FROM scan_misses WHERE height BETWEEN ?1 AND ?2 ORDER BY height",
+            )?;
+            let mut rows = stmt.query(params![from, to])?;
+            let mut out = Vec::new();
+            while let Some(row) = rows.next()? {
+                let height: u32 = row.get(0)?;
+                let bytes: Vec<u8> = row.get(1)?;
+                let arr: [u8; 32] = bytes
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("scan_misses row {height} is not 32 bytes"))?;
+                out.push((height, BlockHash::from_byte_array(arr)));
+            }
+            Ok(out)
+        })
+        .await?
+    }
+
+    async fn save_misses(
+        &self,
+        fingerprint: sha256::Hash,
+        misses: &[(u32, BlockHash)],
+    ) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        let misses = misses.to_vec();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let _tx = conn.unchecked_transaction()?;
+            let fingerprint = fingerprint.to_string();
+            if Self::kv_get(&conn, "misses_fingerprint")?.as_ref() != Some(&fingerprint) {
+                conn.execute("DELETE FROM scan_misses", [])?;
+                Self::kv_set(&conn, "misses_fingerprint", &fingerprint)?;
+            }
+            {
+                let mut stmt = conn.prepare(
+                    "INSERT INTO scan_misses(height,block) VALUES(?1,?2)
+                     ON CONFLICT(height) DO UPDATE SET block=excluded.block",
+                )?;
+                for (height, block) in misses {
+                    stmt.execute(params![height, block.as_byte_array()])?;
+                }
+            }
+            _tx.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn get_watchlist_fingerprint(&self) -> anyhow::Result<Option<sha256::Hash>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            Self::kv_get(&conn, "watchlist_fingerprint")?
+                .map(|s| sha256::Hash::from_str(&s).context("bad watchlist_fingerprint"))
+                .transpose()
+        })
+        .await?
+    }
+
+    async fn watched_scripts(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let mut stmt = conn.prepare("SELECT script FROM watched")?;
+            let scripts = stmt
+                .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+                .map(|script| Ok(ScriptBuf::from_bytes(script?)))
+                .collect::<anyhow::Result<_>>()?;
+            Ok(scripts)
+        })
+        .await?
+    }
+
+    async fn set_watchlist(
+        &self,
+        fingerprint: sha256::Hash,
+        scripts: &[ScriptBuf],
+    ) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        let scripts = scripts.to_vec();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let _tx = conn.unchecked_transaction()?;
+            conn.execute("DELETE FROM watched", [])?;
+            {
+                let mut stmt = conn.prepare("INSERT OR IGNORE INTO watched(script) VALUES(?1)")?;
+                for script in &scripts {
+                    stmt.execute(params![script.as_bytes()])?;
+                }
+            }
+            Self::kv_set(&conn, "watchlist_fingerprint", &fingerprint.to_string())?;
+            _tx.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn get_cf_anchor(
+        &self,
+        filter_type: FilterType,
+    ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let (height_key, hash_key) = Self::typed_keys("cf_anchor", filter_type);
+            match (
+                Self::kv_get(&conn, &height_key)?,
+                Self::kv_get(&conn, &hash_key)?,
+            ) {
+                (Some(h), Some(hash)) => Ok(Some((
+                    h.parse().context("parse cf_anchor_height")?,
+                    BlockHash::from_str(&hash).context("parse cf_anchor_hash")?,
+                ))),
+                _ => Ok(None),
+            }
+        })
+        .await?
+    }
+
+    async fn set_cf_anchor(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+        cfheader: BlockHash,
+    ) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let (height_key, hash_key) = Self::typed_keys("cf_anchor", filter_type);
+            let _tx = conn.unchecked_transaction()?;
+            Self::kv_set(&conn, &height_key, &height.to_string())?;
+            Self::kv_set(&conn, &hash_key, &cfheader.to_string())?;
+            _tx.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn get_cf_tip_block(
+        &self,
+        filter_type: FilterType,
+    ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let (height_key, hash_key) = Self::typed_keys("cf_tip_block", filter_type);
+            match (
+                Self::kv_get(&conn, &height_key)?,
+                Self::kv_get(&conn, &hash_key)?,
+            ) {
+                (Some(h), Some(hash)) => Ok(Some((
+                    h.parse().context("parse cf_tip_block_height")?,
+                    BlockHash::from_str(&hash).context("parse cf_tip_block_hash")?,
+                ))),
+                _ => Ok(None),
+            }
+        })
+        .await?
+    }
+
+    async fn set_cf_tip_block(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+        block: BlockHash,
+    ) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let (height_key, hash_key) = Self::typed_keys("cf_tip_block", filter_type);
+            let _tx = conn.unchecked_transaction()?;
+            Self::kv_set(&conn, &height_key, &height.to_string())?;
+            Self::kv_set(&conn, &hash_key, &block.to_string())?;
+            _tx.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn get_range_progress(&self, start: u32, end: u32) -> anyhow::Result<Option<u32>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let mut stmt =
+                conn.prepare("SELECT scanned FROM scan_ranges WHERE start = ?1 AND end = ?2")?;
+            let mut rows = stmt.query(params![start, end])?;
+            Ok(rows.next()?.map(|row| row.get(0)).transpose()?)
+        })
+        .await?
+    }
+
+    async fn set_range_progress(&self, start: u32, end: u32, scanned: u32) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            conn.execute(
+                "INSERT INTO scan_ranges(start,end,scanned) VALUES(?1,?2,?3)
+                 ON CONFLICT(start,end) DO UPDATE SET scanned=excluded.scanned",
+                params![start, end, scanned],
+            )?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn clear_range_progress(&self) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            conn.execute("DELETE FROM scan_ranges", [])?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn next_delivery_sequence(&self) -> anyhow::Result<Option<u64>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let tx = conn.unchecked_transaction()?;
+            let last = Self::kv_get(&tx, "delivery_sequence")?
+                .map(|s| s.parse::<u64>())
+                .transpose()
+                .context("bad delivery_sequence")?
+                .unwrap_or(0);
+            Self::kv_set(&tx, "delivery_sequence", &(last + 1).to_string())?;
+            tx.commit()?;
+            Ok(Some(last + 1))
+        })
+        .await?
+    }
+
+    async fn scan_sessions(&self) -> anyhow::Result<Vec<String>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            let mut stmt = conn.prepare("SELECT record FROM scan_sessions ORDER BY id")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            Ok(rows.collect::<Result<Vec<String>, _>>()?)
+        })
+        .await?
+    }
+
+    async fn save_scan_session(&self, id: &str, record: &str) -> anyhow::Result<()> {
+        let (path, id, record) = (self.path.clone(), id.to_string(), record.to_string());
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            conn.execute(
+                "INSERT INTO scan_sessions(id,record) VALUES(?1,?2)
+                 ON CONFLICT(id) DO UPDATE SET record=excluded.record",
+                params![id, record],
+            )?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn remove_scan_session(&self, id: &str) -> anyhow::Result<()> {
+        let (path, id) = (self.path.clone(), id.to_string());
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            conn.execute("DELETE FROM scan_sessions WHERE id = ?1", params![id])?;
+            Ok(())
         })
         .await?
     }
@@ -158,4 +876,43 @@ impl Store for SqliteStore {
         })
         .await?
     }
+
+    async fn get_network(&self) -> anyhow::Result<Option<Network>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            Self::kv_get(&conn, "network")?
+                .map(|s| Network::from_str(&s).with_context(|| format!("bad network {s:?}")))
+                .transpose()
+        })
+        .await?
+    }
+
+    async fn set_network(&self, network: Network) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            Self::kv_set(&conn, "network", &network.to_string())
+        })
+        .await?
+    }
+
+    async fn get_config(&self) -> anyhow::Result<Option<String>> {
+        let path = self.path.clone();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            Self::kv_get(&conn, "engine_config")
+        })
+        .await?
+    }
+
+    async fn set_config(&self, record: &str) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        let record = record.to_string();
+        task::spawn_blocking(move || {
+            let conn = Connection::open(path)?;
+            Self::kv_set(&conn, "engine_config", &record)
+        })
+        .await?
+    }
 }
@@ -0,0 +1,1273 @@
+//! In-memory doubles for testing code that embeds the engine.
+//!
+//! Every type here is a cheap handle over shared state: clone it, hand one copy
+//! to [`Niebla158`](crate::Niebla158), and keep the other to script responses
+//! or inspect what happened.
+//!
+//! ```rust,ignore
+//! use niebla_158::testing::*;
+//!
+//! // Ten blocks on top of genesis, the seventh paying `script(1)`.
+//! let (filters, headers) = mock_chain(10, |h| if h == 7 { vec![script(1)] } else { vec![] })?;
+//! let hooks = RecordingHooks::new(vec![script(1)]);
+//! let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers);
+//! engine.run_to_tip().await?;
+//! assert_eq!(hooks.matches()[0].height, 7);
+//! ```
+use crate::{
+    cfheaders::next_header,
+    filter_source::{CfHeadersBatch, FilterSource, FilterType},
+    headers::HeaderSource,
+    hooks::{MatchDetails, WalletHooks},
+    store::Store,
+    utxo::Utxo,
+};
+use anyhow::{anyhow, bail, Context};
+use async_trait::async_trait;
+use bitcoin::{
+    absolute::LockTime,
+    bip158::{self, BlockFilter},
+    block, consensus,
+    constants::genesis_block,
+    hashes::{sha256, sha256d, Hash},
+    transaction, Amount, Block, BlockHash, CompactTarget, Network, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxMerkleNode, TxOut, WPubkeyHash, Witness,
+};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A block whose coinbase pays 1000 sats to each of `scripts`.
+///
+/// `height` goes into the coinbase so blocks at different heights never share a txid.
+pub fn block_paying(height: u32, prev: BlockHash, scripts: &[ScriptBuf]) -> Block {
+    let coinbase = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::builder()
+                .push_int(i64::from(height))
+                .into_script(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: scripts
+            .iter()
+            .map(|s| TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: s.clone(),
+            })
+            .collect(),
+    };
+    let mut block = Block {
+        header: block::Header {
+            version: block::Version::ONE,
+            prev_blockhash: prev,
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: height,
+            bits: CompactTarget::from_consensus(0x207f_ffff),
+            nonce: 0,
+        },
+        txdata: vec![coinbase],
+    };
+    if let Some(root) = block.compute_merkle_root() {
+        block.header.merkle_root = root;
+    }
+    block
+}
+
+/// Genesis of mock chains: the regtest genesis block, which every
+/// [`MockFilterSource`] serves at height 0.
+pub fn genesis_hash() -> BlockHash {
+    genesis_block(Network::Regtest).block_hash()
+}
+
+/// A P2WPKH script distinct for every `b`.
+pub fn script(b: u8) -> ScriptBuf {
+    ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([b; 20]))
+}
+
+/// A best chain of `len` blocks on top of [`genesis_hash`], served by both
+/// mocks; block `h` is [`block_paying`] `pays(h)`.
+pub fn mock_chain(
+    len: u32,
+    mut pays: impl FnMut(u32) -> Vec<ScriptBuf>,
+) -> anyhow::Result<(MockFilterSource, MockHeaderSource)> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=len {
+        prev = headers.push(filters.add_block(h, &block_paying(h, prev, &pays(h)))?);
+    }
+    Ok((filters, headers))
+}
+
+/// Which [`FilterSource`] call an injected failure or counter refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockCall {
+    /// [`FilterSource::get_cfheaders`].
+    CfHeaders,
+    /// [`FilterSource::get_cfcheckpt`].
+    CfCheckpt,
+    /// [`FilterSource::get_cfilter`].
+    Cfilter,
+    /// [`FilterSource::get_block`].
+    Block,
+}
+
+struct MockEntry {
+    height: u32,
+    prev: BlockHash,
+    filter: Vec<u8>,
+    block: Vec<u8>,
+}
+
+#[derive(Default)]
+struct FilterState {
+    blocks: HashMap<BlockHash, MockEntry>,
+    /// Output scripts of every added block, for building BIP-158 filters of spends.
+    outputs: HashMap<OutPoint, ScriptBuf>,
+    failures: HashMap<MockCall, VecDeque<String>>,
+    calls: HashMap<MockCall, usize>,
+    latency: Duration,
+    filter_tip: Option<u32>,
+    best_tip: Option<BlockHash>,
+    label: Option<String>,
+}
+
+/// Scriptable [`FilterSource`] serving filters and blocks from memory.
+///
+/// Blocks may form forks; `get_cfheaders` follows `prev_blockhash` back from the
+/// stop hash, so the answer always describes that branch. `get_cfcheckpt`
+/// rolls that branch's filters from genesis as BIP-157 does, so its
+/// checkpoints are the headers a real node would serve.
+#[derive(Clone)]
+pub struct MockFilterSource {
+    state: Arc<Mutex<FilterState>>,
+}
+
+impl Default for MockFilterSource {
+    fn default() -> Self {
+        let source = Self {
+            state: Default::default(),
+        };
+        source
+            .add_block(0, &genesis_block(Network::Regtest))
+            .expect("genesis filter needs no prevouts");
+        source
+    }
+}
+
+impl MockFilterSource {
+    /// Source serving only the [genesis block](genesis_hash).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `block` at `height` with its real BIP-158 basic filter.
+    ///
+    /// Inputs must spend outputs of blocks added earlier (or the coinbase).
+    pub fn add_block(&self, height: u32, block: &Block) -> anyhow::Result<BlockHash> {
+        let hash = block.block_hash();
+        let mut st = self.state.lock().unwrap();
+        let filter = BlockFilter::new_script_filter(block, |op| {
+            st.outputs
+                .get(op)
+                .cloned()
+                .ok_or(bip158::Error::UtxoMissing(*op))
+        })
+        .with_context(|| format!("build filter for {hash}"))?;
+        for tx in &block.txdata {
+            let txid = tx.compute_txid();
+            for (vout, out) in tx.output.iter().enumerate() {
+                st.outputs
+                    .insert(OutPoint::new(txid, vout as u32), out.script_pubkey.clone());
+            }
+        }
+        st.blocks.insert(
+            hash,
+            MockEntry {
+                height,
+                prev: block.header.prev_blockhash,
+                filter: filter.content,
+                block: consensus::serialize(block),
+            },
+        );
+        Ok(hash)
+    }
+
+    /// Serve arbitrary `filter`/`block` bytes for `hash` at `height` (for malformed-data tests).
+    pub fn add_raw(
+        &self,
+        height: u32,
+        hash: BlockHash,
+        prev: BlockHash,
+        filter: Vec<u8>,
+        block: Vec<u8>,
+    ) {
+        self.state.lock().unwrap().blocks.insert(
+            hash,
+            MockEntry {
+                height,
+                prev,
+                filter,
+                block,
+            },
+        );
+    }
+
+    /// Stop serving `hash` and its filter, like a node that pruned a stale branch.
+    pub fn remove_block(&self, hash: BlockHash) {
+        self.state.lock().unwrap().blocks.remove(&hash);
+    }
+
+    /// Make the next `call` fail with `message` (queue several for repeated failures).
+    pub fn fail_next(&self, call: MockCall, message: impl Into<String>) {
+        let mut st = self.state.lock().unwrap();
+        st.failures
+            .entry(call)
+            .or_default()
+            .push_back(message.into());
+    }
+
+    /// Pretend filters exist only up to `tip` (a node still indexing); `None` lifts it.
+    ///
+    /// `get_cfheaders` then returns a short batch, or fails if nothing in the range
+    /// is available, and `filter_tip_height` reports `tip`.
+    pub fn set_filter_tip(&self, tip: Option<u32>) {
+        self.state.lock().unwrap().filter_tip = tip;
+    }
+
+    /// Report the branch ending at `tip` as the best chain from
+    /// [`FilterSource::block_hash_at`]; `None` (the default) reports nothing.
+    pub fn set_best_tip(&self, tip: Option<BlockHash>) {
+        self.state.lock().unwrap().best_tip = tip;
+    }
+
+    /// Name reported by [`FilterSource::label`].
+    pub fn set_label(&self, label: impl Into<String>) {
+        self.state.lock().unwrap().label = Some(label.into());
+    }
+
+    /// Delay every call by `latency`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// How many times `call` has been made (including failed calls).
+    pub fn calls(&self, call: MockCall) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .get(&call)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Count the call, apply latency, and pop a scripted failure if one is queued.
+    async fn enter(&self, call: MockCall) -> anyhow::Result<()> {
+        let (latency, failure) = {
+            let mut st = self.state.lock().unwrap();
+            *st.calls.entry(call).or_default() += 1;
+            let failure = st.failures.get_mut(&call).and_then(VecDeque::pop_front);
+            (st.latency, failure)
+        };
+        if !latency.is_zero() {
+            crate::rt::sleep(latency).await;
+        }
+        match failure {
+            Some(msg) => Err(anyhow!(msg)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl FilterSource for MockFilterSource {
+    fn label(&self) -> Option<String> {
+        self.state.lock().unwrap().label.clone()
+    }
+
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        self.enter(MockCall::CfHeaders).await?;
+        let st = self.state.lock().unwrap();
+        if st.filter_tip.is_some_and(|tip| tip < start_h) {
+            bail!("mock: no filters at {start_h} yet");
+        }
+        let mut headers = Vec::new();
+        let mut cur = stop_hash;
+        loop {
+            let entry = st
+                .blocks
+                .get(&cur)
+                .with_context(|| format!("mock: unknown block {cur}"))?;
+            if entry.height < start_h {
+                bail!("mock: start height {start_h} above stop height");
+            }
+            if st.filter_tip.is_none_or(|tip| entry.height <= tip) {
+                headers.push(sha256d::Hash::hash(&entry.filter).to_byte_array());
+            }
+            if entry.height == start_h {
+                break;
+            }
+            cur = entry.prev;
+        }
+        headers.reverse();
+        Ok(CfHeadersBatch {
+            start_height: start_h,
+            headers,
+        })
+    }
+
+    async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+        self.enter(MockCall::CfCheckpt).await?;
+        let st = self.state.lock().unwrap();
+        // Filter hashes from genesis up to the stop block on its branch.
+        let mut hashes = Vec::new();
+        let mut cur = stop_hash;
+        loop {
+            let entry = st
+                .blocks
+                .get(&cur)
+                .with_context(|| format!("mock: unknown block {cur}"))?;
+            hashes.push(sha256d::Hash::hash(&entry.filter).to_byte_array());
+            if entry.height == 0 {
+                break;
+            }
+            cur = entry.prev;
+        }
+        hashes.reverse();
+
+        // Rolled as BIP-157 does, genesis over the all-zero header.
+        let mut rolling = BlockHash::all_zeros();
+        let mut checkpoints = Vec::new();
+        for (height, fh) in hashes.iter().enumerate() {
+            rolling = next_header(fh, rolling);
+            if height > 0 && height % 1_000 == 0 {
+                checkpoints.push(rolling);
+            }
+        }
+        Ok(checkpoints)
+    }
+
+    async fn filter_tip_height(&self) -> anyhow::Result<Option<u32>> {
+        Ok(self.state.lock().unwrap().filter_tip)
+    }
+
+    async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        let st = self.state.lock().unwrap();
+        let Some(mut cur) = st.best_tip else {
+            return Ok(None);
+        };
+        while let Some(entry) = st.blocks.get(&cur) {
+            match entry.height.cmp(&height) {
+                std::cmp::Ordering::Equal => return Ok(Some(cur)),
+                std::cmp::Ordering::Less => break,
+                std::cmp::Ordering::Greater => cur = entry.prev,
+            }
+        }
+        bail!("mock: no block at {height} on the best chain")
+    }
+
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.enter(MockCall::Cfilter).await?;
+        let st = self.state.lock().unwrap();
+        let entry = st
+            .blocks
+            .get(&block)
+            .with_context(|| format!("mock: unknown block {block}"))?;
+        Ok(entry.filter.clone())
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.enter(MockCall::Block).await?;
+        let st = self.state.lock().unwrap();
+        let entry = st
+            .blocks
+            .get(&block)
+            .with_context(|| format!("mock: unknown block {block}"))?;
+        Ok(entry.block.clone())
+    }
+}
+
+/// [`HeaderSource`] over an in-memory best chain (index = height).
+#[derive(Clone, Default)]
+pub struct MockHeaderSource {
+    chain: Arc<Mutex<Vec<BlockHash>>>,
+}
+
+impl MockHeaderSource {
+    /// Empty chain; [`push`](Self::push) the genesis hash first.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Chain whose hash at height `i` is `hashes[i]`.
+    pub fn from_hashes(hashes: Vec<BlockHash>) -> Self {
+        Self {
+            chain: Arc::new(Mutex::new(hashes)),
+        }
+    }
+
+    /// Extend the best chain by one block; returns `hash` for chaining.
+    pub fn push(&self, hash: BlockHash) -> BlockHash {
+        self.chain.lock().unwrap().push(hash);
+        hash
+    }
+
+    /// Reorg: drop every block above `fork_height`, then append `new_blocks`.
+    pub fn reorg(&self, fork_height: u32, new_blocks: impl IntoIterator<Item = BlockHash>) {
+        let mut chain = self.chain.lock().unwrap();
+        chain.truncate(fork_height as usize + 1);
+        chain.extend(new_blocks);
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl HeaderSource for MockHeaderSource {
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        let len = self.chain.lock().unwrap().len();
+        let Some(tip) = len.checked_sub(1) else {
+            bail!("mock: empty header chain");
+        };
+        Ok(u32::try_from(tip)?)
+    }
+
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        self.chain
+            .lock()
+            .unwrap()
+            .get(height as usize)
+            .copied()
+            .with_context(|| format!("mock: no block at height {height}"))
+    }
+
+    async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+        let chain = self.chain.lock().unwrap();
+        Ok(chain.iter().rposition(|h| *h == block).map(|i| i as u32))
+    }
+}
+
+/// A kind of misbehaviour a [`FaultySource`] injects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fault {
+    /// The call failed instead of reaching the wrapped source.
+    Failure,
+    /// A filter or block lost its second half, or a cfheaders batch its last headers.
+    Truncated,
+    /// A filter was swapped for an empty, well-formed one that matches nothing.
+    WrongFilter,
+    /// Two neighbouring filter hashes in a cfheaders batch swapped places.
+    Reordered,
+}
+
+struct FaultState {
+    rng: u64,
+    injected: HashMap<Fault, usize>,
+}
+
+impl FaultState {
+    /// splitmix64: small, seedable and good enough to pick faults.
+    fn next(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Decide whether to inject `fault`, which happens with probability `rate`.
+    fn roll(&mut self, fault: Fault, rate: f64) -> bool {
+        let hit = rate > 0.0 && self.unit() < rate;
+        if hit {
+            *self.injected.entry(fault).or_default() += 1;
+        }
+        hit
+    }
+}
+
+/// Wraps any [`FilterSource`] or [`HeaderSource`] and misbehaves like a flaky
+/// or byzantine peer, to check retry, timeout and checkpoint settings before
+/// they meet one in production.
+///
+/// Each call independently fails, is delayed, or has its response corrupted,
+/// at the configured rates. Faults are drawn from a generator seeded in
+/// [`new`](Self::new), so a seed replays the same faults for the same calls.
+/// A filter range is one call, so it fails as a whole, but each filter in it
+/// may be corrupted on its own.
+///
+/// ```rust,ignore
+/// let source = FaultySource::new(filters, 7)
+///     .with_failure_rate(0.2)
+///     .with_latency(Duration::from_millis(5), Duration::from_millis(50))
+///     .with_retry(5, Duration::from_millis(10));
+/// ```
+pub struct FaultySource<S> {
+    inner: Arc<S>,
+    state: Arc<Mutex<FaultState>>,
+    failure_rate: f64,
+    latency: (Duration, Duration),
+    truncation_rate: f64,
+    wrong_filter_rate: f64,
+    reorder_rate: f64,
+}
+
+impl<S> Clone for FaultySource<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            state: self.state.clone(),
+            failure_rate: self.failure_rate,
+            latency: self.latency,
+            truncation_rate: self.truncation_rate,
+            wrong_filter_rate: self.wrong_filter_rate,
+            reorder_rate: self.reorder_rate,
+        }
+    }
+}
+
+impl<S> FaultySource<S> {
+    /// Pass every call through to `inner` until faults are configured; `seed`
+    /// picks which calls they hit.
+    pub fn new(inner: S, seed: u64) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            state: Arc::new(Mutex::new(FaultState {
+                rng: seed,
+                injected: HashMap::new(),
+            })),
+            failure_rate: 0.0,
+            latency: (Duration::ZERO, Duration::ZERO),
+            truncation_rate: 0.0,
+            wrong_filter_rate: 0.0,
+            reorder_rate: 0.0,
+        }
+    }
+
+    /// Fail this fraction of calls (0.0 to 1.0).
+    pub fn with_failure_rate(mut self, rate: f64) -> Self {
+        self.failure_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Delay every call by a random duration between `min` and `max`.
+    pub fn with_latency(mut self, min: Duration, max: Duration) -> Self {
+        self.latency = (min, max.max(min));
+        self
+    }
+
+    /// Cut this fraction of filters, blocks and cfheaders batches short.
+    pub fn with_truncation_rate(mut self, rate: f64) -> Self {
+        self.truncation_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Serve an empty filter, hiding any matches, for this fraction of filters.
+    pub fn with_wrong_filter_rate(mut self, rate: f64) -> Self {
+        self.wrong_filter_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Swap two neighbouring filter hashes in this fraction of cfheaders batches.
+    pub fn with_reorder_rate(mut self, rate: f64) -> Self {
+        self.reorder_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The wrapped source.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// How many times `fault` has been injected, across all clones.
+    pub fn injected(&self, fault: Fault) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .injected
+            .get(&fault)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Apply latency, and fail if this call draws a failure.
+    async fn enter(&self, call: &str) -> anyhow::Result<()> {
+        let (latency, fail) = {
+            let mut st = self.state.lock().unwrap();
+            let (min, max) = self.latency;
+            let latency = min + (max - min).mul_f64(st.unit());
+            (latency, st.roll(Fault::Failure, self.failure_rate))
+        };
+        if !latency.is_zero() {
+            crate::rt::sleep(latency).await;
+        }
+        if fail {
+            bail!("faulty: injected failure in {call}");
+        }
+        Ok(())
+    }
+
+    fn roll(&self, fault: Fault, rate: f64) -> bool {
+        self.state.lock().unwrap().roll(fault, rate)
+    }
+
+    /// Keep the first half of `bytes` if this response draws a truncation.
+    fn truncate(&self, mut bytes: Vec<u8>) -> Vec<u8> {
+        if !bytes.is_empty() && self.roll(Fault::Truncated, self.truncation_rate) {
+            bytes.truncate(bytes.len() / 2);
+        }
+        bytes
+    }
+
+    /// Swap `filter` for an empty one, or truncate it, if it draws either fault.
+    fn corrupt_filter(&self, filter: Vec<u8>) -> Vec<u8> {
+        if self.roll(Fault::WrongFilter, self.wrong_filter_rate) {
+            return vec![0];
+        }
+        self.truncate(filter)
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl<S: FilterSource> FilterSource for FaultySource<S> {
+    fn label(&self) -> Option<String> {
+        self.inner.label()
+    }
+
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        self.get_cfheaders_typed(FilterType::BASIC, start_h, stop_hash)
+            .await
+    }
+
+    async fn get_cfcheckpt(&self, stop_hash: BlockHash) -> anyhow::Result<Vec<BlockHash>> {
+        self.get_cfcheckpt_typed(FilterType::BASIC, stop_hash).await
+    }
+
+    async fn filter_tip_height(&self) -> anyhow::Result<Option<u32>> {
+        self.inner.filter_tip_height().await
+    }
+
+    async fn block_hash_at(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        self.enter("block_hash_at").await?;
+        self.inner.block_hash_at(height).await
+    }
+
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.get_cfilter_typed(FilterType::BASIC, block).await
+    }
+
+    async fn get_cfheaders_typed(
+        &self,
+        filter_type: FilterType,
+        start_h: u32,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        self.enter("get_cfheaders").await?;
+        let mut batch = self
+            .inner
+            .get_cfheaders_typed(filter_type, start_h, stop_hash)
+            .await?;
+        let len = batch.headers.len();
+        if len > 1 && self.roll(Fault::Reordered, self.reorder_rate) {
+            let i = (self.state.lock().unwrap().next() % (len as u64 - 1)) as usize;
+            batch.headers.swap(i, i + 1);
+        }
+        if len > 0 && self.roll(Fault::Truncated, self.truncation_rate) {
+            batch.headers.truncate(len / 2);
+        }
+        Ok(batch)
+    }
+
+    async fn get_cfcheckpt_typed(
+        &self,
+        filter_type: FilterType,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<Vec<BlockHash>> {
+        self.enter("get_cfcheckpt").await?;
+        self.inner.get_cfcheckpt_typed(filter_type, stop_hash).await
+    }
+
+    async fn get_cfilter_typed(
+        &self,
+        filter_type: FilterType,
+        block: BlockHash,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.enter("get_cfilter").await?;
+        let filter = self.inner.get_cfilter_typed(filter_type, block).await?;
+        Ok(self.corrupt_filter(filter))
+    }
+
+    async fn get_cfilter_range(
+        &self,
+        filter_type: FilterType,
+        blocks: &[BlockHash],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        self.enter("get_cfilter_range").await?;
+        let filters = self.inner.get_cfilter_range(filter_type, blocks).await?;
+        Ok(filters
+            .into_iter()
+            .map(|f| self.corrupt_filter(f))
+            .collect())
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.enter("get_block").await?;
+        let raw = self.inner.get_block(block).await?;
+        Ok(self.truncate(raw))
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl<S: HeaderSource> HeaderSource for FaultySource<S> {
+    fn label(&self) -> Option<String> {
+        self.inner.label()
+    }
+
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        self.enter("tip_height").await?;
+        self.inner.tip_height().await
+    }
+
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        self.enter("hash_at_height").await?;
+        self.inner.hash_at_height(height).await
+    }
+
+    async fn header(&self, block: BlockHash) -> anyhow::Result<Option<block::Header>> {
+        self.enter("header").await?;
+        self.inner.header(block).await
+    }
+
+    async fn headers_from(
+        &self,
+        start: BlockHash,
+        count: u32,
+    ) -> anyhow::Result<Vec<block::Header>> {
+        self.enter("headers_from").await?;
+        self.inner.headers_from(start, count).await
+    }
+
+    async fn height_of(&self, block: BlockHash) -> anyhow::Result<Option<u32>> {
+        self.enter("height_of").await?;
+        self.inner.height_of(block).await
+    }
+}
+
+#[derive(Default)]
+struct StoreState {
+    cf_tip: BTreeMap<FilterType, (u32, BlockHash)>,
+    cf_headers: BTreeMap<(FilterType, u32), BlockHash>,
+    cf_anchor: BTreeMap<FilterType, (u32, BlockHash)>,
+    cf_tip_block: BTreeMap<FilterType, (u32, BlockHash)>,
+    delivered: BTreeMap<u32, BlockHash>,
+    pending: BTreeMap<u32, BlockHash>,
+    last_scanned: u32,
+    last_scanned_hash: Option<BlockHash>,
+    utxos: BTreeMap<OutPoint, Utxo>,
+    misses: Option<(sha256::Hash, BTreeMap<u32, BlockHash>)>,
+    watchlist: Option<(sha256::Hash, Vec<ScriptBuf>)>,
+    ranges: BTreeMap<(u32, u32), u32>,
+    sessions: BTreeMap<String, String>,
+    delivery_sequence: u64,
+    birth: Option<u32>,
+    network: Option<Network>,
+    config: Option<String>,
+}
+
+/// [`Store`] kept entirely in memory, including per-height cfheaders.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<StoreState>>,
+}
+
+impl MemoryStore {
+    /// Empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl Store for MemoryStore {
+    async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        self.load_cf_tip_typed(FilterType::BASIC).await
+    }
+
+    async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
+        self.save_cf_tip_typed(FilterType::BASIC, height, cfheader)
+            .await
+    }
+
+    async fn save_cf_headers(
+        &self,
+        start_height: u32,
+        headers: &[BlockHash],
+    ) -> anyhow::Result<()> {
+        self.save_cf_headers_typed(FilterType::BASIC, start_height, headers)
+            .await
+    }
+
+    async fn load_cf_header(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        self.load_cf_header_typed(FilterType::BASIC, height).await
+    }
+
+    async fn load_cf_tip_typed(
+        &self,
+        filter_type: FilterType,
+    ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        Ok(self.state.lock().unwrap().cf_tip.get(&filter_type).copied())
+    }
+
+    async fn save_cf_tip_typed(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+        cfheader: BlockHash,
+    ) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        st.cf_tip.insert(filter_type, (height, cfheader));
+        Ok(())
+    }
+
+    async fn get_cf_anchor(
+        &self,
+        filter_type: FilterType,
+    ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .cf_anchor
+            .get(&filter_type)
+            .copied())
+    }
+
+    async fn set_cf_anchor(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+        cfheader: BlockHash,
+    ) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        st.cf_anchor.insert(filter_type, (height, cfheader));
+        Ok(())
+    }
+
+    async fn get_cf_tip_block(
+        &self,
+        filter_type: FilterType,
+    ) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .cf_tip_block
+            .get(&filter_type)
+            .copied())
+    }
+
+    async fn set_cf_tip_block(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+        block: BlockHash,
+    ) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        st.cf_tip_block.insert(filter_type, (height, block));
+        Ok(())
+    }
+
+    async fn save_cf_headers_typed(
+        &self,
+        filter_type: FilterType,
+        start_height: u32,
+        headers: &[BlockHash],
+    ) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        for (h, header) in (start_height..).zip(headers) {
+            st.cf_headers.insert((filter_type, h), *header);
+        }
+        Ok(())
+    }
+
+    async fn load_cf_header_typed(
+        &self,
+        filter_type: FilterType,
+        height: u32,
+    ) -> anyhow::Result<Option<BlockHash>> {
+        let st = self.state.lock().unwrap();
+        Ok(st.cf_headers.get(&(filter_type, height)).copied())
+    }
+
+    async fn get_delivered(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        Ok(self.state.lock().unwrap().delivered.get(&height).copied())
+    }
+
+    async fn set_delivered(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+        self.state.lock().unwrap().delivered.insert(height, block);
+        Ok(())
+    }
+
+    async fn enqueue_match(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+        self.state.lock().unwrap().pending.insert(height, block);
+        Ok(())
+    }
+
+    async fn pending_matches(&self) -> anyhow::Result<Vec<(u32, BlockHash)>> {
+        let st = self.state.lock().unwrap();
+        Ok(st.pending.iter().map(|(h, b)| (*h, *b)).collect())
+    }
+
+    async fn dequeue_match(&self, height: u32) -> anyhow::Result<()> {
+        self.state.lock().unwrap().pending.remove(&height);
+        Ok(())
+    }
+
+    async fn get_last_scanned(&self) -> anyhow::Result<u32> {
+        Ok(self.state.lock().unwrap().last_scanned)
+    }
+
+    async fn set_last_scanned(&self, height: u32) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        st.last_scanned = height;
+        st.last_scanned_hash = None;
+        Ok(())
+    }
+
+    async fn get_last_scanned_block(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        let st = self.state.lock().unwrap();
+        Ok(st.last_scanned_hash.map(|hash| (st.last_scanned, hash)))
+    }
+
+    async fn set_last_scanned_block(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        st.last_scanned = height;
+        st.last_scanned_hash = Some(block);
+        Ok(())
+    }
+
+    async fn save_utxo(&self, utxo: &Utxo) -> anyhow::Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .utxos
+            .insert(utxo.outpoint, utxo.clone());
+        Ok(())
+    }
+
+    async fn load_utxo(&self, outpoint: OutPoint) -> anyhow::Result<Option<Utxo>> {
+        Ok(self.state.lock().unwrap().utxos.get(&outpoint).cloned())
+    }
+
+    async fn load_utxos(&self, outpoints: &[OutPoint]) -> anyhow::Result<Vec<Utxo>> {
+        let st = self.state.lock().unwrap();
+        Ok(outpoints
+            .iter()
+            .filter_map(|o| st.utxos.get(o).cloned())
+            .collect())
+    }
+
+    async fn utxos(&self) -> anyhow::Result<Vec<Utxo>> {
+        Ok(self.state.lock().unwrap().utxos.values().cloned().collect())
+    }
+
+    async fn rollback_utxos(&self, height: u32) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        for u in st.utxos.values_mut() {
+            if u.height > height {
+                u.orphaned = true;
+            }
+            if u.spent_at.is_some_and(|h| h > height) {
+                u.spent_at = None;
+            }
+        }
+        Ok(())
+    }
+
+    async fn load_misses(
+        &self,
+        fingerprint: sha256::Hash,
+        from: u32,
+        to: u32,
+    ) -> anyhow::Result<Vec<(u32, BlockHash)>> {
+        Ok(match &self.state.lock().unwrap().misses {
+            Some((saved, misses)) if *saved == fingerprint => {
+                misses.range(from..=to).map(|(h, b)| (*h, *b)).collect()
+            }
+            _ => vec![],
+        })
+    }
+
+    async fn save_misses(
+        &self,
+        fingerprint: sha256::Hash,
+        misses: &[(u32, BlockHash)],
+    ) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        if st.misses.as_ref().map(|(saved, _)| *saved) != Some(fingerprint) {
+            st.misses = Some((fingerprint, BTreeMap::new()));
+        }
+        let (_, saved) = st.misses.as_mut().expect("just set");
+        saved.extend(misses.iter().copied());
+        Ok(())
+    }
+
+    async fn get_watchlist_fingerprint(&self) -> anyhow::Result<Option<sha256::Hash>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .watchlist
+            .as_ref()
+            .map(|(f, _)| *f))
+    }
+
+    async fn watched_scripts(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        let st = self.state.lock().unwrap();
+        Ok(st
+            .watchlist
+            .as_ref()
+            .map(|(_, s)| s.clone())
+            .unwrap_or_default())
+    }
+
+    async fn set_watchlist(
+        &self,
+        fingerprint: sha256::Hash,
+        scripts: &[ScriptBuf],
+    ) -> anyhow::Result<()> {
+        self.state.lock().unwrap().watchlist = Some((fingerprint, scripts.to_vec()));
+        Ok(())
+    }
+
+    async fn get_range_progress(&self, start: u32, end: u32) -> anyhow::Result<Option<u32>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .ranges
+            .get(&(start, end))
+            .copied())
+    }
+
+    async fn set_range_progress(&self, start: u32, end: u32, scanned: u32) -> anyhow::Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .ranges
+            .insert((start, end), scanned);
+        Ok(())
+    }
+
+    async fn clear_range_progress(&self) -> anyhow::Result<()> {
+        self.state.lock().unwrap().ranges.clear();
+        Ok(())
+    }
+
+    async fn next_delivery_sequence(&self) -> anyhow::Result<Option<u64>> {
+        let mut st = self.state.lock().unwrap();
+        st.delivery_sequence += 1;
+        Ok(Some(st.delivery_sequence))
+    }
+
+    async fn scan_sessions(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .sessions
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn save_scan_session(&self, id: &str, record: &str) -> anyhow::Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .sessions
+            .insert(id.to_string(), record.to_string());
+        Ok(())
+    }
+
+    async fn remove_scan_session(&self, id: &str) -> anyhow::Result<()> {
+        self.state.lock().unwrap().sessions.remove(id);
+        Ok(())
+    }
+
+    async fn wipe(&self) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        *st = StoreState {
+            birth: st.birth,
+            network: st.network,
+            delivery_sequence: st.delivery_sequence,
+            ..StoreState::default()
+        };
+        Ok(())
+    }
+
+    async fn get_birth_height(&self) -> anyhow::Result<Option<u32>> {
+        Ok(self.state.lock().unwrap().birth)
+    }
+
+    async fn set_birth_height(&self, h: u32) -> anyhow::Result<()> {
+        self.state.lock().unwrap().birth = Some(h);
+        Ok(())
+    }
+
+    async fn get_network(&self) -> anyhow::Result<Option<Network>> {
+        Ok(self.state.lock().unwrap().network)
+    }
+
+    async fn set_network(&self, network: Network) -> anyhow::Result<()> {
+        self.state.lock().unwrap().network = Some(network);
+        Ok(())
+    }
+
+    async fn get_config(&self) -> anyhow::Result<Option<String>> {
+        Ok(self.state.lock().unwrap().config.clone())
+    }
+
+    async fn set_config(&self, record: &str) -> anyhow::Result<()> {
+        self.state.lock().unwrap().config = Some(record.to_string());
+        Ok(())
+    }
+}
+
+/// One match delivered to [`RecordingHooks`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMatch {
+    /// Height passed to the hook.
+    pub height: u32,
+    /// Block hash passed to the hook.
+    pub block: BlockHash,
+    /// Transactions passed to the hook.
+    pub txs: Vec<Transaction>,
+    /// [`MatchDetails::header`], when delivered through `on_match`.
+    pub header: Option<block::Header>,
+    /// [`MatchDetails::median_time_past`], when delivered through `on_match`.
+    pub median_time_past: Option<u32>,
+    /// [`MatchDetails::match_id`], when delivered through `on_match`.
+    pub match_id: Option<sha256::Hash>,
+    /// [`MatchDetails::sequence`] (0 when delivered through `on_block_match`).
+    pub sequence: u64,
+}
+
+#[derive(Default)]
+struct HooksState {
+    watch: Vec<ScriptBuf>,
+    matches: Vec<RecordedMatch>,
+    failures: VecDeque<String>,
+}
+
+/// [`WalletHooks`] with a settable watchlist that records every match.
+#[derive(Clone, Default)]
+pub struct RecordingHooks {
+    state: Arc<Mutex<HooksState>>,
+}
+
+impl RecordingHooks {
+    /// Hooks watching `watch`.
+    pub fn new(watch: Vec<ScriptBuf>) -> Self {
+        let hooks = Self::default();
+        hooks.set_watchlist(watch);
+        hooks
+    }
+
+    /// Replace the watchlist (seen by the next run).
+    pub fn set_watchlist(&self, watch: Vec<ScriptBuf>) {
+        self.state.lock().unwrap().watch = watch;
+    }
+
+    /// Every match delivered so far, in order.
+    pub fn matches(&self) -> Vec<RecordedMatch> {
+        self.state.lock().unwrap().matches.clone()
+    }
+
+    /// Heights of every match delivered so far, in order.
+    pub fn matched_heights(&self) -> Vec<u32> {
+        self.state
+            .lock()
+            .unwrap()
+            .matches
+            .iter()
+            .map(|m| m.height)
+            .collect()
+    }
+
+    /// Make the next delivery fail with `message` (the match is not recorded).
+    pub fn fail_next(&self, message: impl Into<String>) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .push_back(message.into());
+    }
+
+    fn record(&self, m: RecordedMatch) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap();
+        if let Some(msg) = st.failures.pop_front() {
+            bail!(msg);
+        }
+        st.matches.push(m);
+        Ok(())
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl WalletHooks for RecordingHooks {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(self.state.lock().unwrap().watch.clone())
+    }
+
+    async fn on_block_match(
+        &self,
+        height: u32,
+        block: BlockHash,
+        txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        self.record(RecordedMatch {
+            height,
+            block,
+            txs,
+            header: None,
+            median_time_past: None,
+            match_id: None,
+            sequence: 0,
+        })
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        self.record(RecordedMatch {
+            height: details.height,
+            block: details.block,
+            txs: details.txs,
+            header: details.header,
+            median_time_past: details.median_time_past,
+            match_id: Some(details.match_id),
+            sequence: details.sequence,
+        })
+    }
+}
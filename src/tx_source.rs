@@ -0,0 +1,26 @@
+//! Lookup of arbitrary transactions by txid.
+//!
+//! Matched blocks carry the transactions that pay or spend watched scripts,
+//! but not the outputs those transactions spend. A [`TxSource`] fills that
+//! gap (see [`Niebla158::with_tx_source`](crate::Niebla158::with_tx_source))
+//! so spent amounts and fees can be computed, and serves any other consumer
+//! that needs a prevout, such as silent-payments tweaks. Implementations for
+//! Bitcoin Core (REST and RPC) and Esplora live in [`http`](crate::http).
+use crate::compat::{MaybeSend, MaybeSync};
+use async_trait::async_trait;
+use bitcoin::{Transaction, Txid};
+
+/// Provider of confirmed transactions by txid (e.g. a node with `txindex` or
+/// an Esplora backend).
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+pub trait TxSource: MaybeSend + MaybeSync {
+    /// Name of this source (e.g. its URL) for errors and events.
+    fn label(&self) -> Option<String> {
+        None
+    }
+
+    /// The transaction `txid`, or `None` if this source does not know it.
+    /// Answering with any other transaction fails the run as a source error.
+    async fn get_transaction(&self, txid: Txid) -> anyhow::Result<Option<Transaction>>;
+}
@@ -0,0 +1,113 @@
+//! Confirmed UTXO tracking for watch-only wallets.
+//!
+//! With [`Niebla158::with_utxo_tracking`](crate::Niebla158::with_utxo_tracking)
+//! the engine records, in each wallet's [`Store`], every output paying a
+//! watched script and the height it is spent at. On a reorg, outputs from
+//! blocks that left the chain are kept as [orphaned](Utxo::orphaned) until
+//! their transaction confirms again, and spends from those blocks are undone.
+//! [`balance`] and [`unspent`] read the result; [`balance_at`] and [`deltas`]
+//! replay it for balance history.
+use crate::store::Store;
+use bitcoin::{Amount, OutPoint, TxOut};
+use std::collections::BTreeMap;
+
+/// A wallet output and its confirmed history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utxo {
+    /// Where the output lives.
+    pub outpoint: OutPoint,
+    /// Its value and script.
+    pub txout: TxOut,
+    /// Height of the block that created it.
+    pub height: u32,
+    /// Height of the block that spent it, if any.
+    pub spent_at: Option<u32>,
+    /// The block at [`height`](Self::height) was reorged out and the output
+    /// has not confirmed again since. Orphaned outputs count towards no balance.
+    pub orphaned: bool,
+}
+
+/// Unspent outputs recorded in `store`, oldest first.
+pub async fn unspent(store: &dyn Store) -> anyhow::Result<Vec<Utxo>> {
+    let mut coins: Vec<Utxo> = store
+        .utxos()
+        .await?
+        .into_iter()
+        .filter(|u| u.spent_at.is_none() && !u.orphaned)
+        .collect();
+    coins.sort_by_key(|u| (u.height, u.outpoint));
+    Ok(coins)
+}
+
+/// Outputs whose block was reorged out and that have not confirmed again,
+/// oldest first.
+pub async fn orphaned(store: &dyn Store) -> anyhow::Result<Vec<Utxo>> {
+    let mut coins: Vec<Utxo> = store
+        .utxos()
+        .await?
+        .into_iter()
+        .filter(|u| u.orphaned)
+        .collect();
+    coins.sort_by_key(|u| (u.height, u.outpoint));
+    Ok(coins)
+}
+
+/// Sum of the unspent outputs recorded in `store`.
+pub async fn balance(store: &dyn Store) -> anyhow::Result<Amount> {
+    Ok(unspent(store).await?.iter().map(|u| u.txout.value).sum())
+}
+
+/// Sum of the outputs recorded in `store` that were unspent right after the
+/// block at `height`.
+pub async fn balance_at(store: &dyn Store, height: u32) -> anyhow::Result<Amount> {
+    Ok(store
+        .utxos()
+        .await?
+        .iter()
+        .filter(|u| !u.orphaned && u.height <= height && u.spent_at.is_none_or(|s| s > height))
+        .map(|u| u.txout.value)
+        .sum())
+}
+
+/// How one block changed a wallet's balance.
+///
+/// Also delivered live as [`EngineEvent::BalanceChanged`](crate::events::EngineEvent::BalanceChanged)
+/// while the engine tracks UTXOs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceDelta {
+    /// Height of the block.
+    pub height: u32,
+    /// Sum of the watched outputs it created.
+    pub received: Amount,
+    /// Sum of the recorded outputs it spent.
+    pub sent: Amount,
+    /// Balance right after it.
+    pub balance: Amount,
+}
+
+/// One [`BalanceDelta`] per block that created or spent a recorded output,
+/// lowest height first: everything needed to chart the balance.
+pub async fn deltas(store: &dyn Store) -> anyhow::Result<Vec<BalanceDelta>> {
+    let mut blocks: BTreeMap<u32, (Amount, Amount)> = BTreeMap::new();
+    for u in store.utxos().await?.into_iter().filter(|u| !u.orphaned) {
+        blocks.entry(u.height).or_default().0 += u.txout.value;
+        if let Some(spent_at) = u.spent_at {
+            blocks.entry(spent_at).or_default().1 += u.txout.value;
+        }
+    }
+    let mut balance = Amount::ZERO;
+    Ok(blocks
+        .into_iter()
+        .map(|(height, (received, sent))| {
+            balance = (balance + received)
+                .checked_sub(sent)
+                .unwrap_or(Amount::ZERO);
+            BalanceDelta {
+                height,
+                received,
+                sent,
+                balance,
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,121 @@
+//! Breach detection for Lightning channels.
+//!
+//! A watchtower watches the output scripts of revoked commitment transactions
+//! (tagged, say, with the channel id) and must react as soon as one confirms.
+//! Implement [`BreachHooks`], wrap it in [`Watchtower`] and run the engine
+//! with [`Niebla158::with_watchtower`](crate::Niebla158::with_watchtower):
+//!
+//! ```rust,ignore
+//! let engine = Niebla158::new(store, Watchtower::new(my_tower), filters, headers)
+//!     .with_watchtower();
+//! engine.run_to_tip().await?;
+//! ```
+//!
+//! Every block paying a watched script reaches [`BreachHooks::on_breach`]
+//! whole, during the scan. Each hit is queued in the store first and dequeued
+//! once `on_breach` returns `Ok`, so delivery is at least once across
+//! failures and crashes: build the justice transaction idempotently. A
+//! breach queued by a failed run is delivered before the next one rescans its
+//! block, so that block may arrive twice unless the engine also runs with
+//! [`Delivery::ExactlyOnce`](crate::hooks::Delivery::ExactlyOnce). It is
+//! delivered even if a reorg has removed its block since.
+use crate::compat::{MaybeSend, MaybeSync};
+use crate::hooks::{MatchDetails, WalletHooks, WatchItem};
+use anyhow::Context;
+use async_trait::async_trait;
+use bitcoin::{block::Header, hashes::sha256, BlockHash, ScriptBuf, Transaction};
+
+/// A block confirming a watched commitment transaction.
+#[derive(Debug, Clone)]
+pub struct Breach {
+    /// Height of the block.
+    pub height: u32,
+    /// Hash of the block.
+    pub block: BlockHash,
+    /// Header of the block, when known (see [`MatchDetails::header`]).
+    pub header: Option<Header>,
+    /// The block as served.
+    pub raw_block: Vec<u8>,
+    /// Every transaction of the block, in block order.
+    pub txs: Vec<Transaction>,
+    /// The watched outputs the block pays, with their tags.
+    pub items: Vec<WatchItem>,
+    /// See [`MatchDetails::match_id`].
+    pub match_id: sha256::Hash,
+    /// See [`MatchDetails::sequence`].
+    pub sequence: u64,
+}
+
+/// Watchtower callbacks, handed to the engine through [`Watchtower`].
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+pub trait BreachHooks: MaybeSend + MaybeSync {
+    /// Output scripts of the revoked commitment transactions to watch for.
+    async fn commitment_outputs(&self) -> anyhow::Result<Vec<WatchItem>>;
+
+    /// Called as soon as a block paying one of them is scanned. Returning an
+    /// error fails the run; the breach is delivered again by the next one.
+    async fn on_breach(&self, breach: Breach) -> anyhow::Result<()>;
+}
+
+/// [`WalletHooks`] over a [`BreachHooks`], turning matches into [`Breach`]es.
+///
+/// Filter false positives (blocks paying none of the scripts) are dropped.
+pub struct Watchtower<B> {
+    inner: B,
+}
+
+impl<B: BreachHooks> Watchtower<B> {
+    /// Wrap `inner`.
+    pub fn new(inner: B) -> Self {
+        Self { inner }
+    }
+
+    /// The wrapped hooks.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl<B: BreachHooks> WalletHooks for Watchtower<B> {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        let items = self.inner.commitment_outputs().await?;
+        Ok(items.into_iter().map(|i| i.script).collect())
+    }
+
+    async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+        self.inner.commitment_outputs().await
+    }
+
+    async fn on_block_match(
+        &self,
+        height: u32,
+        block: BlockHash,
+        _txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("watchtower hooks need match details (block {block} @{height})")
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        if details.items.is_empty() {
+            return Ok(());
+        }
+        let raw_block = details
+            .raw_block
+            .context("watchtower hooks need whole blocks: run the engine with_watchtower()")?;
+        self.inner
+            .on_breach(Breach {
+                height: details.height,
+                block: details.block,
+                header: details.header,
+                raw_block,
+                txs: details.txs,
+                items: details.items,
+                match_id: details.match_id,
+                sequence: details.sequence,
+            })
+            .await
+    }
+}
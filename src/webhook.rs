@@ -0,0 +1,153 @@
+//! Deliver matches to an HTTP endpoint instead of Rust callbacks.
+//!
+//! [`WebhookWallet`] is a [`WalletHooks`] that POSTs every match as a
+//! [`MatchRecordV1`] (without the raw block), keeping only the
+//! [relevant](MatchDetails::relevant_txids) transactions (blocks with none,
+//! e.g. filter false positives, are not posted).
+//!
+//! With a secret set, each request carries
+//! `X-Niebla-Signature: sha256=<hex HMAC-SHA256 of the body>`. Failed posts
+//! (network errors or non-2xx answers) are retried with exponential backoff;
+//! when retries run out the engine run fails and the match is delivered again
+//! by the next run, so receivers should dedupe on `match_id` (or `(height, block)`).
+use crate::hooks::{MatchDetails, SharedWatchlist, WalletHooks, WatchItem};
+use crate::record::MatchRecordV1;
+use anyhow::Context;
+use async_trait::async_trait;
+use bitcoin::{
+    hashes::{hmac, sha256, Hash, HashEngine},
+    BlockHash, ScriptBuf, Transaction, Txid,
+};
+use std::{collections::HashSet, time::Duration};
+
+/// Header carrying the body's HMAC-SHA256 when a secret is configured.
+pub const SIGNATURE_HEADER: &str = "X-Niebla-Signature";
+
+/// [`WalletHooks`] watching a fixed set of items and POSTing matches to a URL.
+pub struct WebhookWallet {
+    url: String,
+    items: SharedWatchlist,
+    secret: Option<Vec<u8>>,
+    attempts: u32,
+    backoff: Duration,
+    client: reqwest::Client,
+}
+
+impl WebhookWallet {
+    /// Post matches for `items` to `url`, unsigned, with 5 attempts starting 1s apart.
+    pub fn new(url: impl Into<String>, items: Vec<WatchItem>) -> Self {
+        Self {
+            url: url.into(),
+            items: SharedWatchlist::new(items),
+            secret: None,
+            attempts: 5,
+            backoff: Duration::from_secs(1),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The watched items, for adding scripts while the engine runs.
+    pub fn watchlist_handle(&self) -> SharedWatchlist {
+        self.items.clone()
+    }
+
+    /// Sign each body with HMAC-SHA256 under `secret` (see [`SIGNATURE_HEADER`]).
+    pub fn with_secret(mut self, secret: impl Into<Vec<u8>>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    /// Try each delivery up to `attempts` times, waiting `backoff` after the
+    /// first failure and doubling it after each further one.
+    pub fn with_retries(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    /// Signature header value for `body`, if a secret is set.
+    pub fn signature(&self, body: &[u8]) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(secret);
+        engine.input(body);
+        Some(format!(
+            "sha256={}",
+            hmac::Hmac::<sha256::Hash>::from_engine(engine)
+        ))
+    }
+
+    /// Post `record` with only the `relevant` transactions, if any.
+    async fn post_relevant(
+        &self,
+        mut record: MatchRecordV1,
+        relevant: &HashSet<Txid>,
+    ) -> anyhow::Result<()> {
+        record.retain_txs(|txid| relevant.contains(txid));
+        if record.txs.is_empty() {
+            return Ok(());
+        }
+        record.raw_block = None;
+        self.post(record.to_json().into_bytes()).await
+    }
+
+    async fn post(&self, body: Vec<u8>) -> anyhow::Result<()> {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            let mut req = self
+                .client
+                .post(&self.url)
+                .header("content-type", "application/json")
+                .body(body.clone());
+            if let Some(sig) = self.signature(&body) {
+                req = req.header(SIGNATURE_HEADER, sig);
+            }
+            let res = match req.send().await {
+                Ok(resp) => resp.error_for_status().map(drop),
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= self.attempts => {
+                    return Err(e).with_context(|| {
+                        format!("POST {} failed after {attempt} attempts", self.url)
+                    })
+                }
+                Err(_) => {
+                    crate::rt::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg_attr(niebla_unsend, async_trait(?Send))]
+#[cfg_attr(not(niebla_unsend), async_trait)]
+impl WalletHooks for WebhookWallet {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(self.items.items().into_iter().map(|i| i.script).collect())
+    }
+
+    async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+        Ok(self.items.items())
+    }
+
+    /// Without amounts to tell them apart, posts all of `txs`.
+    async fn on_block_match(
+        &self,
+        height: u32,
+        block: BlockHash,
+        txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        let record = MatchRecordV1::from_block_match(height, block, &txs);
+        let relevant = txs.iter().map(Transaction::compute_txid).collect();
+        self.post_relevant(record, &relevant).await
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        let record = MatchRecordV1::from_details(&details);
+        self.post_relevant(record, &details.relevant_txids()).await
+    }
+}
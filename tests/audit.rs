@@ -0,0 +1,81 @@
+use bitcoin::bip158::BlockFilter;
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, ScriptBuf};
+use niebla_158::audit::Discrepancy;
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+
+#[tokio::test]
+async fn audit_reports_what_changed_under_a_scanner() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(10, |h| match h {
+        7 => vec![script(1)],
+        _ => vec![script(100 + h as u8)],
+    })?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine =
+        Niebla158::new(store.clone(), hooks, filters.clone(), headers.clone()).with_verdict_cache();
+    engine.run_to_tip().await?;
+
+    let report = engine.audit(0..=20).await?;
+    assert!(report.is_clean(), "{report:?}");
+    assert_eq!(report.range, 0..=10);
+    assert_eq!((report.cfheaders_checked, report.misses_checked), (11, 9));
+
+    // The source starts serving a different filter for block 2, one that pays the wallet.
+    let at_1 = headers.hash_at_height(1).await?;
+    let mut tampered = block_paying(2, at_1, &[script(1)]);
+    tampered.header = block_paying(2, at_1, &[script(102)]).header;
+    let filter = BlockFilter::new_script_filter(&tampered, |_| {
+        Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(Default::default()))
+    })?;
+    filters.add_raw(
+        2,
+        tampered.block_hash(),
+        at_1,
+        filter.content,
+        serialize(&tampered),
+    );
+    // And a stored cfheader gets corrupted.
+    let stored_5 = store.load_cf_header(5).await?.unwrap();
+    store.save_cf_headers(5, &[BlockHash::all_zeros()]).await?;
+
+    let stored_2 = store.load_cf_header(2).await?.unwrap();
+    let audit = Niebla158::new(
+        store.clone(),
+        RecordingHooks::new(vec![script(1)]),
+        filters,
+        headers,
+    )
+    .with_verdict_cache()
+    .with_checkpoints(vec![(2, stored_2), (5, stored_5)]);
+    let report = audit.audit(1..=10).await?;
+    let found: Vec<(u32, &str)> = report
+        .discrepancies
+        .iter()
+        .map(|d| {
+            let kind = match d {
+                Discrepancy::CfHeader { .. } => "cfheader",
+                Discrepancy::Checkpoint { .. } => "checkpoint",
+                Discrepancy::CachedMissMatches { .. } => "miss",
+                _ => "other",
+            };
+            (d.height(), kind)
+        })
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (2, "cfheader"),
+            (2, "miss"),
+            (5, "cfheader"),
+            (5, "checkpoint"),
+            (6, "cfheader"),
+        ]
+    );
+    assert_eq!(report.checkpoints_checked, 2);
+    assert_eq!(report, audit.audit(1..=10).await?);
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use bitcoin::bip158::BlockFilter;
+use bitcoin::{consensus, Block, BlockHash, ScriptBuf, Transaction};
+use niebla_158::block_source::{BitcoinCodec, BlockCodec};
+use niebla_158::testing::*;
+use niebla_158::Niebla158;
+
+/// A vendor-patched encoding: Bitcoin blocks behind a 4-byte magic.
+struct Prefixed;
+
+impl Prefixed {
+    fn strip(raw: &[u8]) -> anyhow::Result<&[u8]> {
+        raw.strip_prefix(b"FORK")
+            .ok_or_else(|| anyhow::anyhow!("missing magic"))
+    }
+}
+
+impl BlockCodec for Prefixed {
+    fn block_hash(&self, raw: &[u8]) -> anyhow::Result<BlockHash> {
+        BitcoinCodec.block_hash(Self::strip(raw)?)
+    }
+
+    fn prev_block_hash(&self, raw: &[u8]) -> anyhow::Result<BlockHash> {
+        BitcoinCodec.prev_block_hash(Self::strip(raw)?)
+    }
+
+    fn decode_txs(&self, raw: &[u8]) -> anyhow::Result<Vec<Transaction>> {
+        BitcoinCodec.decode_txs(Self::strip(raw)?)
+    }
+}
+
+/// Serve `block` at height 1 as `bytes`, with its real filter.
+fn serve(block: &Block, bytes: Vec<u8>) -> anyhow::Result<(MockFilterSource, MockHeaderSource)> {
+    let filter = BlockFilter::new_script_filter(block, |_| {
+        Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(Default::default()))
+    })?;
+    let filters = MockFilterSource::new();
+    filters.add_raw(1, block.block_hash(), genesis_hash(), filter.content, bytes);
+    let headers = MockHeaderSource::from_hashes(vec![genesis_hash(), block.block_hash()]);
+    Ok((filters, headers))
+}
+
+#[tokio::test]
+async fn custom_codecs_decode_patched_blocks() -> anyhow::Result<()> {
+    let block = block_paying(1, genesis_hash(), &[script(1)]);
+    let patched = [&b"FORK"[..], &consensus::serialize(&block)].concat();
+    let (filters, headers) = serve(&block, patched)?;
+
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .with_block_codec(Prefixed)
+        .run_to_tip()
+        .await?;
+    assert_eq!(hooks.matches()[0].txs, block.txdata);
+    Ok(())
+}
+
+#[tokio::test]
+async fn blocks_must_hash_to_the_requested_block() -> anyhow::Result<()> {
+    let block = block_paying(1, genesis_hash(), &[script(1)]);
+    let other = block_paying(2, genesis_hash(), &[script(1)]);
+    let (filters, headers) = serve(&block, consensus::serialize(&other))?;
+
+    let err = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(1)]),
+        filters,
+        headers,
+    )
+    .run_to_tip()
+    .await
+    .unwrap_err();
+    assert!(
+        format!("{err:#}").contains(&format!("served block {}", other.block_hash())),
+        "{err:#}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn matches_carry_the_block_header() -> anyhow::Result<()> {
+    let block = block_paying(1, genesis_hash(), &[script(1)]);
+    let (filters, headers) = serve(&block, consensus::serialize(&block))?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .run_to_tip()
+        .await?;
+    assert_eq!(hooks.matches()[0].header, Some(block.header));
+
+    // A codec that cannot decode headers leaves them to the header source.
+    let patched = [&b"FORK"[..], &consensus::serialize(&block)].concat();
+    let (filters, headers) = serve(&block, patched)?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .with_block_codec(Prefixed)
+        .run_to_tip()
+        .await?;
+    assert_eq!(hooks.matches()[0].header, None);
+    assert_eq!(hooks.matches()[0].median_time_past, None);
+    Ok(())
+}
+
+#[test]
+fn bitcoin_codec_decodes_transactions_one_at_a_time() -> anyhow::Result<()> {
+    let mut block = block_paying(1, genesis_hash(), &[script(1)]);
+    for h in 2..=4 {
+        block
+            .txdata
+            .extend(block_paying(h, genesis_hash(), &[script(2)]).txdata);
+    }
+    let raw = consensus::serialize(&block);
+    assert_eq!(BitcoinCodec.decode_txs(&raw)?, block.txdata);
+
+    // Visiting stops as soon as the visitor is done.
+    let mut seen = vec![];
+    BitcoinCodec.visit_txs(&raw, &mut |tx| {
+        seen.push(tx.compute_txid());
+        seen.len() < 2
+    })?;
+    assert_eq!(
+        seen,
+        vec![
+            block.txdata[0].compute_txid(),
+            block.txdata[1].compute_txid()
+        ]
+    );
+
+    let err = BitcoinCodec
+        .decode_txs(&[&raw[..], &[0]].concat())
+        .unwrap_err();
+    assert!(err.to_string().contains("trailing bytes"), "{err:#}");
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use niebla_158::filter_source::FilterType;
+use niebla_158::testing::*;
+use niebla_158::{EngineError, Niebla158, Store};
+
+/// A 20-block chain paying `script(1)` at 5 and 15, and its rolling cfheaders.
+async fn chain() -> anyhow::Result<(MockFilterSource, MockHeaderSource, MemoryStore)> {
+    let (filters, headers) = mock_chain(20, |h| match h {
+        5 | 15 => vec![script(1)],
+        _ => vec![script(100 + h as u8)],
+    })?;
+    let reference = MemoryStore::new();
+    Niebla158::new(
+        reference.clone(),
+        RecordingHooks::new(vec![]),
+        filters.clone(),
+        headers.clone(),
+    )
+    .run_to_tip()
+    .await?;
+    Ok((filters, headers, reference))
+}
+
+#[tokio::test]
+async fn sync_and_scan_start_at_the_anchor() -> anyhow::Result<()> {
+    let (filters, headers, reference) = chain().await?;
+    let at_10 = reference.load_cf_header(10).await?.unwrap();
+
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(store.clone(), hooks.clone(), filters, headers)
+        .with_cfheader_anchor(10, at_10)
+        .run_to_tip()
+        .await?;
+
+    assert_eq!(store.load_cf_tip().await?, reference.load_cf_tip().await?);
+    assert_eq!(store.load_cf_header(5).await?, None);
+    assert_eq!(hooks.matched_heights(), vec![15]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn a_wrong_anchor_fails_the_next_checkpoint() -> anyhow::Result<()> {
+    let (filters, headers, reference) = chain().await?;
+    let at_15 = reference.load_cf_header(15).await?.unwrap();
+
+    let err = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(1)]),
+        filters,
+        headers,
+    )
+    .with_cfheader_anchor(10, BlockHash::all_zeros())
+    .with_checkpoints(vec![(15, at_15)])
+    .run_to_tip()
+    .await
+    .unwrap_err();
+    match err.downcast_ref::<EngineError>() {
+        Some(EngineError::CheckpointMismatch(m)) => assert_eq!(m.height, 15),
+        _ => panic!("expected a checkpoint mismatch, got {err:#}"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn first_sync_anchors_below_the_birth_height() -> anyhow::Result<()> {
+    let (filters, headers, reference) = chain().await?;
+    let mut checkpoints = vec![];
+    for h in [5, 10, 15] {
+        checkpoints.push((h, reference.load_cf_header(h).await?.unwrap()));
+    }
+
+    let store = MemoryStore::new();
+    store.set_birth_height(14).await?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_checkpoints(checkpoints.clone());
+    engine.run_to_tip().await?;
+    assert_eq!(
+        store.get_cf_anchor(FilterType::BASIC).await?,
+        Some(checkpoints[1])
+    );
+    assert_eq!(store.load_cf_header(10).await?, None);
+    assert_eq!(store.load_cf_tip().await?, reference.load_cf_tip().await?);
+    assert_eq!(hooks.matched_heights(), vec![15]);
+    // The recorded anchor keeps audits from reporting the skipped history.
+    assert!(engine.audit(1..=20).await?.is_clean());
+
+    // A wallet without a birth height needs the whole history.
+    let store = MemoryStore::new();
+    store.set_birth_height(14).await?;
+    Niebla158::new(store.clone(), RecordingHooks::new(vec![]), filters, headers)
+        .with_checkpoints(checkpoints)
+        .with_wallet(MemoryStore::new(), RecordingHooks::new(vec![script(1)]))
+        .run_to_tip()
+        .await?;
+    assert_eq!(store.get_cf_anchor(FilterType::BASIC).await?, None);
+    assert!(store.load_cf_header(5).await?.is_some());
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use bitcoin::bip158::{FilterHash, FilterHeader};
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::message_filter::{CFCheckpt, CFHeaders};
+use bitcoin::BlockHash;
+use niebla_158::cfheaders::{verify_checkpoints, CfCheckpoints, CfHeaderChain};
+use niebla_158::filter_source::{FilterSource, FilterType};
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::{EngineError, Niebla158, Store};
+
+#[tokio::test]
+async fn wire_cfheaders_roll_like_the_engine() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(10, |h| vec![script(h as u8)])?;
+    let reference = MemoryStore::new();
+    Niebla158::new(
+        reference.clone(),
+        RecordingHooks::new(vec![]),
+        filters.clone(),
+        headers.clone(),
+    )
+    .run_to_tip()
+    .await?;
+
+    // Heights 6..=10 as a peer would send them, on top of the header at 5.
+    let at_5 = reference.load_cf_header(5).await?.unwrap();
+    let stop = headers.hash_at_height(10).await?;
+    let batch = filters.get_cfheaders(6, stop).await?;
+    let message = serialize(&CFHeaders {
+        filter_type: 0,
+        stop_hash: stop,
+        previous_filter_header: FilterHeader::from_byte_array(at_5.to_byte_array()),
+        filter_hashes: batch
+            .headers
+            .iter()
+            .map(|h| FilterHash::from_byte_array(*h))
+            .collect(),
+    });
+
+    // A wrong checkpoint leaves the chain where it was.
+    let mut chain = CfHeaderChain::new(FilterType::BASIC, 5, at_5);
+    let err = chain
+        .apply_cfheaders_message(&message, &[(8, BlockHash::all_zeros())])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::CheckpointMismatch(m)) if m.height == 8
+    ));
+    assert_eq!(chain, CfHeaderChain::new(FilterType::BASIC, 5, at_5));
+
+    let at_8 = reference.load_cf_header(8).await?.unwrap();
+    let (stop_hash, rolled) = chain.apply_cfheaders_message(&message, &[(8, at_8)])?;
+    assert_eq!(stop_hash, stop);
+    assert_eq!(rolled.len(), 5);
+    assert_eq!(
+        Some((chain.tip_height, chain.tip_hash)),
+        reference.load_cf_tip().await?
+    );
+    verify_checkpoints(FilterType::BASIC, 6, &rolled, &[(8, at_8)])?;
+
+    // The same message no longer extends the chain.
+    assert!(chain.apply_cfheaders_message(&message, &[]).is_err());
+    Ok(())
+}
+
+#[test]
+fn cfcheckpt_entries_are_every_thousand_heights() -> anyhow::Result<()> {
+    let (a, b) = ([1u8; 32], [2u8; 32]);
+    let message = serialize(&CFCheckpt {
+        filter_type: 0,
+        stop_hash: BlockHash::all_zeros(),
+        filter_headers: vec![
+            FilterHeader::from_byte_array(a),
+            FilterHeader::from_byte_array(b),
+        ],
+    });
+    let parsed = CfCheckpoints::parse(&message)?;
+    assert_eq!(
+        (parsed.filter_type, parsed.stop_hash),
+        (FilterType::BASIC, BlockHash::all_zeros())
+    );
+    assert_eq!(
+        parsed.checkpoints,
+        vec![
+            (1000, BlockHash::from_byte_array(a)),
+            (2000, BlockHash::from_byte_array(b))
+        ]
+    );
+    assert!(CfCheckpoints::parse(&message[..40]).is_err());
+    Ok(())
+}
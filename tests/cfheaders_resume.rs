@@ -0,0 +1,62 @@
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+
+#[tokio::test]
+async fn verified_cfheaders_survive_a_failure_mid_batch() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(1_200, |_| vec![])?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+
+    // A bogus checkpoint inside the single 0..=1200 batch aborts verification there.
+    let bogus = vec![(1_100, BlockHash::from_byte_array([9; 32]))];
+    let engine = Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_checkpoints(bogus);
+    assert!(engine.run_to_tip().await.is_err());
+    assert_eq!(filters.calls(MockCall::CfHeaders), 1);
+
+    // Everything before the failing chunk is already on disk, per height and as the tip.
+    let (tip_h, tip) = store.load_cf_tip().await?.expect("partial progress saved");
+    assert_eq!(tip_h, 999);
+    assert_eq!(store.load_cf_header(999).await?, Some(tip));
+    assert!(store.load_cf_header(0).await?.is_some());
+    assert_eq!(store.load_cf_header(1_000).await?, None);
+
+    // A restart resumes from there instead of re-verifying from genesis.
+    let engine = Niebla158::new(store.clone(), hooks, filters, headers);
+    engine.run_to_tip().await?;
+    assert_eq!(store.load_cf_tip().await?.map(|(h, _)| h), Some(1_200));
+    Ok(())
+}
+
+#[tokio::test]
+async fn cfheaders_can_be_verified_ahead_of_scanning() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(20, |h| if h == 7 { vec![script(1)] } else { vec![] })?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    );
+
+    assert_eq!(engine.sync_cfheaders_only().await?, 20);
+    assert_eq!(store.load_cf_tip().await?.map(|(h, _)| h), Some(20));
+    assert_eq!(store.get_last_scanned().await?, 0);
+    assert_eq!(filters.calls(MockCall::Cfilter), 0);
+    assert!(hooks.matched_heights().is_empty());
+
+    // The wallet's first run only scans.
+    let cfheaders = filters.calls(MockCall::CfHeaders);
+    engine.run_to_tip().await?;
+    assert_eq!(filters.calls(MockCall::CfHeaders), cfheaders);
+    assert_eq!(hooks.matched_heights(), vec![7]);
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use bitcoin::bip158::BlockFilter;
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, ScriptBuf};
+use niebla_158::events::EngineEvent;
+use niebla_158::testing::*;
+use niebla_158::{EngineError, Niebla158, Store};
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn mismatch_reports_forensics_and_emits_an_event() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(30, |_| vec![])?;
+    filters.set_label("mirror-a");
+    let store = MemoryStore::new();
+    let bogus = BlockHash::from_byte_array([9; 32]);
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    let engine = Niebla158::new(store.clone(), RecordingHooks::new(vec![]), filters, headers)
+        .with_checkpoints(vec![(20, bogus)])
+        .with_events(move |e: &EngineEvent| sink.lock().unwrap().push(e.clone()));
+
+    let err = engine.run_to_tip().await.unwrap_err();
+    let Some(EngineError::CheckpointMismatch(m)) = err.downcast_ref::<EngineError>() else {
+        panic!("expected a checkpoint mismatch, got {err:#}");
+    };
+    assert_eq!((m.height, m.batch_start), (20, 0));
+    assert_eq!(m.expected, bogus);
+    assert_ne!(m.computed, bogus);
+    assert_eq!(m.source.as_deref(), Some("mirror-a"));
+    assert!(format!("{err:#}").contains("checkpoint mismatch @20"));
+    assert!(format!("{err:#}").contains("source mirror-a"));
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![EngineEvent::CheckpointMismatch(m.clone())]
+    );
+    assert_eq!(store.load_cf_tip().await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn reanchors_on_a_fallback_source_after_mismatch() -> anyhow::Result<()> {
+    let paid = script(5);
+    let (good, bad, headers) = (
+        MockFilterSource::new(),
+        MockFilterSource::new(),
+        MockHeaderSource::new(),
+    );
+    let mut prev = headers.push(genesis_hash());
+    let mut blocks = vec![];
+    for h in 1..=30 {
+        let pays = if h == 12 { vec![paid.clone()] } else { vec![] };
+        let block = block_paying(h, prev, &pays);
+        good.add_block(h, &block)?;
+        bad.add_block(h, &block)?;
+        prev = block.block_hash();
+        blocks.push(block);
+    }
+    // The bad source lies about the filter at height 15 (serving another block's).
+    let forged = &blocks[14];
+    let decoy = block_paying(15, forged.header.prev_blockhash, &[script(7)]);
+    let decoy_filter = BlockFilter::new_script_filter(&decoy, |op| {
+        Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(*op))
+    })?;
+    bad.add_raw(
+        15,
+        forged.block_hash(),
+        forged.header.prev_blockhash,
+        decoy_filter.content,
+        bitcoin::consensus::serialize(forged),
+    );
+    bad.set_label("bad");
+    good.set_label("good");
+
+    // Trusted checkpoints from an honest sync.
+    let reference = MemoryStore::new();
+    let all = MockHeaderSource::from_hashes(
+        std::iter::once(genesis_hash())
+            .chain(blocks.iter().map(|b| b.block_hash()))
+            .collect(),
+    );
+    Niebla158::new(
+        reference.clone(),
+        RecordingHooks::new(vec![]),
+        good.clone(),
+        all,
+    )
+    .run_to_tip()
+    .await?;
+    let mut checkpoints = vec![];
+    for h in [10, 25] {
+        checkpoints.push((h, reference.load_cf_header(h).await?.unwrap()));
+    }
+
+    // First run only reaches height 20, so the forged header is stored unnoticed.
+    for block in &blocks[..20] {
+        headers.push(block.block_hash());
+    }
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![paid]);
+    Niebla158::new(store.clone(), hooks.clone(), bad.clone(), headers.clone())
+        .with_checkpoints(checkpoints.clone())
+        .run_to_tip()
+        .await?;
+    assert_eq!(store.get_last_scanned().await?, 20);
+
+    for block in &blocks[20..] {
+        headers.push(block.block_hash());
+    }
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    Niebla158::new(store.clone(), hooks.clone(), bad, headers)
+        .with_checkpoints(checkpoints)
+        .with_fallback_source(good)
+        .with_reanchor(1)
+        .with_events(move |e: &EngineEvent| sink.lock().unwrap().push(e.clone()))
+        .run_to_tip()
+        .await?;
+
+    assert_eq!(store.load_cf_tip().await?, reference.load_cf_tip().await?);
+    assert_eq!(
+        store.load_cf_header(15).await?,
+        reference.load_cf_header(15).await?
+    );
+    let events = events.lock().unwrap();
+    assert!(matches!(&events[0], EngineEvent::CheckpointMismatch(m) if m.height == 25));
+    assert_eq!(
+        events[1],
+        EngineEvent::Reanchored {
+            height: 10,
+            source: Some("good".into())
+        }
+    );
+    // Wallets rescan from the checkpoint, so height 12 is delivered again.
+    assert_eq!(hooks.matched_heights(), vec![12, 12]);
+    Ok(())
+}
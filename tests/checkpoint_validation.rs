@@ -0,0 +1,156 @@
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, Network};
+use niebla_158::checkpoints::{genesis_cfheader, CheckpointError, Checkpoints};
+use niebla_158::filter_source::FilterType;
+use niebla_158::testing::*;
+use niebla_158::{EngineError, Niebla158};
+use std::str::FromStr;
+
+fn cp(b: u8) -> BlockHash {
+    BlockHash::from_byte_array([b; 32])
+}
+
+#[test]
+fn checkpoint_lists_must_be_strictly_increasing() {
+    let ok = Checkpoints::from(vec![(1_000, cp(1)), (2_000, cp(2))]);
+    assert_eq!(ok.validate(), Ok(()));
+    assert_eq!(Checkpoints::default().validate(), Ok(()));
+
+    let repeated = Checkpoints(vec![(1_000, cp(1)), (1_000, cp(2))]);
+    assert_eq!(
+        repeated.validate(),
+        Err(CheckpointError::Duplicate { height: 1_000 })
+    );
+    let unsorted = Checkpoints(vec![(2_000, cp(2)), (1_000, cp(1))]);
+    assert_eq!(
+        unsorted.validate(),
+        Err(CheckpointError::OutOfOrder {
+            height: 1_000,
+            previous: 2_000
+        })
+    );
+}
+
+#[test]
+fn genesis_checkpoints_are_anchored_to_the_network() -> anyhow::Result<()> {
+    // BIP-158 test vector: the basic filter header of the testnet genesis block.
+    let testnet =
+        BlockHash::from_str("21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750")?;
+    assert_eq!(genesis_cfheader(Network::Testnet), testnet);
+
+    let anchored = Checkpoints(vec![(0, testnet), (1_000, cp(1))]);
+    assert_eq!(
+        anchored.validate_for(Network::Testnet, FilterType::BASIC),
+        Ok(())
+    );
+    assert_eq!(
+        anchored.validate_for(Network::Bitcoin, FilterType::BASIC),
+        Err(CheckpointError::WrongGenesis {
+            network: Network::Bitcoin,
+            expected: genesis_cfheader(Network::Bitcoin),
+            found: testnet,
+        })
+    );
+    // Only basic filters have a known genesis header.
+    assert_eq!(
+        anchored.validate_for(Network::Bitcoin, FilterType(0x42)),
+        Ok(())
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn runs_verify_genesis_and_later_checkpoints() -> anyhow::Result<()> {
+    use bitcoin::bip158::{BlockFilter, FilterHeader};
+    use niebla_158::filter_source::FilterSource;
+    use niebla_158::headers::HeaderSource;
+    use niebla_158::Store;
+
+    let (filters, headers) = mock_chain(1_200, |h| vec![script(h as u8)])?;
+    let mut rolling = FilterHeader::all_zeros();
+    for h in 0..=1_000 {
+        let block = headers.hash_at_height(h).await?;
+        rolling = BlockFilter::new(&filters.get_cfilter(block).await?).filter_header(&rolling);
+    }
+    let at_1000 = BlockHash::from_byte_array(rolling.to_byte_array());
+    let genesis = genesis_cfheader(Network::Regtest);
+
+    let store = MemoryStore::new();
+    Niebla158::new(
+        store.clone(),
+        RecordingHooks::new(vec![]),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_network(Network::Regtest)
+    .with_checkpoints(vec![(0, genesis), (1_000, at_1000)])
+    .run_to_tip()
+    .await?;
+    assert_eq!(store.load_cf_header(0).await?, Some(genesis));
+    assert_eq!(store.load_cf_header(1_000).await?, Some(at_1000));
+
+    // Without a network to check it against up front, a wrong genesis
+    // checkpoint is caught while verifying.
+    let err = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![]),
+        filters,
+        headers,
+    )
+    .with_checkpoints(vec![(0, cp(0))])
+    .run_to_tip()
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::CheckpointMismatch(m)) if m.height == 0 && m.computed == genesis
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn runs_refuse_malformed_checkpoints() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(3, |_| vec![])?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let store = MemoryStore::new();
+    let err = Niebla158::new(store.clone(), hooks, filters, headers)
+        .with_checkpoints(vec![(3, cp(3)), (2, cp(2))])
+        .run_to_tip()
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<EngineError>(),
+        Some(&EngineError::InvalidCheckpoints(
+            CheckpointError::OutOfOrder {
+                height: 2,
+                previous: 3
+            }
+        ))
+    );
+    Ok(())
+}
+
+#[test]
+fn checkpoint_lists_round_trip_through_text() -> anyhow::Result<()> {
+    let list = Checkpoints(vec![(1_000, cp(1)), (2_000, cp(2))]);
+    let text = list.to_text(Network::Signet);
+    assert_eq!(
+        Checkpoints::from_text(&format!("# signed list\n\n{text}"))?,
+        (Network::Signet, list.clone())
+    );
+    assert!(Checkpoints::from_text("1000 00").is_err());
+    assert!(Checkpoints::from_text("network signet\n1000").is_err());
+
+    // A refresh may add checkpoints but not drop or change one.
+    let longer = Checkpoints(vec![(1_000, cp(1)), (2_000, cp(2)), (3_000, cp(3))]);
+    assert_eq!(longer.extends(&list), Ok(()));
+    assert_eq!(
+        Checkpoints(vec![(1_000, cp(1))]).extends(&list),
+        Err(CheckpointError::Dropped {
+            height: 2_000,
+            expected: cp(2),
+            found: None
+        })
+    );
+    Ok(())
+}
@@ -0,0 +1,110 @@
+// The `niebla` binary is a stub under `local` (see src/bin/niebla.rs).
+#![cfg(all(feature = "cli", not(feature = "local")))]
+
+use bitcoin::hashes::sha256d;
+use bitcoin::{
+    absolute::LockTime, bip158::BlockFilter, block, hashes::Hash as _, transaction, Address,
+    Amount, Block, BlockHash, CompactTarget, Network, OutPoint, ScriptBuf, Sequence, Transaction,
+    TxIn, TxMerkleNode, TxOut, Witness,
+};
+use niebla_158::http;
+use std::collections::HashMap;
+
+mod common;
+use common::http::serve;
+
+fn block_paying(script: ScriptBuf) -> Block {
+    let coinbase = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::from_bytes(vec![0x51, 0x51]),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(50_000),
+            script_pubkey: script,
+        }],
+    };
+    Block {
+        header: block::Header {
+            version: block::Version::ONE,
+            prev_blockhash: BlockHash::all_zeros(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: 0,
+            bits: CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        },
+        txdata: vec![coinbase],
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cli_prints_matching_transactions_as_json_lines() -> anyhow::Result<()> {
+    let addr = Address::p2wsh(&ScriptBuf::from_bytes(vec![0x51]), Network::Regtest);
+    let block = block_paying(addr.script_pubkey());
+    let hash = block.block_hash();
+    let filter = BlockFilter::new_script_filter(&block, |_| -> Result<ScriptBuf, _> {
+        unreachable!("coinbase only")
+    })?;
+
+    let genesis = bitcoin::constants::genesis_block(Network::Regtest);
+    let genesis_filter = BlockFilter::new_script_filter(&genesis, |_| -> Result<ScriptBuf, _> {
+        unreachable!("coinbase only")
+    })?;
+
+    let mut routes = HashMap::new();
+    routes.insert(http::tip_path(), b"1".to_vec());
+    routes.insert(
+        http::blockhash_path(0),
+        genesis.block_hash().to_string().into_bytes(),
+    );
+    routes.insert(http::blockhash_path(1), hash.to_string().into_bytes());
+    // Filter hashes from genesis on, which the cfheaders chain starts with.
+    let filter_hashes = [&genesis_filter.content, &filter.content]
+        .into_iter()
+        .flat_map(|f| sha256d::Hash::hash(f).to_byte_array())
+        .collect();
+    routes.insert(http::cfheaders_path(0, hash), filter_hashes);
+    routes.insert(http::cfilter_path(hash), filter.content.clone());
+    routes.insert(
+        http::block_path(hash),
+        bitcoin::consensus::serialize(&block),
+    );
+    let base = serve(routes).await?;
+
+    let dir = tempfile::tempdir()?;
+    let db = dir.path().join("cli.sqlite");
+    let out = tokio::task::spawn_blocking(move || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_niebla"))
+            .args(["--server", &base, "--network", "regtest"])
+            .args(["--db", db.to_str().unwrap()])
+            .args(["--address", &addr.to_string()])
+            .output()
+    })
+    .await??;
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let stdout = String::from_utf8(out.stdout)?;
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    // One `MatchRecordV1` per matching block.
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["version"], 1);
+    assert_eq!(lines[0]["height"], 1);
+    assert_eq!(lines[0]["block"], hash.to_string());
+    assert_eq!(lines[0]["txs"].as_array().map(Vec::len), Some(1));
+    assert_eq!(
+        lines[0]["txs"][0]["txid"],
+        block.txdata[0].compute_txid().to_string()
+    );
+    Ok(())
+}
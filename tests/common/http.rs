@@ -0,0 +1,53 @@
+//! A tiny HTTP/1.1 server for the HTTP clients under test.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Serve a fixed `path -> body` map over plain HTTP/1.1 (one request per connection).
+pub async fn serve(routes: HashMap<String, Vec<u8>>) -> anyhow::Result<String> {
+    Ok(serve_counted(routes).await?.0)
+}
+
+/// [`serve`], tagging every body with an `ETag` (its path) and answering matching
+/// `If-None-Match` requests with `304`. Also returns the number of bodies sent.
+pub async fn serve_counted(
+    routes: HashMap<String, Vec<u8>>,
+) -> anyhow::Result<(String, Arc<AtomicUsize>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let routes = Arc::new(routes);
+    let sent = Arc::new(AtomicUsize::new(0));
+    let counter = sent.clone();
+    tokio::spawn(async move {
+        while let Ok((mut sock, _)) = listener.accept().await {
+            let (routes, counter) = (routes.clone(), counter.clone());
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                let n = sock.read(&mut buf).await.unwrap_or(0);
+                let req = String::from_utf8_lossy(&buf[..n]);
+                let path = req.split_whitespace().nth(1).unwrap_or("/").to_owned();
+                let etag = format!("\"{path}\"");
+                let revalidated = req
+                    .lines()
+                    .any(|l| l.eq_ignore_ascii_case(&format!("if-none-match: {etag}")));
+                let (status, body) = match routes.get(&path) {
+                    Some(_) if revalidated => ("304 Not Modified", Vec::new()),
+                    Some(b) => {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        ("200 OK", b.clone())
+                    }
+                    None => ("404 Not Found", Vec::new()),
+                };
+                let head = format!(
+                    "HTTP/1.1 {status}\r\netag: {etag}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = sock.write_all(head.as_bytes()).await;
+                let _ = sock.write_all(&body).await;
+            });
+        }
+    });
+    Ok((format!("http://{addr}"), sent))
+}
@@ -0,0 +1,7 @@
+//! Helpers shared by the integration tests. Each test crate uses only some of
+//! them, hence the `dead_code` allowance.
+#![allow(dead_code)]
+
+pub mod http;
+#[cfg(not(feature = "local"))]
+pub mod store;
@@ -0,0 +1,39 @@
+//! A minimal in-memory [`Store`], for tests that exercise the trait defaults.
+use async_trait::async_trait;
+use bitcoin::BlockHash;
+use niebla_158::Store;
+use std::sync::Mutex;
+
+/// Keeps only the cfheaders tip, scan progress and birth height; every other
+/// [`Store`] method is the trait's default.
+#[derive(Default)]
+pub struct MemStore {
+    cf_tip: Mutex<Option<(u32, BlockHash)>>,
+    last_scanned: Mutex<u32>,
+    birth: Mutex<Option<u32>>,
+}
+
+#[async_trait]
+impl Store for MemStore {
+    async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        Ok(*self.cf_tip.lock().unwrap())
+    }
+    async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
+        *self.cf_tip.lock().unwrap() = Some((height, cfheader));
+        Ok(())
+    }
+    async fn get_last_scanned(&self) -> anyhow::Result<u32> {
+        Ok(*self.last_scanned.lock().unwrap())
+    }
+    async fn set_last_scanned(&self, height: u32) -> anyhow::Result<()> {
+        *self.last_scanned.lock().unwrap() = height;
+        Ok(())
+    }
+    async fn get_birth_height(&self) -> anyhow::Result<Option<u32>> {
+        Ok(*self.birth.lock().unwrap())
+    }
+    async fn set_birth_height(&self, h: u32) -> anyhow::Result<()> {
+        *self.birth.lock().unwrap() = Some(h);
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use niebla_158::events::EngineEvent;
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+use std::sync::{Arc, Mutex};
+
+/// Ten blocks on top of genesis paying the wallet at 3 and 7.
+fn chain() -> anyhow::Result<(MockFilterSource, MockHeaderSource)> {
+    mock_chain(10, |h| vec![script(if matches!(h, 3 | 7) { 1 } else { 9 })])
+}
+
+#[tokio::test]
+async fn failing_hooks_do_not_hold_up_the_scan() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    hooks.fail_next("wallet is busy");
+
+    let engine = Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_delivery_queue();
+    engine.run_to_tip().await?;
+    engine.wait_for_pending().await;
+
+    // Scanning finished without touching blocks or hooks; both hits are queued.
+    assert_eq!(store.get_last_scanned().await?, 10);
+    assert_eq!(filters.calls(MockCall::Block), 0);
+    let queued: Vec<u32> = store.pending_matches().await?.iter().map(|p| p.0).collect();
+    assert_eq!(queued, vec![3, 7]);
+
+    let err = engine.deliver_pending().await.unwrap_err();
+    assert!(format!("{err:#}").contains("wallet is busy"));
+    assert_eq!(store.pending_matches().await?.len(), 2);
+
+    // The queue survives a restart and drains in height order.
+    drop(engine);
+    let engine =
+        Niebla158::new(store.clone(), hooks.clone(), filters, headers).with_delivery_queue();
+    assert_eq!(engine.deliver_pending().await?, 2);
+    assert_eq!(hooks.matched_heights(), vec![3, 7]);
+    assert!(store.pending_matches().await?.is_empty());
+    assert_eq!(engine.deliver_pending().await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn matches_reorged_out_of_the_queue_are_reported() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = || {
+        Niebla158::new(
+            store.clone(),
+            hooks.clone(),
+            filters.clone(),
+            headers.clone(),
+        )
+        .with_delivery_queue()
+    };
+    engine().run_to_tip().await?;
+    let stale = headers.hash_at_height(7).await?;
+
+    // Heights 6..=10 are replaced by a branch that pays the wallet at 8.
+    let mut prev = headers.hash_at_height(5).await?;
+    let mut branch = vec![];
+    for h in 6..=10 {
+        let pays = script(if h == 8 { 1 } else { 9 });
+        prev = filters.add_block(h, &block_paying(h, prev, &[pays, script(4)]))?;
+        branch.push(prev);
+    }
+    headers.reorg(5, branch.clone());
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    engine()
+        .with_events(move |e: &EngineEvent| {
+            if matches!(e, EngineEvent::MatchDropped { .. }) {
+                sink.lock().unwrap().push(e.clone());
+            }
+        })
+        .run_to_tip()
+        .await?;
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![EngineEvent::MatchDropped {
+            wallet: 0,
+            height: 7,
+            block: stale,
+        }]
+    );
+    assert_eq!(
+        store.pending_matches().await?,
+        vec![(3, headers.hash_at_height(3).await?), (8, branch[2])]
+    );
+    Ok(())
+}
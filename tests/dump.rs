@@ -0,0 +1,39 @@
+use niebla_158::dump::PayloadDump;
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::{FilterSource, Niebla158};
+
+#[tokio::test]
+async fn payloads_in_the_window_are_dumped() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(6, |_| vec![script(9)])?;
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("payloads.log");
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(1)]),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_payload_dump(PayloadDump::create(&path, 3..=4)?);
+    engine.run_to_tip().await?;
+
+    let text = std::fs::read_to_string(&path)?;
+    let lines: Vec<Vec<&str>> = text.lines().map(|l| l.split('\t').collect()).collect();
+    let kinds: Vec<_> = lines.iter().map(|l| (l[0], l[2])).collect();
+    assert_eq!(
+        kinds,
+        [
+            ("cfheader", "3"),
+            ("cfheader", "4"),
+            ("cfilter", "3"),
+            ("cfilter", "4"),
+        ]
+    );
+    for line in &lines {
+        assert_eq!((line[1], line[4]), ("0", "-"));
+    }
+    let block = headers.hash_at_height(3).await?;
+    assert_eq!(lines[2][3], block.to_string());
+    assert_eq!(lines[2][5], hex::encode(filters.get_cfilter(block).await?));
+    Ok(())
+}
@@ -0,0 +1,46 @@
+#![cfg(feature = "sqlite")]
+
+use niebla_158::filter_source::FilterSource;
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::{DynEngine, Niebla158, SqliteStore, Store};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// The store a config names.
+fn store_from_config(kind: &str, dir: &TempDir) -> anyhow::Result<Box<dyn Store>> {
+    Ok(match kind {
+        "sqlite" => Box::new(SqliteStore::new(dir.path().join("niebla.db"))?),
+        _ => Box::new(MemoryStore::new()),
+    })
+}
+
+#[tokio::test]
+async fn engines_from_runtime_choices() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(6, |h| if h == 4 { vec![script(1)] } else { vec![] })?;
+
+    let dir = TempDir::new()?;
+    for kind in ["memory", "sqlite"] {
+        let hooks = RecordingHooks::new(vec![script(1)]);
+        let source: Box<dyn FilterSource> = Box::new(filters.clone());
+        let header_source: Box<dyn HeaderSource> = Box::new(headers.clone());
+        let engine: DynEngine = Niebla158::new(
+            store_from_config(kind, &dir)?,
+            Box::new(hooks.clone()),
+            source,
+            header_source,
+        );
+        engine.run_to_tip().await?;
+        assert_eq!(hooks.matched_heights(), vec![4], "{kind}");
+    }
+
+    // Shared handles work too: the caller keeps reading the same store.
+    let store = Arc::new(MemoryStore::new());
+    let hooks = Arc::new(RecordingHooks::new(vec![script(1)]));
+    Niebla158::new(store.clone(), hooks.clone(), filters, headers)
+        .run_to_tip()
+        .await?;
+    assert_eq!(store.get_last_scanned().await?, 6);
+    assert_eq!(hooks.matched_heights(), vec![4]);
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use niebla_158::events::EngineEvent;
+use niebla_158::testing::*;
+use niebla_158::{EngineError, Niebla158, Store};
+use std::sync::{Arc, Mutex};
+
+fn setting(err: &anyhow::Error) -> &'static str {
+    match err.downcast_ref::<EngineError>() {
+        Some(EngineError::IncompatibleConfig { setting, .. }) => setting,
+        _ => panic!("expected an incompatible configuration, got {err:#}"),
+    }
+}
+
+#[tokio::test]
+async fn stores_remember_the_configuration_they_were_synced_with() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(10, |_| vec![])?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = || {
+        Niebla158::new(
+            store.clone(),
+            hooks.clone(),
+            filters.clone(),
+            headers.clone(),
+        )
+    };
+
+    let first = engine();
+    first.run_to_tip().await?;
+    assert_eq!(store.get_config().await?, Some(first.config().to_string()));
+
+    // Harmless changes are recorded and reported.
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    let sharded = engine()
+        .with_watchlist_shards(4)
+        .with_events(move |e: &EngineEvent| {
+            if let EngineEvent::ConfigChanged { .. } = e {
+                sink.lock().unwrap().push(e.clone())
+            }
+        });
+    sharded.run_to_tip().await?;
+    sharded.run_to_tip().await?;
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![EngineEvent::ConfigChanged {
+            settings: vec!["watchlist_shards".into()],
+        }]
+    );
+    assert_eq!(
+        store.get_config().await?,
+        Some(sharded.config().to_string())
+    );
+
+    // Outputs paid before tracking began would be missing from the UTXO set.
+    let tracking = engine().with_utxo_tracking();
+    let err = tracking.run_to_tip().await.unwrap_err();
+    assert_eq!(setting(&err), "utxo_tracking");
+
+    // Stored cfheaders that contradict a checkpoint would never be re-verified.
+    let bogus = vec![(5, BlockHash::from_byte_array([9; 32]))];
+    let err = engine()
+        .with_checkpoints(bogus)
+        .run_to_tip()
+        .await
+        .unwrap_err();
+    assert_eq!(setting(&err), "checkpoints");
+
+    // Starting over accepts the new configuration.
+    tracking.reset().await?;
+    tracking.run_to_tip().await?;
+    assert_eq!(
+        store.get_config().await?,
+        Some(tracking.config().to_string())
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn cfheaders_from_older_rules_are_verified_again() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(10, |_| vec![])?;
+    let store = MemoryStore::new();
+    let engine = || {
+        Niebla158::new(
+            store.clone(),
+            RecordingHooks::new(vec![script(1)]),
+            filters.clone(),
+            headers.clone(),
+        )
+    };
+    engine().run_to_tip().await?;
+    let tip = store.load_cf_tip().await?;
+
+    // A store synced before cfheaders were rolled as BIP-157 does: its
+    // record lacks the rules and its tip was hashed differently.
+    let record = store.get_config().await?.unwrap();
+    let old: String = record
+        .lines()
+        .filter(|l| !l.starts_with("cfheaders="))
+        .map(|l| format!("{l}\n"))
+        .collect();
+    store.set_config(&old).await?;
+    store
+        .save_cf_tip(10, BlockHash::from_byte_array([9; 32]))
+        .await?;
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    let migrated = engine().with_events(move |e: &EngineEvent| {
+        if let EngineEvent::ConfigChanged { .. } = e {
+            sink.lock().unwrap().push(e.clone())
+        }
+    });
+    migrated.run_to_tip().await?;
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![EngineEvent::ConfigChanged {
+            settings: vec!["cfheaders".into()],
+        }]
+    );
+    assert_eq!(store.load_cf_tip().await?, tip);
+    assert_eq!(store.get_config().await?, Some(record));
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use bitcoin::{
+    absolute::LockTime, consensus, transaction, Amount, Block, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Witness,
+};
+use niebla_158::export::{self, ExportWallet, ExportedMatch};
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, WatchItem};
+
+#[tokio::test]
+async fn matches_are_exported_as_files() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    let mut paying = vec![];
+    for h in 1..=5u32 {
+        let pays = if h == 2 || h == 4 {
+            vec![script(1)]
+        } else {
+            vec![script(9)]
+        };
+        let block = block_paying(h, prev, &pays);
+        if h == 2 || h == 4 {
+            paying.push(block.clone());
+        }
+        prev = headers.push(filters.add_block(h, &block)?);
+    }
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().join("transfer");
+    let wallet = ExportWallet::new(&out, vec![WatchItem::new(script(1))])?.with_blocks();
+    let engine = Niebla158::new(MemoryStore::new(), wallet, filters.clone(), headers.clone())
+        .with_raw_blocks();
+    engine.run_to_tip().await?;
+
+    let exported = export::read_dir(&out)?;
+    assert_eq!(
+        exported.iter().map(|m| m.height).collect::<Vec<_>>(),
+        [2, 4]
+    );
+    for (m, block) in exported.iter().zip(&paying) {
+        assert_eq!(m.block, headers.hash_at_height(m.height).await?);
+        assert_eq!(m.txs, block.txdata);
+        let raw: Block = consensus::deserialize(m.raw_block.as_deref().unwrap())?;
+        assert_eq!(&raw, block);
+        assert!(out.join(m.file_name()).exists());
+        assert_eq!(m.to_string().parse::<ExportedMatch>()?, *m);
+    }
+    assert_ne!(exported[0].match_id, exported[1].match_id);
+    assert!(exported[0].sequence < exported[1].sequence);
+    Ok(())
+}
+
+#[tokio::test]
+async fn blocks_need_raw_block_delivery() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    headers.push(filters.add_block(1, &block_paying(1, prev, &[script(1)]))?);
+    let dir = tempfile::tempdir()?;
+    let wallet = ExportWallet::new(dir.path(), vec![WatchItem::new(script(1))])?.with_blocks();
+    let engine = Niebla158::new(MemoryStore::new(), wallet, filters, headers);
+
+    let err = engine.run_to_tip().await.unwrap_err();
+    assert!(format!("{err:#}").contains("with_raw_blocks"), "{err:#}");
+    assert!(export::read_dir(dir.path())?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn spends_are_exported_across_restarts_with_utxo_tracking() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let funding = block_paying(1, prev, &[script(1)]);
+    let prev = headers.push(filters.add_block(1, &funding)?);
+    let store = MemoryStore::new();
+    let dir = tempfile::tempdir()?;
+    let run = || async {
+        let wallet = ExportWallet::new(dir.path(), vec![WatchItem::new(script(1))])?;
+        Niebla158::new(store.clone(), wallet, filters.clone(), headers.clone())
+            .with_utxo_tracking()
+            .run_to_tip()
+            .await
+    };
+    run().await?;
+
+    // After a restart, block 2 spends the coin to an outsider.
+    let spend = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(funding.txdata[0].compute_txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(900),
+            script_pubkey: script(7),
+        }],
+    };
+    let mut block = block_paying(2, prev, &[script(9)]);
+    block.txdata.push(spend.clone());
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    headers.push(filters.add_block(2, &block)?);
+    run().await?;
+
+    let exported = export::read_dir(dir.path())?;
+    assert_eq!(
+        exported.iter().map(|m| m.height).collect::<Vec<_>>(),
+        [1, 2]
+    );
+    assert_eq!(exported[1].txs, vec![spend]);
+    Ok(())
+}
+
+#[test]
+fn unreadable_exports_fail_rather_than_vanish() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("0000000001-x.json"), "{}")?;
+    std::fs::write(dir.path().join("0000000001-x.tmp"), "half a match")?;
+    let err = export::read_dir(dir.path()).unwrap_err();
+    assert!(format!("{err:#}").contains("0000000001-x.json"), "{err:#}");
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use bitcoin::block::{Header, Version};
+use bitcoin::consensus::serialize;
+use bitcoin::constants::genesis_block;
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, CompactTarget, Network, TxMerkleNode};
+use niebla_158::headers::{FileHeaderSource, HeaderSource};
+
+/// A regtest header on `prev`, with its nonce ground to meet the target.
+fn mine(prev: BlockHash, time: u32) -> Header {
+    let mut header = Header {
+        version: Version::TWO,
+        prev_blockhash: prev,
+        merkle_root: TxMerkleNode::all_zeros(),
+        time,
+        bits: CompactTarget::from_consensus(0x207fffff),
+        nonce: 0,
+    };
+    while header.validate_pow(header.target()).is_err() {
+        header.nonce += 1;
+    }
+    header
+}
+
+/// Regtest genesis plus `n` mined headers.
+fn chain(n: u32) -> Vec<Header> {
+    let mut headers = vec![genesis_block(Network::Regtest).header];
+    for i in 1..=n {
+        let prev = headers.last().unwrap().block_hash();
+        headers.push(mine(prev, 1_700_000_000 + i));
+    }
+    headers
+}
+
+fn bytes(headers: &[Header]) -> Vec<u8> {
+    headers.iter().flat_map(serialize).collect()
+}
+
+#[tokio::test]
+async fn serves_heights_from_a_headers_file() -> anyhow::Result<()> {
+    let headers = chain(20);
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("headers.bin");
+    std::fs::write(&path, bytes(&headers))?;
+
+    let source = FileHeaderSource::open(&path, Network::Regtest)?;
+    assert_eq!(source.tip_height().await?, 20);
+    for (h, header) in headers.iter().enumerate() {
+        assert_eq!(source.hash_at_height(h as u32).await?, header.block_hash());
+        assert_eq!(source.header(header.block_hash()).await?, Some(*header));
+    }
+    assert!(source.hash_at_height(21).await.is_err());
+    assert_eq!(source.header(BlockHash::all_zeros()).await?, None);
+    Ok(())
+}
+
+#[test]
+fn rejects_invalid_headers_files() {
+    let headers = chain(5);
+    let check = |raw: &[u8], network, expected: &str| {
+        let err = FileHeaderSource::from_bytes(raw, network).unwrap_err();
+        assert!(format!("{err:#}").contains(expected), "{err:#}");
+    };
+
+    check(&bytes(&headers)[..80 * 3 + 7], Network::Regtest, "80-byte");
+    check(&bytes(&headers), Network::Signet, "genesis");
+
+    let mut unlinked = headers.clone();
+    unlinked.remove(2);
+    check(
+        &bytes(&unlinked),
+        Network::Regtest,
+        "header 2 does not connect",
+    );
+
+    let mut weak = headers.clone();
+    while weak[3].validate_pow(weak[3].target()).is_ok() {
+        weak[3].nonce += 1;
+    }
+    check(&bytes(&weak), Network::Regtest, "header 3");
+}
@@ -0,0 +1,131 @@
+use bitcoin::bip158::BlockFilter;
+use bitcoin::consensus;
+use bitcoin::ScriptBuf;
+use niebla_158::events::{EmptyFilterKind, EngineEvent};
+use niebla_158::testing::*;
+use niebla_158::{EngineError, Niebla158, Store};
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn empty_and_missing_filters_are_reported() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    filters.set_label("cdn-1");
+    let prev = headers.push(genesis_hash());
+    // 1: a block without scripts (N = 0); 2: the source serves zero bytes; 3: normal.
+    let prev = headers.push(filters.add_block(1, &block_paying(1, prev, &[]))?);
+    let withheld = block_paying(2, prev, &[script(1)]);
+    let hash = withheld.block_hash();
+    filters.add_raw(2, hash, prev, vec![], consensus::serialize(&withheld));
+    let prev = headers.push(hash);
+    headers.push(filters.add_block(3, &block_paying(3, prev, &[script(2)]))?);
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .with_events(move |e: &EngineEvent| sink.lock().unwrap().push(e.clone()))
+        .run_to_tip()
+        .await?;
+
+    // The payment at 2 is missed, but no longer silently.
+    assert_eq!(hooks.matched_heights(), Vec::<u32>::new());
+    let events = events.lock().unwrap();
+    assert!(matches!(
+        &events[..],
+        [
+            EngineEvent::EmptyFilter {
+                height: 1,
+                kind: EmptyFilterKind::NoElements,
+                ..
+            },
+            EngineEvent::EmptyFilter {
+                height: 2,
+                block,
+                kind: EmptyFilterKind::Missing,
+                source: Some(label),
+            },
+        ] if *block == hash && label == "cdn-1"
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn strict_mode_rejects_missing_filters() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    filters.set_label("cdn-1");
+    let prev = headers.push(genesis_hash());
+    // A block without scripts is fine, even in strict mode.
+    let prev = headers.push(filters.add_block(1, &block_paying(1, prev, &[]))?);
+    let block = block_paying(2, prev, &[script(1)]);
+    let hash = block.block_hash();
+    filters.add_raw(2, hash, prev, vec![], consensus::serialize(&block));
+    headers.push(hash);
+
+    let store = MemoryStore::new();
+    let err = Niebla158::new(
+        store.clone(),
+        RecordingHooks::new(vec![script(1)]),
+        filters,
+        headers,
+    )
+    .with_strict_filters()
+    .run_to_tip()
+    .await
+    .unwrap_err();
+    match err.downcast_ref::<EngineError>() {
+        Some(EngineError::InvalidFilter(i)) => {
+            assert_eq!((i.height, i.block), (2, hash));
+            assert_eq!(i.reason, "zero-length filter");
+            assert_eq!(i.source.as_deref(), Some("cdn-1"));
+        }
+        _ => panic!("expected InvalidFilter, got {err:#}"),
+    }
+    assert_eq!(store.get_last_scanned().await?, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn malformed_filters_are_invalid_and_reported() -> anyhow::Result<()> {
+    let prev = genesis_hash();
+    let good = block_paying(1, prev, &[script(1)]);
+    let good_filter = BlockFilter::new_script_filter(&good, |_| {
+        Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(Default::default()))
+    })?
+    .content;
+    let with_trailing = [good_filter.clone(), vec![0x00]].concat();
+
+    for (bad, reason) in [
+        (vec![0x05, 0xff], "cannot fit"),
+        (vec![0x02, 0xff, 0xff, 0xff, 0xff, 0xff], "truncated"),
+        (with_trailing, "trailing bytes"),
+        (vec![0xfd, 0x01, 0x00], "non-canonical"),
+        (vec![0xff, 0, 0, 0, 0, 1, 0, 0, 0], "exceeds u32"),
+    ] {
+        let filters = MockFilterSource::new();
+        let hash = good.block_hash();
+        filters.add_raw(1, hash, prev, bad, consensus::serialize(&good));
+        let headers = MockHeaderSource::from_hashes(vec![genesis_hash(), hash]);
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let sink = events.clone();
+        let err = Niebla158::new(
+            MemoryStore::new(),
+            RecordingHooks::new(vec![script(1)]),
+            filters,
+            headers,
+        )
+        .with_events(move |e: &EngineEvent| sink.lock().unwrap().push(e.clone()))
+        .run_to_tip()
+        .await
+        .unwrap_err();
+        let Some(EngineError::InvalidFilter(i)) = err.downcast_ref::<EngineError>() else {
+            panic!("expected InvalidFilter, got {err:#}");
+        };
+        assert!(i.reason.contains(reason), "{reason}: {}", i.reason);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![EngineEvent::InvalidFilter(i.clone())]
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, PhaseTimings, RunState};
+use std::time::Duration;
+
+#[tokio::test]
+async fn health_tracks_source_calls_and_chain_progress() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=5 {
+        let pays = if h == 2 { script(1) } else { script(9) };
+        let block = block_paying(h, prev, &[pays]);
+        filters.add_block(h, &block)?;
+        prev = headers.push(block.block_hash());
+    }
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks, filters.clone(), headers.clone());
+    let handle = engine.handle();
+
+    let before = handle.health();
+    assert_eq!(before.state, RunState::Idle);
+    assert_eq!(before.filter_source_ok_at, None);
+    assert_eq!(before.new_block_at, None);
+    assert_eq!(before.secs_since_new_block, None);
+
+    engine.run_to_tip().await?;
+    let health = handle.health();
+    assert!(health.filter_source_ok_at.is_some());
+    assert!(health.header_source_ok_at.is_some());
+    assert!(health.block_source_ok_at.is_some());
+    assert_eq!(health.consecutive_failures, 0);
+    assert_eq!((health.scanned_height, health.chain_tip_height), (5, 5));
+    assert!(health.secs_since_new_block.unwrap() < 60);
+    let first_seen = health.new_block_at.unwrap();
+
+    // Failed runs are counted until the next successful one.
+    let block = block_paying(6, prev, &[script(9)]);
+    filters.add_block(6, &block)?;
+    headers.push(block.block_hash());
+    filters.fail_next(MockCall::Cfilter, "filter server down");
+    filters.fail_next(MockCall::Cfilter, "filter server down");
+    assert!(engine.run_to_tip().await.is_err());
+    assert!(engine.run_to_tip().await.is_err());
+    let health = handle.health();
+    assert_eq!(health.consecutive_failures, 2);
+    assert_eq!(health.scanned_height, 5);
+    assert_eq!(health.chain_tip_height, 6);
+    assert!(health.new_block_at.unwrap() >= first_seen);
+
+    engine.run_to_tip().await?;
+    assert_eq!(handle.health().consecutive_failures, 0);
+    assert_eq!(handle.health().scanned_height, 6);
+    Ok(())
+}
+
+#[tokio::test]
+async fn timings_split_sync_time_by_phase() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(3, |h| vec![script(h as u8)])?;
+    let latency = Duration::from_millis(20);
+    filters.set_latency(latency);
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(2)]),
+        filters,
+        headers,
+    );
+    let handle = engine.handle();
+    assert_eq!(handle.timings(), PhaseTimings::default());
+
+    engine.run_to_tip().await?;
+    let t = handle.timings();
+    // One call each to the slow source: cfheaders, the filter window, the matching block.
+    assert!(t.cfheaders_fetch >= latency, "{t:?}");
+    assert!(t.filter_fetch >= latency, "{t:?}");
+    assert!(t.block_fetch >= latency, "{t:?}");
+    assert!(t.hooks < latency && t.store < latency, "{t:?}");
+    Ok(())
+}
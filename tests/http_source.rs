@@ -0,0 +1,116 @@
+#![cfg(feature = "http")]
+
+use bitcoin::consensus::encode::{serialize, serialize_hex};
+use bitcoin::hashes::Hash as _;
+use bitcoin::{
+    absolute::LockTime, transaction, Amount, BlockHash, ScriptBuf, Transaction, TxOut, Txid,
+};
+use niebla_158::filter_source::FilterSource;
+use niebla_158::headers::HeaderSource;
+use niebla_158::http::{self, CoreRpcTxSource, EsploraTxSource, HttpFilterSource};
+use niebla_158::tx_source::TxSource;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+
+mod common;
+use common::http::{serve, serve_counted};
+
+#[tokio::test]
+async fn http_source_speaks_the_protocol() -> anyhow::Result<()> {
+    let stop = BlockHash::from_byte_array([2u8; 32]);
+    let mut routes = HashMap::new();
+    routes.insert(http::tip_path(), b"2\n".to_vec());
+    routes.insert(http::blockhash_path(2), stop.to_string().into_bytes());
+    routes.insert(
+        http::cfheaders_path(1, stop),
+        [[1u8; 32], [2u8; 32]].concat(),
+    );
+    routes.insert(http::cfilter_path(stop), vec![0x00]);
+    let base = serve(routes).await?;
+
+    let src = HttpFilterSource::new(format!("{base}/"));
+    assert_eq!(src.tip_height().await?, 2);
+    assert_eq!(src.hash_at_height(2).await?, stop);
+
+    let batch = src.get_cfheaders(1, stop).await?;
+    assert_eq!(batch.start_height, 1);
+    assert_eq!(batch.headers, vec![[1u8; 32], [2u8; 32]]);
+
+    assert_eq!(src.get_cfilter(stop).await?, vec![0x00]);
+    assert!(
+        src.get_block(stop).await.is_err(),
+        "404 surfaces as an error"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn etag_cache_revalidates_immutable_objects() -> anyhow::Result<()> {
+    let stop = BlockHash::from_byte_array([2u8; 32]);
+    let mut routes = HashMap::new();
+    routes.insert(http::cfheaders_path(1, stop), [[1u8; 32]].concat());
+    routes.insert(http::cfilter_path(stop), vec![0x00]);
+    let (base, sent) = serve_counted(routes).await?;
+
+    let src = HttpFilterSource::new(base.clone()).with_etag_cache(2);
+    for _ in 0..3 {
+        assert_eq!(src.get_cfheaders(1, stop).await?.headers, vec![[1u8; 32]]);
+        assert_eq!(src.get_cfilter(stop).await?, vec![0x00]);
+    }
+    assert_eq!(sent.load(Ordering::SeqCst), 2, "later requests got 304s");
+
+    // Without the cache every request downloads the body.
+    let plain = HttpFilterSource::new(base.clone());
+    plain.get_cfilter(stop).await?;
+    plain.get_cfilter(stop).await?;
+    assert_eq!(sent.load(Ordering::SeqCst), 4);
+
+    // A full cache evicts its oldest body, which is then downloaded again.
+    let small = HttpFilterSource::new(base).with_etag_cache(1);
+    small.get_cfheaders(1, stop).await?;
+    small.get_cfilter(stop).await?;
+    small.get_cfilter(stop).await?;
+    assert_eq!(sent.load(Ordering::SeqCst), 6);
+    small.get_cfheaders(1, stop).await?;
+    assert_eq!(sent.load(Ordering::SeqCst), 7);
+    Ok(())
+}
+
+#[tokio::test]
+async fn tx_sources_look_up_transactions() -> anyhow::Result<()> {
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            value: Amount::from_sat(1_000),
+            script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+        }],
+    };
+    let (txid, unknown) = (tx.compute_txid(), Txid::from_byte_array([7; 32]));
+
+    let mut routes = HashMap::new();
+    routes.insert(format!("/tx/{txid}/raw"), serialize(&tx));
+    let esplora = EsploraTxSource::new(serve(routes).await?);
+    assert_eq!(esplora.get_transaction(txid).await?, Some(tx.clone()));
+    assert_eq!(esplora.get_transaction(unknown).await?, None);
+
+    // One canned JSON-RPC answer per node.
+    let rpc = |answer: String| async move {
+        let base = serve(HashMap::from([("/".to_owned(), answer.into_bytes())])).await?;
+        anyhow::Ok(CoreRpcTxSource::new(format!("{base}/")).with_basic_auth("user", "pass"))
+    };
+    let found = rpc(format!(
+        r#"{{"result":"{}","error":null,"id":"niebla"}}"#,
+        serialize_hex(&tx)
+    ))
+    .await?;
+    assert_eq!(found.get_transaction(txid).await?, Some(tx));
+    let missing = rpc(
+        r#"{"result":null,"error":{"code":-5,"message":"No such transaction"},"id":"niebla"}"#
+            .to_owned(),
+    )
+    .await?;
+    assert_eq!(missing.get_transaction(unknown).await?, None);
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use bitcoin::hashes::Hash;
+use bitcoin::{ScriptBuf, WPubkeyHash};
+use niebla_158::testing::*;
+use niebla_158::Niebla158;
+
+fn script(n: u32) -> ScriptBuf {
+    let mut hash = [0u8; 20];
+    hash[..4].copy_from_slice(&n.to_le_bytes());
+    ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(hash))
+}
+
+#[tokio::test]
+async fn sharded_and_indexed_matching_agree_with_plain_matching() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=20u32 {
+        // Every third block pays one script from a different part of the registry.
+        let pays = match h % 3 {
+            0 => vec![script(h * 97), script(1_000_000 + h)],
+            _ => vec![script(1_000_000 + h)],
+        };
+        let block = block_paying(h, prev, &pays);
+        prev = headers.push(filters.add_block(h, &block)?);
+    }
+    let registry: Vec<ScriptBuf> = (0..2_000).map(script).collect();
+
+    let mut matched = vec![];
+    for (shards, indexed) in [
+        (None, false),
+        (Some(7), false),
+        (Some(500), false),
+        (Some(5_000), false),
+        (None, true),
+        (Some(500), true),
+    ] {
+        let hooks = RecordingHooks::new(registry.clone());
+        let engine = Niebla158::new(
+            MemoryStore::new(),
+            hooks.clone(),
+            filters.clone(),
+            headers.clone(),
+        );
+        let engine = match shards {
+            Some(size) => engine.with_watchlist_shards(size),
+            None => engine,
+        };
+        let engine = match indexed {
+            true => engine.with_indexed_matching(),
+            false => engine,
+        };
+        engine.run_to_tip().await?;
+        matched.push(hooks.matched_heights());
+    }
+    assert_eq!(matched[0], vec![3, 6, 9, 12, 15, 18]);
+    assert!(matched.iter().all(|m| *m == matched[0]), "{matched:?}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn shard_size_is_bounded() {
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(1)]),
+        MockFilterSource::new(),
+        MockHeaderSource::new(),
+    )
+    .with_watchlist_shards(0);
+    let err = engine.run_to_tip().await.unwrap_err();
+    assert!(format!("{err:#}").contains("shard size 0"), "{err:#}");
+}
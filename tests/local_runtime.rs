@@ -0,0 +1,110 @@
+#![cfg(feature = "local")]
+
+use async_trait::async_trait;
+use bitcoin::hashes::Hash as _;
+use bitcoin::{BlockHash, ScriptBuf, Transaction};
+use niebla_158::filter_source::CfHeadersBatch;
+use niebla_158::headers::HeaderSource;
+use niebla_158::prelude::*;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// Store and hooks hold `Rc`/`Cell` state, which only `local` builds accept.
+#[derive(Default)]
+struct RcStore {
+    cf_tip: Cell<Option<(u32, BlockHash)>>,
+    last_scanned: Cell<u32>,
+}
+#[async_trait(?Send)]
+impl Store for RcStore {
+    async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        Ok(self.cf_tip.get())
+    }
+    async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
+        self.cf_tip.set(Some((height, cfheader)));
+        Ok(())
+    }
+    async fn get_last_scanned(&self) -> anyhow::Result<u32> {
+        Ok(self.last_scanned.get())
+    }
+    async fn set_last_scanned(&self, height: u32) -> anyhow::Result<()> {
+        self.last_scanned.set(height);
+        Ok(())
+    }
+}
+
+struct RcHooks {
+    watch: Vec<ScriptBuf>,
+    seen: Rc<RefCell<Vec<u32>>>,
+}
+#[async_trait(?Send)]
+impl WalletHooks for RcHooks {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(self.watch.clone())
+    }
+    async fn on_block_match(
+        &self,
+        height: u32,
+        _block: BlockHash,
+        _txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        self.seen.borrow_mut().push(height);
+        Ok(())
+    }
+}
+
+struct Chain;
+#[async_trait(?Send)]
+impl HeaderSource for Chain {
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        Ok(3)
+    }
+    async fn hash_at_height(&self, h: u32) -> anyhow::Result<BlockHash> {
+        Ok(BlockHash::from_byte_array([h as u8; 32]))
+    }
+}
+#[async_trait(?Send)]
+impl FilterSource for Chain {
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        _stop: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        Ok(CfHeadersBatch {
+            start_height: start_h,
+            headers: vec![[0u8; 32]; (4 - start_h) as usize],
+        })
+    }
+    async fn get_cfilter(&self, _block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        Ok(vec![0x00])
+    }
+    async fn get_block(&self, _block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        anyhow::bail!("no blocks")
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn engine_runs_on_a_local_set() -> anyhow::Result<()> {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let engine = Rc::new(Niebla158::new(
+        RcStore::default(),
+        RcHooks {
+            watch: vec![ScriptBuf::new()],
+            seen: seen.clone(),
+        },
+        Chain,
+        Chain,
+    ));
+    let handle = engine.handle();
+
+    let local = tokio::task::LocalSet::new();
+    local
+        .run_until(async move {
+            tokio::task::spawn_local(async move { engine.run_to_tip().await }).await?
+        })
+        .await?;
+
+    assert_eq!(handle.status().scanned_height, 3);
+    assert!(seen.borrow().is_empty());
+    Ok(())
+}
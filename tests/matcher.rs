@@ -0,0 +1,80 @@
+use bitcoin::bip158::BlockFilter;
+use bitcoin::hashes::Hash;
+use bitcoin::{Address, Network, OutPoint, ScriptBuf, TxIn, Txid};
+use niebla_158::filter_source::GolombParams;
+use niebla_158::matcher::{
+    filter_matches_any, filter_matches_any_address, outpoints_maybe_spent, validate_filter,
+    QuerySet,
+};
+use niebla_158::testing::*;
+use niebla_158::WatchItem;
+
+#[test]
+fn ad_hoc_queries_against_a_raw_filter() -> anyhow::Result<()> {
+    // Pays script 1, and spends an output locked to script 2.
+    let mut block = block_paying(1, genesis_hash(), &[script(1)]);
+    let spent = OutPoint::new(Txid::from_byte_array([9; 32]), 0);
+    let mut spend = block.txdata[0].clone();
+    spend.input = vec![TxIn {
+        previous_output: spent,
+        ..Default::default()
+    }];
+    block.txdata.push(spend);
+    let filter = BlockFilter::new_script_filter(&block, |o| {
+        assert_eq!(*o, spent);
+        Ok(script(2))
+    })?;
+    let (raw, hash, basic) = (&filter.content[..], block.block_hash(), GolombParams::BASIC);
+    validate_filter(raw, basic).map_err(anyhow::Error::msg)?;
+
+    assert!(filter_matches_any(
+        raw,
+        hash,
+        &[script(3), script(1)],
+        basic
+    )?);
+    assert!(!filter_matches_any(raw, hash, &[script(3)], basic)?);
+    assert!(!filter_matches_any(raw, hash, &[], basic)?);
+
+    let paid = Address::from_script(&script(1), Network::Regtest)?;
+    let other = Address::from_script(&script(3), Network::Regtest)?;
+    assert!(filter_matches_any_address(
+        raw,
+        hash,
+        &[other.clone(), paid],
+        basic
+    )?);
+    assert!(!filter_matches_any_address(raw, hash, &[other], basic)?);
+
+    let unspent = OutPoint::new(Txid::from_byte_array([8; 32]), 1);
+    assert_eq!(
+        outpoints_maybe_spent(
+            raw,
+            hash,
+            &[(unspent, script(4)), (spent, script(2))],
+            basic
+        )?,
+        vec![spent]
+    );
+    Ok(())
+}
+
+#[test]
+fn overlapping_watch_items_are_queried_once() -> anyhow::Result<()> {
+    let block = block_paying(3, genesis_hash(), &[script(1)]);
+    let filter = BlockFilter::new_script_filter(&block, |_| {
+        Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(Default::default()))
+    })?;
+    // Script 1 from an address (born at 5) and from a descriptor (no birth).
+    let items = [
+        WatchItem::new(script(1)).with_birth_height(5),
+        WatchItem::new(script(2)),
+        WatchItem::new(script(1)).with_tag("descriptor"),
+        WatchItem::new(script(2)).with_birth_height(1),
+    ];
+    let query = QuerySet::new(&items, Some(1), false);
+    assert_eq!(query.len(), 2);
+    // The earliest birth wins.
+    assert!(query.matches(3, block.block_hash(), &filter.content, GolombParams::BASIC)?);
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use bitcoin::constants::genesis_block;
+use bitcoin::Network;
+use niebla_158::testing::*;
+use niebla_158::{EngineError, Niebla158, Store};
+
+/// A 5-block chain on top of `network`'s genesis, paying `script(1)` at 3.
+fn chain(network: Network) -> anyhow::Result<(MockFilterSource, MockHeaderSource)> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(filters.add_block(0, &genesis_block(network))?);
+    for h in 1..=5u32 {
+        let pays = if h == 3 { vec![script(1)] } else { vec![] };
+        prev = headers.push(filters.add_block(h, &block_paying(h, prev, &pays))?);
+    }
+    Ok((filters, headers))
+}
+
+#[tokio::test]
+async fn engines_for_different_networks_share_a_process() -> anyhow::Result<()> {
+    let (regtest, signet) = (chain(Network::Regtest)?, chain(Network::Signet)?);
+    let (regtest_store, signet_store) = (MemoryStore::new(), MemoryStore::new());
+    let (regtest_hooks, signet_hooks) = (
+        RecordingHooks::new(vec![script(1)]),
+        RecordingHooks::new(vec![script(1)]),
+    );
+    let a = Niebla158::new(
+        regtest_store.clone(),
+        regtest_hooks.clone(),
+        regtest.0.clone(),
+        regtest.1.clone(),
+    )
+    .with_network(Network::Regtest);
+    let b = Niebla158::new(
+        signet_store.clone(),
+        signet_hooks.clone(),
+        signet.0.clone(),
+        signet.1.clone(),
+    )
+    .with_network(Network::Signet);
+    let (ra, rb) = tokio::join!(a.run_to_tip(), b.run_to_tip());
+    ra?;
+    rb?;
+
+    assert_eq!(regtest_hooks.matched_heights(), vec![3]);
+    assert_eq!(signet_hooks.matched_heights(), vec![3]);
+    assert_eq!(regtest_store.get_network().await?, Some(Network::Regtest));
+    assert_eq!(a.handle().network(), Some(Network::Regtest));
+    assert_eq!(b.handle().network(), Some(Network::Signet));
+
+    // A signet engine pointed at regtest headers, or at a regtest store, refuses to run.
+    for (store, headers, what) in [
+        (MemoryStore::new(), regtest.1.clone(), "header source"),
+        (regtest_store.clone(), signet.1.clone(), "store"),
+    ] {
+        let err = Niebla158::new(
+            store,
+            RecordingHooks::new(vec![]),
+            signet.0.clone(),
+            headers,
+        )
+        .with_network(Network::Signet)
+        .run_to_tip()
+        .await
+        .unwrap_err();
+        match err.downcast_ref::<EngineError>() {
+            Some(EngineError::WrongNetwork {
+                network, what: w, ..
+            }) => {
+                assert_eq!((*network, *w), (Network::Signet, what));
+            }
+            _ => panic!("expected WrongNetwork, got {err:#}"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "sqlite")]
+
+use bitcoin::hashes::Hash as _;
+use bitcoin::BlockHash;
+use niebla_158::neutrino::{NeutrinoImport, FILTER_HEADERS_FILE};
+use niebla_158::store::{sqlite_store::SqliteStore, Store};
+use tempfile::TempDir;
+
+fn header(h: u8) -> BlockHash {
+    BlockHash::from_byte_array([h; 32])
+}
+
+/// Write a fake Neutrino data dir holding filter headers for heights 0..=2.
+fn neutrino_dir() -> anyhow::Result<TempDir> {
+    let dir = TempDir::new()?;
+    let bytes: Vec<u8> = (0..3u8).flat_map(|h| header(h).to_byte_array()).collect();
+    std::fs::write(dir.path().join(FILTER_HEADERS_FILE), bytes)?;
+    Ok(dir)
+}
+
+#[tokio::test]
+async fn neutrino_headers_seed_the_store() -> anyhow::Result<()> {
+    let dir = neutrino_dir()?;
+    let db = dir.path().join("niebla.db");
+    let store = SqliteStore::new(&db)?;
+
+    let tip = NeutrinoImport::from_dir(dir.path())
+        .with_checkpoints(vec![(1, header(1))])
+        .import_into(&store)
+        .await?;
+
+    assert_eq!(tip, (2, header(2)));
+    assert_eq!(store.load_cf_tip().await?, Some((2, header(2))));
+    assert_eq!(store.load_cf_header(1).await?, Some(header(1)));
+
+    // A second import must not clobber existing progress.
+    assert!(NeutrinoImport::from_dir(dir.path())
+        .import_into(&store)
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn neutrino_import_rejects_checkpoint_mismatch() -> anyhow::Result<()> {
+    let dir = neutrino_dir()?;
+    let store = SqliteStore::new(dir.path().join("niebla.db"))?;
+
+    let err = NeutrinoImport::from_dir(dir.path())
+        .with_checkpoints(vec![(2, header(9))])
+        .import_into(&store)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("checkpoint mismatch @2"));
+    assert_eq!(store.load_cf_tip().await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn neutrino_import_checks_the_genesis_cfheader() -> anyhow::Result<()> {
+    use bitcoin::Network;
+    use niebla_158::checkpoints::genesis_cfheader;
+
+    let dir = neutrino_dir()?;
+    let store = SqliteStore::new(dir.path().join("niebla.db"))?;
+    let err = NeutrinoImport::from_dir(dir.path())
+        .with_network(Network::Regtest)
+        .import_into(&store)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("genesis cfheader"), "{err:#}");
+    assert_eq!(store.load_cf_tip().await?, None);
+
+    // A file that starts at the regtest genesis header imports.
+    let genesis = genesis_cfheader(Network::Regtest);
+    let bytes: Vec<u8> = [genesis, header(1)]
+        .iter()
+        .flat_map(|h| h.to_byte_array())
+        .collect();
+    std::fs::write(dir.path().join(FILTER_HEADERS_FILE), bytes)?;
+    let tip = NeutrinoImport::from_dir(dir.path())
+        .with_network(Network::Regtest)
+        .import_into(&store)
+        .await?;
+    assert_eq!(tip, (1, header(1)));
+    assert_eq!(store.load_cf_header(0).await?, Some(genesis));
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use async_trait::async_trait;
+use bitcoin::bip158::BlockFilter;
+use bitcoin::block::Header;
+use bitcoin::consensus::serialize;
+use bitcoin::constants::genesis_block;
+use bitcoin::{Block, BlockHash, Network, ScriptBuf};
+use niebla_158::headers::HeaderSource;
+use niebla_158::http::{block_path, cfilter_path};
+use niebla_158::offline::OfflineSource;
+use niebla_158::testing::*;
+use niebla_158::Niebla158;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Write a regtest chain of 8 blocks paying `script(1)` at 3 and 6 into `dir`.
+fn export(dir: &Path) -> anyhow::Result<Vec<Block>> {
+    let mut blocks = vec![genesis_block(Network::Regtest)];
+    for h in 1..=8u32 {
+        let pays = if h == 3 || h == 6 {
+            vec![script(1)]
+        } else {
+            vec![script(100 + h as u8)]
+        };
+        let mut block = block_paying(h, blocks.last().unwrap().block_hash(), &pays);
+        while block.header.validate_pow(block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+        blocks.push(block);
+    }
+    let write = |path: String, bytes: Vec<u8>| -> anyhow::Result<()> {
+        let path = dir.join(path.trim_start_matches('/'));
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        Ok(std::fs::write(path, bytes)?)
+    };
+    let headers: Vec<u8> = blocks.iter().flat_map(|b| serialize(&b.header)).collect();
+    write("headers.bin".into(), headers)?;
+    for block in &blocks {
+        let filter = BlockFilter::new_script_filter(block, |_| {
+            Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(Default::default()))
+        })?;
+        write(cfilter_path(block.block_hash()), filter.content)?;
+        write(block_path(block.block_hash()), serialize(block))?;
+    }
+    Ok(blocks)
+}
+
+#[tokio::test]
+async fn scans_a_local_export() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let blocks = export(dir.path())?;
+    let source = OfflineSource::open(dir.path(), Network::Regtest)?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(MemoryStore::new(), hooks.clone(), source.clone(), source)
+        .with_network(Network::Regtest)
+        .run_to_tip()
+        .await?;
+    assert_eq!(hooks.matched_heights(), vec![3, 6]);
+
+    // Block h is stamped h: the median of 1..=6 and genesis is 4.
+    let matches = hooks.matches();
+    assert_eq!(matches[1].header, Some(blocks[6].header));
+    assert_eq!(
+        matches
+            .iter()
+            .map(|m| m.median_time_past)
+            .collect::<Vec<_>>(),
+        vec![Some(3), Some(4)]
+    );
+    Ok(())
+}
+
+/// The export's headers, counting single `header` lookups; `batch` says
+/// whether runs of headers are served too.
+struct Headers {
+    inner: OfflineSource,
+    batch: bool,
+    lookups: Arc<AtomicUsize>,
+}
+
+#[cfg_attr(feature = "local", async_trait(?Send))]
+#[cfg_attr(not(feature = "local"), async_trait)]
+impl HeaderSource for Headers {
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        self.inner.tip_height().await
+    }
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        self.inner.hash_at_height(height).await
+    }
+    async fn header(&self, block: BlockHash) -> anyhow::Result<Option<Header>> {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        self.inner.header(block).await
+    }
+    async fn headers_from(&self, start: BlockHash, count: u32) -> anyhow::Result<Vec<Header>> {
+        match self.batch {
+            true => self.inner.headers_from(start, count).await,
+            false => Ok(vec![]),
+        }
+    }
+}
+
+#[tokio::test]
+async fn median_time_past_takes_one_batch_where_served() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    export(dir.path())?;
+    let source = OfflineSource::open(dir.path(), Network::Regtest)?;
+    for (batch, lookups) in [(true, 0), (false, 3 + 6)] {
+        let hooks = RecordingHooks::new(vec![script(1)]);
+        let counted = Arc::new(AtomicUsize::new(0));
+        let headers = Headers {
+            inner: source.clone(),
+            batch,
+            lookups: counted.clone(),
+        };
+        Niebla158::new(MemoryStore::new(), hooks.clone(), source.clone(), headers)
+            .run_to_tip()
+            .await?;
+        let mtp: Vec<_> = hooks.matches().iter().map(|m| m.median_time_past).collect();
+        assert_eq!(mtp, vec![Some(3), Some(4)]);
+        // The matches' own headers come with their blocks; parents are looked
+        // up one by one only without batches.
+        assert_eq!(counted.load(Ordering::Relaxed), lookups);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn missing_blocks_name_the_export() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let blocks = export(dir.path())?;
+    let missing = blocks[6].block_hash();
+    std::fs::remove_file(dir.path().join(block_path(missing).trim_start_matches('/')))?;
+
+    let source = OfflineSource::open(dir.path(), Network::Regtest)?;
+    let err = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(1)]),
+        source.clone(),
+        source,
+    )
+    .run_to_tip()
+    .await
+    .unwrap_err();
+    assert!(
+        format!("{err:#}").contains(&format!("offline: no block for {missing}")),
+        "{err:#}"
+    );
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use bitcoin::bip158::{BlockFilter, FilterHeader};
+use bitcoin::constants::genesis_block;
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, Network, ScriptBuf};
+use niebla_158::checkpoints::genesis_cfheader;
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+
+#[tokio::test]
+async fn segments_download_together_and_match_sequential_sync() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(3_500, |_| vec![script(9)])?;
+    let hooks = RecordingHooks::new(vec![]);
+
+    let sequential = MemoryStore::new();
+    Niebla158::new(
+        sequential.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .run_to_tip()
+    .await?;
+    let calls_before = filters.calls(MockCall::CfHeaders);
+
+    let parallel = MemoryStore::new();
+    Niebla158::new(parallel.clone(), hooks, filters.clone(), headers)
+        .with_parallel_cfheaders(4)
+        .run_to_tip()
+        .await?;
+
+    // Three checkpointed segments in one round, then the 500-height tail.
+    assert_eq!(filters.calls(MockCall::CfCheckpt), 1);
+    assert_eq!(filters.calls(MockCall::CfHeaders) - calls_before, 4);
+    assert_eq!(
+        parallel.load_cf_tip().await?,
+        sequential.load_cf_tip().await?
+    );
+    for h in [1, 1_000, 2_345, 3_000, 3_500] {
+        assert_eq!(
+            parallel.load_cf_header(h).await?,
+            sequential.load_cf_header(h).await?
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn segments_still_honour_configured_checkpoints() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(2_000, |_| vec![script(9)])?;
+    let store = MemoryStore::new();
+    let engine = Niebla158::new(store.clone(), RecordingHooks::new(vec![]), filters, headers)
+        .with_parallel_cfheaders(2)
+        .with_checkpoints(vec![(1_500, BlockHash::from_byte_array([9; 32]))]);
+
+    let err = engine.run_to_tip().await.unwrap_err();
+    assert!(format!("{err:#}").contains("checkpoint mismatch @1500"));
+    // The first segment was verified and kept.
+    assert_eq!(store.load_cf_tip().await?.map(|(h, _)| h), Some(1_000));
+    Ok(())
+}
+
+#[tokio::test]
+async fn segments_verify_against_bip157_headers() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(2_100, |h| vec![script(h as u8)])?;
+
+    // Rolling headers as BIP-157 defines them, from the blocks themselves.
+    let mut rolling = FilterHeader::all_zeros();
+    let mut expected = vec![];
+    for h in 0..=2_000 {
+        let block = match h {
+            0 => genesis_block(Network::Regtest),
+            _ => {
+                let prev = headers.hash_at_height(h - 1).await?;
+                block_paying(h, prev, &[script(h as u8)])
+            }
+        };
+        let filter = BlockFilter::new_script_filter(&block, |op| {
+            Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(*op))
+        })?;
+        rolling = filter.filter_header(&rolling);
+        expected.push(BlockHash::from_byte_array(rolling.to_byte_array()));
+    }
+    assert_eq!(expected[0], genesis_cfheader(Network::Regtest));
+
+    // The segments must land on the source's cfcheckpt and on these.
+    let store = MemoryStore::new();
+    Niebla158::new(
+        store.clone(),
+        RecordingHooks::new(vec![]),
+        filters.clone(),
+        headers,
+    )
+    .with_parallel_cfheaders(2)
+    .with_checkpoints(vec![(1_000, expected[1_000]), (2_000, expected[2_000])])
+    .run_to_tip()
+    .await?;
+    assert_eq!(filters.calls(MockCall::CfCheckpt), 1);
+    for h in [0, 1, 1_000, 2_000] {
+        assert_eq!(store.load_cf_header(h).await?, Some(expected[h as usize]));
+    }
+    Ok(())
+}
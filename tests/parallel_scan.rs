@@ -0,0 +1,70 @@
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+use std::time::Duration;
+
+/// A 30-block chain paying `script(1)` at 3, 12 and 25, plus two more sources
+/// serving the same filters (the workers' own connections).
+fn chain() -> anyhow::Result<(MockFilterSource, MockHeaderSource, [MockFilterSource; 2])> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let workers = [MockFilterSource::new(), MockFilterSource::new()];
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=30u32 {
+        let pays = match h {
+            3 | 12 | 25 => vec![script(1)],
+            _ => vec![script(100 + h as u8)],
+        };
+        let block = block_paying(h, prev, &pays);
+        for worker in &workers {
+            worker.add_block(h, &block)?;
+            worker.set_latency(Duration::from_millis(1));
+        }
+        prev = headers.push(filters.add_block(h, &block)?);
+    }
+    Ok((filters, headers, workers))
+}
+
+#[tokio::test]
+async fn workers_split_the_ranges_and_matches_arrive_in_order() -> anyhow::Result<()> {
+    let (filters, headers, workers) = chain()?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(store.clone(), hooks.clone(), filters.clone(), headers);
+    engine
+        .run_parallel(vec![21..=30, 1..=10, 11..=20], workers.to_vec())
+        .await?;
+
+    assert_eq!(hooks.matched_heights(), vec![3, 12, 25]);
+    assert_eq!(store.get_last_scanned().await?, 30);
+    assert_eq!(store.get_range_progress(1, 10).await?, None);
+    let calls: Vec<usize> = workers.iter().map(|w| w.calls(MockCall::Cfilter)).collect();
+    assert_eq!(calls.iter().sum::<usize>(), 30);
+    assert!(calls.iter().all(|&n| n > 0), "{calls:?}");
+    assert_eq!(filters.calls(MockCall::Cfilter), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn saved_range_progress_resumes_and_gaps_stay_unscanned() -> anyhow::Result<()> {
+    let (filters, headers, [worker, _]) = chain()?;
+    let store = MemoryStore::new();
+    // An earlier, interrupted call finished 1..=10 and half of 21..=30.
+    store.set_range_progress(1, 10, 10).await?;
+    store.set_range_progress(21, 30, 25).await?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(store.clone(), hooks.clone(), filters, headers);
+    engine
+        .run_parallel(vec![1..=10, 21..=30], vec![worker.clone()])
+        .await?;
+
+    assert_eq!(worker.calls(MockCall::Cfilter), 5);
+    assert_eq!(hooks.matched_heights(), Vec::<u32>::new());
+    // 11..=20 was not scanned, so progress only covers the first range.
+    assert_eq!(store.get_last_scanned().await?, 10);
+
+    let err = engine
+        .run_parallel(vec![1..=10, 5..=20], vec![worker])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("overlap"), "{err:#}");
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use bitcoin::bip158::BlockFilter;
+use bitcoin::consensus;
+use bitcoin::ScriptBuf;
+use niebla_158::filter_source::{CfHeadersBatch, GolombParams};
+use niebla_158::parse;
+use niebla_158::testing::*;
+
+#[test]
+fn cfheaders_batches_must_answer_the_request() {
+    let batch = |start_height, n| CfHeadersBatch {
+        start_height,
+        headers: vec![[0; 32]; n],
+    };
+    assert_eq!(
+        parse::cfheaders_batch(&batch(10, 5), 10, 14).ok(),
+        Some(false)
+    );
+    // Short batches are allowed (the source may be behind); long ones are not.
+    assert_eq!(
+        parse::cfheaders_batch(&batch(10, 2), 10, 14).ok(),
+        Some(true)
+    );
+    assert!(parse::cfheaders_batch(&batch(10, 6), 10, 14).is_err());
+    assert!(parse::cfheaders_batch(&batch(11, 4), 10, 14).is_err());
+}
+
+#[test]
+fn blocks_and_filters_decode_from_bytes() -> anyhow::Result<()> {
+    let block = block_paying(1, genesis_hash(), &[script(1), script(2)]);
+    let raw = consensus::serialize(&block);
+    assert_eq!(parse::block_header(&raw)?, block.header);
+    let mut txs = vec![];
+    parse::block_txs(&raw, &mut |tx| {
+        txs.push(tx);
+        true
+    })?;
+    assert_eq!(txs, block.txdata);
+    assert!(parse::block_txs(&raw[..raw.len() - 1], &mut |_| true).is_err());
+    assert!(parse::block_txs(&[raw.as_slice(), &[0]].concat(), &mut |_| true).is_err());
+
+    let filter = BlockFilter::new_script_filter(&block, |_| {
+        Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(Default::default()))
+    })?;
+    let mut values = vec![];
+    let range = parse::filter_elements(&filter.content, GolombParams::BASIC, |v| values.push(v))
+        .map_err(anyhow::Error::msg)?;
+    assert_eq!(values.len(), 2);
+    assert!(values.windows(2).all(|w| w[0] <= w[1]) && values[1] < range);
+    assert!(parse::filter_elements(&[0x05], GolombParams::BASIC, |_| ()).is_err());
+    Ok(())
+}
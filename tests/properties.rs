@@ -0,0 +1,148 @@
+//! Property tests: the rolling cfheaders chain and checkpoints over random
+//! header chains, and filter matching over random element sets.
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use niebla_158::cfheaders::{next_header, CfHeaderChain};
+use niebla_158::filter_source::{FilterType, GolombParams};
+use niebla_158::hooks::WatchItem;
+use niebla_158::matcher::{build_filter, filter_matches_any, validate_filter, QuerySet};
+use niebla_158::parse;
+use niebla_158::EngineError;
+use proptest::collection::{btree_set, vec};
+use proptest::prelude::*;
+
+fn filter_hashes() -> impl Strategy<Value = Vec<[u8; 32]>> {
+    vec(any::<[u8; 32]>(), 1..200)
+}
+
+/// Rolling headers of `hashes` after `prev`, one by one.
+fn rolled(hashes: &[[u8; 32]], mut prev: BlockHash) -> Vec<BlockHash> {
+    hashes
+        .iter()
+        .map(|h| {
+            prev = next_header(h, prev);
+            prev
+        })
+        .collect()
+}
+
+/// Golomb parameters with `M` no larger than `2^P` (as with standard
+/// filters), so quotients stay short.
+fn golomb_params() -> impl Strategy<Value = GolombParams> {
+    (1u8..=32).prop_flat_map(|p| (1u64..=(1 << p)).prop_map(move |m| GolombParams { p, m }))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn batches_roll_like_single_headers(
+        hashes in filter_hashes(),
+        start in 0u32..1_000_000,
+        tip in any::<[u8; 32]>(),
+        splits in vec(1usize..50, 0..10),
+    ) {
+        let tip = BlockHash::from_byte_array(tip);
+        let expected = rolled(&hashes, tip);
+
+        // However the headers are split into batches, the chain ends up in
+        // the same place and reports the same rolling headers.
+        let mut chain = CfHeaderChain::new(FilterType::BASIC, start, tip);
+        let mut applied = vec![];
+        let mut rest = &hashes[..];
+        for split in splits.into_iter().chain(std::iter::once(usize::MAX)) {
+            let (batch, next) = rest.split_at(split.min(rest.len()));
+            applied.extend(chain.apply_batch(chain.tip_height + 1, batch, &[]).unwrap());
+            rest = next;
+        }
+        prop_assert_eq!(&applied, &expected);
+        prop_assert_eq!(chain.tip_height, start + hashes.len() as u32);
+        prop_assert_eq!(chain.tip_hash, *expected.last().unwrap());
+    }
+
+    #[test]
+    fn batches_must_be_contiguous(hashes in filter_hashes(), gap in 2u32..100) {
+        let mut chain = CfHeaderChain::new(FilterType::BASIC, 10, BlockHash::all_zeros());
+        let before = chain.clone();
+        prop_assert!(chain.apply_batch(10 + gap, &hashes, &[]).is_err());
+        prop_assert!(chain.apply_batch(10, &hashes, &[]).is_err());
+        prop_assert_eq!(chain, before);
+    }
+
+    #[test]
+    fn checkpoints_are_enforced(
+        hashes in filter_hashes(),
+        at in any::<prop::sample::Index>(),
+        flip in any::<prop::sample::Index>(),
+    ) {
+        let expected = rolled(&hashes, BlockHash::all_zeros());
+        let i = at.index(hashes.len());
+        let height = i as u32;
+
+        // A fresh chain starts with the genesis filter, rolled over the all-zero header.
+        let mut chain = CfHeaderChain::new_from_store(FilterType::BASIC, None);
+        prop_assert!(chain.apply_batch(0, &hashes, &[(height, expected[i])]).is_ok());
+
+        // A wrong checkpoint anywhere in the batch rejects all of it.
+        let mut wrong = expected[i].to_byte_array();
+        wrong[flip.index(32)] ^= 1;
+        let wrong = BlockHash::from_byte_array(wrong);
+        let mut chain = CfHeaderChain::new_from_store(FilterType::BASIC, None);
+        let err = chain.apply_batch(0, &hashes, &[(height, wrong)]).unwrap_err();
+        match err.downcast_ref::<EngineError>() {
+            Some(EngineError::CheckpointMismatch(m)) => {
+                prop_assert_eq!(m.height, height);
+                prop_assert_eq!(m.expected, wrong);
+                prop_assert_eq!(m.computed, expected[i]);
+            }
+            other => prop_assert!(false, "unexpected error {:?}", other),
+        }
+        prop_assert!(chain.before_genesis());
+    }
+
+    #[test]
+    fn filters_match_every_element(
+        elements in btree_set(vec(any::<u8>(), 1..40), 1..100),
+        block in any::<[u8; 32]>(),
+        shard_size in prop::option::of(1usize..50),
+        indexed in any::<bool>(),
+    ) {
+        let block = BlockHash::from_byte_array(block);
+        let params = GolombParams::BASIC;
+        let filter = build_filter(block, elements.iter().map(Vec::as_slice), params);
+        prop_assert!(validate_filter(&filter, params).is_ok());
+
+        let mut values = vec![];
+        parse::filter_elements(&filter, params, |v| values.push(v)).unwrap();
+        prop_assert_eq!(values.len(), elements.len());
+
+        // No false negatives, whichever way the watchlist is matched.
+        for element in &elements {
+            let script = bitcoin::ScriptBuf::from_bytes(element.clone());
+            prop_assert!(filter_matches_any(&filter, block, std::slice::from_ref(&script), params)?);
+            let items = [WatchItem::new(script)];
+            let queries = QuerySet::new(&items, shard_size, indexed);
+            prop_assert!(queries.matches(0, block, &filter, params)?);
+        }
+        let items: Vec<WatchItem> = elements
+            .iter()
+            .map(|e| WatchItem::new(bitcoin::ScriptBuf::from_bytes(e.clone())))
+            .collect();
+        let queries = QuerySet::new(&items, shard_size, indexed);
+        prop_assert!(queries.matches(0, block, &filter, params)?);
+    }
+
+    #[test]
+    fn filters_with_other_params_round_trip(
+        elements in btree_set(vec(any::<u8>(), 1..20), 1..100),
+        params in golomb_params(),
+    ) {
+        let block = BlockHash::all_zeros();
+        let filter = build_filter(block, elements.iter().map(Vec::as_slice), params);
+        prop_assert!(validate_filter(&filter, params).is_ok());
+        for element in &elements {
+            let script = bitcoin::ScriptBuf::from_bytes(element.clone());
+            prop_assert!(filter_matches_any(&filter, block, &[script], params)?);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+#![cfg(feature = "serde")]
+
+use niebla_158::export::{self, ExportWallet};
+use niebla_158::headers::HeaderSource;
+use niebla_158::record::{MatchRecordV1, VERSION};
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, WatchItem};
+
+#[tokio::test]
+async fn match_details_round_trip_as_json() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let block = block_paying(1, prev, &[script(1)]);
+    headers.push(filters.add_block(1, &block)?);
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks, filters, headers.clone());
+    let details = engine
+        .check_block(headers.hash_at_height(1).await?)
+        .await?
+        .expect("block 1 pays script 1");
+
+    let record = MatchRecordV1::from_details(&details);
+    let json = record.to_json();
+    assert!(!json.contains('\n'));
+    let value: serde_json::Value = serde_json::from_str(&json)?;
+    assert_eq!(value["version"], VERSION);
+    assert_eq!(value["block"], details.block.to_string());
+    assert_eq!(value["match_id"], details.match_id.to_string());
+    assert_eq!(value["txs"][0]["received"], 1_000);
+    assert_eq!(value["items"][0]["script"], script(1).to_hex_string());
+    assert!(value.get("raw_block").is_none());
+
+    let parsed = MatchRecordV1::from_json(&json)?;
+    assert_eq!(parsed, record);
+    assert_eq!(parsed.transactions()?, block.txdata);
+
+    // Unknown fields are ignored; other versions are not.
+    let newer = json.replacen('{', r#"{"later":true,"#, 1);
+    assert_eq!(MatchRecordV1::from_json(&newer)?, record);
+    let v2 = json.replacen(r#""version":1"#, r#""version":2"#, 1);
+    assert!(MatchRecordV1::from_json(&v2).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn exports_can_be_json() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let block = block_paying(1, prev, &[script(1)]);
+    headers.push(filters.add_block(1, &block)?);
+    let dir = tempfile::tempdir()?;
+    let wallet = ExportWallet::new(
+        dir.path(),
+        vec![WatchItem::new(script(1)).with_tag("acct-7")],
+    )?
+    .with_blocks()
+    .with_json();
+    Niebla158::new(MemoryStore::new(), wallet, filters, headers)
+        .with_raw_blocks()
+        .run_to_tip()
+        .await?;
+
+    let exported = export::read_dir(dir.path())?;
+    assert_eq!(exported.len(), 1);
+    assert_eq!(exported[0].txs, block.txdata);
+    assert_eq!(exported[0].sequence, 1);
+    let name = format!("{:010}-{}.json", 1, block.block_hash());
+    let record = MatchRecordV1::from_json(&std::fs::read_to_string(dir.path().join(name))?)?;
+    assert_eq!(record.sequence, Some(1));
+    assert_eq!(record.items[0].tag.as_deref(), Some("acct-7"));
+    assert_eq!(
+        record.raw_block_bytes()?,
+        Some(bitcoin::consensus::serialize(&block))
+    );
+    Ok(())
+}
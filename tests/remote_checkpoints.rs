@@ -0,0 +1,113 @@
+#![cfg(feature = "remote-checkpoints")]
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, Network};
+use ed25519_compact::{KeyPair, Seed};
+use niebla_158::checkpoints::remote::{parse_key, verify_list, RemoteCheckpoints};
+use niebla_158::checkpoints::Checkpoints;
+use std::collections::HashMap;
+
+mod common;
+use common::http::serve;
+
+const KEY_ID: [u8; 8] = *b"niebla01";
+
+fn cp(b: u8) -> BlockHash {
+    BlockHash::from_byte_array([b; 32])
+}
+
+/// A minisign key pair: the public key's base64 line and the signing key.
+fn keys(seed: u8) -> (String, KeyPair) {
+    let kp = KeyPair::from_seed(Seed::new([seed; 32]));
+    let public = STANDARD.encode([&b"Ed"[..], &KEY_ID, &kp.pk[..]].concat());
+    (public, kp)
+}
+
+/// What `minisign -S` writes for `body`.
+fn sign(kp: &KeyPair, body: &[u8]) -> String {
+    let prehash = blake2b_simd::Params::new().hash_length(64).hash(body);
+    let signature = kp.sk.sign(prehash.as_bytes(), None);
+    let trusted = "timestamp:0\tfile:checkpoints.txt";
+    let global = kp
+        .sk
+        .sign([&signature[..], trusted.as_bytes()].concat(), None);
+    format!(
+        "untrusted comment: signature from minisign secret key\n{}\ntrusted comment: {trusted}\n{}\n",
+        STANDARD.encode([&b"ED"[..], &KEY_ID, &signature[..]].concat()),
+        STANDARD.encode(&global[..]),
+    )
+}
+
+#[test]
+fn lists_must_be_signed_by_a_trusted_key() -> anyhow::Result<()> {
+    let (public, kp) = keys(1);
+    let list = Checkpoints(vec![(1_000, cp(1)), (2_000, cp(2))]);
+    let body = list.to_text(Network::Signet).into_bytes();
+    let signature = sign(&kp, &body);
+    let trusted = [parse_key(&format!(
+        "untrusted comment: minisign public key\n{public}\n"
+    ))?];
+
+    assert_eq!(
+        verify_list(&body, &signature, &trusted, Network::Signet)?,
+        list
+    );
+    // Another key, a tampered body or another network are all refused.
+    let (other, _) = keys(2);
+    assert!(verify_list(&body, &signature, &[parse_key(&other)?], Network::Signet).is_err());
+    let mut tampered = body.clone();
+    *tampered.last_mut().unwrap() = b' ';
+    assert!(verify_list(&tampered, &signature, &trusted, Network::Signet).is_err());
+    assert!(verify_list(&body, &signature, &trusted, Network::Testnet).is_err());
+
+    // Signed lists are still validated.
+    let unsorted = Checkpoints(vec![(2_000, cp(2)), (1_000, cp(1))])
+        .to_text(Network::Signet)
+        .into_bytes();
+    let signature = sign(&kp, &unsorted);
+    assert!(verify_list(&unsorted, &signature, &trusted, Network::Signet).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn refreshes_fetch_the_list_and_its_signature() -> anyhow::Result<()> {
+    let (public, kp) = keys(1);
+    let old = Checkpoints(vec![(1_000, cp(1))]);
+    let new = Checkpoints(vec![(1_000, cp(1)), (2_000, cp(2))]);
+    let body = new.to_text(Network::Signet).into_bytes();
+    let signature = sign(&kp, &body).into_bytes();
+    let base = serve(HashMap::from([
+        ("/signet.txt".to_owned(), body.clone()),
+        ("/signet.txt.minisig".to_owned(), signature.clone()),
+        (
+            "/replayed.txt".to_owned(),
+            old.to_text(Network::Signet).into_bytes(),
+        ),
+        (
+            "/replayed.txt.minisig".to_owned(),
+            sign(&kp, &old.to_text(Network::Signet).into_bytes()).into_bytes(),
+        ),
+    ]))
+    .await?;
+
+    let remote = RemoteCheckpoints::new(format!("{base}/signet.txt"), Network::Signet);
+    // No maintainer keys ship: nothing can be verified, so nothing is fetched.
+    let err = remote.clone().fetch().await.unwrap_err();
+    assert!(
+        format!("{err:#}").contains("no trusted keys configured"),
+        "{err:#}"
+    );
+    let other = RemoteCheckpoints::new(format!("{base}/signet.txt"), Network::Signet)
+        .with_trusted_key(&keys(2).0)?;
+    assert!(other.fetch().await.is_err());
+    let remote = remote.with_trusted_key(&public)?.with_known(&old);
+    assert_eq!(remote.fetch().await?, new);
+
+    // A validly signed but older list must not drop checkpoints in use.
+    let replayed = RemoteCheckpoints::new(format!("{base}/replayed.txt"), Network::Signet)
+        .with_trusted_key(&public)?
+        .with_known(&new);
+    assert!(replayed.fetch().await.is_err());
+    Ok(())
+}
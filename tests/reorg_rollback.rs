@@ -0,0 +1,127 @@
+use niebla_158::events::EngineEvent;
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn reorg_while_offline_rolls_back_to_the_fork() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=10 {
+        let pays = if h == 9 { vec![script(1)] } else { vec![] };
+        let block = block_paying(h, prev, &pays);
+        prev = headers.push(filters.add_block(h, &block)?);
+    }
+
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .run_to_tip()
+    .await?;
+    assert_eq!(
+        store.get_last_scanned_block().await?,
+        Some((10, headers.hash_at_height(10).await?))
+    );
+
+    // Replace heights 9..=10 with a branch paying the wallet at 9' and 11'.
+    let mut prev = headers.hash_at_height(8).await?;
+    let mut branch = vec![];
+    for h in 9..=11 {
+        let pays = if h == 10 {
+            vec![script(8)]
+        } else {
+            vec![script(1), script(8)]
+        };
+        let block = block_paying(h, prev, &pays);
+        prev = filters.add_block(h, &block)?;
+        branch.push(prev);
+    }
+    headers.reorg(8, branch.clone());
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_events(move |e: &EngineEvent| sink.lock().unwrap().push(e.clone()))
+    .run_to_tip()
+    .await?;
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![EngineEvent::RolledBack {
+            stale_height: 10,
+            fork_height: 8
+        }]
+    );
+    assert_eq!(hooks.matched_heights(), vec![9, 9, 11]);
+    assert_eq!(hooks.matches()[1].block, branch[0]);
+    assert_eq!(store.get_last_scanned_block().await?, Some((11, branch[2])));
+
+    // cfheaders were rebuilt on the new branch.
+    let fresh = MemoryStore::new();
+    Niebla158::new(fresh.clone(), RecordingHooks::new(vec![]), filters, headers)
+        .run_to_tip()
+        .await?;
+    assert_eq!(store.load_cf_tip().await?, fresh.load_cf_tip().await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn reorg_above_the_scan_rewinds_cfheaders_verified_ahead() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(5, |_| vec![])?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = || {
+        Niebla158::new(
+            store.clone(),
+            hooks.clone(),
+            filters.clone(),
+            headers.clone(),
+        )
+    };
+    engine().run_to_tip().await?;
+
+    // cfheaders run ahead to 10 while the wallet stays scanned up to 5.
+    let mut prev = headers.hash_at_height(5).await?;
+    for h in 6..=10 {
+        prev = headers.push(filters.add_block(h, &block_paying(h, prev, &[]))?);
+    }
+    assert_eq!(engine().sync_cfheaders_only().await?, 10);
+    assert_eq!(store.get_last_scanned().await?, 5);
+
+    // Replace heights 8..=10 with a branch paying the wallet at 9'.
+    let mut prev = headers.hash_at_height(7).await?;
+    let mut branch = vec![];
+    for h in 8..=10 {
+        let pays = if h == 9 {
+            vec![script(1)]
+        } else {
+            vec![script(8)]
+        };
+        prev = filters.add_block(h, &block_paying(h, prev, &pays))?;
+        branch.push(prev);
+    }
+    headers.reorg(7, branch.clone());
+
+    engine().run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![9]);
+    assert_eq!(hooks.matches()[0].block, branch[1]);
+
+    // cfheaders were rebuilt on the new branch.
+    let fresh = MemoryStore::new();
+    Niebla158::new(fresh.clone(), RecordingHooks::new(vec![]), filters, headers)
+        .run_to_tip()
+        .await?;
+    assert_eq!(store.load_cf_tip().await?, fresh.load_cf_tip().await?);
+    Ok(())
+}
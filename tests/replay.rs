@@ -0,0 +1,65 @@
+use bitcoin::hashes::Hash;
+use niebla_158::filter_source::FilterSource;
+use niebla_158::headers::HeaderSource;
+use niebla_158::replay::{RecordingSource, ReplaySource};
+use niebla_158::testing::*;
+use niebla_158::Niebla158;
+
+#[tokio::test]
+async fn recorded_runs_replay_exactly() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(6, |h| if h == 4 { vec![script(1)] } else { vec![] })?;
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("session.log");
+
+    // The recorded run hits a flaky filter fetch once before succeeding.
+    filters.fail_next(MockCall::Cfilter, "connection reset");
+    let recorded = RecordingHooks::new(vec![script(1)]);
+    let store = MemoryStore::new();
+    let _ = Niebla158::new(
+        store.clone(),
+        recorded.clone(),
+        RecordingSource::new(filters.clone(), &log)?,
+        RecordingSource::new(headers.clone(), &log)?,
+    )
+    .run_to_tip()
+    .await;
+    Niebla158::new(
+        store,
+        recorded.clone(),
+        RecordingSource::new(filters.clone(), &log)?,
+        RecordingSource::new(headers.clone(), &log)?,
+    )
+    .run_to_tip()
+    .await?;
+    assert_eq!(recorded.matched_heights(), vec![4]);
+    let calls = filters.calls(MockCall::Cfilter);
+
+    // The replay fails and recovers the same way, without touching the sources.
+    let replay = ReplaySource::open(&log)?;
+    let replayed = RecordingHooks::new(vec![script(1)]);
+    let store = MemoryStore::new();
+    let first = Niebla158::new(
+        store.clone(),
+        replayed.clone(),
+        replay.clone(),
+        replay.clone(),
+    )
+    .run_to_tip()
+    .await;
+    assert!(
+        format!("{:#}", first.unwrap_err()).contains("connection reset"),
+        "the recorded failure is replayed"
+    );
+    Niebla158::new(store, replayed.clone(), replay.clone(), replay.clone())
+        .run_to_tip()
+        .await?;
+    assert_eq!(replayed.matched_heights(), vec![4]);
+    assert_eq!(filters.calls(MockCall::Cfilter), calls);
+
+    // Requests that were never recorded fail instead of guessing.
+    let unknown = bitcoin::BlockHash::from_byte_array([9; 32]);
+    let err = replay.get_cfilter(unknown).await.unwrap_err();
+    assert!(err.to_string().contains("no recorded response"), "{err:#}");
+    assert_eq!(replay.tip_height().await?, 6);
+    Ok(())
+}
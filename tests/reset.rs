@@ -0,0 +1,49 @@
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+
+fn chain(len: u32) -> anyhow::Result<(MockFilterSource, MockHeaderSource)> {
+    mock_chain(len, |h| if h == 4 { vec![script(1)] } else { vec![] })
+}
+
+#[tokio::test]
+async fn reset_starts_over_from_the_birth_height() -> anyhow::Result<()> {
+    let (filters, headers) = chain(6)?;
+    let store = MemoryStore::new();
+    store.set_birth_height(2).await?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(store.clone(), hooks.clone(), filters, headers);
+    engine.run_to_tip().await?;
+    assert_eq!(store.load_cf_tip().await?.map(|(h, _)| h), Some(6));
+
+    engine.reset().await?;
+    assert_eq!(store.load_cf_tip().await?, None);
+    assert_eq!(store.load_cf_header(3).await?, None);
+    assert_eq!(store.get_last_scanned().await?, 0);
+    assert_eq!(store.get_birth_height().await?, Some(2));
+    assert_eq!(engine.handle().status().scanned_height, 0);
+
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![4, 4]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn assume_fresh_ignores_existing_progress_once() -> anyhow::Result<()> {
+    let (filters, headers) = chain(6)?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .run_to_tip()
+    .await?;
+
+    let engine = Niebla158::new(store.clone(), hooks.clone(), filters, headers).with_assume_fresh();
+    engine.run_to_tip().await?;
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![4, 4]);
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use async_trait::async_trait;
+use bitcoin::BlockHash;
+use niebla_158::filter_source::{CfHeadersBatch, FilterSource, FilterType};
+use niebla_158::testing::*;
+use niebla_158::Niebla158;
+use std::sync::{Arc, Mutex};
+
+/// Records how many filters each range request asked for.
+#[derive(Clone)]
+struct Windows {
+    inner: MockFilterSource,
+    sizes: Arc<Mutex<Vec<usize>>>,
+}
+
+#[async_trait]
+impl FilterSource for Windows {
+    async fn get_cfheaders(&self, start_h: u32, stop: BlockHash) -> anyhow::Result<CfHeadersBatch> {
+        self.inner.get_cfheaders(start_h, stop).await
+    }
+
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.inner.get_cfilter(block).await
+    }
+
+    async fn get_cfilter_range(
+        &self,
+        filter_type: FilterType,
+        blocks: &[BlockHash],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        self.sizes.lock().unwrap().push(blocks.len());
+        let mut out = vec![];
+        for block in blocks {
+            out.push(self.inner.get_cfilter_typed(filter_type, *block).await?);
+        }
+        Ok(out)
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.inner.get_block(block).await
+    }
+}
+
+#[tokio::test]
+async fn filter_windows_grow_while_the_source_is_fast() -> anyhow::Result<()> {
+    let (inner, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=200 {
+        let pays = if h == 150 { script(1) } else { script(9) };
+        let block = block_paying(h, prev, &[pays]);
+        inner.add_block(h, &block)?;
+        prev = headers.push(block.block_hash());
+    }
+    let source = Windows {
+        inner: inner.clone(),
+        sizes: Arc::default(),
+    };
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), source.clone(), headers);
+    engine.run_to_tip().await?;
+
+    assert_eq!(*source.sizes.lock().unwrap(), vec![8, 16, 32, 64, 80]);
+    assert_eq!(inner.calls(MockCall::Cfilter), 200);
+    assert_eq!(hooks.matched_heights(), vec![150]);
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use async_trait::async_trait;
+use bitcoin::{Address, BlockHash, Network, ScriptBuf, Transaction};
+use niebla_158::testing::*;
+use niebla_158::{AddressHooks, AddressWallet, Niebla158, WalletHooks};
+use std::sync::{Arc, Mutex};
+
+fn address(b: u8) -> Address {
+    Address::p2wsh(&ScriptBuf::from_bytes(vec![b]), Network::Regtest)
+}
+
+#[derive(Clone, Default)]
+struct AddressBook {
+    watch: Arc<Mutex<Vec<Address>>>,
+    hits: Arc<Mutex<Vec<u32>>>,
+}
+
+#[async_trait]
+impl AddressHooks for AddressBook {
+    async fn addresses(&self) -> anyhow::Result<Vec<Address>> {
+        Ok(self.watch.lock().unwrap().clone())
+    }
+
+    async fn on_block_match(
+        &self,
+        height: u32,
+        _block: BlockHash,
+        _txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        self.hits.lock().unwrap().push(height);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn address_hooks_drive_the_engine() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=6 {
+        let pays = match h {
+            2 => address(0x51),
+            5 => address(0x52),
+            _ => address(0x60),
+        };
+        let block = block_paying(h, prev, &[pays.script_pubkey()]);
+        filters.add_block(h, &block)?;
+        prev = headers.push(block.block_hash());
+    }
+
+    let book = AddressBook::default();
+    *book.watch.lock().unwrap() = vec![address(0x51), address(0x52)];
+    let wallet = AddressWallet::new(book.clone());
+    assert_eq!(
+        wallet.watchlist().await?,
+        vec![address(0x51).script_pubkey(), address(0x52).script_pubkey()]
+    );
+
+    let engine = Niebla158::new(MemoryStore::new(), wallet, filters, headers);
+    engine.run_to_tip().await?;
+    assert_eq!(*book.hits.lock().unwrap(), vec![2, 5]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn address_wallet_follows_watchlist_changes() -> anyhow::Result<()> {
+    let book = AddressBook::default();
+    *book.watch.lock().unwrap() = vec![address(0x51)];
+    let wallet = AddressWallet::new(book.clone());
+    assert_eq!(
+        wallet.watchlist().await?,
+        vec![address(0x51).script_pubkey()]
+    );
+
+    *book.watch.lock().unwrap() = vec![address(0x52), address(0x51)];
+    assert_eq!(
+        wallet.watchlist().await?,
+        vec![address(0x52).script_pubkey(), address(0x51).script_pubkey()]
+    );
+    assert_eq!(wallet.inner().watch.lock().unwrap().len(), 2);
+    Ok(())
+}
@@ -6,46 +6,7 @@ use niebla_158::headers::HeaderSource;
 use niebla_158::prelude::*; // Niebla158, Store, WalletHooks, FilterSource
 use std::sync::{Arc, Mutex};
 
-/// Minimal in-memory Store for tests (keeps engine generic & fast).
-struct MemStore {
-    cf_tip: Mutex<Option<(u32, BlockHash)>>,
-    last_scanned: Mutex<u32>,
-    birth: Mutex<Option<u32>>,
-}
-impl MemStore {
-    fn new() -> Self {
-        Self {
-            cf_tip: Mutex::new(None),
-            last_scanned: Mutex::new(0),
-            birth: Mutex::new(None),
-        }
-    }
-}
-
-#[async_trait]
-impl Store for MemStore {
-    async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
-        Ok(*self.cf_tip.lock().unwrap())
-    }
-    async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
-        *self.cf_tip.lock().unwrap() = Some((height, cfheader));
-        Ok(())
-    }
-    async fn get_last_scanned(&self) -> anyhow::Result<u32> {
-        Ok(*self.last_scanned.lock().unwrap())
-    }
-    async fn set_last_scanned(&self, height: u32) -> anyhow::Result<()> {
-        *self.last_scanned.lock().unwrap() = height;
-        Ok(())
-    }
-    async fn get_birth_height(&self) -> anyhow::Result<Option<u32>> {
-        Ok(*self.birth.lock().unwrap())
-    }
-    async fn set_birth_height(&self, h: u32) -> anyhow::Result<()> {
-        *self.birth.lock().unwrap() = Some(h);
-        Ok(())
-    }
-}
+use crate::common::store::MemStore;
 
 /// Wallet hooks: a tiny watchlist and a hit recorder.
 struct TestHooks {
@@ -91,9 +52,11 @@ impl FilterSource for NoHitSource {
         start_h: u32,
         _stop: BlockHash,
     ) -> anyhow::Result<CfHeadersBatch> {
+        // `NoHeaders` is genesis alone.
+        let count = usize::from(start_h == 0);
         Ok(CfHeadersBatch {
             start_height: start_h,
-            headers: vec![],
+            headers: vec![[0u8; 32]; count],
         })
     }
     async fn get_cfilter(&self, _block: BlockHash) -> anyhow::Result<Vec<u8>> {
@@ -106,7 +69,7 @@ impl FilterSource for NoHitSource {
 
 #[tokio::test]
 async fn engine_compiles_and_runs_with_no_hits() -> anyhow::Result<()> {
-    let store = MemStore::new();
+    let store = MemStore::default();
 
     // One fake script in the watchlist
     let script = bitcoin::ScriptBuf::new(); // empty script (fine for smoke)
@@ -0,0 +1,116 @@
+use async_trait::async_trait;
+use bitcoin::{BlockHash, ScriptBuf, Transaction};
+use niebla_158::events::EngineEvent;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, SharedWatchlist, Store, WalletHooks, WatchItem};
+use std::sync::{Arc, Mutex};
+
+/// Hooks over a [`SharedWatchlist`], recording matched heights.
+#[derive(Clone, Default)]
+struct Shared {
+    items: SharedWatchlist,
+    hits: Arc<Mutex<Vec<u32>>>,
+}
+
+#[async_trait]
+impl WalletHooks for Shared {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(self.items.items().into_iter().map(|i| i.script).collect())
+    }
+
+    async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+        Ok(self.items.items())
+    }
+
+    async fn on_block_match(
+        &self,
+        height: u32,
+        _block: BlockHash,
+        _txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        self.hits.lock().unwrap().push(height);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn added_scripts_rescan_from_their_birth_height() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(10, |h| match h {
+        3 => vec![script(2)],
+        6 => vec![script(3)],
+        8 => vec![script(1)],
+        _ => vec![script(100 + h as u8)],
+    })?;
+
+    let store = MemoryStore::new();
+    store.set_birth_height(2).await?;
+    let hooks = Shared::default();
+    hooks.items.add(WatchItem::new(script(1)));
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    let engine = Niebla158::new(store.clone(), hooks.clone(), filters, headers)
+        .with_auto_rescan()
+        .with_events(move |e: &EngineEvent| sink.lock().unwrap().push(e.clone()));
+    engine.run_to_tip().await?;
+    assert_eq!(*hooks.hits.lock().unwrap(), vec![8]);
+
+    // A script born at 5 rescans from there; one born above the tip does not.
+    hooks
+        .items
+        .add(WatchItem::new(script(3)).with_birth_height(5));
+    hooks
+        .items
+        .add(WatchItem::new(script(4)).with_birth_height(11));
+    engine.run_to_tip().await?;
+    assert_eq!(*hooks.hits.lock().unwrap(), vec![8, 6, 8]);
+
+    // Without one, the store's birth height applies.
+    hooks.items.add(WatchItem::new(script(2)));
+    engine.run_to_tip().await?;
+    assert_eq!(*hooks.hits.lock().unwrap(), vec![8, 6, 8, 3, 6, 8]);
+
+    // Unchanged: nothing to do.
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.hits.lock().unwrap().len(), 6);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            EngineEvent::WatchlistChanged {
+                added: 2,
+                removed: 0,
+                rescan_from: Some(5),
+            },
+            EngineEvent::WatchlistChanged {
+                added: 1,
+                removed: 0,
+                rescan_from: Some(2),
+            },
+        ]
+    );
+    assert_eq!(store.watched_scripts().await?.len(), 4);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scripts_are_not_matched_below_their_birth_height() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=8u32 {
+        // Payments to script 1 before its birth at 5 can only be false positives.
+        let pays = match h {
+            2 | 6 => vec![script(1)],
+            _ => vec![script(100 + h as u8)],
+        };
+        prev = headers.push(filters.add_block(h, &block_paying(h, prev, &pays))?);
+    }
+
+    let hooks = Shared::default();
+    hooks
+        .items
+        .add(WatchItem::new(script(1)).with_birth_height(5));
+    Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .run_to_tip()
+        .await?;
+    assert_eq!(*hooks.hits.lock().unwrap(), vec![6]);
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use bitcoin::{Block, BlockHash, ScriptBuf, Transaction};
+use niebla_158::testing::*;
+use niebla_158::{BlockSource, Niebla158};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn blocks_come_from_the_configured_block_source() -> anyhow::Result<()> {
+    // Same chain served twice: `mirror` for filters, `node` for blocks.
+    let (mirror, node, headers) = (
+        MockFilterSource::new(),
+        MockFilterSource::new(),
+        MockHeaderSource::new(),
+    );
+    let mut prev: BlockHash = headers.push(genesis_hash());
+    for h in 1..=4 {
+        let pays = if h == 2 {
+            vec![script(1)]
+        } else {
+            vec![script(2)]
+        };
+        let block = block_paying(h, prev, &pays);
+        mirror.add_block(h, &block)?;
+        node.add_block(h, &block)?;
+        prev = headers.push(block.block_hash());
+    }
+
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), mirror.clone(), headers)
+        .with_block_source(node.clone());
+    engine.run_to_tip().await?;
+
+    assert_eq!(hooks.matched_heights(), vec![2]);
+    assert_eq!(mirror.calls(MockCall::Block), 0);
+    assert_eq!(mirror.calls(MockCall::Cfilter), 4);
+    assert_eq!(node.calls(MockCall::Block), 1);
+    assert_eq!(node.calls(MockCall::Cfilter), 0);
+    Ok(())
+}
+
+/// Esplora-style backend holding one block: answers with relevant txs when `supported`.
+struct RelevantOnly {
+    block: Block,
+    supported: bool,
+    queries: Arc<AtomicUsize>,
+    full_blocks: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl BlockSource for RelevantOnly {
+    async fn get_block(&self, _block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.full_blocks.fetch_add(1, Ordering::SeqCst);
+        Ok(bitcoin::consensus::serialize(&self.block))
+    }
+
+    async fn get_relevant_txs(
+        &self,
+        _block: BlockHash,
+        scripts: &[ScriptBuf],
+    ) -> anyhow::Result<Option<Vec<Transaction>>> {
+        self.queries.fetch_add(1, Ordering::SeqCst);
+        if !self.supported {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.block
+                .txdata
+                .iter()
+                .filter(|tx| tx.output.iter().any(|o| scripts.contains(&o.script_pubkey)))
+                .cloned()
+                .collect(),
+        ))
+    }
+}
+
+/// Returns `(hooks, relevant-tx queries, full block downloads)`.
+async fn run_with(supported: bool) -> anyhow::Result<(RecordingHooks, usize, usize)> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let block = block_paying(1, prev, &[script(1)]);
+    filters.add_block(1, &block)?;
+    headers.push(block.block_hash());
+
+    let (queries, full_blocks) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters.clone(), headers)
+        .with_block_source(RelevantOnly {
+            block,
+            supported,
+            queries: queries.clone(),
+            full_blocks: full_blocks.clone(),
+        });
+    engine.run_to_tip().await?;
+    assert_eq!(filters.calls(MockCall::Block), 0);
+    Ok((
+        hooks,
+        queries.load(Ordering::SeqCst),
+        full_blocks.load(Ordering::SeqCst),
+    ))
+}
+
+#[tokio::test]
+async fn relevant_txs_skip_the_full_block_download() -> anyhow::Result<()> {
+    let (hooks, queries, full_blocks) = run_with(true).await?;
+    assert_eq!((queries, full_blocks), (1, 0));
+    assert_eq!(hooks.matched_heights(), vec![1]);
+    assert_eq!(hooks.matches()[0].txs.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn unsupported_relevant_txs_fall_back_to_blocks() -> anyhow::Result<()> {
+    let (hooks, queries, full_blocks) = run_with(false).await?;
+    assert_eq!((queries, full_blocks), (1, 1));
+    assert_eq!(hooks.matched_heights(), vec![1]);
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use async_trait::async_trait;
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, ScriptBuf};
+use niebla_158::filter_source::{CfHeadersBatch, FilterSource, FilterType};
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+
+/// Only heights `low..` of a mock chain, with no hash index of its own.
+struct Partial<'a>(&'a MockHeaderSource, u32);
+
+#[async_trait]
+impl HeaderSource for Partial<'_> {
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        self.0.tip_height().await
+    }
+
+    async fn hash_at_height(&self, height: u32) -> anyhow::Result<BlockHash> {
+        anyhow::ensure!(height >= self.1, "below the first header");
+        self.0.hash_at_height(height).await
+    }
+
+    async fn has_height(&self, height: u32) -> anyhow::Result<bool> {
+        Ok(height >= self.1 && height <= self.tip_height().await?)
+    }
+}
+
+#[tokio::test]
+async fn a_single_block_is_checked_on_demand() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(5, |h| if h == 3 { vec![script(1)] } else { vec![] })?;
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    );
+
+    let paid = headers.hash_at_height(3).await?;
+    let details = engine
+        .check_block(paid)
+        .await?
+        .expect("block 3 pays script 1");
+    assert_eq!((details.height, details.block), (3, paid));
+    assert_eq!(details.items[0].script, script(1));
+    assert!(engine
+        .check_block(headers.hash_at_height(4).await?)
+        .await?
+        .is_none());
+
+    // Nothing was scanned or delivered.
+    assert!(hooks.matched_heights().is_empty());
+    assert_eq!(store.get_last_scanned().await?, 0);
+
+    let err = engine
+        .check_block(BlockHash::from_byte_array([7; 32]))
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("not on the header source's chain"),
+        "{err:#}"
+    );
+
+    // The default lookup walks back from the tip, down to the source's first header.
+    let partial = Partial(&headers, 2);
+    assert_eq!(partial.height_of(paid).await?, Some(3));
+    assert_eq!(partial.height_of(genesis_hash()).await?, None);
+    Ok(())
+}
+
+/// Leaves the last filter out of every range it serves.
+struct Short(MockFilterSource);
+
+#[async_trait]
+impl FilterSource for Short {
+    async fn get_cfheaders(&self, start_h: u32, stop: BlockHash) -> anyhow::Result<CfHeadersBatch> {
+        self.0.get_cfheaders(start_h, stop).await
+    }
+
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.0.get_cfilter(block).await
+    }
+
+    async fn get_cfilter_range(
+        &self,
+        filter_type: FilterType,
+        blocks: &[BlockHash],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut filters = self.0.get_cfilter_range(filter_type, blocks).await?;
+        filters.pop();
+        Ok(filters)
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.0.get_block(block).await
+    }
+}
+
+#[tokio::test]
+async fn transactions_are_found_by_their_scripts() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    let mut wanted = None;
+    for h in 1..=6u32 {
+        let pays = if h == 2 || h == 4 {
+            vec![script(1)]
+        } else {
+            vec![]
+        };
+        let block = block_paying(h, prev, &pays);
+        if h == 4 {
+            wanted = Some(block.txdata[0].clone());
+        }
+        prev = headers.push(filters.add_block(h, &block)?);
+    }
+    let wanted = wanted.unwrap();
+    // The wallet watches nothing: the lookup does not need it to.
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![]),
+        filters.clone(),
+        headers.clone(),
+    );
+
+    let found = engine
+        .find_transaction(wanted.compute_txid(), &[script(1)], 1..=100)
+        .await?;
+    assert_eq!(
+        found,
+        Some((4, headers.hash_at_height(4).await?, wanted.clone()))
+    );
+    // Block 2 pays the same script, so it was downloaded too.
+    assert_eq!(filters.calls(MockCall::Block), 2);
+
+    let txid = wanted.compute_txid();
+    assert_eq!(
+        engine.find_transaction(txid, &[script(1)], 1..=3).await?,
+        None
+    );
+    assert_eq!(
+        engine.find_transaction(txid, &[script(2)], 1..=6).await?,
+        None
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn deposits_are_found_for_many_scripts() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(6, |h| match h {
+        2 => vec![script(1), script(9), script(2)],
+        5 => vec![script(3)],
+        _ => vec![script(9)],
+    })?;
+    let hooks = RecordingHooks::new(vec![]);
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    );
+
+    // One address per customer; script 9 belongs to somebody else.
+    let customers: Vec<ScriptBuf> = (1..=200).filter(|&b| b != 9).map(script).collect();
+    let deposits = engine.scan_deposits(&customers, 2..=100).await?;
+    let found: Vec<_> = deposits
+        .iter()
+        .map(|d| {
+            (
+                d.height,
+                d.outpoint.vout,
+                d.txout.script_pubkey.clone(),
+                d.confirmations,
+            )
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (2, 0, script(1), 5),
+            (2, 2, script(2), 5),
+            (5, 0, script(3), 2),
+        ]
+    );
+    assert_eq!(deposits[0].block, headers.hash_at_height(2).await?);
+    assert!(deposits
+        .iter()
+        .all(|d| d.coinbase && d.txout.value.to_sat() == 1_000));
+    // Only the two paying blocks were downloaded, and nothing was delivered.
+    assert_eq!(filters.calls(MockCall::Block), 2);
+    assert!(hooks.matched_heights().is_empty());
+
+    assert!(engine.scan_deposits(&customers, 3..=4).await?.is_empty());
+    assert!(engine.scan_deposits(&[], 1..=6).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn short_filter_ranges_fail_lookups_rather_than_miss_blocks() -> anyhow::Result<()> {
+    // Only the top block pays: it is the one a short answer drops.
+    let (filters, headers) = mock_chain(4, |h| if h == 4 { vec![script(1)] } else { vec![] })?;
+    let paid = filters.get_block(headers.hash_at_height(4).await?).await?;
+    let paid: bitcoin::Block = bitcoin::consensus::deserialize(&paid)?;
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![]),
+        Short(filters),
+        headers,
+    );
+
+    let err = engine
+        .find_transaction(paid.txdata[0].compute_txid(), &[script(1)], 1..=4)
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{err:#}").contains("get_cfilter_range(1..=4) returned 3 filters, expected 4"),
+        "{err:#}"
+    );
+    let err = engine.scan_deposits(&[script(1)], 1..=4).await.unwrap_err();
+    assert!(format!("{err:#}").contains("returned 3 filters"), "{err:#}");
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use async_trait::async_trait;
+use bitcoin::BlockHash;
+use niebla_158::hooks::Delivery;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+use std::sync::Mutex;
+
+/// Store that "crashes" (fails) once when asked to persist progress at `crash_at`.
+struct CrashingStore {
+    inner: MemoryStore,
+    crash_at: Mutex<Option<u32>>,
+}
+
+#[async_trait]
+impl Store for CrashingStore {
+    async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
+        self.inner.load_cf_tip().await
+    }
+    async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
+        self.inner.save_cf_tip(height, cfheader).await
+    }
+    async fn get_last_scanned(&self) -> anyhow::Result<u32> {
+        self.inner.get_last_scanned().await
+    }
+    async fn set_last_scanned(&self, height: u32) -> anyhow::Result<()> {
+        let crash = self.crash_at.lock().unwrap().take_if(|h| *h == height);
+        if crash.is_some() {
+            anyhow::bail!("simulated crash before persisting {height}");
+        }
+        self.inner.set_last_scanned(height).await
+    }
+    async fn get_delivered(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+        self.inner.get_delivered(height).await
+    }
+    async fn set_delivered(&self, height: u32, block: BlockHash) -> anyhow::Result<()> {
+        self.inner.set_delivered(height, block).await
+    }
+}
+
+/// Run twice with a crash right after the match at height 2 was delivered.
+async fn deliveries_after_crash(delivery: Delivery) -> anyhow::Result<Vec<u32>> {
+    let (filters, headers) = mock_chain(3, |h| vec![script(if h == 2 { 1 } else { 2 })])?;
+
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let store = CrashingStore {
+        inner: MemoryStore::new(),
+        crash_at: Mutex::new(Some(2)),
+    };
+    let engine = Niebla158::new(store, hooks.clone(), filters, headers).with_delivery(delivery);
+
+    assert!(engine.run_to_tip().await.is_err());
+    engine.run_to_tip().await?;
+    Ok(hooks.matched_heights())
+}
+
+#[tokio::test]
+async fn at_least_once_redelivers_after_a_crash() -> anyhow::Result<()> {
+    assert_eq!(
+        deliveries_after_crash(Delivery::AtLeastOnce).await?,
+        vec![2, 2]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn exactly_once_skips_recorded_deliveries() -> anyhow::Result<()> {
+    assert_eq!(
+        deliveries_after_crash(Delivery::ExactlyOnce).await?,
+        vec![2]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn redeliveries_keep_their_match_id_and_get_a_new_sequence() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(3, |h| vec![script(if h == 2 { 1 } else { 2 })])?;
+    let (store, hooks) = (MemoryStore::new(), RecordingHooks::new(vec![script(1)]));
+    let engine = Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    );
+    engine.run_to_tip().await?;
+
+    // A restarted engine rescanning over the same store.
+    let engine = Niebla158::new(store, hooks.clone(), filters, headers);
+    engine.handle().rescan_from(1);
+    engine.run_to_tip().await?;
+
+    let matches = hooks.matches();
+    assert_eq!(hooks.matched_heights(), vec![2, 2]);
+    assert!(matches[0].match_id.is_some());
+    assert_eq!(matches[0].match_id, matches[1].match_id);
+    assert_eq!((matches[0].sequence, matches[1].sequence), (1, 2));
+    Ok(())
+}
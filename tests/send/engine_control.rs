@@ -0,0 +1,341 @@
+use async_trait::async_trait;
+use bitcoin::hashes::Hash as _;
+use bitcoin::{BlockHash, ScriptBuf, Transaction, Txid};
+use niebla_158::events::EngineEvent;
+use niebla_158::filter_source::CfHeadersBatch;
+use niebla_158::headers::HeaderSource;
+use niebla_158::policy::MaxBlocksPerSession;
+use niebla_158::prelude::*;
+use niebla_158::session::ScanSession;
+use niebla_158::testing::{
+    mock_chain, script, MemoryStore, MockFilterSource, MockHeaderSource, RecordingHooks,
+};
+use niebla_158::{EnginePhase, MatchDetails, RunState, WatchItem};
+use std::sync::{Arc, Mutex};
+
+use crate::common::store::MemStore;
+
+/// ------- Wallet with a fixed watchlist and no expected hits -------
+struct Watching(Vec<ScriptBuf>);
+#[async_trait]
+impl WalletHooks for Watching {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(self.0.clone())
+    }
+    async fn on_block_match(
+        &self,
+        _height: u32,
+        _block: BlockHash,
+        _txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// ------- Chain of `tip` blocks with synthetic hashes -------
+struct Chain {
+    tip: u32,
+}
+#[async_trait]
+impl HeaderSource for Chain {
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        Ok(self.tip)
+    }
+    async fn hash_at_height(&self, h: u32) -> anyhow::Result<BlockHash> {
+        let mut b = [0u8; 32];
+        b[..4].copy_from_slice(&h.to_le_bytes());
+        Ok(BlockHash::from_byte_array(b))
+    }
+}
+#[async_trait]
+impl FilterSource for Chain {
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        _stop: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        Ok(CfHeadersBatch {
+            start_height: start_h,
+            headers: vec![[0u8; 32]; (self.tip + 1 - start_h) as usize],
+        })
+    }
+    async fn get_cfilter(&self, _block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        Ok(vec![0x00]) // N = 0 elements → never matches
+    }
+    async fn get_block(&self, _block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        anyhow::bail!("no blocks")
+    }
+}
+
+#[tokio::test]
+async fn pause_parks_engine_until_resumed() -> anyhow::Result<()> {
+    let engine = Arc::new(Niebla158::new(
+        MemStore::default(),
+        Watching(vec![]),
+        Chain { tip: 3 },
+        Chain { tip: 3 },
+    ));
+    let handle = engine.handle();
+    assert_eq!(handle.status().state, RunState::Idle);
+
+    // Pause before the run starts: the engine parks at its first safe point.
+    handle.pause();
+    let runner = {
+        let engine = engine.clone();
+        tokio::spawn(async move { engine.run_to_tip().await })
+    };
+    while handle.status().state != RunState::Paused {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(handle.status().cf_tip_height, 0, "no progress while paused");
+    assert_eq!(handle.status().chain_tip_height, 3);
+    assert_eq!(handle.phase(), EnginePhase::VerifyingHeaders);
+
+    handle.resume();
+    runner.await??;
+
+    let status = handle.status();
+    assert_eq!(status.state, RunState::Idle);
+    assert_eq!(status.phase, EnginePhase::Idle);
+    assert_eq!(status.cf_tip_height, 3);
+    assert_eq!(status.scanned_height, 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn policy_can_cap_blocks_per_session() -> anyhow::Result<()> {
+    let engine = Niebla158::new(
+        MemStore::default(),
+        Watching(vec![ScriptBuf::new()]),
+        Chain { tip: 10 },
+        Chain { tip: 10 },
+    )
+    .with_policy(MaxBlocksPerSession(4));
+    let handle = engine.handle();
+
+    engine.run_to_tip().await?;
+    assert_eq!(handle.status().scanned_height, 4);
+
+    // Next session picks up where the previous one stopped.
+    engine.run_to_tip().await?;
+    assert_eq!(handle.status().scanned_height, 8);
+    Ok(())
+}
+
+#[tokio::test]
+async fn phases_are_reported_as_events() -> anyhow::Result<()> {
+    let paid = ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([1; 20]));
+    let (filters, headers) = mock_chain(3, |h| if h == 2 { vec![paid.clone()] } else { vec![] })?;
+    let phases = Arc::new(Mutex::new(vec![]));
+    let sink = phases.clone();
+    let engine = Niebla158::new(MemStore::default(), Watching(vec![paid]), filters, headers)
+        .with_phase_events()
+        .with_events(move |e: &EngineEvent| {
+            if let EngineEvent::PhaseChanged(phase) = e {
+                sink.lock().unwrap().push(*phase);
+            }
+        });
+
+    engine.run_to_tip().await?;
+    assert_eq!(
+        *phases.lock().unwrap(),
+        vec![
+            EnginePhase::VerifyingHeaders,
+            EnginePhase::Scanning { height: 1 },
+            EnginePhase::Scanning { height: 2 },
+            EnginePhase::FetchingBlock { height: 2 },
+            EnginePhase::Delivering { height: 2 },
+            EnginePhase::Scanning { height: 3 },
+            EnginePhase::Idle,
+        ]
+    );
+    assert_eq!(engine.handle().phase(), EnginePhase::Idle);
+    Ok(())
+}
+
+type Phases = Arc<Mutex<Vec<EnginePhase>>>;
+type Engine<W> = Niebla158<MemoryStore, W, MockFilterSource, MockHeaderSource>;
+
+/// Engine over three blocks paying `script(1)` at height 2, with its phase
+/// events in `Phases`.
+fn phased_engine<W: WalletHooks + 'static>(
+    hooks: W,
+) -> anyhow::Result<(Engine<W>, MockHeaderSource, Phases)> {
+    let (filters, headers) = mock_chain(3, |h| vec![script(if h == 2 { 1 } else { 9 })])?;
+    let phases = Phases::default();
+    let sink = phases.clone();
+    let engine = Niebla158::new(MemoryStore::new(), hooks, filters, headers.clone())
+        .with_phase_events()
+        .with_events(move |e: &EngineEvent| {
+            if let EngineEvent::PhaseChanged(phase) = e {
+                sink.lock().unwrap().push(*phase);
+            }
+        });
+    Ok((engine, headers, phases))
+}
+
+/// Phases reported since the last call, which must have ended back at `Idle`.
+fn taken<W: WalletHooks + 'static>(engine: &Engine<W>, phases: &Phases) -> Vec<EnginePhase> {
+    assert_eq!(engine.handle().phase(), EnginePhase::Idle);
+    std::mem::take(&mut *phases.lock().unwrap())
+}
+
+/// Wallet whose one script is a priority item.
+struct Priority;
+#[async_trait]
+impl WalletHooks for Priority {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        unreachable!("the engine asks for watch_items")
+    }
+    async fn on_block_match(
+        &self,
+        _height: u32,
+        _block: BlockHash,
+        _txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        unreachable!("the engine calls on_match")
+    }
+    async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+        Ok(vec![WatchItem::new(script(1)).with_priority()])
+    }
+    async fn on_match(&self, _details: MatchDetails) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn quick_check_reports_its_phases() -> anyhow::Result<()> {
+    let (engine, _, phases) = phased_engine(Priority)?;
+    assert_eq!(engine.quick_check(1..=3).await?.len(), 1);
+    assert_eq!(
+        taken(&engine, &phases),
+        vec![
+            EnginePhase::Scanning { height: 1 },
+            EnginePhase::Scanning { height: 2 },
+            EnginePhase::FetchingBlock { height: 2 },
+            EnginePhase::Scanning { height: 3 },
+            EnginePhase::Idle,
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn check_block_reports_its_phases() -> anyhow::Result<()> {
+    let (engine, headers, phases) = phased_engine(RecordingHooks::new(vec![script(1)]))?;
+    let block = headers.hash_at_height(2).await?;
+    assert!(engine.check_block(block).await?.is_some());
+    assert_eq!(
+        taken(&engine, &phases),
+        vec![
+            EnginePhase::Scanning { height: 2 },
+            EnginePhase::FetchingBlock { height: 2 },
+            EnginePhase::Idle,
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn adhoc_searches_report_their_phases() -> anyhow::Result<()> {
+    let (engine, _, phases) = phased_engine(RecordingHooks::new(vec![]))?;
+    let searched = vec![
+        EnginePhase::Scanning { height: 1 },
+        EnginePhase::FetchingBlock { height: 2 },
+        EnginePhase::Idle,
+    ];
+
+    let found = engine
+        .find_transaction(Txid::all_zeros(), &[script(1)], 1..=3)
+        .await?;
+    assert!(found.is_none());
+    assert_eq!(taken(&engine, &phases), searched);
+
+    assert_eq!(engine.scan_deposits(&[script(1)], 1..=3).await?.len(), 1);
+    assert_eq!(taken(&engine, &phases), searched);
+    Ok(())
+}
+
+#[tokio::test]
+async fn audit_reports_its_phases() -> anyhow::Result<()> {
+    let (engine, _, phases) = phased_engine(RecordingHooks::new(vec![script(1)]))?;
+    engine.run_to_tip().await?;
+    taken(&engine, &phases);
+
+    assert!(engine.audit(0..=3).await?.discrepancies.is_empty());
+    assert_eq!(
+        taken(&engine, &phases),
+        vec![EnginePhase::VerifyingHeaders, EnginePhase::Idle]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn deliver_pending_reports_its_phases_and_ends_idle_on_errors() -> anyhow::Result<()> {
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let (engine, _, phases) = phased_engine(hooks.clone())?;
+    let engine = engine.with_delivery_queue();
+    engine.run_to_tip().await?;
+    taken(&engine, &phases);
+    let delivering = vec![
+        EnginePhase::FetchingBlock { height: 2 },
+        EnginePhase::Delivering { height: 2 },
+        EnginePhase::Idle,
+    ];
+
+    hooks.fail_next("wallet is busy");
+    assert!(engine.deliver_pending().await.is_err());
+    assert_eq!(taken(&engine, &phases), delivering);
+
+    assert_eq!(engine.deliver_pending().await?, 1);
+    assert_eq!(taken(&engine, &phases), delivering);
+    Ok(())
+}
+
+#[tokio::test]
+async fn run_session_reports_its_phases() -> anyhow::Result<()> {
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let (engine, _, phases) = phased_engine(hooks.clone())?;
+    engine.sync_cfheaders_only().await?;
+    engine.add_session(&ScanSession::new("s", 1..=3)).await?;
+    taken(&engine, &phases);
+
+    engine.run_session("s").await?;
+    assert_eq!(hooks.matched_heights(), vec![2]);
+    assert_eq!(
+        taken(&engine, &phases),
+        vec![
+            EnginePhase::Scanning { height: 1 },
+            EnginePhase::Scanning { height: 2 },
+            EnginePhase::FetchingBlock { height: 2 },
+            EnginePhase::Delivering { height: 2 },
+            EnginePhase::Scanning { height: 3 },
+            EnginePhase::Idle,
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn calls_beside_a_run_leave_it_its_phase() -> anyhow::Result<()> {
+    let (engine, headers, _) = phased_engine(RecordingHooks::new(vec![script(1)]))?;
+    let engine = Arc::new(engine);
+    let handle = engine.handle();
+
+    // The run parks verifying headers while a block is checked beside it.
+    handle.pause();
+    let runner = {
+        let engine = engine.clone();
+        tokio::spawn(async move { engine.run_to_tip().await })
+    };
+    while handle.status().state != RunState::Paused {
+        tokio::task::yield_now().await;
+    }
+    engine.check_block(headers.hash_at_height(2).await?).await?;
+    assert_eq!(handle.phase(), EnginePhase::FetchingBlock { height: 2 });
+
+    handle.resume();
+    runner.await??;
+    assert_eq!(handle.phase(), EnginePhase::Idle);
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use async_trait::async_trait;
+use bitcoin::{consensus, Block, BlockHash};
+use niebla_158::filter_source::{CfHeadersBatch, FilterSource, FilterType, GolombParams};
+use niebla_158::matcher::build_filter;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+
+const TAPROOT: FilterType = FilterType(0x01);
+
+/// Serves basic filters from one mock and `TAPROOT` filters from another.
+#[derive(Clone)]
+struct TwoTypes {
+    basic: MockFilterSource,
+    taproot: MockFilterSource,
+}
+
+impl TwoTypes {
+    fn pick(&self, filter_type: FilterType) -> anyhow::Result<&MockFilterSource> {
+        match filter_type {
+            FilterType::BASIC => Ok(&self.basic),
+            TAPROOT => Ok(&self.taproot),
+            other => anyhow::bail!("no {other} filters"),
+        }
+    }
+}
+
+#[async_trait]
+impl FilterSource for TwoTypes {
+    async fn get_cfheaders(&self, start_h: u32, stop: BlockHash) -> anyhow::Result<CfHeadersBatch> {
+        self.basic.get_cfheaders(start_h, stop).await
+    }
+
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.basic.get_cfilter(block).await
+    }
+
+    async fn get_cfheaders_typed(
+        &self,
+        filter_type: FilterType,
+        start_h: u32,
+        stop: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        self.pick(filter_type)?.get_cfheaders(start_h, stop).await
+    }
+
+    async fn get_cfilter_typed(
+        &self,
+        filter_type: FilterType,
+        block: BlockHash,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.pick(filter_type)?.get_cfilter(block).await
+    }
+
+    async fn get_block(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.basic.get_block(block).await
+    }
+}
+
+const PRIVATE: GolombParams = GolombParams { p: 10, m: 1_024 };
+
+/// Output-script filter of `block` coded with `params`.
+fn private_filter(block: &Block, params: GolombParams) -> Vec<u8> {
+    let scripts = block.txdata.iter().flat_map(|tx| &tx.output);
+    build_filter(
+        block.block_hash(),
+        scripts.map(|o| o.script_pubkey.as_bytes()),
+        params,
+    )
+}
+
+/// Ten blocks paying the wallet at height 4. The taproot filters are empty
+/// unless `private` is set, in which case they are coded with those parameters.
+fn chain_with(private: Option<GolombParams>) -> anyhow::Result<(TwoTypes, MockHeaderSource)> {
+    let source = TwoTypes {
+        basic: MockFilterSource::new(),
+        taproot: MockFilterSource::new(),
+    };
+    let headers = MockHeaderSource::new();
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=10 {
+        let pays = if h == 4 { script(1) } else { script(9) };
+        let block = block_paying(h, prev, &[pays]);
+        let hash = source.basic.add_block(h, &block)?;
+        let filter = private.map_or(vec![0x00], |params| private_filter(&block, params));
+        source
+            .taproot
+            .add_raw(h, hash, prev, filter, consensus::serialize(&block));
+        prev = headers.push(hash);
+    }
+    Ok((source, headers))
+}
+
+fn chain() -> anyhow::Result<(TwoTypes, MockHeaderSource)> {
+    chain_with(None)
+}
+
+#[tokio::test]
+async fn filter_types_keep_separate_cfheader_chains() -> anyhow::Result<()> {
+    let (source, headers) = chain()?;
+    let store = MemoryStore::new();
+
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        source.clone(),
+        headers.clone(),
+    )
+    .with_filter_type(TAPROOT);
+    engine.run_to_tip().await?;
+    // Matching ran against the (empty) taproot filters only.
+    assert!(hooks.matches().is_empty());
+    assert_eq!(source.basic.calls(MockCall::Cfilter), 0);
+    let (tip_h, taproot_tip) = store.load_cf_tip_typed(TAPROOT).await?.expect("tip");
+    assert_eq!(tip_h, 10);
+    assert_eq!(store.load_cf_tip().await?, None);
+
+    // A basic engine on the same store verifies its own chain from genesis.
+    let basic_store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        source.clone(),
+        headers.clone(),
+    )
+    .with_wallet(basic_store, hooks.clone());
+    engine.run_to_tip().await?;
+    assert_eq!(source.basic.calls(MockCall::CfHeaders), 1);
+    assert_eq!(hooks.matched_heights(), vec![4]);
+    let (tip_h, basic_tip) = store.load_cf_tip().await?.expect("tip");
+    assert_eq!(tip_h, 10);
+    assert_ne!(basic_tip, taproot_tip);
+    assert_eq!(
+        store.load_cf_tip_typed(TAPROOT).await?,
+        Some((10, taproot_tip))
+    );
+    assert_eq!(
+        store.load_cf_header_typed(TAPROOT, 10).await?,
+        Some(taproot_tip)
+    );
+    assert_eq!(store.load_cf_header(10).await?, Some(basic_tip));
+    Ok(())
+}
+
+#[tokio::test]
+async fn sources_reject_filter_types_they_do_not_serve() -> anyhow::Result<()> {
+    let (source, headers) = chain()?;
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(1)]),
+        source.basic,
+        headers,
+    )
+    .with_filter_type(TAPROOT);
+    let err = engine.run_to_tip().await.unwrap_err();
+    assert!(format!("{err:#}").contains("does not serve 0x01 filters"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn custom_golomb_parameters_decode_private_filters() -> anyhow::Result<()> {
+    let (source, headers) = chain_with(Some(PRIVATE))?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), source, headers)
+        .with_filter_type(TAPROOT)
+        .with_golomb_params(PRIVATE);
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![4]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn golomb_parameters_are_checked_against_the_filter_type() -> anyhow::Result<()> {
+    let (source, headers) = chain()?;
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(1)]),
+        source.clone(),
+        headers.clone(),
+    )
+    .with_golomb_params(PRIVATE);
+    let err = engine.run_to_tip().await.unwrap_err();
+    assert!(err.to_string().contains("basic filters use P=19 M=784931"));
+    assert_eq!(source.basic.calls(MockCall::CfHeaders), 0);
+
+    assert!(GolombParams { p: 0, m: 1 }.validate_for(TAPROOT).is_err());
+    assert!(GolombParams { p: 20, m: 0 }.validate_for(TAPROOT).is_err());
+    assert!(PRIVATE.validate_for(TAPROOT).is_ok());
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use async_trait::async_trait;
+use bitcoin::hashes::Hash as _;
+use bitcoin::BlockHash;
+use niebla_158::filter_source::{CfHeadersBatch, FilterSource};
+use niebla_158::grpc::{GrpcService, GrpcSource};
+use niebla_158::headers::HeaderSource;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+
+/// Backend with two blocks whose hashes are `[h; 32]`.
+struct Fixed;
+#[async_trait]
+impl FilterSource for Fixed {
+    async fn get_cfheaders(
+        &self,
+        start_h: u32,
+        _stop: BlockHash,
+    ) -> anyhow::Result<CfHeadersBatch> {
+        Ok(CfHeadersBatch {
+            start_height: start_h,
+            headers: vec![[9u8; 32]; 2],
+        })
+    }
+    async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        Ok(block.to_byte_array()[..1].to_vec())
+    }
+    async fn get_block(&self, _block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        anyhow::bail!("pruned")
+    }
+}
+#[async_trait]
+impl HeaderSource for Fixed {
+    async fn tip_height(&self) -> anyhow::Result<u32> {
+        Ok(2)
+    }
+    async fn hash_at_height(&self, h: u32) -> anyhow::Result<BlockHash> {
+        Ok(BlockHash::from_byte_array([h as u8; 32]))
+    }
+}
+
+#[tokio::test]
+async fn grpc_roundtrip_through_service_adapter() -> anyhow::Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(GrpcService::new(Fixed).into_server())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let src = GrpcSource::connect(format!("http://{addr}")).await?;
+    assert_eq!(src.tip_height().await?, 2);
+    let h2 = src.hash_at_height(2).await?;
+    assert_eq!(h2, BlockHash::from_byte_array([2u8; 32]));
+
+    let batch = src.get_cfheaders(1, h2).await?;
+    assert_eq!(batch.start_height, 1);
+    assert_eq!(batch.headers, vec![[9u8; 32]; 2]);
+
+    assert_eq!(src.get_cfilter(h2).await?, vec![2u8]);
+    assert!(src.get_cfcheckpt(h2).await?.is_empty());
+    assert!(src.get_block(h2).await.is_err());
+    Ok(())
+}
@@ -13,47 +13,13 @@ use bitcoin::{
 use niebla_158::filter_source::CfHeadersBatch;
 use niebla_158::headers::HeaderSource;
 use niebla_158::prelude::*;
-use std::sync::{Arc, Mutex};
+use niebla_158::EngineError;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
 
-/// ------- Minimal in-memory Store -------
-struct MemStore {
-    cf_tip: Mutex<Option<(u32, BlockHash)>>,
-    last_scanned: Mutex<u32>,
-    birth: Mutex<Option<u32>>,
-}
-impl MemStore {
-    fn new() -> Self {
-        Self {
-            cf_tip: Mutex::new(None),
-            last_scanned: Mutex::new(0),
-            birth: Mutex::new(None),
-        }
-    }
-}
-#[async_trait]
-impl Store for MemStore {
-    async fn load_cf_tip(&self) -> anyhow::Result<Option<(u32, BlockHash)>> {
-        Ok(*self.cf_tip.lock().unwrap())
-    }
-    async fn save_cf_tip(&self, height: u32, cfheader: BlockHash) -> anyhow::Result<()> {
-        *self.cf_tip.lock().unwrap() = Some((height, cfheader));
-        Ok(())
-    }
-    async fn get_last_scanned(&self) -> anyhow::Result<u32> {
-        Ok(*self.last_scanned.lock().unwrap())
-    }
-    async fn set_last_scanned(&self, height: u32) -> anyhow::Result<()> {
-        *self.last_scanned.lock().unwrap() = height;
-        Ok(())
-    }
-    async fn get_birth_height(&self) -> anyhow::Result<Option<u32>> {
-        Ok(*self.birth.lock().unwrap())
-    }
-    async fn set_birth_height(&self, h: u32) -> anyhow::Result<()> {
-        *self.birth.lock().unwrap() = Some(h);
-        Ok(())
-    }
-}
+use crate::common::store::MemStore;
 
 /// ------- Wallet hooks: watchlist + hit recorder -------
 struct TestHooks {
@@ -76,9 +42,10 @@ impl WalletHooks for TestHooks {
     }
 }
 
-/// ------- Header source that knows about exactly one block at height 1 -------
+/// ------- Header source that knows about one block at height 1, and maybe an all-zero genesis -------
 struct OneHeader {
     bh: BlockHash,
+    genesis: bool,
 }
 #[async_trait]
 impl HeaderSource for OneHeader {
@@ -86,12 +53,15 @@ impl HeaderSource for OneHeader {
         Ok(1)
     }
     async fn hash_at_height(&self, h: u32) -> anyhow::Result<BlockHash> {
-        if h == 1 {
-            Ok(self.bh)
-        } else {
-            anyhow::bail!("out of range");
+        match h {
+            0 if self.genesis => Ok(BlockHash::all_zeros()),
+            1 => Ok(self.bh),
+            _ => anyhow::bail!("out of range"),
         }
     }
+    async fn has_height(&self, h: u32) -> anyhow::Result<bool> {
+        Ok(h == 1 || (h == 0 && self.genesis))
+    }
 }
 
 /// ------- Filter source that advances cfheaders and returns a matching filter -------
@@ -99,21 +69,20 @@ struct OneHitSource {
     block_bytes: Vec<u8>,
     block_hash: BlockHash,
     filter_bytes: Vec<u8>,
+    filter_calls: Arc<AtomicUsize>,
 }
 #[async_trait]
 impl FilterSource for OneHitSource {
-    async fn get_cfheaders(
-        &self,
-        start_h: u32,
-        _stop: BlockHash,
-    ) -> anyhow::Result<CfHeadersBatch> {
-        // Advance by one header so cf-tip becomes height 1.
+    async fn get_cfheaders(&self, start_h: u32, stop: BlockHash) -> anyhow::Result<CfHeadersBatch> {
+        // One header per height up to the stop block, so cf-tip can reach height 1.
+        let stop_h = u32::from(stop == self.block_hash);
         Ok(CfHeadersBatch {
             start_height: start_h,
-            headers: vec![[0u8; 32]],
+            headers: vec![[0u8; 32]; (stop_h + 1).saturating_sub(start_h) as usize],
         })
     }
     async fn get_cfilter(&self, block: BlockHash) -> anyhow::Result<Vec<u8>> {
+        self.filter_calls.fetch_add(1, Ordering::SeqCst);
         if block == self.block_hash {
             Ok(self.filter_bytes.clone())
         } else {
@@ -189,17 +158,21 @@ async fn engine_hits_when_filter_contains_watch_script() -> anyhow::Result<()> {
 
     let filter_bytes = bf.content.clone();
 
-    let store = MemStore::new();
+    let store = MemStore::default();
     let hits: Arc<Mutex<Vec<(u32, BlockHash, usize)>>> = Arc::new(Mutex::new(Vec::new()));
     let hooks = TestHooks {
         watch: vec![watch_script.clone()],
         hits: hits.clone(),
     };
-    let headers = OneHeader { bh: block_hash };
+    let headers = OneHeader {
+        bh: block_hash,
+        genesis: true,
+    };
     let source = OneHitSource {
         block_bytes,
         block_hash,
         filter_bytes,
+        filter_calls: Arc::default(),
     };
 
     let engine = Niebla158::new(store, hooks, source, headers);
@@ -216,3 +189,94 @@ async fn engine_hits_when_filter_contains_watch_script() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn wallets_share_one_filter_download() -> anyhow::Result<()> {
+    let watched = ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([7u8; 20]));
+    let unrelated = ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([9u8; 20]));
+
+    let block = make_block_with_output(&watched);
+    let block_hash = block.block_hash();
+    let filter_bytes =
+        BlockFilter::new_script_filter(
+            &block,
+            |_op: &OutPoint| Ok::<_, BfError>(ScriptBuf::new()),
+        )?
+        .content;
+
+    let filter_calls = Arc::new(AtomicUsize::new(0));
+    let source = OneHitSource {
+        block_bytes: consensus::encode::serialize(&block),
+        block_hash,
+        filter_bytes,
+        filter_calls: filter_calls.clone(),
+    };
+
+    let primary_hits = Arc::new(Mutex::new(Vec::new()));
+    let other_hits = Arc::new(Mutex::new(Vec::new()));
+    let engine = Niebla158::new(
+        MemStore::default(),
+        TestHooks {
+            watch: vec![unrelated],
+            hits: primary_hits.clone(),
+        },
+        source,
+        OneHeader {
+            bh: block_hash,
+            genesis: true,
+        },
+    )
+    .with_wallet(
+        MemStore::default(),
+        TestHooks {
+            watch: vec![watched],
+            hits: other_hits.clone(),
+        },
+    );
+
+    engine.run_to_tip().await?;
+
+    assert_eq!(
+        filter_calls.load(Ordering::SeqCst),
+        1,
+        "filter fetched once"
+    );
+    assert!(primary_hits.lock().unwrap().is_empty());
+    assert_eq!(other_hits.lock().unwrap().len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn heights_the_header_source_lacks_are_a_typed_error() -> anyhow::Result<()> {
+    let block = make_block_with_output(&ScriptBuf::new());
+    let source = OneHitSource {
+        block_bytes: consensus::encode::serialize(&block),
+        block_hash: block.block_hash(),
+        filter_bytes: vec![],
+        filter_calls: Arc::default(),
+    };
+    let hooks = TestHooks {
+        watch: vec![],
+        hits: Arc::default(),
+    };
+    // The network check needs genesis, which `OneHeader` starts above.
+    let err = Niebla158::new(
+        MemStore::default(),
+        hooks,
+        source,
+        OneHeader {
+            bh: block.block_hash(),
+            genesis: false,
+        },
+    )
+    .with_network(bitcoin::Network::Regtest)
+    .run_to_tip()
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<EngineError>(),
+        Some(&EngineError::HeightOutOfRange { height: 0 }),
+        "{err:#}"
+    );
+    Ok(())
+}
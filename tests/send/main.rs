@@ -0,0 +1,32 @@
+//! Tests whose `FilterSource`, `Store`, `WalletHooks` and `HeaderSource`
+//! implementations use `Send` futures (`#[async_trait]`), so they only build
+//! without the `local` feature; tests/local_runtime.rs covers `local` builds.
+//!
+//! Run one file with e.g. `cargo test --test send engine_control::`.
+#![cfg(not(feature = "local"))]
+
+#[path = "../common/mod.rs"]
+mod common;
+
+mod adaptive_batching;
+mod address_hooks;
+mod api_smoke;
+mod auto_rescan;
+mod block_source;
+mod check_block;
+mod delivery;
+mod engine_control;
+mod filter_types;
+#[cfg(feature = "grpc")]
+mod grpc_source;
+mod hit_flow;
+mod opaque_blocks;
+mod raw_elements;
+#[cfg(feature = "test-regtest")]
+mod regtest;
+#[cfg(feature = "rpc")]
+mod rpc;
+mod tx_summaries;
+mod watch_tags;
+mod watchtower;
+mod zero_conf;
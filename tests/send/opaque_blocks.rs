@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use bitcoin::bip158::BlockFilter;
+use bitcoin::{BlockHash, ScriptBuf, Transaction};
+use niebla_158::testing::*;
+use niebla_158::{MatchDetails, Niebla158, WalletHooks};
+use std::sync::{Arc, Mutex};
+
+/// Records every delivery as is.
+#[derive(Clone, Default)]
+struct Raw(Arc<Mutex<Vec<MatchDetails>>>);
+
+#[async_trait]
+impl WalletHooks for Raw {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(vec![script(1)])
+    }
+
+    async fn on_block_match(
+        &self,
+        _height: u32,
+        _block: BlockHash,
+        _txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        unreachable!("on_match is overridden")
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        self.0.lock().unwrap().push(details);
+        Ok(())
+    }
+}
+
+/// A chain whose block at height 1 pays `script(1)` per its filter, but is
+/// served in a format the engine cannot parse (as an Elements block would be).
+fn foreign_chain() -> anyhow::Result<(MockFilterSource, MockHeaderSource, BlockHash)> {
+    let stand_in = block_paying(1, genesis_hash(), &[script(1)]);
+    let filter = BlockFilter::new_script_filter(&stand_in, |_| {
+        Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(Default::default()))
+    })?;
+    let hash = stand_in.block_hash();
+    let filters = MockFilterSource::new();
+    filters.add_raw(
+        1,
+        hash,
+        genesis_hash(),
+        filter.content,
+        b"elements block".to_vec(),
+    );
+    let headers = MockHeaderSource::from_hashes(vec![genesis_hash(), hash]);
+    Ok((filters, headers, hash))
+}
+
+#[tokio::test]
+async fn opaque_blocks_are_delivered_undecoded() -> anyhow::Result<()> {
+    let (filters, headers, hash) = foreign_chain()?;
+    let hooks = Raw::default();
+    Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .with_opaque_blocks()
+        .run_to_tip()
+        .await?;
+
+    let delivered = hooks.0.lock().unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!((delivered[0].height, delivered[0].block), (1, hash));
+    assert_eq!(
+        delivered[0].raw_block.as_deref(),
+        Some(&b"elements block"[..])
+    );
+    assert!(delivered[0].txs.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn foreign_blocks_fail_without_opaque_mode() -> anyhow::Result<()> {
+    let (filters, headers, _) = foreign_chain()?;
+    let err = Niebla158::new(
+        MemoryStore::new(),
+        Raw::default(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .run_to_tip()
+    .await
+    .unwrap_err();
+    assert!(format!("{err:#}").contains("deserialize"), "{err:#}");
+
+    // Opaque blocks have no outputs to track.
+    let err = Niebla158::new(MemoryStore::new(), Raw::default(), filters, headers)
+        .with_opaque_blocks()
+        .with_utxo_tracking()
+        .run_to_tip()
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("UTXO tracking"), "{err:#}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn raw_blocks_come_with_their_transactions() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let block = block_paying(1, headers.push(genesis_hash()), &[script(1)]);
+    headers.push(filters.add_block(1, &block)?);
+    let hooks = Raw::default();
+    Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .with_raw_blocks()
+        .run_to_tip()
+        .await?;
+
+    let delivered = hooks.0.lock().unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(
+        delivered[0].raw_block,
+        Some(bitcoin::consensus::serialize(&block))
+    );
+    assert_eq!(delivered[0].txs, block.txdata);
+    assert_eq!(delivered[0].items.len(), 1);
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use async_trait::async_trait;
+use bitcoin::bip158::BlockFilter;
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    absolute::LockTime, transaction, Amount, BlockHash, OutPoint, ScriptBuf, Sequence, Transaction,
+    TxIn, TxOut, Txid, WPubkeyHash, Witness,
+};
+use niebla_158::testing::*;
+use niebla_158::{MatchDetails, Niebla158, WalletHooks, WatchItem};
+use std::sync::{Arc, Mutex};
+
+/// A watchtower's view: the witness script of a channel's to_local output.
+const TO_LOCAL: &[u8] = b"\x63\x21to_local revocation script\x67\x68\xac";
+
+#[derive(Clone, Default)]
+struct Tower {
+    seen: Arc<Mutex<Vec<MatchDetails>>>,
+}
+
+#[async_trait]
+impl WalletHooks for Tower {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        unreachable!("the engine asks for watch_items")
+    }
+
+    async fn on_block_match(
+        &self,
+        _: u32,
+        _: BlockHash,
+        _: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        unreachable!("the engine calls on_match")
+    }
+
+    async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+        Ok(vec![
+            WatchItem::element(TO_LOCAL).with_tag("channel 1"),
+            WatchItem::element(&b"another channel"[..]).with_tag("channel 2"),
+        ])
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        self.seen.lock().unwrap().push(details);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn raw_elements_match_filters_and_witnesses() -> anyhow::Result<()> {
+    // Block 1 sweeps a to_local output; its filter commits to the spent script as is.
+    let mut block = block_paying(1, genesis_hash(), &[]);
+    block.txdata.push(Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([3; 32]), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence(144),
+            witness: Witness::from_slice(&[&b"sig"[..], TO_LOCAL]),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(9_000),
+            script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([9; 20])),
+        }],
+    });
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    let filter =
+        BlockFilter::new_script_filter(&block, |_| Ok(ScriptBuf::from(TO_LOCAL.to_vec())))?;
+
+    let filters = MockFilterSource::new();
+    let headers = MockHeaderSource::new();
+    let prev = headers.push(genesis_hash());
+    filters.add_raw(
+        1,
+        block.block_hash(),
+        prev,
+        filter.content,
+        bitcoin::consensus::serialize(&block),
+    );
+    headers.push(block.block_hash());
+    let mut prev = block.block_hash();
+    for h in 2..=4 {
+        prev = headers.push(filters.add_block(h, &block_paying(h, prev, &[]))?);
+    }
+
+    let tower = Tower::default();
+    Niebla158::new(MemoryStore::new(), tower.clone(), filters, headers)
+        .run_to_tip()
+        .await?;
+
+    let seen = tower.seen.lock().unwrap();
+    assert_eq!(seen.len(), 1);
+    assert_eq!((seen[0].height, seen[0].txs.len()), (1, 2));
+    let tags: Vec<_> = seen[0].items.iter().map(|i| i.tag.as_deref()).collect();
+    assert_eq!(tags, vec![Some("channel 1")]);
+    // No watched amounts move, yet the sweep is what the tower is after.
+    let sweep = block.txdata[1].compute_txid();
+    assert_eq!(seen[0].relevant_txids(), [sweep].into());
+    Ok(())
+}
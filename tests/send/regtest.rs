@@ -0,0 +1,250 @@
+//! End-to-end tests against a real `bitcoind -regtest`.
+//!
+//! Run with `cargo test --features test-regtest --test send regtest`. The node
+//! binary is taken from `$BITCOIND` or `bitcoind` on `PATH`; when neither exists
+//! the tests print a notice and pass, so `--all-features` builds stay usable.
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use bitcoin::{Address, BlockHash, Network, ScriptBuf, Transaction};
+use niebla_158::http::CoreRestSource;
+use niebla_158::prelude::*;
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const RPC_USER: &str = "niebla";
+const RPC_PASS: &str = "niebla";
+
+/// A throwaway regtest node with REST and the basic block filter index enabled.
+struct Bitcoind {
+    child: Child,
+    rpc_url: String,
+    client: reqwest::Client,
+    _datadir: tempfile::TempDir,
+}
+
+impl Bitcoind {
+    /// Spawn the node, or `None` if no `bitcoind` binary is available.
+    async fn spawn() -> anyhow::Result<Option<Self>> {
+        let exe = std::env::var_os("BITCOIND")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| "bitcoind".into());
+        let datadir = tempfile::tempdir()?;
+        let rpc_port = free_port()?;
+        let p2p_port = free_port()?;
+        let spawned = Command::new(&exe)
+            .arg("-regtest")
+            .arg(format!("-datadir={}", datadir.path().display()))
+            .arg(format!("-rpcport={rpc_port}"))
+            .arg(format!("-port={p2p_port}"))
+            .arg(format!("-rpcuser={RPC_USER}"))
+            .arg(format!("-rpcpassword={RPC_PASS}"))
+            .args(["-server", "-rest", "-blockfilterindex=1", "-listen=0"])
+            .args(["-disablewallet", "-printtoconsole=0"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let child = match spawned {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("spawn {}", exe.display())),
+        };
+        let node = Self {
+            child,
+            rpc_url: format!("http://127.0.0.1:{rpc_port}"),
+            client: reqwest::Client::new(),
+            _datadir: datadir,
+        };
+        node.wait_ready().await?;
+        Ok(Some(node))
+    }
+
+    fn rest(&self) -> CoreRestSource {
+        CoreRestSource::new(&self.rpc_url)
+    }
+
+    async fn rpc(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let body = json!({"jsonrpc": "1.0", "id": "niebla", "method": method, "params": params});
+        let bytes = self
+            .client
+            .post(&self.rpc_url)
+            .basic_auth(RPC_USER, Some(RPC_PASS))
+            .body(body.to_string())
+            .send()
+            .await?
+            .bytes()
+            .await?;
+        let resp: Value = serde_json::from_slice(&bytes)?;
+        if !resp["error"].is_null() {
+            bail!("{method}: {}", resp["error"]);
+        }
+        Ok(resp["result"].clone())
+    }
+
+    async fn wait_ready(&self) -> anyhow::Result<()> {
+        for _ in 0..100 {
+            if self.rpc("getblockchaininfo", json!([])).await.is_ok() {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        bail!("bitcoind did not come up")
+    }
+
+    /// Mine `n` blocks paying the coinbase to `addr`, then wait for the filter index.
+    async fn mine_to(&self, n: u32, addr: &Address) -> anyhow::Result<Vec<BlockHash>> {
+        let hashes = self
+            .rpc("generatetoaddress", json!([n, addr.to_string()]))
+            .await?;
+        self.wait_for_filter_index().await?;
+        hashes
+            .as_array()
+            .context("generatetoaddress: expected array")?
+            .iter()
+            .map(|h| Ok(BlockHash::from_str(h.as_str().context("hash")?)?))
+            .collect()
+    }
+
+    async fn invalidate(&self, block: BlockHash) -> anyhow::Result<()> {
+        self.rpc("invalidateblock", json!([block.to_string()]))
+            .await?;
+        Ok(())
+    }
+
+    async fn wait_for_filter_index(&self) -> anyhow::Result<()> {
+        let tip = self.rpc("getblockcount", json!([])).await?;
+        for _ in 0..100 {
+            let info = self
+                .rpc("getindexinfo", json!(["basic block filter index"]))
+                .await?;
+            let idx = &info["basic block filter index"];
+            if idx["synced"] == json!(true) && idx["best_block_height"] == tip {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        bail!("block filter index did not catch up to {tip}")
+    }
+}
+
+impl Drop for Bitcoind {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn free_port() -> anyhow::Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+/// Regtest address whose script nobody else uses: P2WSH of `OP_TRUE`.
+fn watched_address() -> Address {
+    Address::p2wsh(&ScriptBuf::from_bytes(vec![0x51]), Network::Regtest)
+}
+
+/// Regtest address for filler blocks: P2WSH of `OP_2`.
+fn other_address() -> Address {
+    Address::p2wsh(&ScriptBuf::from_bytes(vec![0x52]), Network::Regtest)
+}
+
+type Hits = Arc<Mutex<Vec<(u32, BlockHash)>>>;
+
+struct Recorder {
+    watch: Vec<ScriptBuf>,
+    hits: Hits,
+}
+
+#[async_trait]
+impl WalletHooks for Recorder {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(self.watch.clone())
+    }
+
+    async fn on_block_match(
+        &self,
+        height: u32,
+        block: BlockHash,
+        txs: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        assert!(txs
+            .iter()
+            .flat_map(|tx| &tx.output)
+            .any(|o| self.watch.contains(&o.script_pubkey)));
+        self.hits.lock().unwrap().push((height, block));
+        Ok(())
+    }
+}
+
+type Engine = Niebla158<SqliteStore, Recorder, CoreRestSource, CoreRestSource>;
+
+fn new_engine(node: &Bitcoind, db: &std::path::Path) -> anyhow::Result<(Engine, Hits)> {
+    let hits = Arc::new(Mutex::new(Vec::new()));
+    let hooks = Recorder {
+        watch: vec![watched_address().script_pubkey()],
+        hits: hits.clone(),
+    };
+    let engine = Niebla158::new(SqliteStore::new(db)?, hooks, node.rest(), node.rest());
+    Ok((engine, hits))
+}
+
+macro_rules! node_or_skip {
+    () => {
+        match Bitcoind::spawn().await? {
+            Some(node) => node,
+            None => {
+                eprintln!("bitcoind not found (set $BITCOIND); skipping regtest test");
+                return Ok(());
+            }
+        }
+    };
+}
+
+#[tokio::test]
+async fn regtest_core_rest_finds_coinbase_payments() -> anyhow::Result<()> {
+    let node = node_or_skip!();
+    node.mine_to(101, &other_address()).await?;
+    let paid = node.mine_to(1, &watched_address()).await?;
+    node.mine_to(5, &other_address()).await?;
+
+    let dir = tempfile::tempdir()?;
+    let (engine, hits) = new_engine(&node, &dir.path().join("regtest.sqlite"))?;
+    engine.run_to_tip().await?;
+
+    assert_eq!(*hits.lock().unwrap(), vec![(102, paid[0])]);
+    let status = engine.handle().status();
+    assert_eq!(status.scanned_height, 107);
+    assert_eq!(status.cf_tip_height, 107);
+    Ok(())
+}
+
+#[tokio::test]
+async fn regtest_resumes_across_a_reorg() -> anyhow::Result<()> {
+    let node = node_or_skip!();
+    node.mine_to(101, &other_address()).await?;
+    let old = node.mine_to(1, &watched_address()).await?;
+
+    let dir = tempfile::tempdir()?;
+    let db = dir.path().join("reorg.sqlite");
+    let (engine, hits) = new_engine(&node, &db)?;
+    engine.run_to_tip().await?;
+    assert_eq!(*hits.lock().unwrap(), vec![(102, old[0])]);
+
+    // Replace block 102 with a longer branch that pays the wallet at 102' and 103'.
+    node.invalidate(old[0]).await?;
+    let new = node.mine_to(2, &watched_address()).await?;
+    assert_ne!(new[0], old[0]);
+
+    let (engine, hits) = new_engine(&node, &db)?;
+    engine.run_to_tip().await?;
+    // The stale scanned block 102 is detected and progress rolls back to 101,
+    // so both blocks of the new branch are delivered.
+    assert_eq!(*hits.lock().unwrap(), vec![(102, new[0]), (103, new[1])]);
+    assert_eq!(engine.handle().status().scanned_height, 103);
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use async_trait::async_trait;
+use bitcoin::{BlockHash, ScriptBuf, Transaction};
+use niebla_158::rpc::{self, RpcService};
+use niebla_158::testing::*;
+use niebla_158::{MatchDetails, Niebla158, SharedWatchlist, WalletHooks, WatchItem};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// `(height, tags)` per match.
+type Hits = Vec<(u32, Vec<Option<String>>)>;
+
+/// Hooks reading a [`SharedWatchlist`] and recording [`Hits`].
+#[derive(Clone)]
+struct Shared {
+    items: SharedWatchlist,
+    hits: Arc<Mutex<Hits>>,
+}
+
+#[async_trait]
+impl WalletHooks for Shared {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(self.items.items().into_iter().map(|i| i.script).collect())
+    }
+
+    async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+        Ok(self.items.items())
+    }
+
+    async fn on_block_match(
+        &self,
+        _: u32,
+        _: BlockHash,
+        _: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        unreachable!("on_match is overridden")
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        let tags = details.items.into_iter().map(|i| i.tag).collect();
+        self.hits.lock().unwrap().push((details.height, tags));
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn rpc_adds_scripts_and_rescans() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(6, |h| if h == 3 { vec![script(2)] } else { vec![] })?;
+    let hooks = Shared {
+        items: SharedWatchlist::new(vec![WatchItem::new(script(1))]),
+        hits: Arc::default(),
+    };
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers);
+    let rpc = RpcService::new(engine.handle()).with_watchlist(hooks.items.clone());
+
+    engine.run_to_tip().await?;
+    assert!(hooks.hits.lock().unwrap().is_empty());
+    assert_eq!(rpc.call("getprogress", &Value::Null)?["scanned_height"], 6);
+
+    let added = json!([script(2).to_hex_string(), "late"]);
+    assert_eq!(rpc.call("addwatchscript", &added)?, json!(true));
+    assert_eq!(rpc.call("addwatchscript", &added)?, json!(false));
+    rpc.call("rescan", &json!([1]))?;
+    engine.run_to_tip().await?;
+    assert_eq!(
+        *hooks.hits.lock().unwrap(),
+        vec![(3, vec![Some("late".to_owned())])]
+    );
+
+    rpc.call("pause", &Value::Null)?;
+    assert!(engine.handle().is_pause_requested());
+    rpc.call("resume", &Value::Null)?;
+    assert!(!engine.handle().is_pause_requested());
+
+    assert_eq!(rpc.call("nope", &Value::Null).unwrap_err().code, -32601);
+    assert_eq!(rpc.call("rescan", &json!(["x"])).unwrap_err().code, -32602);
+    let born = json!([script(3).to_hex_string(), null, 5]);
+    assert_eq!(rpc.call("addwatchscript", &born)?, json!(true));
+    let bad_birth = json!([script(4).to_hex_string(), null, "x"]);
+    assert_eq!(
+        rpc.call("addwatchscript", &bad_birth).unwrap_err().code,
+        -32602
+    );
+    assert_eq!(
+        hooks.items.items().last().and_then(|i| i.birth_height),
+        Some(5)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn router_speaks_json_rpc_over_http() -> anyhow::Result<()> {
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![]),
+        MockFilterSource::new(),
+        MockHeaderSource::from_hashes(vec![genesis_hash()]),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let app = rpc::router(RpcService::new(engine.handle()));
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let body = r#"{"jsonrpc":"2.0","id":7,"method":"getprogress"}"#;
+    let mut sock = tokio::net::TcpStream::connect(addr).await?;
+    let req = format!(
+        "POST / HTTP/1.1\r\nhost: {addr}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    sock.write_all(req.as_bytes()).await?;
+    let mut resp = String::new();
+    sock.read_to_string(&mut resp).await?;
+    let json: Value = serde_json::from_str(resp.split("\r\n\r\n").nth(1).unwrap_or_default())?;
+    assert_eq!(json["id"], 7);
+    assert_eq!(json["result"]["state"], "idle");
+    Ok(())
+}
@@ -0,0 +1,394 @@
+use async_trait::async_trait;
+use bitcoin::bip158::BlockFilter;
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    absolute::LockTime, transaction, Amount, BlockHash, OutPoint, ScriptBuf, Sequence, Transaction,
+    TxIn, TxOut, Witness,
+};
+use niebla_158::testing::*;
+use niebla_158::tx_source::TxSource;
+use niebla_158::{MatchDetails, Niebla158, TxSummary, WalletHooks};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// `(height, summaries)` of every match.
+type Seen = Arc<Mutex<Vec<(u32, Vec<TxSummary>)>>>;
+
+#[derive(Clone, Default)]
+struct Summaries(Seen);
+
+#[async_trait]
+impl WalletHooks for Summaries {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        Ok(vec![script(1)])
+    }
+
+    async fn on_block_match(
+        &self,
+        _: u32,
+        _: BlockHash,
+        _: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        unreachable!("on_match is overridden")
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .push((details.height, details.summaries));
+        Ok(())
+    }
+}
+
+/// A node with `txindex`: transactions by txid.
+struct Txs(HashMap<bitcoin::Txid, Transaction>);
+
+#[async_trait]
+impl TxSource for Txs {
+    async fn get_transaction(&self, txid: bitcoin::Txid) -> anyhow::Result<Option<Transaction>> {
+        Ok(self.0.get(&txid).cloned())
+    }
+}
+
+fn tx(inputs: &[OutPoint], outputs: &[(u64, ScriptBuf)]) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|op| TxIn {
+                previous_output: *op,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs
+            .iter()
+            .map(|(sats, script)| TxOut {
+                value: Amount::from_sat(*sats),
+                script_pubkey: script.clone(),
+            })
+            .collect(),
+    }
+}
+
+#[tokio::test]
+async fn matches_carry_received_sent_and_fee() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let funding = block_paying(1, prev, &[script(1)]);
+    let prev = headers.push(filters.add_block(1, &funding)?);
+
+    // Spend the 1000 sats: 600 out, 300 change back to us, 100 fee.
+    let coin = OutPoint::new(funding.txdata[0].compute_txid(), 0);
+    let spend = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: coin,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![
+            TxOut {
+                value: Amount::from_sat(600),
+                script_pubkey: script(7),
+            },
+            TxOut {
+                value: Amount::from_sat(300),
+                script_pubkey: script(1),
+            },
+        ],
+    };
+    let mut block = block_paying(2, prev, &[script(8)]);
+    block.txdata.push(spend.clone());
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    headers.push(filters.add_block(2, &block)?);
+
+    let hooks = Summaries::default();
+    Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .run_to_tip()
+        .await?;
+
+    let seen = hooks.0.lock().unwrap();
+    assert_eq!(seen.len(), 2);
+    assert_eq!(
+        seen[0].1,
+        vec![TxSummary {
+            txid: coin.txid,
+            received: Amount::from_sat(1_000),
+            sent: Amount::ZERO,
+            fee: None,
+            involved: true,
+        }]
+    );
+    // Block 2: the coinbase pays someone else, the spend is ours.
+    assert_eq!(seen[1].1[0].received, Amount::ZERO);
+    assert!(!seen[1].1[0].involved);
+    assert_eq!(
+        seen[1].1[1],
+        TxSummary {
+            txid: spend.compute_txid(),
+            received: Amount::from_sat(300),
+            sent: Amount::from_sat(1_000),
+            fee: Some(Amount::from_sat(100)),
+            involved: true,
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn prevouts_of_coins_received_before_the_scan_come_from_a_tx_source() -> anyhow::Result<()> {
+    // Funded long before the scanned blocks: the engine never saw it paid.
+    let funding = tx(
+        &[OutPoint::new(bitcoin::Txid::from_byte_array([4; 32]), 0)],
+        &[(1_000, script(1))],
+    );
+    let coin = OutPoint::new(funding.compute_txid(), 0);
+    let spend = tx(&[coin], &[(600, script(7)), (300, script(1))]);
+
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let mut block = block_paying(1, prev, &[script(8)]);
+    block.txdata.push(spend.clone());
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    let filter = BlockFilter::new_script_filter(&block, |_| Ok(script(1)))?;
+    filters.add_raw(
+        1,
+        block.block_hash(),
+        prev,
+        filter.content,
+        bitcoin::consensus::serialize(&block),
+    );
+    headers.push(block.block_hash());
+
+    // Without a lookup the spent amount and fee stay unknown.
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        Summaries::default(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_prevouts();
+    let details = engine.check_block(block.block_hash()).await?.unwrap();
+    assert!(details.prevouts.is_empty());
+    assert_eq!(details.summaries[1].fee, None);
+
+    let hooks = Summaries::default();
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .with_tx_source(Txs(HashMap::from([(coin.txid, funding.clone())])));
+    engine.run_to_tip().await?;
+    assert_eq!(
+        hooks.0.lock().unwrap()[0].1[1],
+        TxSummary {
+            txid: spend.compute_txid(),
+            received: Amount::from_sat(300),
+            sent: Amount::from_sat(1_000),
+            fee: Some(Amount::from_sat(100)),
+            involved: true,
+        }
+    );
+    let details = engine.check_block(block.block_hash()).await?.unwrap();
+    assert_eq!(details.prevouts, vec![(coin, funding.output[0].clone())]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn a_tx_source_serving_the_wrong_transaction_fails_the_run() -> anyhow::Result<()> {
+    let funding = tx(
+        &[OutPoint::new(bitcoin::Txid::from_byte_array([4; 32]), 0)],
+        &[(1_000, script(1))],
+    );
+    let coin = OutPoint::new(funding.compute_txid(), 0);
+    let spend = tx(&[coin], &[(600, script(7)), (300, script(1))]);
+
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let mut block = block_paying(1, prev, &[script(8)]);
+    block.txdata.push(spend);
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    let filter = BlockFilter::new_script_filter(&block, |_| Ok(script(1)))?;
+    filters.add_raw(
+        1,
+        block.block_hash(),
+        prev,
+        filter.content,
+        bitcoin::consensus::serialize(&block),
+    );
+    headers.push(block.block_hash());
+
+    // Asked for the funding tx, the source answers with one paying us a fortune.
+    let forged = tx(
+        &[OutPoint::new(bitcoin::Txid::from_byte_array([5; 32]), 0)],
+        &[(21_000_000, script(1))],
+    );
+    let hooks = Summaries::default();
+    let err = Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .with_tx_source(Txs(HashMap::from([(coin.txid, forged.clone())])))
+        .run_to_tip()
+        .await
+        .unwrap_err();
+    let msg = format!("{err:#}");
+    assert!(
+        msg.contains(&format!("get_transaction({})", coin.txid)),
+        "{msg}"
+    );
+    assert!(
+        msg.contains(&format!("served transaction {}", forged.compute_txid())),
+        "{msg}"
+    );
+    assert!(hooks.0.lock().unwrap().is_empty());
+    Ok(())
+}
+
+/// Block `height` on `prev`: a coinbase paying script 8, then `txs`.
+fn block_with(height: u32, prev: BlockHash, txs: &[Transaction]) -> bitcoin::Block {
+    let mut block = block_paying(height, prev, &[script(8)]);
+    block.txdata.extend_from_slice(txs);
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    block
+}
+
+#[tokio::test]
+async fn with_utxo_tracking_spends_are_valued_from_the_store() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let funding = block_paying(1, prev, &[script(1)]);
+    let prev = headers.push(filters.add_block(1, &funding)?);
+    let coin = OutPoint::new(funding.txdata[0].compute_txid(), 0);
+    let store = MemoryStore::new();
+    Niebla158::new(
+        store.clone(),
+        Summaries::default(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_utxo_tracking()
+    .run_to_tip()
+    .await?;
+
+    // A new engine (a restart) has seen nothing paid, but the store has.
+    let spend = tx(&[coin], &[(600, script(7)), (300, script(1))]);
+    headers.push(filters.add_block(2, &block_with(2, prev, std::slice::from_ref(&spend)))?);
+    let hooks = Summaries::default();
+    Niebla158::new(store, hooks.clone(), filters, headers)
+        .with_utxo_tracking()
+        .run_to_tip()
+        .await?;
+    assert_eq!(
+        hooks.0.lock().unwrap()[0].1[1],
+        TxSummary {
+            txid: spend.compute_txid(),
+            received: Amount::from_sat(300),
+            sent: Amount::from_sat(1_000),
+            fee: Some(Amount::from_sat(100)),
+            involved: true,
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn outputs_seen_on_a_stale_branch_are_forgotten() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let genesis = headers.push(genesis_hash());
+    let funding = block_paying(1, genesis, &[script(1)]);
+    headers.push(filters.add_block(1, &funding)?);
+    let coin = OutPoint::new(funding.txdata[0].compute_txid(), 0);
+    let hooks = Summaries::default();
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    );
+    engine.run_to_tip().await?;
+
+    // Block 1 is reorged out; the new chain spends its output anyway.
+    let replacement = filters.add_block(1, &block_paying(1, genesis, &[script(9)]))?;
+    let spend = tx(&[coin], &[(300, script(1))]);
+    let block = block_with(2, replacement, std::slice::from_ref(&spend));
+    headers.reorg(0, [replacement, filters.add_block(2, &block)?]);
+    engine.run_to_tip().await?;
+
+    let seen = hooks.0.lock().unwrap();
+    let (height, summaries) = seen.last().unwrap();
+    assert_eq!(*height, 2);
+    assert_eq!(
+        summaries[1],
+        TxSummary {
+            txid: spend.compute_txid(),
+            received: Amount::from_sat(300),
+            sent: Amount::ZERO,
+            fee: None,
+            involved: true,
+        }
+    );
+    Ok(())
+}
+
+/// [`Summaries`] whose first delivery of block 2 fails.
+#[derive(Clone, Default)]
+struct FailOnce(Summaries, Arc<AtomicBool>);
+
+#[async_trait]
+impl WalletHooks for FailOnce {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        self.0.watchlist().await
+    }
+
+    async fn on_block_match(
+        &self,
+        _: u32,
+        _: BlockHash,
+        _: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        unreachable!("on_match is overridden")
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        if details.height == 2 && !self.1.swap(true, Ordering::Relaxed) {
+            anyhow::bail!("hook down");
+        }
+        self.0.on_match(details).await
+    }
+}
+
+#[tokio::test]
+async fn a_failed_delivery_is_retried_with_the_same_amounts() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let funding = block_paying(1, prev, &[script(1)]);
+    let prev = headers.push(filters.add_block(1, &funding)?);
+    let coin = OutPoint::new(funding.txdata[0].compute_txid(), 0);
+    let spend = tx(&[coin], &[(600, script(7)), (300, script(1))]);
+    let block = block_with(2, prev, std::slice::from_ref(&spend));
+    headers.push(filters.add_block(2, &block)?);
+
+    let hooks = FailOnce::default();
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers);
+    assert!(engine.run_to_tip().await.is_err());
+    // Neither looking at the block nor the failed delivery forgets the coin.
+    let checked = engine.check_block(block.block_hash()).await?.unwrap();
+    assert_eq!(checked.summaries[1].sent, Amount::from_sat(1_000));
+    engine.run_to_tip().await?;
+
+    let seen = hooks.0 .0.lock().unwrap();
+    assert_eq!(seen.len(), 2);
+    assert_eq!(
+        seen[1].1[1],
+        TxSummary {
+            txid: spend.compute_txid(),
+            received: Amount::from_sat(300),
+            sent: Amount::from_sat(1_000),
+            fee: Some(Amount::from_sat(100)),
+            involved: true,
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use async_trait::async_trait;
+use bitcoin::{BlockHash, ScriptBuf, Transaction};
+use niebla_158::testing::*;
+use niebla_158::{MatchDetails, Niebla158, WalletHooks, WatchItem};
+use std::sync::{Arc, Mutex};
+
+type Routed = Arc<Mutex<Vec<(u32, Vec<Option<String>>)>>>;
+
+/// Two accounts, one tagged item each, plus an untagged script.
+struct Accounts {
+    routed: Routed,
+}
+
+#[async_trait]
+impl WalletHooks for Accounts {
+    async fn watchlist(&self) -> anyhow::Result<Vec<ScriptBuf>> {
+        unreachable!("the engine asks for watch_items")
+    }
+
+    async fn on_block_match(
+        &self,
+        _: u32,
+        _: BlockHash,
+        _: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        unreachable!("the engine calls on_match")
+    }
+
+    async fn watch_items(&self) -> anyhow::Result<Vec<WatchItem>> {
+        Ok(vec![
+            WatchItem::new(script(1))
+                .with_tag("m/84'/0'/0'/0/0")
+                .with_priority(),
+            WatchItem::new(script(2)).with_tag("m/84'/0'/1'/0/0"),
+            script(3).into(),
+        ])
+    }
+
+    async fn on_match(&self, details: MatchDetails) -> anyhow::Result<()> {
+        let tags = details.items.into_iter().map(|i| i.tag).collect();
+        self.routed.lock().unwrap().push((details.height, tags));
+        Ok(())
+    }
+}
+
+fn chain() -> anyhow::Result<(MockFilterSource, MockHeaderSource)> {
+    mock_chain(5, |h| match h {
+        2 => vec![script(2)],
+        4 => vec![script(1), script(3)],
+        _ => vec![script(9)],
+    })
+}
+
+#[tokio::test]
+async fn matches_echo_the_tags_of_paid_items() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    let routed = Routed::default();
+    let hooks = Accounts {
+        routed: routed.clone(),
+    };
+    Niebla158::new(MemoryStore::new(), hooks, filters, headers)
+        .run_to_tip()
+        .await?;
+
+    assert_eq!(
+        *routed.lock().unwrap(),
+        vec![
+            (2, vec![Some("m/84'/0'/1'/0/0".to_string())]),
+            (4, vec![Some("m/84'/0'/0'/0/0".to_string()), None]),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn quick_check_scans_priority_items_only() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    let routed = Routed::default();
+    let hooks = Accounts {
+        routed: routed.clone(),
+    };
+    let store = MemoryStore::new();
+    let engine = Niebla158::new(store.clone(), hooks, filters.clone(), headers);
+
+    // Height 2 pays a non-priority item; heights past the tip are ignored.
+    let quick = engine.quick_check(2..=100).await?;
+    assert_eq!(quick.len(), 1);
+    assert_eq!(quick[0].height, 4);
+    assert_eq!(quick[0].items.len(), 1);
+    assert_eq!(quick[0].items[0].script, script(1));
+    assert_eq!(filters.calls(MockCall::Cfilter), 4);
+
+    // Nothing was delivered or persisted; the full scan still sees everything.
+    assert!(routed.lock().unwrap().is_empty());
+    assert_eq!(niebla_158::Store::get_last_scanned(&store).await?, 0);
+    engine.run_to_tip().await?;
+    assert_eq!(routed.lock().unwrap().len(), 2);
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use async_trait::async_trait;
+use bitcoin::{consensus, Block};
+use niebla_158::events::EngineEvent;
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::watchtower::{Breach, BreachHooks, Watchtower};
+use niebla_158::{hooks::Delivery, FilterSource, Niebla158, Store, WatchItem};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Default)]
+struct Tower {
+    breaches: Arc<Mutex<Vec<Breach>>>,
+    down: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl BreachHooks for Tower {
+    async fn commitment_outputs(&self) -> anyhow::Result<Vec<WatchItem>> {
+        Ok(vec![WatchItem::new(script(1)).with_tag("channel 7")])
+    }
+
+    async fn on_breach(&self, breach: Breach) -> anyhow::Result<()> {
+        if self.down.load(Ordering::SeqCst) {
+            anyhow::bail!("justice transaction not broadcast");
+        }
+        self.breaches.lock().unwrap().push(breach);
+        Ok(())
+    }
+}
+
+/// A chain of 6 blocks whose block 3 confirms the revoked commitment.
+async fn chain() -> anyhow::Result<(MockFilterSource, MockHeaderSource, Block)> {
+    let (filters, headers) = mock_chain(6, |h| vec![script(if h == 3 { 1 } else { 2 })])?;
+    let breach = filters.get_block(headers.hash_at_height(3).await?).await?;
+    Ok((filters, headers, consensus::deserialize(&breach)?))
+}
+
+#[tokio::test]
+async fn breaches_are_delivered_whole_and_at_least_once() -> anyhow::Result<()> {
+    let (filters, headers, block) = chain().await?;
+    let store = MemoryStore::new();
+    let tower = Tower::default();
+    let engine = Niebla158::new(
+        store.clone(),
+        Watchtower::new(tower.clone()),
+        filters,
+        headers,
+    )
+    .with_watchtower()
+    .with_delivery(Delivery::ExactlyOnce);
+
+    // A failing callback leaves the breach queued in the store.
+    tower.down.store(true, Ordering::SeqCst);
+    assert!(engine.run_to_tip().await.is_err());
+    assert_eq!(
+        store.pending_matches().await?,
+        vec![(3, block.block_hash())]
+    );
+
+    tower.down.store(false, Ordering::SeqCst);
+    engine.run_to_tip().await?;
+    assert_eq!(store.pending_matches().await?, vec![]);
+    // Delivered from the queue, then skipped by the rescan of height 3.
+    let breaches = tower.breaches.lock().unwrap();
+    assert_eq!(breaches.len(), 1);
+    let breach = &breaches[0];
+    assert_eq!((breach.height, breach.block), (3, block.block_hash()));
+    assert_eq!(consensus::deserialize::<Block>(&breach.raw_block)?, block);
+    assert_eq!(breach.txs, block.txdata);
+    assert_eq!(breach.items[0].tag.as_deref(), Some("channel 7"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn watchtower_hooks_need_watchtower_mode() -> anyhow::Result<()> {
+    let (filters, headers, _) = chain().await?;
+    let tower = Watchtower::new(Tower::default());
+    let err = Niebla158::new(MemoryStore::new(), tower, filters.clone(), headers.clone())
+        .run_to_tip()
+        .await
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("whole blocks"), "{err:#}");
+
+    let err = Niebla158::new(
+        MemoryStore::new(),
+        Watchtower::new(Tower::default()),
+        filters,
+        headers,
+    )
+    .with_watchtower()
+    .with_delivery_queue()
+    .run_to_tip()
+    .await
+    .unwrap_err();
+    assert!(err.to_string().contains("delivery queue"), "{err:#}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn a_breach_reorged_out_after_a_failed_delivery_is_still_delivered() -> anyhow::Result<()> {
+    let (filters, headers, block) = chain().await?;
+    let store = MemoryStore::new();
+    let tower = Tower::default();
+    let engine = || {
+        Niebla158::new(
+            store.clone(),
+            Watchtower::new(tower.clone()),
+            filters.clone(),
+            headers.clone(),
+        )
+        .with_watchtower()
+    };
+    tower.down.store(true, Ordering::SeqCst);
+    assert!(engine().run_to_tip().await.is_err());
+    assert_eq!(store.get_last_scanned().await?, 2);
+
+    // Heights 2..=6 are replaced by a branch without the commitment.
+    let mut prev = headers.hash_at_height(1).await?;
+    let mut branch = vec![];
+    for h in 2..=6 {
+        prev = filters.add_block(h, &block_paying(h, prev, &[script(2), script(4)]))?;
+        branch.push(prev);
+    }
+    headers.reorg(1, branch);
+
+    tower.down.store(false, Ordering::SeqCst);
+    engine().run_to_tip().await?;
+    assert_eq!(store.pending_matches().await?, vec![]);
+    let breaches = tower.breaches.lock().unwrap();
+    assert_eq!(breaches.len(), 1);
+    assert_eq!(
+        (breaches[0].height, breaches[0].block),
+        (3, block.block_hash())
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn a_breach_no_source_serves_any_more_does_not_stall_the_scan() -> anyhow::Result<()> {
+    let (filters, headers, block) = chain().await?;
+    let store = MemoryStore::new();
+    let tower = Tower::default();
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    let engine = Niebla158::new(
+        store.clone(),
+        Watchtower::new(tower.clone()),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_watchtower()
+    .with_events(move |e: &EngineEvent| {
+        if matches!(e, EngineEvent::MatchDropped { .. }) {
+            sink.lock().unwrap().push(e.clone());
+        }
+    });
+    tower.down.store(true, Ordering::SeqCst);
+    assert!(engine.run_to_tip().await.is_err());
+
+    // Only the breach block is reorged out, so progress (height 2) stays on
+    // the chain, and the stale block is gone from the source.
+    let mut prev = headers.hash_at_height(2).await?;
+    let mut branch = vec![];
+    for h in 3..=6 {
+        prev = filters.add_block(h, &block_paying(h, prev, &[script(2), script(4)]))?;
+        branch.push(prev);
+    }
+    headers.reorg(2, branch);
+    filters.remove_block(block.block_hash());
+
+    tower.down.store(false, Ordering::SeqCst);
+    engine.run_to_tip().await?;
+    assert_eq!(store.get_last_scanned().await?, 6);
+    assert_eq!(store.pending_matches().await?, vec![]);
+    assert!(tower.breaches.lock().unwrap().is_empty());
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![EngineEvent::MatchDropped {
+            wallet: 0,
+            height: 3,
+            block: block.block_hash(),
+        }]
+    );
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    absolute::LockTime, transaction, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Txid, Witness,
+};
+use niebla_158::mempool::{MempoolWatcher, ZeroConfAlert, ZeroConfHooks};
+use niebla_158::testing::script;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// A tx spending `inputs` with one output of `sats` to `to`.
+fn tx(inputs: &[OutPoint], to: ScriptBuf, sats: u64) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|op| TxIn {
+                previous_output: *op,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey: to,
+        }],
+    }
+}
+
+fn outpoint(b: u8) -> OutPoint {
+    OutPoint::new(Txid::from_byte_array([b; 32]), 0)
+}
+
+#[derive(Clone, Default)]
+struct Alerts {
+    seen: Arc<Mutex<Vec<Txid>>>,
+    alerts: Arc<Mutex<Vec<ZeroConfAlert>>>,
+}
+
+#[async_trait]
+impl ZeroConfHooks for Alerts {
+    async fn on_unconfirmed(&self, tx: &Transaction) -> anyhow::Result<()> {
+        self.seen.lock().unwrap().push(tx.compute_txid());
+        Ok(())
+    }
+
+    async fn on_zero_conf_alert(&self, alert: ZeroConfAlert) -> anyhow::Result<()> {
+        self.alerts.lock().unwrap().push(alert);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn conflicts_replacements_and_evictions_are_reported() -> anyhow::Result<()> {
+    let hooks = Alerts::default();
+    let watcher = MempoolWatcher::new(hooks.clone(), [script(1)]);
+
+    let pay = tx(&[outpoint(1)], script(1), 10_000);
+    let bumped = tx(&[outpoint(1)], script(1), 9_000);
+    let other = tx(&[outpoint(2)], script(1), 5_000);
+    watcher.observe(tx(&[outpoint(9)], script(7), 1)).await?;
+    watcher.observe(pay.clone()).await?;
+    watcher.observe(other.clone()).await?;
+    assert_eq!(
+        *hooks.seen.lock().unwrap(),
+        vec![pay.compute_txid(), other.compute_txid()]
+    );
+
+    // RBF bump that still pays us, then a double-spend that does not.
+    watcher.observe(bumped.clone()).await?;
+    let theft = tx(&[outpoint(1)], script(7), 9_500);
+    watcher.observe(theft.clone()).await?;
+    // `other` disappears from the mempool without confirming.
+    watcher
+        .retain(&HashSet::from([theft.compute_txid()]))
+        .await?;
+
+    assert_eq!(
+        *hooks.alerts.lock().unwrap(),
+        vec![
+            ZeroConfAlert::Replaced {
+                watched: pay.compute_txid(),
+                replacement: bumped.clone(),
+            },
+            ZeroConfAlert::DoubleSpend {
+                watched: bumped.compute_txid(),
+                conflicting: theft,
+            },
+            ZeroConfAlert::Evicted {
+                watched: other.compute_txid(),
+            },
+        ]
+    );
+    assert!(watcher.tracked().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn mined_payments_stop_being_tracked() -> anyhow::Result<()> {
+    let hooks = Alerts::default();
+    let watcher = MempoolWatcher::new(hooks.clone(), [script(1)]);
+    let pay = tx(&[outpoint(1)], script(1), 10_000);
+    let other = tx(&[outpoint(2)], script(1), 5_000);
+    watcher.observe(pay.clone()).await?;
+    watcher.observe(other.clone()).await?;
+
+    // `pay` confirms; a conflict of `other` is mined in the same block.
+    let conflict = tx(&[outpoint(2)], script(7), 4_000);
+    watcher.confirmed(&[pay, conflict.clone()]).await?;
+    assert_eq!(
+        *hooks.alerts.lock().unwrap(),
+        vec![ZeroConfAlert::DoubleSpend {
+            watched: other.compute_txid(),
+            conflicting: conflict,
+        }]
+    );
+    assert!(watcher.tracked().is_empty());
+    Ok(())
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "serde")]
+
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, ScriptBuf, WPubkeyHash};
+use niebla_158::checkpoints::Checkpoints;
+use niebla_158::control::{EnginePhase, EngineStatus, Health, PhaseTimings, RunState};
+use niebla_158::filter_source::CfHeadersBatch;
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, WatchItem};
+use serde_json::json;
+use std::time::Duration;
+
+#[test]
+fn watch_items_and_checkpoints_round_trip() -> anyhow::Result<()> {
+    let script = ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20]));
+    let item = WatchItem::new(script.clone())
+        .with_tag("acct-7")
+        .with_birth_height(800_000);
+    let json = serde_json::to_value(&item)?;
+    assert_eq!(json["script"], script.to_hex_string());
+    assert_eq!(serde_json::from_value::<WatchItem>(json)?, item);
+    // Everything but the script is optional.
+    let bare = json!({ "script": script.to_hex_string() });
+    assert_eq!(
+        serde_json::from_value::<WatchItem>(bare)?,
+        WatchItem::new(script)
+    );
+
+    let checkpoints = Checkpoints(vec![(1_000, BlockHash::from_byte_array([7; 32]))]);
+    let text = serde_json::to_string(&checkpoints)?;
+    assert_eq!(serde_json::from_str::<Checkpoints>(&text)?, checkpoints);
+
+    let batch = CfHeadersBatch {
+        start_height: 5,
+        headers: vec![[0xab; 32], [1; 32]],
+    };
+    let json = serde_json::to_value(&batch)?;
+    assert_eq!(json["headers"][0], "ab".repeat(32));
+    let back: CfHeadersBatch = serde_json::from_value(json)?;
+    assert_eq!((back.start_height, back.headers), (5, batch.headers));
+    let short = json!({ "start_height": 5, "headers": ["abcd"] });
+    assert!(serde_json::from_value::<CfHeadersBatch>(short).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn status_snapshots_serialize() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(3, |_| vec![])?;
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![]),
+        filters,
+        headers,
+    );
+    engine.run_to_tip().await?;
+    let handle = engine.handle();
+
+    let status = handle.status();
+    let json = serde_json::to_value(status)?;
+    assert_eq!(json["scanned_height"], 3);
+    assert_eq!(serde_json::from_value::<EngineStatus>(json)?, status);
+
+    let health = handle.health();
+    let text = serde_json::to_string(&health)?;
+    assert_eq!(serde_json::from_str::<Health>(&text)?, health);
+
+    let timings = PhaseTimings {
+        hooks: Duration::from_millis(1_500),
+        ..PhaseTimings::default()
+    };
+    let text = serde_json::to_string(&timings)?;
+    assert_eq!(serde_json::from_str::<PhaseTimings>(&text)?, timings);
+
+    for phase in [EnginePhase::Idle, EnginePhase::Scanning { height: 9 }] {
+        let text = serde_json::to_string(&phase)?;
+        assert_eq!(serde_json::from_str::<EnginePhase>(&text)?, phase);
+    }
+    assert_eq!(serde_json::to_value(RunState::Paused)?, "Paused");
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use niebla_158::session::{ScanDirection, ScanSession};
+use niebla_158::testing::*;
+use niebla_158::{Niebla158, Store};
+
+#[tokio::test]
+async fn overlapping_sessions_keep_their_own_progress() -> anyhow::Result<()> {
+    let (filters, headers) =
+        mock_chain(10, |h| vec![script(if matches!(h, 3 | 7) { 1 } else { 9 })])?;
+    let (store, hooks) = (MemoryStore::new(), RecordingHooks::new(vec![script(1)]));
+    let engine = Niebla158::new(store.clone(), hooks.clone(), filters, headers);
+    engine
+        .add_session(&ScanSession::new("recovery", 1..=10))
+        .await?;
+    engine
+        .add_session(&ScanSession::new("rescan", 5..=8).with_direction(ScanDirection::Backward))
+        .await?;
+
+    // Nothing is verified yet: sessions wait for cfheaders.
+    assert_eq!(engine.run_session("rescan").await?.scanned, None);
+    engine.sync_cfheaders_only().await?;
+
+    let rescan = engine.run_session("rescan").await?;
+    assert!(rescan.is_done());
+    assert_eq!(rescan.scanned, Some(5));
+    assert_eq!(hooks.matched_heights(), vec![7]);
+
+    let recovery = engine.run_session("recovery").await?;
+    assert_eq!(recovery.scanned, Some(10));
+    assert_eq!(hooks.matched_heights(), vec![7, 3, 7]);
+
+    // The wallet's own scan progress is untouched.
+    assert_eq!(store.get_last_scanned().await?, 0);
+    let ids: Vec<String> = engine.sessions().await?.into_iter().map(|s| s.id).collect();
+    assert_eq!(ids, ["recovery", "rescan"]);
+    engine.remove_session("rescan").await?;
+    assert_eq!(engine.session("rescan").await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn sessions_resume_after_a_restart() -> anyhow::Result<()> {
+    let (filters, headers) =
+        mock_chain(10, |h| vec![script(if matches!(h, 3 | 7) { 1 } else { 9 })])?;
+    let (store, hooks) = (MemoryStore::new(), RecordingHooks::new(vec![script(1)]));
+    let engine = Niebla158::new(
+        store.clone(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    );
+    engine.sync_cfheaders_only().await?;
+    engine
+        .add_session(&ScanSession::new("recovery", 1..=10))
+        .await?;
+    hooks.fail_next("wallet busy");
+    hooks.fail_next("wallet busy");
+    engine.run_session("recovery").await.unwrap_err();
+    assert_eq!(engine.session("recovery").await?.unwrap().scanned, Some(2));
+
+    // A new engine over the same store picks the session up at height 3.
+    let engine = Niebla158::new(store, hooks.clone(), filters, headers);
+    engine.run_session("recovery").await.unwrap_err();
+    let session = engine.run_session("recovery").await?;
+    assert!(session.is_done());
+    assert_eq!(hooks.matched_heights(), vec![3, 7]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn a_new_watchlist_starts_the_session_over() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(10, |h| vec![script(if h == 3 { 1 } else { 9 })])?;
+    let hooks = RecordingHooks::new(vec![script(2)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers);
+    engine.sync_cfheaders_only().await?;
+    engine.add_session(&ScanSession::new("s", 1..=10)).await?;
+    assert!(engine.run_session("s").await?.is_done());
+    assert!(hooks.matches().is_empty());
+
+    hooks.set_watchlist(vec![script(1)]);
+    assert!(engine.run_session("s").await?.is_done());
+    assert_eq!(hooks.matched_heights(), vec![3]);
+    Ok(())
+}
+
+#[test]
+fn session_records_round_trip() -> anyhow::Result<()> {
+    let mut session = ScanSession::new("acct 2", 100..=200).with_direction(ScanDirection::Backward);
+    assert_eq!(session.to_string().parse::<ScanSession>()?, session);
+    session.fingerprint = Some(niebla_158::hooks::watchlist_fingerprint(&[script(1)]));
+    session.scanned = Some(150);
+    assert_eq!(session.to_string().parse::<ScanSession>()?, session);
+    assert_eq!(session.next_height(), Some(149));
+    assert!("id=x\nstart=1\n".parse::<ScanSession>().is_err());
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use niebla_158::testing::*;
+use niebla_158::{EngineError, Niebla158};
+use std::time::Duration;
+
+/// Ten blocks; the wallet is paid at heights 3 and 8.
+fn chain() -> anyhow::Result<(MockFilterSource, MockHeaderSource)> {
+    mock_chain(10, |h| vec![script(if matches!(h, 3 | 8) { 1 } else { 2 })])
+}
+
+#[tokio::test]
+async fn lagging_source_is_reported_after_scanning_what_it_has() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    filters.set_filter_tip(Some(5));
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters.clone(), headers);
+
+    let err = engine.run_to_tip().await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<EngineError>(),
+        Some(&EngineError::SourceBehindTip {
+            source_height: 5,
+            chain_height: 10
+        })
+    );
+    assert_eq!(hooks.matched_heights(), vec![3]);
+    assert_eq!(engine.handle().status().scanned_height, 5);
+
+    // Still behind, and nothing new in range: the failing fetch is classified too.
+    let err = engine.run_to_tip().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::SourceBehindTip {
+            source_height: 5,
+            ..
+        })
+    ));
+
+    filters.set_filter_tip(None);
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![3, 8]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn source_wait_retries_until_the_source_catches_up() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    filters.set_filter_tip(Some(5));
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters.clone(), headers)
+        .with_source_wait(Duration::from_secs(5), Duration::from_millis(5));
+
+    let catch_up = filters.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        catch_up.set_filter_tip(None);
+    });
+
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![3, 8]);
+    assert_eq!(engine.handle().status().scanned_height, 10);
+    Ok(())
+}
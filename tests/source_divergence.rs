@@ -0,0 +1,73 @@
+use bitcoin::BlockHash;
+use niebla_158::testing::*;
+use niebla_158::{EngineError, Niebla158, Store};
+
+/// A filter source knowing two branches forking above height 3, and a header
+/// source following the second one. Returns the tips of both branches.
+fn forked() -> anyhow::Result<(MockFilterSource, MockHeaderSource, BlockHash, BlockHash)> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=3u32 {
+        prev = headers.push(filters.add_block(h, &block_paying(h, prev, &[]))?);
+    }
+    let (mut ours, mut theirs) = (prev, prev);
+    for h in 4..=6u32 {
+        ours = headers.push(filters.add_block(h, &block_paying(h, ours, &[script(1)]))?);
+        theirs = filters.add_block(h, &block_paying(h, theirs, &[script(2)]))?;
+    }
+    Ok((filters, headers, ours, theirs))
+}
+
+#[tokio::test]
+async fn diverged_sources_stop_the_run() -> anyhow::Result<()> {
+    let (filters, headers, ours, theirs) = forked()?;
+    filters.set_best_tip(Some(theirs));
+    filters.set_label("filters.example");
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(store.clone(), hooks.clone(), filters.clone(), headers)
+        .with_source_cross_check(1_000);
+
+    let err = engine.run_to_tip().await.unwrap_err();
+    match err.downcast_ref::<EngineError>() {
+        Some(EngineError::SourcesDiverged {
+            height,
+            header_source,
+            filter_source,
+            source,
+        }) => {
+            assert_eq!(*height, 6);
+            assert_eq!((*header_source, *filter_source), (ours, theirs));
+            assert_eq!(source.as_deref(), Some("filters.example"));
+        }
+        other => panic!("unexpected error {other:?}: {err:#}"),
+    }
+    assert_eq!(store.get_last_scanned().await?, 0);
+    assert!(hooks.matched_heights().is_empty());
+
+    // Once the filter source follows the same chain, the run goes through.
+    filters.set_best_tip(Some(ours));
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), [4, 5, 6]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn sources_are_only_compared_when_asked() -> anyhow::Result<()> {
+    let (filters, headers, _, theirs) = forked()?;
+    filters.set_best_tip(Some(theirs));
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters.clone(), headers);
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), [4, 5, 6]);
+
+    // Sources that cannot tell their chain pass the check.
+    let (filters, headers, _, _) = forked()?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers)
+        .with_source_cross_check(1)
+        .run_to_tip()
+        .await?;
+    assert_eq!(hooks.matched_heights(), [4, 5, 6]);
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use niebla_158::control::SourceKind;
+use niebla_158::events::EngineEvent;
+use niebla_158::filter_source::{FilterSource, FilterType};
+use niebla_158::headers::HeaderSource;
+use niebla_158::layers::SourceExt;
+use niebla_158::testing::*;
+use niebla_158::Niebla158;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn chain() -> anyhow::Result<(MockFilterSource, MockHeaderSource)> {
+    mock_chain(6, |h| if h == 2 { vec![script(1)] } else { vec![] })
+}
+
+#[tokio::test]
+async fn layers_compose_around_a_source() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    let layered = filters
+        .clone()
+        .with_rate_limit(1_000)
+        .with_timeout(Duration::from_secs(5))
+        .with_retry(3, Duration::from_millis(1))
+        .with_cache(100);
+    filters.fail_next(MockCall::Cfilter, "flaky");
+    filters.fail_next(MockCall::Cfilter, "flaky");
+
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), layered, headers);
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![2]);
+    assert_eq!(filters.calls(MockCall::Cfilter), 8);
+
+    // A rescan is served from the cache.
+    engine.handle().rescan_from(1);
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![2, 2]);
+    assert_eq!(filters.calls(MockCall::Cfilter), 8);
+    Ok(())
+}
+
+#[tokio::test]
+async fn each_layer_on_its_own() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    let block = headers.hash_at_height(1).await?;
+
+    // Retries give up after the last attempt.
+    let retrying = filters.clone().with_retry(2, Duration::from_millis(1));
+    filters.fail_next(MockCall::Cfilter, "down");
+    filters.fail_next(MockCall::Cfilter, "still down");
+    let err = retrying.get_cfilter(block).await.unwrap_err();
+    assert!(err.to_string().contains("still down"), "{err:#}");
+
+    // Slow requests time out.
+    filters.set_latency(Duration::from_millis(200));
+    let timed = filters.clone().with_timeout(Duration::from_millis(20));
+    let err = timed.get_cfilter(block).await.unwrap_err();
+    assert!(err.to_string().contains("timed out"), "{err:#}");
+    filters.set_latency(Duration::ZERO);
+
+    // Requests are spaced out.
+    let limited = filters.clone().with_rate_limit(50);
+    let started = Instant::now();
+    for _ in 0..5 {
+        limited.get_cfilter(block).await?;
+    }
+    assert!(started.elapsed() >= Duration::from_millis(80));
+
+    // The cache evicts its oldest filters past capacity.
+    let cached = filters.clone().with_cache(1);
+    let other = headers.hash_at_height(2).await?;
+    let before = filters.calls(MockCall::Cfilter);
+    cached
+        .get_cfilter_range(FilterType::BASIC, &[block, other])
+        .await?;
+    cached.get_cfilter(other).await?;
+    cached.get_cfilter(block).await?;
+    assert_eq!(filters.calls(MockCall::Cfilter), before + 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn labels_attribute_source_failures() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    filters.set_label("cdn-1");
+    let labeled = filters
+        .clone()
+        .with_label("primary")
+        .with_timeout(Duration::from_secs(5));
+    assert_eq!(FilterSource::label(&labeled).as_deref(), Some("primary"));
+    assert_eq!(
+        FilterSource::label(&filters.clone().with_cache(10)).as_deref(),
+        Some("cdn-1")
+    );
+
+    let failures = Arc::new(Mutex::new(vec![]));
+    let sink = failures.clone();
+    filters.fail_next(MockCall::Cfilter, "connection reset");
+    let err = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(1)]),
+        labeled,
+        headers,
+    )
+    .with_events(move |e: &EngineEvent| {
+        if let EngineEvent::SourceFailed { .. } = e {
+            sink.lock().unwrap().push(e.clone())
+        }
+    })
+    .run_to_tip()
+    .await
+    .unwrap_err();
+    assert!(
+        format!("{err:#}").contains("filter source primary: connection reset"),
+        "{err:#}"
+    );
+    assert_eq!(
+        *failures.lock().unwrap(),
+        vec![EngineEvent::SourceFailed {
+            kind: SourceKind::Filters,
+            source: Some("primary".into()),
+            error: "connection reset".into(),
+        }]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn shared_sources_take_turns_between_engines() -> anyhow::Result<()> {
+    let (filters, headers) = chain()?;
+    let block = headers.hash_at_height(1).await?;
+    let pool = filters.clone().with_fair_share(1);
+    let (busy, quiet) = (pool.handle(), pool.handle());
+
+    // One engine queues a burst; another's single request waits for one turn,
+    // not for the whole burst.
+    filters.set_latency(Duration::from_millis(20));
+    let burst = futures_util::future::join_all((0..10).map(|_| busy.get_cfilter(block)));
+    let single = async {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(pool.load(), (1, 9));
+        let started = Instant::now();
+        quiet.get_cfilter(block).await?;
+        anyhow::Ok(started.elapsed())
+    };
+    let (burst, waited) = tokio::join!(burst, single);
+    assert!(burst.iter().all(Result::is_ok));
+    assert!(waited? < Duration::from_millis(100));
+    assert_eq!(pool.load(), (0, 0));
+    filters.set_latency(Duration::ZERO);
+
+    // Engines sync side by side through their handles.
+    let (a, b) = (
+        RecordingHooks::new(vec![script(1)]),
+        RecordingHooks::new(vec![script(1)]),
+    );
+    let engine_a = Niebla158::new(
+        MemoryStore::new(),
+        a.clone(),
+        pool.handle(),
+        headers.clone(),
+    );
+    let engine_b = Niebla158::new(MemoryStore::new(), b.clone(), pool.handle(), headers);
+    let (ra, rb) = tokio::join!(engine_a.run_to_tip(), engine_b.run_to_tip());
+    ra?;
+    rb?;
+    assert_eq!(
+        (a.matched_heights(), b.matched_heights()),
+        (vec![2], vec![2])
+    );
+    Ok(())
+}
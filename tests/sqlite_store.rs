@@ -1,6 +1,10 @@
-use bitcoin::hashes::{sha256d, Hash};
-use bitcoin::BlockHash;
+#![cfg(feature = "sqlite")]
+
+use bitcoin::hashes::{sha256, sha256d, Hash};
+use bitcoin::{Amount, BlockHash, OutPoint, ScriptBuf, TxOut, Txid};
+use niebla_158::filter_source::FilterType;
 use niebla_158::store::{sqlite_store::SqliteStore, Store}; // bring trait methods into scope // for all_zeros() + from_raw_hash()
+use niebla_158::utxo::{self, Utxo};
 
 use tempfile::NamedTempFile;
 
@@ -36,9 +40,453 @@ async fn sqlite_store_roundtrips() -> anyhow::Result<()> {
 
     store.set_last_scanned(h).await?;
     assert_eq!(store.get_last_scanned().await?, h);
+    assert_eq!(store.get_last_scanned_block().await?, None);
+    let scanned = BlockHash::from_byte_array([5u8; 32]);
+    store.set_last_scanned_block(h + 1, scanned).await?;
+    assert_eq!(
+        store.get_last_scanned_block().await?,
+        Some((h + 1, scanned))
+    );
+    // Moving the height without a hash forgets the stale one.
+    store.set_last_scanned(h).await?;
+    assert_eq!(store.get_last_scanned_block().await?, None);
+
+    store.set_birth_height(200_000).await?;
+    assert_eq!(store.get_birth_height().await?, Some(200_000));
+
+    assert_eq!(store.get_delivered(h).await?, None);
+    let block = BlockHash::from_byte_array([7u8; 32]);
+    store.set_delivered(h, block).await?;
+    assert_eq!(store.get_delivered(h).await?, Some(block));
+
+    store.enqueue_match(9, block).await?;
+    store.enqueue_match(4, cf).await?;
+    assert_eq!(store.pending_matches().await?, vec![(4, cf), (9, block)]);
+    store.dequeue_match(4).await?;
+    assert_eq!(store.pending_matches().await?, vec![(9, block)]);
+
+    // Other filter types live in their own namespace next to the basic chain.
+    let taproot = FilterType(0x01);
+    assert_eq!(store.load_cf_tip_typed(taproot).await?, None);
+    let other = BlockHash::from_byte_array([3u8; 32]);
+    store.save_cf_tip_typed(taproot, 10, other).await?;
+    store.save_cf_headers_typed(taproot, 10, &[other]).await?;
+    assert_eq!(store.load_cf_tip_typed(taproot).await?, Some((10, other)));
+    assert_eq!(store.load_cf_header_typed(taproot, 10).await?, Some(other));
+    assert_eq!(store.load_cf_header(10).await?, None);
+    assert_eq!(store.load_cf_tip().await?, Some((h, cf)));
+
+    // UTXOs: created at 5, spent at 12; rolling back below 12 unspends it.
+    let coin = Utxo {
+        outpoint: OutPoint::new(Txid::from_byte_array([5u8; 32]), 1),
+        txout: TxOut {
+            value: Amount::from_sat(1_500),
+            script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+        },
+        height: 5,
+        spent_at: None,
+        orphaned: false,
+    };
+    store.save_utxo(&coin).await?;
+    assert_eq!(store.load_utxo(coin.outpoint).await?, Some(coin.clone()));
+    store
+        .save_utxo(&Utxo {
+            spent_at: Some(12),
+            ..coin.clone()
+        })
+        .await?;
+    assert_eq!(utxo::balance(&store).await?, Amount::ZERO);
+    store.rollback_utxos(11).await?;
+    assert_eq!(utxo::unspent(&store).await?, vec![coin.clone()]);
+    store.rollback_utxos(4).await?;
+    assert_eq!(utxo::unspent(&store).await?, vec![]);
+    assert_eq!(
+        utxo::orphaned(&store).await?,
+        vec![Utxo {
+            orphaned: true,
+            ..coin.clone()
+        }]
+    );
+    store.save_utxo(&coin).await?;
+
+    // Cached misses belong to one watchlist fingerprint at a time.
+    let (a, b) = (sha256::Hash::hash(b"a"), sha256::Hash::hash(b"b"));
+    let miss = |n: u8| BlockHash::from_byte_array([n; 32]);
+    store.save_misses(a, &[(5, miss(5)), (6, miss(6))]).await?;
+    store.save_misses(a, &[(6, miss(7)), (9, miss(9))]).await?;
+    assert_eq!(
+        store.load_misses(a, 6, 9).await?,
+        vec![(6, miss(7)), (9, miss(9))]
+    );
+    assert_eq!(store.load_misses(b, 0, 10).await?, vec![]);
+    store.save_misses(b, &[(3, miss(3))]).await?;
+    assert_eq!(store.load_misses(a, 0, 10).await?, vec![]);
+    assert_eq!(store.load_misses(b, 0, 10).await?, vec![(3, miss(3))]);
+
+    // The saved watchlist is replaced as a whole.
+    let (one, two) = (
+        ScriptBuf::from_bytes(vec![0x51]),
+        ScriptBuf::from_bytes(vec![0x52]),
+    );
+    assert_eq!(store.get_watchlist_fingerprint().await?, None);
+    store.set_watchlist(a, &[one, two.clone()]).await?;
+    store.set_watchlist(b, &[two]).await?;
+    assert_eq!(store.get_watchlist_fingerprint().await?, Some(b));
+    assert_eq!(
+        store.watched_scripts().await?,
+        vec![ScriptBuf::from_bytes(vec![0x52])]
+    );
+
+    assert_eq!(store.get_network().await?, None);
+    store.set_network(bitcoin::Network::Signet).await?;
+
+    // Anchors are kept per filter type.
+    let anchor = BlockHash::from_byte_array([7; 32]);
+    assert_eq!(store.get_cf_anchor(FilterType::BASIC).await?, None);
+    store
+        .set_cf_anchor(FilterType::BASIC, 199_000, anchor)
+        .await?;
+    assert_eq!(
+        store.get_cf_anchor(FilterType::BASIC).await?,
+        Some((199_000, anchor))
+    );
+    assert_eq!(store.get_cf_anchor(taproot).await?, None);
+    assert_eq!(store.get_cf_tip_block(FilterType::BASIC).await?, None);
+    store
+        .set_cf_tip_block(FilterType::BASIC, h, scanned)
+        .await?;
+    assert_eq!(
+        store.get_cf_tip_block(FilterType::BASIC).await?,
+        Some((h, scanned))
+    );
+    assert_eq!(store.get_cf_tip_block(taproot).await?, None);
+
+    assert_eq!(store.get_config().await?, None);
+    store.set_config("filter_type=0\n").await?;
+    assert_eq!(
+        store.get_config().await?.as_deref(),
+        Some("filter_type=0\n")
+    );
+
+    assert_eq!(store.next_delivery_sequence().await?, Some(1));
+    assert_eq!(store.next_delivery_sequence().await?, Some(2));
+
+    assert!(store.scan_sessions().await?.is_empty());
+    store.save_scan_session("a", "id=a\nscanned=none\n").await?;
+    store.save_scan_session("a", "id=a\nscanned=5\n").await?;
+    store.save_scan_session("b", "id=b\n").await?;
+    assert_eq!(
+        store.scan_sessions().await?,
+        vec!["id=a\nscanned=5\n".to_string(), "id=b\n".to_string()]
+    );
+    store.remove_scan_session("b").await?;
+    assert_eq!(store.scan_sessions().await?.len(), 1);
+
+    // Wiping clears progress everywhere but keeps the birth height and network.
+    store.wipe().await?;
+    assert_eq!(store.load_cf_tip().await?, None);
+    assert_eq!(store.load_cf_tip_typed(taproot).await?, None);
+    assert_eq!(store.load_cf_header_typed(taproot, 10).await?, None);
+    assert_eq!(store.get_last_scanned().await?, 0);
+    assert_eq!(store.get_delivered(h).await?, None);
+    assert_eq!(store.pending_matches().await?, vec![]);
+    assert_eq!(store.utxos().await?, vec![]);
+    assert_eq!(store.load_misses(b, 0, 10).await?, vec![]);
+    assert_eq!(store.get_watchlist_fingerprint().await?, None);
+    assert!(store.watched_scripts().await?.is_empty());
+    assert_eq!(store.get_cf_anchor(FilterType::BASIC).await?, None);
+    assert_eq!(store.get_cf_tip_block(FilterType::BASIC).await?, None);
+    assert_eq!(store.get_config().await?, None);
+    assert!(store.scan_sessions().await?.is_empty());
+    assert_eq!(store.next_delivery_sequence().await?, Some(3));
+    assert_eq!(store.get_birth_height().await?, Some(200_000));
+    assert_eq!(store.get_network().await?, Some(bitcoin::Network::Signet));
+
+    Ok(())
+}
+
+/// A store on a fresh temp file, deleted when the returned file is dropped.
+fn fresh_store() -> anyhow::Result<(NamedTempFile, SqliteStore)> {
+    let tmp = NamedTempFile::new()?;
+    let store = SqliteStore::new(tmp.path())?;
+    Ok((tmp, store))
+}
+
+fn hash(n: u8) -> BlockHash {
+    BlockHash::from_byte_array([n; 32])
+}
+
+/// Output 1 of a made-up transaction, worth 1500 sats, created at 5.
+fn coin() -> Utxo {
+    Utxo {
+        outpoint: OutPoint::new(Txid::from_byte_array([5u8; 32]), 1),
+        txout: TxOut {
+            value: Amount::from_sat(1_500),
+            script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+        },
+        height: 5,
+        spent_at: None,
+        orphaned: false,
+    }
+}
+
+#[tokio::test]
+async fn fresh_store_has_no_progress() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    assert!(
+        store.load_cf_tip().await?.is_none(),
+        "fresh DB should have no cfheaders tip yet"
+    );
+    assert_eq!(
+        store.get_last_scanned().await?,
+        0,
+        "fresh DB starts at last_scanned=0"
+    );
+    assert!(
+        store.get_birth_height().await?.is_none(),
+        "birth height is optional and unset by default"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn progress_roundtrips() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    let h = 123_456u32;
+    let cf = BlockHash::from_raw_hash(sha256d::Hash::all_zeros()); // any 32-byte value is fine here
+    store.save_cf_tip(h, cf).await?;
+    assert_eq!(store.load_cf_tip().await?, Some((h, cf)));
+
+    store.set_last_scanned(h).await?;
+    assert_eq!(store.get_last_scanned().await?, h);
+    assert_eq!(store.get_last_scanned_block().await?, None);
+    store.set_last_scanned_block(h + 1, hash(5)).await?;
+    assert_eq!(
+        store.get_last_scanned_block().await?,
+        Some((h + 1, hash(5)))
+    );
+    // Moving the height without a hash forgets the stale one.
+    store.set_last_scanned(h).await?;
+    assert_eq!(store.get_last_scanned_block().await?, None);
 
     store.set_birth_height(200_000).await?;
     assert_eq!(store.get_birth_height().await?, Some(200_000));
+    Ok(())
+}
+
+#[tokio::test]
+async fn delivered_matches_roundtrip() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    assert_eq!(store.get_delivered(7).await?, None);
+    store.set_delivered(7, hash(7)).await?;
+    assert_eq!(store.get_delivered(7).await?, Some(hash(7)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn pending_matches_drain_by_height() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    store.enqueue_match(9, hash(9)).await?;
+    store.enqueue_match(4, hash(4)).await?;
+    assert_eq!(
+        store.pending_matches().await?,
+        vec![(4, hash(4)), (9, hash(9))]
+    );
+    store.dequeue_match(4).await?;
+    assert_eq!(store.pending_matches().await?, vec![(9, hash(9))]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn typed_cfheaders_live_in_their_own_namespace() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    store.save_cf_tip(20, hash(1)).await?;
+
+    // Other filter types live in their own namespace next to the basic chain.
+    let taproot = FilterType(0x01);
+    assert_eq!(store.load_cf_tip_typed(taproot).await?, None);
+    store.save_cf_tip_typed(taproot, 10, hash(3)).await?;
+    store.save_cf_headers_typed(taproot, 10, &[hash(3)]).await?;
+    assert_eq!(store.load_cf_tip_typed(taproot).await?, Some((10, hash(3))));
+    assert_eq!(
+        store.load_cf_header_typed(taproot, 10).await?,
+        Some(hash(3))
+    );
+    assert_eq!(store.load_cf_header(10).await?, None);
+    assert_eq!(store.load_cf_tip().await?, Some((20, hash(1))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn anchors_and_tip_blocks_are_kept_per_filter_type() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    let taproot = FilterType(0x01);
+    assert_eq!(store.get_cf_anchor(FilterType::BASIC).await?, None);
+    store
+        .set_cf_anchor(FilterType::BASIC, 199_000, hash(7))
+        .await?;
+    assert_eq!(
+        store.get_cf_anchor(FilterType::BASIC).await?,
+        Some((199_000, hash(7)))
+    );
+    assert_eq!(store.get_cf_anchor(taproot).await?, None);
+
+    assert_eq!(store.get_cf_tip_block(FilterType::BASIC).await?, None);
+    store
+        .set_cf_tip_block(FilterType::BASIC, 200_000, hash(5))
+        .await?;
+    assert_eq!(
+        store.get_cf_tip_block(FilterType::BASIC).await?,
+        Some((200_000, hash(5)))
+    );
+    assert_eq!(store.get_cf_tip_block(taproot).await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn utxos_roll_back_and_orphan() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    // Created at 5, spent at 12; rolling back below 12 unspends it.
+    let coin = coin();
+    store.save_utxo(&coin).await?;
+    assert_eq!(store.load_utxo(coin.outpoint).await?, Some(coin.clone()));
+    store
+        .save_utxo(&Utxo {
+            spent_at: Some(12),
+            ..coin.clone()
+        })
+        .await?;
+    assert_eq!(utxo::balance(&store).await?, Amount::ZERO);
+    store.rollback_utxos(11).await?;
+    assert_eq!(utxo::unspent(&store).await?, vec![coin.clone()]);
+    store.rollback_utxos(4).await?;
+    assert_eq!(utxo::unspent(&store).await?, vec![]);
+    assert_eq!(
+        utxo::orphaned(&store).await?,
+        vec![Utxo {
+            orphaned: true,
+            ..coin
+        }]
+    );
+    Ok(())
+}
 
+#[tokio::test]
+async fn cached_misses_belong_to_one_fingerprint() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    let (a, b) = (sha256::Hash::hash(b"a"), sha256::Hash::hash(b"b"));
+    store.save_misses(a, &[(5, hash(5)), (6, hash(6))]).await?;
+    store.save_misses(a, &[(6, hash(7)), (9, hash(9))]).await?;
+    assert_eq!(
+        store.load_misses(a, 6, 9).await?,
+        vec![(6, hash(7)), (9, hash(9))]
+    );
+    assert_eq!(store.load_misses(b, 0, 10).await?, vec![]);
+    store.save_misses(b, &[(3, hash(3))]).await?;
+    assert_eq!(store.load_misses(a, 0, 10).await?, vec![]);
+    assert_eq!(store.load_misses(b, 0, 10).await?, vec![(3, hash(3))]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn the_watchlist_is_replaced_as_a_whole() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    let (a, b) = (sha256::Hash::hash(b"a"), sha256::Hash::hash(b"b"));
+    let (one, two) = (
+        ScriptBuf::from_bytes(vec![0x51]),
+        ScriptBuf::from_bytes(vec![0x52]),
+    );
+    assert_eq!(store.get_watchlist_fingerprint().await?, None);
+    store.set_watchlist(a, &[one, two.clone()]).await?;
+    store.set_watchlist(b, std::slice::from_ref(&two)).await?;
+    assert_eq!(store.get_watchlist_fingerprint().await?, Some(b));
+    assert_eq!(store.watched_scripts().await?, vec![two]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn network_and_config_roundtrip() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    assert_eq!(store.get_network().await?, None);
+    store.set_network(bitcoin::Network::Signet).await?;
+    assert_eq!(store.get_network().await?, Some(bitcoin::Network::Signet));
+
+    assert_eq!(store.get_config().await?, None);
+    store.set_config("filter_type=0\n").await?;
+    assert_eq!(
+        store.get_config().await?.as_deref(),
+        Some("filter_type=0\n")
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn delivery_sequence_counts_from_one() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    assert_eq!(store.next_delivery_sequence().await?, Some(1));
+    assert_eq!(store.next_delivery_sequence().await?, Some(2));
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_sessions_are_saved_by_id() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    assert!(store.scan_sessions().await?.is_empty());
+    store.save_scan_session("a", "id=a\nscanned=none\n").await?;
+    store.save_scan_session("a", "id=a\nscanned=5\n").await?;
+    store.save_scan_session("b", "id=b\n").await?;
+    assert_eq!(
+        store.scan_sessions().await?,
+        vec!["id=a\nscanned=5\n".to_string(), "id=b\n".to_string()]
+    );
+    store.remove_scan_session("b").await?;
+    assert_eq!(store.scan_sessions().await?.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn wipe_keeps_birth_height_network_and_sequence() -> anyhow::Result<()> {
+    let (_tmp, store) = fresh_store()?;
+    let taproot = FilterType(0x01);
+    let fingerprint = sha256::Hash::hash(b"a");
+    store.save_cf_tip(20, hash(1)).await?;
+    store.save_cf_tip_typed(taproot, 10, hash(3)).await?;
+    store.save_cf_headers_typed(taproot, 10, &[hash(3)]).await?;
+    store.set_cf_anchor(FilterType::BASIC, 5, hash(5)).await?;
+    store
+        .set_cf_tip_block(FilterType::BASIC, 20, hash(2))
+        .await?;
+    store.set_last_scanned(20).await?;
+    store.set_delivered(7, hash(7)).await?;
+    store.enqueue_match(9, hash(9)).await?;
+    store.save_utxo(&coin()).await?;
+    store.save_misses(fingerprint, &[(3, hash(3))]).await?;
+    store
+        .set_watchlist(fingerprint, &[ScriptBuf::from_bytes(vec![0x51])])
+        .await?;
+    store.set_config("filter_type=0\n").await?;
+    store.save_scan_session("a", "id=a\n").await?;
+    store.set_birth_height(200_000).await?;
+    store.set_network(bitcoin::Network::Signet).await?;
+    assert_eq!(store.next_delivery_sequence().await?, Some(1));
+
+    store.wipe().await?;
+    assert_eq!(store.load_cf_tip().await?, None);
+    assert_eq!(store.load_cf_tip_typed(taproot).await?, None);
+    assert_eq!(store.load_cf_header_typed(taproot, 10).await?, None);
+    assert_eq!(store.get_cf_anchor(FilterType::BASIC).await?, None);
+    assert_eq!(store.get_cf_tip_block(FilterType::BASIC).await?, None);
+    assert_eq!(store.get_last_scanned().await?, 0);
+    assert_eq!(store.get_delivered(7).await?, None);
+    assert_eq!(store.pending_matches().await?, vec![]);
+    assert_eq!(store.utxos().await?, vec![]);
+    assert_eq!(store.load_misses(fingerprint, 0, 10).await?, vec![]);
+    assert_eq!(store.get_watchlist_fingerprint().await?, None);
+    assert!(store.watched_scripts().await?.is_empty());
+    assert_eq!(store.get_config().await?, None);
+    assert!(store.scan_sessions().await?.is_empty());
+    assert_eq!(store.next_delivery_sequence().await?, Some(2));
+    assert_eq!(store.get_birth_height().await?, Some(200_000));
+    assert_eq!(store.get_network().await?, Some(bitcoin::Network::Signet));
     Ok(())
 }
@@ -0,0 +1,177 @@
+use niebla_158::testing::*;
+use niebla_158::Niebla158;
+use std::time::Duration;
+
+#[tokio::test]
+async fn mocks_drive_the_engine() -> anyhow::Result<()> {
+    let (filters, headers) =
+        mock_chain(10, |h| vec![script(if matches!(h, 3 | 7) { 1 } else { 9 })])?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let store = MemoryStore::new();
+    let engine = Niebla158::new(store.clone(), hooks.clone(), filters.clone(), headers);
+
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![3, 7]);
+    assert_eq!(filters.calls(MockCall::Cfilter), 10);
+    assert_eq!(filters.calls(MockCall::Block), 2);
+    assert_eq!(engine.handle().status().scanned_height, 10);
+    Ok(())
+}
+
+#[tokio::test]
+async fn injected_failures_surface_and_runs_resume() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(10, |h| vec![script(if h == 5 { 1 } else { 9 })])?;
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    filters.set_latency(Duration::from_millis(1));
+    filters.fail_next(MockCall::Block, "block download refused");
+
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers);
+    let err = engine.run_to_tip().await.unwrap_err();
+    assert!(format!("{err:#}").contains("block download refused"));
+    assert!(hooks.matches().is_empty());
+
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![5]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn header_reorg_serves_the_new_branch() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(10, |_| vec![script(9)])?;
+    // Replace heights 9..=10 with a longer branch that pays the wallet at 11'.
+    let fork_tip = niebla_158::headers::HeaderSource::hash_at_height(&headers, 8).await?;
+    let mut prev = fork_tip;
+    let mut branch = vec![];
+    for h in 9..=11 {
+        let pays = if h == 11 {
+            vec![script(1)]
+        } else {
+            vec![script(8)]
+        };
+        let block = block_paying(h, prev, &pays);
+        prev = filters.add_block(h, &block)?;
+        branch.push(prev);
+    }
+    headers.reorg(8, branch.clone());
+
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), filters, headers);
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![11]);
+    assert_eq!(hooks.matches()[0].block, branch[2]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn retries_ride_out_a_faulty_source() -> anyhow::Result<()> {
+    use niebla_158::layers::SourceExt;
+
+    let (filters, headers) =
+        mock_chain(10, |h| vec![script(if matches!(h, 3 | 7) { 1 } else { 9 })])?;
+    let faulty = FaultySource::new(filters, 7)
+        .with_failure_rate(0.3)
+        .with_latency(Duration::ZERO, Duration::from_millis(2));
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        hooks.clone(),
+        faulty.clone().with_retry(10, Duration::from_millis(1)),
+        headers,
+    );
+
+    engine.run_to_tip().await?;
+    assert_eq!(hooks.matched_heights(), vec![3, 7]);
+    assert!(faulty.injected(Fault::Failure) > 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn checkpoints_catch_reordered_cfheaders() -> anyhow::Result<()> {
+    use niebla_158::filter_source::FilterType;
+    use niebla_158::store::Store;
+
+    let (filters, headers) = mock_chain(10, |_| vec![script(9)])?;
+    let store = MemoryStore::new();
+    let honest = Niebla158::new(
+        store.clone(),
+        RecordingHooks::new(vec![]),
+        filters.clone(),
+        headers.clone(),
+    );
+    honest.run_to_tip().await?;
+    let checkpoint = store
+        .load_cf_header_typed(FilterType::BASIC, 10)
+        .await?
+        .unwrap();
+
+    let faulty = FaultySource::new(filters, 1).with_reorder_rate(1.0);
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        RecordingHooks::new(vec![script(1)]),
+        faulty.clone(),
+        headers,
+    )
+    .with_checkpoints(vec![(10, checkpoint)]);
+    let err = engine.run_to_tip().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<niebla_158::EngineError>(),
+        Some(niebla_158::EngineError::CheckpointMismatch(_))
+    ));
+    assert!(faulty.injected(Fault::Reordered) > 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn same_seed_injects_the_same_faults() -> anyhow::Result<()> {
+    let mut injected = vec![];
+    for _ in 0..2 {
+        let (filters, headers) =
+            mock_chain(10, |h| vec![script(if matches!(h, 3 | 7) { 1 } else { 9 })])?;
+        let faulty = FaultySource::new(filters, 42)
+            .with_wrong_filter_rate(0.5)
+            .with_truncation_rate(0.2);
+        let hooks = RecordingHooks::new(vec![script(1)]);
+        let engine = Niebla158::new(MemoryStore::new(), hooks.clone(), faulty.clone(), headers);
+        let outcome = engine.run_to_tip().await.map_err(|e| format!("{e:#}"));
+        injected.push((
+            outcome,
+            hooks.matched_heights(),
+            faulty.injected(Fault::WrongFilter),
+            faulty.injected(Fault::Truncated),
+        ));
+    }
+    assert_eq!(injected[0], injected[1]);
+    assert!(injected[0].2 + injected[0].3 > 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn mock_cfcheckpt_holds_bip157_headers() -> anyhow::Result<()> {
+    use bitcoin::bip158::{BlockFilter, FilterHeader};
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+    use niebla_158::checkpoints::genesis_cfheader;
+    use niebla_158::filter_source::FilterSource;
+    use niebla_158::headers::HeaderSource;
+
+    let (filters, headers) = mock_chain(2_500, |h| vec![script(h as u8)])?;
+
+    // Roll the served filters the BIP-157 way, genesis over the all-zero header.
+    let mut rolling = FilterHeader::all_zeros();
+    let mut every_1000 = vec![];
+    for h in 0..=2_500 {
+        let block = headers.hash_at_height(h).await?;
+        rolling = BlockFilter::new(&filters.get_cfilter(block).await?).filter_header(&rolling);
+        if h == 0 {
+            assert_eq!(
+                rolling.to_byte_array(),
+                genesis_cfheader(bitcoin::Network::Regtest).to_byte_array()
+            );
+        } else if h % 1_000 == 0 {
+            every_1000.push(BlockHash::from_byte_array(rolling.to_byte_array()));
+        }
+    }
+    let tip = headers.hash_at_height(2_500).await?;
+    assert_eq!(filters.get_cfcheckpt(tip).await?, every_1000);
+    Ok(())
+}
@@ -0,0 +1,291 @@
+use bitcoin::{
+    absolute::LockTime, transaction, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Witness,
+};
+use niebla_158::events::EngineEvent;
+use niebla_158::headers::HeaderSource;
+use niebla_158::testing::*;
+use niebla_158::utxo::{self, BalanceDelta, Utxo};
+use niebla_158::{Niebla158, Store};
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn tracks_funding_spends_and_reorgs() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    let funding = block_paying(1, prev, &[script(1)]);
+    let prev = headers.push(filters.add_block(1, &funding)?);
+
+    // Spend the 1000 sats: 600 out, 300 change back to us.
+    let coin = OutPoint::new(funding.txdata[0].compute_txid(), 0);
+    let spend = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: coin,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![
+            TxOut {
+                value: Amount::from_sat(600),
+                script_pubkey: script(7),
+            },
+            TxOut {
+                value: Amount::from_sat(300),
+                script_pubkey: script(1),
+            },
+        ],
+    };
+    let mut block = block_paying(2, prev, &[script(8)]);
+    block.txdata.push(spend.clone());
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    headers.push(filters.add_block(2, &block)?);
+
+    let store = MemoryStore::new();
+    let engine = || {
+        Niebla158::new(
+            store.clone(),
+            RecordingHooks::new(vec![script(1)]),
+            filters.clone(),
+            headers.clone(),
+        )
+        .with_utxo_tracking()
+    };
+    engine().run_to_tip().await?;
+
+    let change = Utxo {
+        outpoint: OutPoint::new(spend.compute_txid(), 1),
+        txout: spend.output[1].clone(),
+        height: 2,
+        spent_at: None,
+        orphaned: false,
+    };
+    assert_eq!(utxo::balance(&store).await?, Amount::from_sat(300));
+    assert_eq!(utxo::unspent(&store).await?, vec![change.clone()]);
+    assert_eq!(
+        store.load_utxo(coin).await?.map(|u| (u.height, u.spent_at)),
+        Some((1, Some(2)))
+    );
+
+    // The spend is reorged out: the coin is back, the change is orphaned.
+    let replacement = block_paying(2, headers.hash_at_height(1).await?, &[script(9)]);
+    let prev = filters.add_block(2, &replacement)?;
+    headers.reorg(1, [prev]);
+    engine().run_to_tip().await?;
+
+    let funded = Utxo {
+        outpoint: coin,
+        txout: funding.txdata[0].output[0].clone(),
+        height: 1,
+        spent_at: None,
+        orphaned: false,
+    };
+    assert_eq!(utxo::balance(&store).await?, Amount::from_sat(1_000));
+    assert_eq!(utxo::unspent(&store).await?, vec![funded.clone()]);
+    assert_eq!(
+        utxo::orphaned(&store).await?,
+        vec![Utxo {
+            orphaned: true,
+            ..change.clone()
+        }]
+    );
+
+    // It confirms again one block later and is delivered as a fresh match.
+    let mut block = block_paying(3, prev, &[script(8)]);
+    block.txdata.push(spend.clone());
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    headers.push(filters.add_block(3, &block)?);
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    Niebla158::new(store.clone(), hooks.clone(), filters, headers)
+        .with_utxo_tracking()
+        .run_to_tip()
+        .await?;
+
+    assert_eq!(hooks.matched_heights(), vec![3]);
+    assert_eq!(utxo::orphaned(&store).await?, vec![]);
+    assert_eq!(
+        utxo::unspent(&store).await?,
+        vec![Utxo {
+            height: 3,
+            ..change
+        }]
+    );
+    assert_eq!(
+        store.load_utxo(coin).await?.and_then(|u| u.spent_at),
+        Some(3)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn balance_history_per_block() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    let mut coins = vec![];
+    for h in 1..=4 {
+        let pays = match h {
+            1 | 3 => vec![script(1), script(2)],
+            _ => vec![script(8)],
+        };
+        let block = block_paying(h, prev, &pays);
+        coins.extend((0..2).map(|vout| OutPoint::new(block.txdata[0].compute_txid(), vout)));
+        prev = headers.push(filters.add_block(h, &block)?);
+    }
+    // Block 5 spends one of block 1's coins back to an outsider.
+    let spend = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: coins[0],
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(900),
+            script_pubkey: script(7),
+        }],
+    };
+    let mut block = block_paying(5, prev, &[script(2)]);
+    block.txdata.push(spend);
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    headers.push(filters.add_block(5, &block)?);
+
+    let store = MemoryStore::new();
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    let engine = Niebla158::new(
+        store.clone(),
+        RecordingHooks::new(vec![script(1), script(2)]),
+        filters,
+        headers,
+    )
+    .with_utxo_tracking()
+    .with_events(move |e: &EngineEvent| {
+        if let EngineEvent::BalanceChanged(d) = e {
+            sink.lock().unwrap().push(*d)
+        }
+    });
+    engine.run_to_tip().await?;
+
+    let sats = Amount::from_sat;
+    let expected = vec![
+        BalanceDelta {
+            height: 1,
+            received: sats(2_000),
+            sent: Amount::ZERO,
+            balance: sats(2_000),
+        },
+        BalanceDelta {
+            height: 3,
+            received: sats(2_000),
+            sent: Amount::ZERO,
+            balance: sats(4_000),
+        },
+        BalanceDelta {
+            height: 5,
+            received: sats(1_000),
+            sent: sats(1_000),
+            balance: sats(4_000),
+        },
+    ];
+    assert_eq!(utxo::deltas(&store).await?, expected);
+    assert_eq!(*events.lock().unwrap(), expected);
+    assert_eq!(utxo::balance_at(&store, 0).await?, Amount::ZERO);
+    assert_eq!(utxo::balance_at(&store, 2).await?, sats(2_000));
+    assert_eq!(utxo::balance_at(&store, 4).await?, sats(4_000));
+
+    // A rescan reports the balances of its blocks again, not the current one.
+    events.lock().unwrap().clear();
+    engine.handle().rescan_from(3);
+    engine.run_to_tip().await?;
+    assert_eq!(*events.lock().unwrap(), expected[1..]);
+    Ok(())
+}
+
+/// A transaction moving `coin` to `value` sats paid to `to`.
+fn spending(coin: OutPoint, value: u64, to: ScriptBuf) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: coin,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: to,
+        }],
+    }
+}
+
+#[tokio::test]
+async fn a_stale_spend_delivered_after_a_reorg_spends_nothing_on_the_new_branch(
+) -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let genesis = headers.push(genesis_hash());
+    let outsider = block_paying(1, genesis, &[script(5)]);
+    let a1 = headers.push(filters.add_block(1, &outsider)?);
+    // The wallet is paid at 2, and spends the coin at 3.
+    let funding = spending(
+        OutPoint::new(outsider.txdata[0].compute_txid(), 0),
+        900,
+        script(1),
+    );
+    let coin = OutPoint::new(funding.compute_txid(), 0);
+    let mut block = block_paying(2, a1, &[script(8)]);
+    block.txdata.push(funding.clone());
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    let a2 = headers.push(filters.add_block(2, &block)?);
+
+    let store = MemoryStore::new();
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = || {
+        Niebla158::new(
+            store.clone(),
+            hooks.clone(),
+            filters.clone(),
+            headers.clone(),
+        )
+        .with_utxo_tracking()
+        .with_watchtower()
+    };
+    engine().run_to_tip().await?;
+
+    // The spend's delivery fails, so it stays queued.
+    let mut block = block_paying(3, a2, &[script(8)]);
+    block.txdata.push(spending(coin, 800, script(7)));
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    headers.push(filters.add_block(3, &block)?);
+    hooks.fail_next("wallet is busy");
+    assert!(engine().run_to_tip().await.is_err());
+
+    // A reorg from 1 confirms the funding again at 3', without the spend.
+    let b2 = filters.add_block(2, &block_paying(2, a1, &[script(9)]))?;
+    let mut block = block_paying(3, b2, &[script(9)]);
+    block.txdata.push(funding.clone());
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    let b3 = filters.add_block(3, &block)?;
+    let b4 = filters.add_block(4, &block_paying(4, b3, &[script(9)]))?;
+    headers.reorg(1, [b2, b3, b4]);
+    engine().run_to_tip().await?;
+
+    // The stale spend was delivered, but the coin is unspent on the new branch.
+    assert_eq!(hooks.matched_heights(), vec![2, 3, 3]);
+    assert_eq!(
+        utxo::unspent(&store).await?,
+        vec![Utxo {
+            outpoint: coin,
+            txout: funding.output[0].clone(),
+            height: 3,
+            spent_at: None,
+            orphaned: false,
+        }]
+    );
+    assert_eq!(utxo::balance(&store).await?, Amount::from_sat(900));
+    Ok(())
+}
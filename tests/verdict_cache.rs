@@ -0,0 +1,62 @@
+use niebla_158::headers::HeaderSource;
+use niebla_158::hooks::watchlist_fingerprint;
+use niebla_158::testing::*;
+use niebla_158::Niebla158;
+
+#[tokio::test]
+async fn rescans_only_fetch_filters_without_a_cached_miss() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let mut prev = headers.push(genesis_hash());
+    for h in 1..=10u32 {
+        let pays = match h {
+            4 => vec![script(1)],
+            7 => vec![script(2)],
+            _ => vec![script(100 + h as u8)],
+        };
+        prev = headers.push(filters.add_block(h, &block_paying(h, prev, &pays))?);
+    }
+
+    let hooks = RecordingHooks::new(vec![script(1)]);
+    let engine = Niebla158::new(
+        MemoryStore::new(),
+        hooks.clone(),
+        filters.clone(),
+        headers.clone(),
+    )
+    .with_verdict_cache();
+    engine.run_to_tip().await?;
+    assert_eq!(filters.calls(MockCall::Cfilter), 10);
+
+    // Same watchlist: only the block that matched is fetched again.
+    engine.handle().rescan_from(1);
+    engine.run_to_tip().await?;
+    assert_eq!(filters.calls(MockCall::Cfilter), 11);
+    assert_eq!(hooks.matched_heights(), vec![4, 4]);
+
+    // A new script invalidates every verdict.
+    hooks.set_watchlist(vec![script(1), script(2)]);
+    engine.handle().rescan_from(1);
+    engine.run_to_tip().await?;
+    assert_eq!(filters.calls(MockCall::Cfilter), 21);
+    assert_eq!(hooks.matched_heights(), vec![4, 4, 4, 7]);
+
+    // Blocks replaced by a reorg are fetched again too.
+    let mut prev = headers.hash_at_height(7).await?;
+    let mut new_blocks = vec![];
+    for h in 8..=10u32 {
+        prev = filters.add_block(h, &block_paying(h, prev, &[script(200 + h as u8)]))?;
+        new_blocks.push(prev);
+    }
+    headers.reorg(7, new_blocks);
+    engine.handle().rescan_from(1);
+    engine.run_to_tip().await?;
+    assert_eq!(filters.calls(MockCall::Cfilter), 26);
+    Ok(())
+}
+
+#[test]
+fn fingerprint_ignores_order_and_duplicates() {
+    let a = watchlist_fingerprint(&[script(1), script(2)]);
+    assert_eq!(a, watchlist_fingerprint(&[script(2), script(1), script(2)]));
+    assert_ne!(a, watchlist_fingerprint(&[script(1)]));
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "webhook")]
+
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use niebla_158::testing::*;
+use niebla_158::webhook::WebhookWallet;
+use niebla_158::{Niebla158, WatchItem};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// A received request: lower-cased headers and the body.
+type Request = (Vec<(String, String)>, Vec<u8>);
+
+/// Accept POSTs, answering `500` to the first `failures` and `200` after that.
+async fn receiver(failures: usize) -> anyhow::Result<(String, Arc<Mutex<Vec<Request>>>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let seen = Arc::new(Mutex::new(vec![]));
+    let log = seen.clone();
+    tokio::spawn(async move {
+        while let Ok((mut sock, _)) = listener.accept().await {
+            let mut buf = vec![];
+            let mut chunk = [0u8; 4096];
+            let (head_len, body_len) = loop {
+                let n = sock.read(&mut chunk).await.unwrap_or(0);
+                if n == 0 {
+                    break (0, 0);
+                }
+                buf.extend_from_slice(&chunk[..n]);
+                if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+                    let len = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("content-length:"))
+                        .and_then(|v| v.trim().parse().ok())
+                        .unwrap_or(0);
+                    break (end + 4, len);
+                }
+            };
+            while buf.len() < head_len + body_len {
+                let n = sock.read(&mut chunk).await.unwrap_or(0);
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let headers = String::from_utf8_lossy(&buf[..head_len])
+                .lines()
+                .skip(1)
+                .filter_map(|l| l.split_once(':'))
+                .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_owned()))
+                .collect();
+            let body = buf[head_len..].to_vec();
+            let status = {
+                let mut log = log.lock().unwrap();
+                log.push((headers, body));
+                if log.len() <= failures {
+                    "500 Internal Server Error"
+                } else {
+                    "200 OK"
+                }
+            };
+            let resp =
+                format!("HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+            let _ = sock.write_all(resp.as_bytes()).await;
+        }
+    });
+    Ok((format!("http://{addr}/hook"), seen))
+}
+
+#[tokio::test]
+async fn matches_are_posted_signed_and_retried() -> anyhow::Result<()> {
+    let (filters, headers) = mock_chain(4, |h| if h == 3 { vec![script(1)] } else { vec![] })?;
+
+    let (url, seen) = receiver(1).await?;
+    let wallet = WebhookWallet::new(url, vec![WatchItem::new(script(1)).with_tag("acct-7")])
+        .with_secret("s3cret")
+        .with_retries(3, Duration::from_millis(10));
+    Niebla158::new(MemoryStore::new(), wallet, filters, headers)
+        .run_to_tip()
+        .await?;
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 2, "one failed attempt, one delivered");
+    assert_eq!(seen[0].1, seen[1].1);
+    let (headers, body) = &seen[1];
+    let json: serde_json::Value = serde_json::from_slice(body)?;
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["height"], 3);
+    assert_eq!(json["txs"].as_array().map(Vec::len), Some(1));
+    assert_eq!(json["items"][0]["script"], script(1).to_hex_string());
+    assert_eq!(json["items"][0]["tag"], "acct-7");
+    assert_eq!(json["sequence"], 1);
+    assert!(json["match_id"].is_string());
+
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(b"s3cret");
+    engine.input(body);
+    let expected = format!("sha256={}", hmac::Hmac::<sha256::Hash>::from_engine(engine));
+    let sig = headers
+        .iter()
+        .find(|(k, _)| k == "x-niebla-signature")
+        .map(|(_, v)| v.clone());
+    assert_eq!(sig, Some(expected));
+    Ok(())
+}
+
+#[tokio::test]
+async fn exhausted_retries_fail_the_run() -> anyhow::Result<()> {
+    let (filters, headers) = (MockFilterSource::new(), MockHeaderSource::new());
+    let prev = headers.push(genesis_hash());
+    headers.push(filters.add_block(1, &block_paying(1, prev, &[script(1)]))?);
+
+    let (url, seen) = receiver(usize::MAX).await?;
+    let store = MemoryStore::new();
+    let wallet = WebhookWallet::new(url, vec![WatchItem::new(script(1))])
+        .with_retries(2, Duration::from_millis(10));
+    let err = Niebla158::new(store.clone(), wallet, filters, headers)
+        .run_to_tip()
+        .await
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("after 2 attempts"));
+    assert_eq!(seen.lock().unwrap().len(), 2);
+    // Not marked as scanned, so the next run delivers it again.
+    assert_eq!(niebla_158::Store::get_last_scanned(&store).await?, 0);
+    Ok(())
+}